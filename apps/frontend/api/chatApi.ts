@@ -9,6 +9,17 @@ import {
 } from '../types/chat'
 import { getRestUrl, DEFAULT_ROOM_ID } from '../config/api'
 
+/**
+ * Identify the user to the local dev server, which trusts these headers
+ * (AUTH_TRUST_CLIENT_HEADERS); deployed stages ignore them and require a bearer token
+ */
+function identityHeaders(userId?: string, username?: string): Record<string, string> {
+    return {
+        ...(userId ? { 'X-User-Id': userId } : {}),
+        ...(username ? { 'X-Username': username } : {}),
+    }
+}
+
 /**
  * Fetch messages for a specific room from the backend
  */
@@ -24,6 +35,7 @@ export async function fetchMessages(
             method: 'GET',
             headers: {
                 'Content-Type': 'application/json',
+                ...identityHeaders(currentUserId),
             },
         })
 
@@ -55,6 +67,7 @@ export async function sendMessage(request: SendMessageRequestUI): Promise<SendMe
             method: 'POST',
             headers: {
                 'Content-Type': 'application/json',
+                ...identityHeaders(request.userId, request.username),
             },
             body: JSON.stringify(backendRequest),
         })
@@ -1,7 +1,7 @@
 import { useEffect, useRef, useState, useCallback } from 'react'
 import { useUserStore } from '../stores/userStore'
 import { getWebSocketUrl, DEFAULT_ROOM_ID } from '../config/api'
-import { type ChatMessage, chatMessageToMessage } from '../types/chat'
+import { type ServerFrame, chatMessageToMessage } from '../types/chat'
 import { useOptimisticMessage } from './useChatQueries'
 
 export interface UseChatSocketOptions {
@@ -104,8 +104,13 @@ export function useChatSocket(options: UseChatSocketOptions = {}): UseChatSocket
 
             ws.onmessage = (event) => {
                 try {
-                    const chatMessage: ChatMessage = JSON.parse(event.data)
-                    console.log('Received WebSocket message:', chatMessage)
+                    const frame: ServerFrame = JSON.parse(event.data)
+                    console.log('Received WebSocket frame:', frame.type)
+                    // Only new messages are handled here so far
+                    if (frame.type !== 'message.new') {
+                        return
+                    }
+                    const chatMessage = frame.message
 
                     // Convert to frontend Message format
                     const message = chatMessageToMessage(chatMessage, userId)
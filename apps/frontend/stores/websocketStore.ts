@@ -2,7 +2,7 @@ import { create } from 'zustand'
 import { subscribeWithSelector } from 'zustand/middleware'
 import { AppState } from 'react-native'
 import { getWebSocketUrl, DEFAULT_ROOM_ID } from '../config/api'
-import { type Message, type ChatMessage, chatMessageToMessage } from '../types/chat'
+import { type Message, type ChatMessage, type ServerFrame, chatMessageToMessage } from '../types/chat'
 
 export interface WebSocketState {
     // Connection state
@@ -106,8 +106,13 @@ export const useWebSocketStore = create<WebSocketState>()(
 
                 ws.onmessage = (event) => {
                     try {
-                        const chatMessage: ChatMessage = JSON.parse(event.data)
-                        console.log('Received WebSocket message:', chatMessage)
+                        const frame: ServerFrame = JSON.parse(event.data)
+                        console.log('Received WebSocket frame:', frame.type)
+                        // Only new messages are handled here so far
+                        if (frame.type !== 'message.new') {
+                            return
+                        }
+                        const chatMessage = frame.message
 
                         // Convert to frontend Message format
                         const message = chatMessageToMessage(chatMessage, userId)
@@ -2,6 +2,7 @@
 import type { ChatMessage as BackendChatMessage } from '../../../packages/types/bindings/ChatMessage'
 import type { SendMessageRequest as BackendSendMessageRequest } from '../../../packages/types/bindings/SendMessageRequest'
 import type { GetMessagesResponse } from '../../../packages/types/bindings/GetMessagesResponse'
+import type { ServerFrame } from '../../../packages/types/bindings/ServerFrame'
 
 // Frontend-specific message type that extends backend type with UI properties
 export interface Message extends Omit<BackendChatMessage, 'created_at' | 'message_text'> {
@@ -15,6 +16,7 @@ export type {
     BackendChatMessage as ChatMessage,
     BackendSendMessageRequest as SendMessageRequest,
     GetMessagesResponse,
+    ServerFrame,
 }
 
 // Frontend-specific request type
@@ -3,14 +3,38 @@
 
 # Set environment variables for deployed DynamoDB tables
 export CHAT_ROOMS_TABLE="chat-rooms"
-export CHAT_MESSAGES_TABLE="chat-messages"
+export CHAT_MESSAGES_TABLE="chat-messages-v2"
 export CONNECTIONS_TABLE="chat-connections"
+export CHAT_DEDUP_TABLE="chat-message-dedup"
+export CHAT_MEMBERS_TABLE="chat-room-members"
+export CHAT_SANCTIONS_TABLE="chat-room-sanctions"
+export CHAT_REPORTS_TABLE="chat-message-reports"
+export CHAT_READS_TABLE="chat-read-markers"
 export AWS_REGION="us-east-1"
 export AWS_PROFILE="sb-beta"
 
 # Optional: Set stage for metrics
 export STAGE="beta"
 
+# Optional: Storage backend ("dynamodb" by default, "memory" or "sqlite" to run without AWS)
+# With "sqlite", SQLITE_PATH selects the database file (defaults to ./chat.db)
+export CHAT_STORE=${CHAT_STORE:-dynamodb}
+
+# Authentication: locally, trust X-User-Id/X-Username (or ?userId=&username= on /ws).
+# Only honoured by `dev` builds. Set AUTH_TOKEN_SECRET, AUTH_API_KEYS or COGNITO_USER_POOL_ID
+# to exercise real credentials instead.
+export AUTH_TRUST_CLIENT_HEADERS=${AUTH_TRUST_CLIENT_HEADERS:-true}
+
+# Optional: posting limits (token buckets, kept in process by the dev server).
+# Defaults: 30/min with bursts of 10 per user, 300/min with bursts of 60 per room; 0 disables.
+#   export RATE_LIMIT_USER_PER_MIN=30 RATE_LIMIT_USER_BURST=10
+#   export RATE_LIMIT_ROOM_PER_MIN=300 RATE_LIMIT_ROOM_BURST=60
+
+# Optional: content filters. Actions are flag, mask or reject.
+#   export FILTER_BLOCKLIST="word1,word2" FILTER_BLOCKLIST_ACTION=mask
+#   export FILTER_LINKS_DENY="bad.example" FILTER_LINKS_ACTION=reject   # or FILTER_LINKS_ALLOW
+#   export FILTER_MAX_MENTIONS=10 FILTER_MAX_REPEAT=20                 # 0 disables
+
 # Optional: Public URL for local broadcast fan-out
 # If you expose your local server (port 3001) via a tunnel (e.g., ngrok, Cloudflare Tunnel),
 # set DEV_BROADCAST_URL to that public base URL so the AWS broadcast Lambda can call back:
@@ -23,8 +47,14 @@ echo "📊 DynamoDB Tables:"
 echo "   - Rooms: $CHAT_ROOMS_TABLE"
 echo "   - Messages: $CHAT_MESSAGES_TABLE"
 echo "   - Connections: $CONNECTIONS_TABLE"
+echo "   - Message dedup: $CHAT_DEDUP_TABLE"
+echo "   - Room members: $CHAT_MEMBERS_TABLE"
+echo "   - Room sanctions: $CHAT_SANCTIONS_TABLE"
+echo "   - Message reports: $CHAT_REPORTS_TABLE"
+echo "   - Read markers: $CHAT_READS_TABLE"
 echo "🌐 Region: $AWS_REGION"
 echo "👤 Profile: $AWS_PROFILE"
+echo "🗄️  Store: $CHAT_STORE"
 if [ -n "$DEV_BROADCAST_URL" ]; then
   echo "🔁 Dev Broadcast URL: $DEV_BROADCAST_URL (Lambda will POST /dev/broadcast here)"
 else
@@ -0,0 +1,98 @@
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use super::{AuthError, Authenticator, Credentials, Identity};
+
+/// Static API keys for bots, sent as `X-Api-Key`. Only SHA-256 digests of the keys are
+/// kept, so configuration never holds the keys themselves.
+#[derive(Default)]
+pub struct ApiKeys {
+    by_digest: HashMap<String, Identity>,
+}
+
+impl ApiKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hex SHA-256 digest of a key, as listed in `AUTH_API_KEYS`
+    pub fn digest(key: &str) -> String {
+        Sha256::digest(key.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn with_key(self, key: &str, identity: Identity) -> Self {
+        self.with_digest(&Self::digest(key), identity)
+    }
+
+    pub fn with_digest(mut self, digest: &str, identity: Identity) -> Self {
+        self.by_digest.insert(digest.to_ascii_lowercase(), identity);
+        self
+    }
+
+    /// Parse `user_id=<hex sha256 of key>` entries separated by commas. A bot's username is
+    /// its user id.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut keys = Self::new();
+        for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (user_id, digest) = entry
+                .split_once('=')
+                .map(|(user_id, digest)| (user_id.trim(), digest.trim()))
+                .ok_or_else(|| format!("expected user_id=digest, got {}", entry))?;
+            if user_id.is_empty() {
+                return Err(format!("missing user id in {}", entry));
+            }
+            if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("digest for {} is not a hex SHA-256", user_id));
+            }
+            let identity = Identity { user_id: user_id.to_string(), username: user_id.to_string() };
+            keys = keys.with_digest(digest, identity);
+        }
+        Ok(keys)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_digest.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_digest.is_empty()
+    }
+}
+
+#[async_trait]
+impl Authenticator for ApiKeys {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<Option<Identity>, AuthError> {
+        let Some(key) = &credentials.api_key else {
+            return Ok(None);
+        };
+        match self.by_digest.get(&Self::digest(key)) {
+            Some(identity) => Ok(Some(identity.clone())),
+            None => Err(AuthError::Invalid("unknown API key".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        let spec = format!("deploy-bot={}, ", ApiKeys::digest("s3cret").to_uppercase());
+        let keys = ApiKeys::parse(&spec).unwrap();
+        assert_eq!(keys.len(), 1);
+
+        let with_key =
+            |key: &str| Credentials { api_key: Some(key.to_string()), ..Default::default() };
+        let identity = keys.authenticate(&with_key("s3cret")).await.unwrap().unwrap();
+        assert_eq!(identity.user_id, "deploy-bot");
+        assert!(keys.authenticate(&with_key("guess")).await.is_err());
+        assert_eq!(keys.authenticate(&Credentials::default()).await, Ok(None));
+
+        assert!(ApiKeys::parse("deploy-bot").is_err());
+        assert!(ApiKeys::parse("deploy-bot=abc").is_err());
+        assert!(ApiKeys::parse(&format!("={}", ApiKeys::digest("x"))).is_err());
+        assert!(ApiKeys::parse("").unwrap().is_empty());
+    }
+}
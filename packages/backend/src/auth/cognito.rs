@@ -0,0 +1,295 @@
+use async_trait::async_trait;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::Deserialize;
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+use tracing::info;
+
+use super::{AuthError, Authenticator, Credentials, Identity};
+
+// A cached JWKS is refetched for an unknown key id at most this often (key rotation)
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// Claims of a Cognito ID or access token
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    token_use: Option<String>,
+    // Cognito ID tokens carry a single audience: the app client id
+    #[serde(default)]
+    aud: Option<String>,
+    // Access tokens name the app client here instead
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default, rename = "cognito:username")]
+    cognito_username: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    preferred_username: Option<String>,
+}
+
+impl Claims {
+    fn into_identity(self) -> Identity {
+        let username = self
+            .preferred_username
+            .or(self.cognito_username)
+            .or(self.username)
+            .unwrap_or_else(|| self.sub.clone());
+        Identity { user_id: self.sub, username }
+    }
+}
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+enum KeySource {
+    /// A Cognito user pool's JWKS endpoint, fetched on first use and cached
+    Remote { url: String, http: reqwest::Client, cache: RwLock<Option<CachedJwks>> },
+    /// A JWKS read from a local file
+    Static(JwkSet),
+    /// A shared HS256 secret, for offline tests
+    Secret(DecodingKey),
+}
+
+/// Verifies bearer tokens issued by a Cognito user pool (or a local stand-in for tests)
+pub struct JwtVerifier {
+    keys: KeySource,
+    issuer: Option<String>,
+    client_id: Option<String>,
+}
+
+impl JwtVerifier {
+    /// Verifier for a Cognito user pool, e.g. `us-east-1_AbCdEf123`. Tokens must come from
+    /// `client_id` when one is given.
+    pub fn cognito(user_pool_id: &str, client_id: Option<String>) -> Self {
+        // Pool ids are "<region>_<id>"
+        let region = user_pool_id.split('_').next().unwrap_or("us-east-1");
+        let issuer = format!("https://cognito-idp.{}.amazonaws.com/{}", region, user_pool_id);
+        Self {
+            keys: KeySource::Remote {
+                url: format!("{}/.well-known/jwks.json", issuer),
+                http: reqwest::Client::new(),
+                cache: RwLock::new(None),
+            },
+            issuer: Some(issuer),
+            client_id,
+        }
+    }
+
+    /// Verifier for RS256 tokens signed by the keys in a local JWKS
+    pub fn with_jwks(keys: JwkSet, issuer: Option<String>, client_id: Option<String>) -> Self {
+        Self { keys: KeySource::Static(keys), issuer, client_id }
+    }
+
+    /// Verifier for HS256 tokens signed with a shared secret
+    pub fn with_secret(secret: &[u8], issuer: Option<String>, client_id: Option<String>) -> Self {
+        Self { keys: KeySource::Secret(DecodingKey::from_secret(secret)), issuer, client_id }
+    }
+
+    /// Verifier configured from the environment, or None when no user pool is configured.
+    ///
+    /// `COGNITO_USER_POOL_ID` (with optional `COGNITO_CLIENT_ID`) selects a user pool.
+    /// Offline, `AUTH_JWKS_FILE` supplies the keys instead, with optional `AUTH_JWT_ISSUER`
+    /// and `COGNITO_CLIENT_ID` checks.
+    pub fn from_env() -> Option<Self> {
+        let client_id = env::var("COGNITO_CLIENT_ID").ok();
+        let issuer = env::var("AUTH_JWT_ISSUER").ok();
+
+        if let Ok(pool_id) = env::var("COGNITO_USER_POOL_ID") {
+            info!("Verifying tokens from Cognito user pool {}", pool_id);
+            return Some(Self::cognito(&pool_id, client_id));
+        }
+        if let Ok(path) = env::var("AUTH_JWKS_FILE") {
+            info!("Verifying tokens against keys in {}", path);
+            let json = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Failed to read AUTH_JWKS_FILE {}: {}", path, e));
+            let keys: JwkSet = serde_json::from_str(&json)
+                .unwrap_or_else(|e| panic!("AUTH_JWKS_FILE {} is not a JWKS: {}", path, e));
+            return Some(Self::with_jwks(keys, issuer, client_id));
+        }
+        None
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<Identity, AuthError> {
+        let header = decode_header(token).map_err(|e| AuthError::Invalid(e.to_string()))?;
+
+        let (key, algorithm) = match &self.keys {
+            KeySource::Secret(key) => (key.clone(), Algorithm::HS256),
+            KeySource::Static(keys) => {
+                let kid = header.kid.as_deref().ok_or_else(missing_kid)?;
+                (decoding_key(keys.find(kid))?, Algorithm::RS256)
+            }
+            KeySource::Remote { .. } => {
+                let kid = header.kid.as_deref().ok_or_else(missing_kid)?;
+                (decoding_key(self.remote_key(kid).await?.as_ref())?, Algorithm::RS256)
+            }
+        };
+        if header.alg != algorithm {
+            return Err(AuthError::Invalid(format!("unexpected algorithm {:?}", header.alg)));
+        }
+
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        // Checked below: ID tokens name the client in `aud`, access tokens in `client_id`
+        validation.validate_aud = false;
+
+        let claims = decode::<Claims>(token, &key, &validation)
+            .map_err(|e| AuthError::Invalid(e.to_string()))?
+            .claims;
+        self.check_client(&claims)?;
+        Ok(claims.into_identity())
+    }
+
+    fn check_client(&self, claims: &Claims) -> Result<(), AuthError> {
+        let client = match claims.token_use.as_deref() {
+            Some("id") => claims.aud.as_deref(),
+            Some("access") => claims.client_id.as_deref(),
+            Some(other) => {
+                return Err(AuthError::Invalid(format!("unexpected token_use {}", other)))
+            }
+            None => claims.aud.as_deref().or(claims.client_id.as_deref()),
+        };
+        match &self.client_id {
+            Some(expected) if client != Some(expected.as_str()) => {
+                Err(AuthError::Invalid("token was issued to another client".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The key for `kid`, refetching the JWKS once if it is unknown and the cache is stale
+    async fn remote_key(&self, kid: &str) -> Result<Option<Jwk>, AuthError> {
+        let KeySource::Remote { url, http, cache } = &self.keys else {
+            return Ok(None);
+        };
+
+        if let Some(cached) = cache.read().await.as_ref() {
+            if let Some(jwk) = cached.keys.find(kid) {
+                return Ok(Some(jwk.clone()));
+            }
+            if cached.fetched_at.elapsed() < JWKS_REFRESH_INTERVAL {
+                return Ok(None);
+            }
+        }
+
+        let mut cache = cache.write().await;
+        // Another request may have refreshed it while we waited for the lock
+        if let Some(cached) = cache.as_ref() {
+            if cached.fetched_at.elapsed() < JWKS_REFRESH_INTERVAL {
+                return Ok(cached.keys.find(kid).cloned());
+            }
+        }
+        info!("Fetching JWKS from {}", url);
+        let keys: JwkSet = http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| AuthError::Unavailable(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| AuthError::Unavailable(e.to_string()))?;
+        let jwk = keys.find(kid).cloned();
+        *cache = Some(CachedJwks { keys, fetched_at: Instant::now() });
+        Ok(jwk)
+    }
+}
+
+#[async_trait]
+impl Authenticator for JwtVerifier {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<Option<Identity>, AuthError> {
+        match &credentials.bearer {
+            Some(token) => self.verify(token).await.map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn missing_kid() -> AuthError {
+    AuthError::Invalid("token has no key id".to_string())
+}
+
+fn decoding_key(jwk: Option<&Jwk>) -> Result<DecodingKey, AuthError> {
+    let jwk = jwk.ok_or_else(|| AuthError::Invalid("unknown signing key".to_string()))?;
+    DecodingKey::from_jwk(jwk).map_err(|e| AuthError::Invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"test-secret";
+
+    fn token(claims: serde_json::Value) -> String {
+        encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    fn exp() -> i64 {
+        chrono::Utc::now().timestamp() + 300
+    }
+
+    #[tokio::test]
+    async fn test_verify_claims() {
+        let issuer = "https://issuer.test".to_string();
+        let verifier = JwtVerifier::with_secret(SECRET, Some(issuer), Some("app".to_string()));
+
+        let id_token = token(json!({
+            "sub": "u-123",
+            "cognito:username": "alice",
+            "token_use": "id",
+            "aud": "app",
+            "iss": "https://issuer.test",
+            "exp": exp(),
+        }));
+        assert_eq!(
+            verifier.verify(&id_token).await,
+            Ok(Identity { user_id: "u-123".to_string(), username: "alice".to_string() })
+        );
+
+        let access_token = token(json!({
+            "sub": "u-123",
+            "username": "alice",
+            "token_use": "access",
+            "client_id": "app",
+            "iss": "https://issuer.test",
+            "exp": exp(),
+        }));
+        assert_eq!(verifier.verify(&access_token).await.unwrap().username, "alice");
+
+        for claims in [
+            // Expired
+            json!({ "sub": "u", "aud": "app", "iss": "https://issuer.test", "exp": 1 }),
+            // Another issuer
+            json!({ "sub": "u", "aud": "app", "iss": "https://evil.test", "exp": exp() }),
+            // Another app client
+            json!({ "sub": "u", "aud": "other", "iss": "https://issuer.test", "exp": exp() }),
+        ] {
+            let result = verifier.verify(&token(claims.clone())).await;
+            assert!(matches!(result, Err(AuthError::Invalid(_))), "{} should fail", claims);
+        }
+
+        let forged = encode(
+            &Header::new(Algorithm::HS256),
+            &json!({ "sub": "u" }),
+            &EncodingKey::from_secret(b"x"),
+        )
+        .unwrap();
+        assert!(verifier.verify(&forged).await.is_err());
+        assert!(verifier.verify("not a token").await.is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use async_trait::async_trait;
+use std::{env, fmt, sync::Arc};
+use tracing::{info, warn};
+use types::AuthTokenResponse;
+
+use crate::handlers::HandlerError;
+
+pub mod api_key;
+pub mod cognito;
+#[cfg(feature = "dev")]
+pub mod dev;
+pub mod token;
+
+pub use api_key::ApiKeys;
+pub use cognito::JwtVerifier;
+#[cfg(feature = "dev")]
+pub use dev::TrustClientHeaders;
+pub use token::TokenIssuer;
+
+// The caller as established by an authenticator
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub user_id: String,
+    pub username: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    /// The request carries no credentials
+    Missing,
+    /// The credentials are malformed, expired, badly signed or meant for someone else
+    Invalid(String),
+    /// The signing keys could not be loaded
+    Unavailable(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Missing => write!(f, "Missing credentials"),
+            AuthError::Invalid(reason) => write!(f, "Invalid credentials: {}", reason),
+            AuthError::Unavailable(reason) => write!(f, "Signing keys unavailable: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<AuthError> for HandlerError {
+    fn from(err: AuthError) -> Self {
+        match err {
+            AuthError::Unavailable(_) => Self::internal(err.to_string()),
+            _ => Self::unauthorized(err.to_string()),
+        }
+    }
+}
+
+/// The token in an `Authorization: Bearer <token>` header value
+pub fn bearer_token(header: Option<&str>) -> Option<&str> {
+    let (scheme, token) = header?.trim().split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+// Everything a request presents to prove who it is. Each entrypoint collects these the same
+// way, so the axum routes, the rest lambda and ws_connect accept the same credentials.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Credentials {
+    pub bearer: Option<String>, // `Authorization: Bearer`, or ?token= for WebSockets
+    pub api_key: Option<String>, // `X-Api-Key`
+    pub claimed_user_id: Option<String>, // `X-User-Id` or ?userId=; only trusted in dev mode
+    pub claimed_username: Option<String>, // `X-Username` or ?username=
+}
+
+impl Credentials {
+    /// Collect credentials given a case-insensitive header lookup and a query parameter lookup
+    pub fn collect<'a>(
+        header: impl Fn(&str) -> Option<&'a str>,
+        query: impl Fn(&str) -> Option<&'a str>,
+    ) -> Self {
+        let owned = |value: Option<&str>| {
+            value.map(str::trim).filter(|value| !value.is_empty()).map(str::to_string)
+        };
+        Self {
+            bearer: owned(bearer_token(header("authorization")).or_else(|| query("token"))),
+            api_key: owned(header("x-api-key")),
+            claimed_user_id: owned(header("x-user-id").or_else(|| query("userId"))),
+            claimed_username: owned(header("x-username").or_else(|| query("username"))),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// A way of proving identity: Cognito tokens, backend-issued tokens, API keys, ...
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// The caller the credentials prove, None when they carry nothing this authenticator
+    /// understands, or an error when they are meant for it but do not check out
+    async fn authenticate(&self, credentials: &Credentials) -> Result<Option<Identity>, AuthError>;
+}
+
+/// Tries each authenticator in turn; the first to recognise the credentials decides
+#[derive(Default, Clone)]
+pub struct AuthChain {
+    authenticators: Vec<Arc<dyn Authenticator>>,
+    tokens: Option<Arc<TokenIssuer>>,
+}
+
+impl AuthChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, authenticator: impl Authenticator + 'static) -> Self {
+        self.authenticators.push(Arc::new(authenticator));
+        self
+    }
+
+    /// Accept tokens signed by `issuer`, and let `issue_token` hand them out
+    pub fn with_tokens(mut self, issuer: TokenIssuer) -> Self {
+        let issuer = Arc::new(issuer);
+        self.authenticators.push(issuer.clone());
+        self.tokens = Some(issuer);
+        self
+    }
+
+    /// The authenticators enabled by the environment, in the order they are tried:
+    ///
+    /// - `AUTH_TOKEN_SECRET`: tokens from `POST /auth/token`, valid for `AUTH_TOKEN_TTL_SECS`
+    /// - `AUTH_API_KEYS`: static API keys for bots, see `ApiKeys::parse`
+    /// - `COGNITO_USER_POOL_ID` or `AUTH_JWKS_FILE`: see `JwtVerifier::from_env`
+    /// - `AUTH_TRUST_CLIENT_HEADERS=true`: trust `X-User-Id`, only in `dev` builds
+    pub fn from_env() -> Self {
+        let mut chain = Self::new();
+
+        if let Ok(secret) = env::var("AUTH_TOKEN_SECRET") {
+            let ttl = env::var("AUTH_TOKEN_TTL_SECS")
+                .ok()
+                .and_then(|ttl| ttl.parse().ok())
+                .unwrap_or(token::DEFAULT_TTL_SECS);
+            info!("Accepting backend-issued tokens (ttl {}s)", ttl);
+            chain = chain.with_tokens(TokenIssuer::new(secret.as_bytes(), ttl));
+        }
+        if let Ok(spec) = env::var("AUTH_API_KEYS") {
+            let keys = ApiKeys::parse(&spec)
+                .unwrap_or_else(|e| panic!("AUTH_API_KEYS is malformed: {}", e));
+            info!("Accepting {} API key(s)", keys.len());
+            chain = chain.with(keys);
+        }
+        if let Some(verifier) = JwtVerifier::from_env() {
+            chain = chain.with(verifier);
+        }
+        #[cfg(feature = "dev")]
+        if matches!(env::var("AUTH_TRUST_CLIENT_HEADERS").as_deref(), Ok("1" | "true")) {
+            warn!("Trusting client-supplied user ids (AUTH_TRUST_CLIENT_HEADERS); dev only");
+            chain = chain.with(TrustClientHeaders);
+        }
+
+        if chain.authenticators.is_empty() {
+            warn!("No authenticators configured; every request will be rejected");
+        }
+        chain
+    }
+
+    pub async fn authenticate(&self, credentials: &Credentials) -> Result<Identity, AuthError> {
+        for authenticator in &self.authenticators {
+            if let Some(identity) = authenticator.authenticate(credentials).await? {
+                return Ok(identity);
+            }
+        }
+        if credentials.is_empty() {
+            Err(AuthError::Missing)
+        } else {
+            Err(AuthError::Invalid("unrecognised credentials".to_string()))
+        }
+    }
+
+    /// A short-lived backend token for an already authenticated caller
+    pub fn issue_token(&self, identity: &Identity) -> Result<AuthTokenResponse, HandlerError> {
+        let issuer = self
+            .tokens
+            .as_ref()
+            .ok_or_else(|| HandlerError::not_found("Token issuing is disabled"))?;
+        Ok(issuer.issue(identity)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(user_id: &str) -> Identity {
+        Identity { user_id: user_id.to_string(), username: user_id.to_string() }
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token(Some("Bearer abc")), Some("abc"));
+        assert_eq!(bearer_token(Some("bearer  abc ")), Some("abc"));
+        assert_eq!(bearer_token(Some("Basic abc")), None);
+        assert_eq!(bearer_token(Some("Bearer ")), None);
+        assert_eq!(bearer_token(None), None);
+    }
+
+    #[test]
+    fn test_collect_credentials() {
+        let credentials = Credentials::collect(
+            |name| match name {
+                "authorization" => Some("Bearer header-token"),
+                "x-api-key" => Some(" key "),
+                _ => None,
+            },
+            |name| match name {
+                "token" => Some("query-token"),
+                "userId" => Some("alice"),
+                "username" => Some(""),
+                _ => None,
+            },
+        );
+        assert_eq!(
+            credentials,
+            Credentials {
+                bearer: Some("header-token".to_string()),
+                api_key: Some("key".to_string()),
+                claimed_user_id: Some("alice".to_string()),
+                claimed_username: None,
+            }
+        );
+        assert!(Credentials::collect(|_| None, |_| None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_chain() {
+        let tokens = TokenIssuer::new(b"secret", 60);
+        let bot_token = tokens.issue(&identity("alice")).unwrap().token;
+        let chain = AuthChain::new()
+            .with_tokens(tokens)
+            .with(ApiKeys::new().with_key("bot-key", identity("bot")));
+
+        let with_key = Credentials { api_key: Some("bot-key".to_string()), ..Default::default() };
+        assert_eq!(chain.authenticate(&with_key).await, Ok(identity("bot")));
+        let with_token = Credentials { bearer: Some(bot_token), ..Default::default() };
+        assert_eq!(chain.authenticate(&with_token).await, Ok(identity("alice")));
+        assert_eq!(chain.issue_token(&identity("bot")).unwrap().user_id, "bot");
+
+        // Claimed ids prove nothing outside dev mode
+        let claimed =
+            Credentials { claimed_user_id: Some("alice".to_string()), ..Default::default() };
+        assert!(matches!(chain.authenticate(&claimed).await, Err(AuthError::Invalid(_))));
+        assert_eq!(chain.authenticate(&Credentials::default()).await, Err(AuthError::Missing));
+
+        let bad_key = Credentials { api_key: Some("nope".to_string()), ..Default::default() };
+        assert!(chain.authenticate(&bad_key).await.is_err());
+
+        assert_eq!(AuthChain::new().issue_token(&identity("bot")).unwrap_err().status, 404);
+    }
+}
@@ -0,0 +1,120 @@
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::{Deserialize, Serialize};
+use types::AuthTokenResponse;
+
+use super::{AuthError, Authenticator, Credentials, Identity};
+
+/// Lifetime of an issued token unless configured otherwise
+pub const DEFAULT_TTL_SECS: i64 = 60 * 60;
+
+// Marks our own tokens, so Cognito tokens on the same header are left to their verifier
+const KEY_ID: &str = "swflcoders-backend";
+const ISSUER: &str = "swflcoders-backend";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    username: String,
+    iss: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// Issues and verifies HMAC-signed (HS256) tokens for identities the backend has already
+/// authenticated, e.g. so a bot holding an API key can open a WebSocket with `?token=`
+pub struct TokenIssuer {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    ttl_secs: i64,
+}
+
+impl TokenIssuer {
+    pub fn new(secret: &[u8], ttl_secs: i64) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            ttl_secs,
+        }
+    }
+
+    pub fn issue(&self, identity: &Identity) -> Result<AuthTokenResponse, AuthError> {
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            sub: identity.user_id.clone(),
+            username: identity.username.clone(),
+            iss: ISSUER.to_string(),
+            iat: now,
+            exp: now + self.ttl_secs,
+        };
+        let header = Header { kid: Some(KEY_ID.to_string()), ..Header::new(Algorithm::HS256) };
+        let token = encode(&header, &claims, &self.encoding)
+            .map_err(|e| AuthError::Unavailable(e.to_string()))?;
+
+        Ok(AuthTokenResponse {
+            token,
+            user_id: claims.sub,
+            username: claims.username,
+            expires_at: Utc.timestamp_opt(claims.exp, 0).single().unwrap_or_default(),
+        })
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Identity, AuthError> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_issuer(&[ISSUER]);
+        let claims = decode::<Claims>(token, &self.decoding, &validation)
+            .map_err(|e| AuthError::Invalid(e.to_string()))?
+            .claims;
+        Ok(Identity { user_id: claims.sub, username: claims.username })
+    }
+}
+
+#[async_trait]
+impl Authenticator for TokenIssuer {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<Option<Identity>, AuthError> {
+        let Some(token) = &credentials.bearer else {
+            return Ok(None);
+        };
+        match decode_header(token) {
+            Ok(header) if header.kid.as_deref() == Some(KEY_ID) => self.verify(token).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_issue_and_verify() {
+        let issuer = TokenIssuer::new(b"secret", 60);
+        let alice = Identity { user_id: "alice".to_string(), username: "Alice".to_string() };
+        let issued = issuer.issue(&alice).unwrap();
+        assert!(issued.expires_at > Utc::now());
+
+        let bearer =
+            |token: &str| Credentials { bearer: Some(token.to_string()), ..Default::default() };
+        assert_eq!(issuer.authenticate(&bearer(&issued.token)).await, Ok(Some(alice.clone())));
+
+        // Another secret, or an expired token, is rejected
+        let forged = TokenIssuer::new(b"other", 60).issue(&alice).unwrap().token;
+        assert!(issuer.authenticate(&bearer(&forged)).await.is_err());
+        let expired = TokenIssuer::new(b"secret", -120).issue(&alice).unwrap().token;
+        assert!(issuer.authenticate(&bearer(&expired)).await.is_err());
+
+        // Tokens without our key id belong to another authenticator
+        let foreign = encode(
+            &Header::new(Algorithm::HS256),
+            &serde_json::json!({ "sub": "mallory", "exp": Utc::now().timestamp() + 60 }),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert_eq!(issuer.authenticate(&bearer(&foreign)).await, Ok(None));
+        assert_eq!(issuer.authenticate(&bearer("not a token")).await, Ok(None));
+        assert_eq!(issuer.authenticate(&Credentials::default()).await, Ok(None));
+    }
+}
@@ -0,0 +1,26 @@
+// One-off migration: copy messages from the legacy room_id + ts keyed table into the
+// ULID-keyed messages table. Safe to re-run; already-copied messages are skipped.
+//
+// Usage: CHAT_MESSAGES_LEGACY_TABLE=chat-messages-<stage> CHAT_MESSAGES_TABLE=... \
+//        CHAT_DEDUP_TABLE=... migrate-messages
+use aws_sdk_dynamodb::Client as DynamoDbClient;
+use backend::store::{DynamoStore, Tables};
+use std::env;
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt().with_target(false).init();
+
+    let legacy_table = env::var("CHAT_MESSAGES_LEGACY_TABLE")
+        .map_err(|_| "CHAT_MESSAGES_LEGACY_TABLE must be set")?;
+    let tables = Tables::messages_from_env();
+    info!("Migrating messages from {} to {}", legacy_table, tables.messages);
+
+    let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    let store = DynamoStore::new(DynamoDbClient::new(&aws_config), tables);
+    let copied = store.migrate_legacy_messages(&legacy_table).await?;
+
+    info!("Copied {} messages", copied);
+    Ok(())
+}
@@ -0,0 +1,538 @@
+use std::{env, ops::Range};
+use tracing::info;
+use types::{FilterAction, FilterDecision};
+
+/// A span of a message a filter objects to, and what masking puts in its place
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub range: Range<usize>, // Byte range in the text the filter was given
+    pub replacement: String,
+}
+
+/// One check in the chain. Filters only find matches; the chain decides what to do with them.
+pub trait ContentFilter: Send + Sync {
+    /// Stable identifier, recorded on flagged and masked messages
+    fn name(&self) -> &'static str;
+
+    /// Non-overlapping matches in `text`, in order
+    fn matches(&self, text: &str) -> Vec<Match>;
+}
+
+// Characters people slip into words to get them past a filter
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{00ad}' | '\u{200b}'..='\u{200f}' | '\u{2060}' | '\u{feff}')
+        || ('\u{0300}'..='\u{036f}').contains(&c) // combining diacritics
+}
+
+// Fold a lowercased character onto the ASCII letter it is standing in for
+fn fold(c: char) -> char {
+    match c {
+        // Fullwidth forms
+        '\u{ff01}'..='\u{ff5e}' => {
+            fold(char::from_u32(c as u32 - 0xfee0).unwrap_or(c).to_ascii_lowercase())
+        }
+        // Leetspeak
+        '0' => 'o',
+        '1' | '!' | '|' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        // Accented Latin letters
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' => 'a',
+        'ç' | 'ć' | 'č' => 'c',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' => 'e',
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'ı' => 'i',
+        'ñ' | 'ń' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' => 'o',
+        'ś' | 'š' => 's',
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' => 'u',
+        'ý' | 'ÿ' => 'y',
+        'ž' | 'ź' | 'ż' => 'z',
+        // Cyrillic and Greek lookalikes
+        'а' | 'α' => 'a',
+        'в' | 'β' => 'b',
+        'с' | 'ς' => 'c',
+        'е' | 'ε' => 'e',
+        'һ' => 'h',
+        'і' | 'ι' => 'i',
+        'ј' => 'j',
+        'к' | 'κ' => 'k',
+        'м' => 'm',
+        'п' | 'η' => 'n',
+        'о' | 'ο' => 'o',
+        'р' | 'ρ' => 'p',
+        'ѕ' => 's',
+        'т' | 'τ' => 't',
+        'υ' => 'u',
+        'ν' => 'v',
+        'ш' | 'ω' => 'w',
+        'х' | 'χ' => 'x',
+        'у' | 'γ' => 'y',
+        _ => c,
+    }
+}
+
+/// The text as the blocklist sees it: lowercase, folded to ASCII where a character is a
+/// stand-in for a letter, invisible characters dropped. Each character keeps the byte
+/// range it came from, so matches can be masked in the original.
+pub fn normalize(text: &str) -> Vec<(char, Range<usize>)> {
+    let mut normalized = Vec::with_capacity(text.len());
+    for (start, c) in text.char_indices() {
+        if is_invisible(c) {
+            continue;
+        }
+        let range = start..start + c.len_utf8();
+        for lower in c.to_lowercase() {
+            normalized.push((fold(lower), range.clone()));
+        }
+    }
+    normalized
+}
+
+fn mask(text: &str) -> String {
+    "*".repeat(text.chars().count())
+}
+
+/// Words that may not appear, however they are spelled: case, leetspeak, accents, lookalike
+/// letters, invisible characters and stretched letters ("baaad") are all seen through
+pub struct Blocklist {
+    words: Vec<Vec<char>>,
+}
+
+impl Blocklist {
+    pub fn new<S: AsRef<str>>(words: impl IntoIterator<Item = S>) -> Self {
+        let words = words
+            .into_iter()
+            .map(|word| normalize(word.as_ref().trim()).into_iter().map(|(c, _)| c).collect())
+            .filter(|word: &Vec<char>| !word.is_empty())
+            .collect();
+        Self { words }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    // Length of `word` matched at the start of `chars`, letting each letter repeat
+    fn match_at(word: &[char], chars: &[char]) -> Option<usize> {
+        let mut at = 0;
+        for (i, &letter) in word.iter().enumerate() {
+            if chars.get(at) != Some(&letter) {
+                return None;
+            }
+            at += 1;
+            // A doubled letter in the word needs both; otherwise swallow the run
+            if word.get(i + 1) != Some(&letter) {
+                while chars.get(at) == Some(&letter) {
+                    at += 1;
+                }
+            }
+        }
+        Some(at)
+    }
+}
+
+impl ContentFilter for Blocklist {
+    fn name(&self) -> &'static str {
+        "blocklist"
+    }
+
+    fn matches(&self, text: &str) -> Vec<Match> {
+        let normalized = normalize(text);
+        let chars: Vec<char> = normalized.iter().map(|(c, _)| *c).collect();
+        // Word boundaries come from the original text, where "heck!" ends at the "!"
+        let is_word = |i: usize| {
+            normalized.get(i).is_some_and(|(_, range)| {
+                text[range.clone()].chars().next().is_some_and(char::is_alphanumeric)
+            })
+        };
+
+        let mut matches: Vec<Match> = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let found = (i == 0 || !is_word(i - 1))
+                .then(|| {
+                    self.words
+                        .iter()
+                        .filter_map(|word| Self::match_at(word, &chars[i..]))
+                        .filter(|len| !is_word(i + len))
+                        .max()
+                })
+                .flatten();
+            match found {
+                Some(len) => {
+                    let range = normalized[i].1.start..normalized[i + len - 1].1.end;
+                    matches.push(Match { replacement: mask(&text[range.clone()]), range });
+                    i += len;
+                }
+                None => i += 1,
+            }
+        }
+        matches
+    }
+}
+
+/// Links by domain: with an allow list only those domains (and their subdomains) may be
+/// linked; anything on the deny list never may
+pub struct LinkFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl LinkFilter {
+    pub fn new<S: AsRef<str>>(
+        allow: impl IntoIterator<Item = S>,
+        deny: impl IntoIterator<Item = S>,
+    ) -> Self {
+        let domains = |list: Vec<S>| {
+            list.iter()
+                .map(|domain| domain.as_ref().trim().trim_start_matches("www.").to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect()
+        };
+        Self {
+            allow: domains(allow.into_iter().collect()),
+            deny: domains(deny.into_iter().collect()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    fn listed(list: &[String], host: &str) -> bool {
+        list.iter().any(|domain| {
+            host == domain || host.strip_suffix(domain.as_str()).is_some_and(|h| h.ends_with('.'))
+        })
+    }
+
+    /// The host a link points at, lowercased and without `www.`
+    pub fn host(link: &str) -> Option<String> {
+        let lower = link.to_lowercase();
+        let rest = ["https://", "http://"]
+            .iter()
+            .find_map(|scheme| lower.strip_prefix(scheme))
+            .or_else(|| lower.starts_with("www.").then_some(lower.as_str()))?;
+        let authority = rest.split(['/', '?', '#']).next()?;
+        let host = authority.rsplit('@').next()?.split(':').next()?;
+        let host = host.trim_end_matches('.').trim_start_matches("www.");
+        (!host.is_empty()).then(|| host.to_string())
+    }
+}
+
+impl ContentFilter for LinkFilter {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn matches(&self, text: &str) -> Vec<Match> {
+        let mut matches = Vec::new();
+        let mut offset = 0;
+        for token in text.split_inclusive(char::is_whitespace) {
+            let start = offset;
+            offset += token.len();
+            // Punctuation around a link is not part of it
+            let trimmed = token.trim_start_matches(['(', '<', '"', '\'']);
+            let start = start + token.len() - trimmed.len();
+            let link =
+                trimmed.trim_end().trim_end_matches(['.', ',', '!', '?', ';', ':', ')', '>', '"']);
+            let Some(host) = Self::host(link) else {
+                continue;
+            };
+            let refused = Self::listed(&self.deny, &host)
+                || (!self.allow.is_empty() && !Self::listed(&self.allow, &host));
+            if refused {
+                let range = start..start + link.len();
+                matches.push(Match { replacement: mask(link), range });
+            }
+        }
+        matches
+    }
+}
+
+/// At most `max` @mentions in one message; masking strips the @ from the rest
+pub struct MentionLimit {
+    max: usize,
+}
+
+impl MentionLimit {
+    pub fn new(max: usize) -> Self {
+        Self { max }
+    }
+}
+
+impl ContentFilter for MentionLimit {
+    fn name(&self) -> &'static str {
+        "mentions"
+    }
+
+    fn matches(&self, text: &str) -> Vec<Match> {
+        let is_handle = |c: char| c.is_alphanumeric() || matches!(c, '_' | '.' | '-');
+        let mut mentions = Vec::new();
+        let mut previous = None;
+        for (start, c) in text.char_indices() {
+            let at_word_start = previous.is_none_or(char::is_whitespace);
+            previous = Some(c);
+            if c != '@' || !at_word_start {
+                continue;
+            }
+            let handle_len =
+                text[start + 1..].find(|c| !is_handle(c)).unwrap_or(text.len() - start - 1);
+            if handle_len > 0 {
+                let range = start..start + 1 + handle_len;
+                mentions.push(Match { replacement: text[start + 1..range.end].to_string(), range });
+            }
+        }
+        mentions.into_iter().skip(self.max).collect()
+    }
+}
+
+/// Runs of more than `max_run` of the same character ("!!!!!!!!", "nooooooo"); masking
+/// shortens them to `max_run`
+pub struct RepeatedCharacters {
+    max_run: usize,
+}
+
+impl RepeatedCharacters {
+    pub fn new(max_run: usize) -> Self {
+        Self { max_run: max_run.max(1) }
+    }
+}
+
+impl ContentFilter for RepeatedCharacters {
+    fn name(&self) -> &'static str {
+        "repeated_characters"
+    }
+
+    fn matches(&self, text: &str) -> Vec<Match> {
+        let mut matches = Vec::new();
+        let mut chars = text.char_indices().peekable();
+        while let Some((_, c)) = chars.next() {
+            let mut run = 1;
+            let mut excess_start = None;
+            while let Some(&(at, next)) = chars.peek() {
+                if next != c {
+                    break;
+                }
+                run += 1;
+                if run == self.max_run + 1 {
+                    excess_start = Some(at);
+                }
+                chars.next();
+            }
+            if let Some(start) = excess_start {
+                let end = chars.peek().map_or(text.len(), |&(at, _)| at);
+                matches.push(Match { range: start..end, replacement: String::new() });
+            }
+        }
+        matches
+    }
+}
+
+/// What the chain made of a message
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterOutcome {
+    /// The text to store, with masked matches replaced
+    pub text: String,
+    /// Each filter that matched, with the action it took, in the order they ran
+    pub hits: Vec<(&'static str, FilterAction)>,
+}
+
+impl FilterOutcome {
+    /// The filter that refused the message, if one did; the chain stops there
+    pub fn rejected_by(&self) -> Option<&'static str> {
+        self.hits.iter().find(|(_, action)| *action == FilterAction::Reject).map(|(name, _)| *name)
+    }
+
+    /// What to record on the stored message
+    pub fn decision(&self) -> Option<FilterDecision> {
+        let action = self.hits.iter().map(|(_, action)| *action).max()?;
+        Some(FilterDecision {
+            action,
+            filters: self.hits.iter().map(|(name, _)| name.to_string()).collect(),
+        })
+    }
+}
+
+/// Filters run in order over message text, each with the action to take when it matches.
+/// Later filters see the text as earlier ones masked it.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<(Box<dyn ContentFilter>, FilterAction)>,
+}
+
+impl FilterChain {
+    pub const DEFAULT_MAX_MENTIONS: usize = 10;
+    pub const DEFAULT_MAX_REPEAT: usize = 20;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, filter: impl ContentFilter + 'static, action: FilterAction) -> Self {
+        self.filters.push((Box::new(filter), action));
+        self
+    }
+
+    /// The chain configured by the environment, in the order it runs:
+    ///
+    /// - `FILTER_BLOCKLIST`: comma-separated words (`FILTER_BLOCKLIST_ACTION`, default mask)
+    /// - `FILTER_LINKS_ALLOW`/`FILTER_LINKS_DENY`: comma-separated domains
+    ///   (`FILTER_LINKS_ACTION`, default reject)
+    /// - `FILTER_MAX_MENTIONS`: default 10, 0 turns it off (`FILTER_MENTIONS_ACTION`, default
+    ///   reject)
+    /// - `FILTER_MAX_REPEAT`: longest run of one character, default 20, 0 turns it off
+    ///   (`FILTER_REPEAT_ACTION`, default mask)
+    pub fn from_env() -> Self {
+        let list = |name: &str| {
+            env::var(name)
+                .map(|value| value.split(',').map(str::to_string).collect::<Vec<_>>())
+                .unwrap_or_default()
+        };
+        let action = |name: &str, default: FilterAction| {
+            env::var(name)
+                .ok()
+                .map(|value| {
+                    FilterAction::parse(value.trim())
+                        .unwrap_or_else(|| panic!("{} must be flag, mask or reject", name))
+                })
+                .unwrap_or(default)
+        };
+        let limit = |name: &str, default: usize| {
+            env::var(name).ok().and_then(|value| value.trim().parse().ok()).unwrap_or(default)
+        };
+
+        let mut chain = Self::new();
+        let blocklist = Blocklist::new(list("FILTER_BLOCKLIST"));
+        if !blocklist.is_empty() {
+            info!("Filtering {} blocked word(s)", blocklist.words.len());
+            chain = chain.with(blocklist, action("FILTER_BLOCKLIST_ACTION", FilterAction::Mask));
+        }
+        let links = LinkFilter::new(list("FILTER_LINKS_ALLOW"), list("FILTER_LINKS_DENY"));
+        if !links.is_empty() {
+            chain = chain.with(links, action("FILTER_LINKS_ACTION", FilterAction::Reject));
+        }
+        let max_mentions = limit("FILTER_MAX_MENTIONS", Self::DEFAULT_MAX_MENTIONS);
+        if max_mentions > 0 {
+            let mentions = MentionLimit::new(max_mentions);
+            chain = chain.with(mentions, action("FILTER_MENTIONS_ACTION", FilterAction::Reject));
+        }
+        let max_repeat = limit("FILTER_MAX_REPEAT", Self::DEFAULT_MAX_REPEAT);
+        if max_repeat > 0 {
+            let repeats = RepeatedCharacters::new(max_repeat);
+            chain = chain.with(repeats, action("FILTER_REPEAT_ACTION", FilterAction::Mask));
+        }
+        chain
+    }
+
+    pub fn apply(&self, text: &str) -> FilterOutcome {
+        let mut outcome = FilterOutcome { text: text.to_string(), hits: Vec::new() };
+        for (filter, action) in &self.filters {
+            let matches = filter.matches(&outcome.text);
+            if matches.is_empty() {
+                continue;
+            }
+            outcome.hits.push((filter.name(), *action));
+            match action {
+                FilterAction::Reject => break,
+                FilterAction::Mask => {
+                    // Back to front, so earlier ranges stay valid
+                    for m in matches.iter().rev() {
+                        outcome.text.replace_range(m.range.clone(), &m.replacement);
+                    }
+                }
+                FilterAction::Flag => {}
+            }
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn masked(filter: &dyn ContentFilter, text: &str) -> String {
+        let mut text = text.to_string();
+        for m in filter.matches(&text.clone()).iter().rev() {
+            text.replace_range(m.range.clone(), &m.replacement);
+        }
+        text
+    }
+
+    #[test]
+    fn test_blocklist_sees_through_disguises() {
+        let blocklist = Blocklist::new(["darn", "heck"]);
+        assert_eq!(masked(&blocklist, "Darn it"), "**** it");
+        assert_eq!(masked(&blocklist, "d4rn, h3ck!"), "****, ****!");
+        assert_eq!(masked(&blocklist, "dаrn"), "****"); // Cyrillic а
+        assert_eq!(masked(&blocklist, "ｄａｒｎ"), "****"); // fullwidth
+        assert_eq!(masked(&blocklist, "d\u{200b}arn"), "*****");
+        assert_eq!(masked(&blocklist, "dáaaarn"), "*******");
+        assert_eq!(masked(&blocklist, "HECK."), "****.");
+
+        // Only whole words
+        assert!(blocklist.matches("darnation and checks").is_empty());
+        assert!(Blocklist::new([" ", ""]).is_empty());
+    }
+
+    #[test]
+    fn test_links_by_domain() {
+        assert_eq!(
+            LinkFilter::host("https://User@Docs.Example.com:443/a?b"),
+            Some("docs.example.com".into())
+        );
+        assert_eq!(LinkFilter::host("www.example.com/path"), Some("example.com".into()));
+        assert_eq!(LinkFilter::host("example.com"), None);
+
+        let deny = LinkFilter::new(vec![], vec!["evil.com"]);
+        assert_eq!(masked(&deny, "see https://cdn.evil.com/x."), "see **********************.");
+        assert!(deny.matches("https://notevil.com and https://example.com").is_empty());
+
+        let allow = LinkFilter::new(vec!["example.com"], vec![]);
+        assert!(allow.matches("(https://docs.example.com/page)").is_empty());
+        assert_eq!(masked(&allow, "(www.other.org)"), "(*************)");
+        assert_eq!(allow.matches("http://other.org http://example.com").len(), 1);
+    }
+
+    #[test]
+    fn test_mentions_and_repeats() {
+        let mentions = MentionLimit::new(2);
+        assert!(mentions.matches("@a @b and an email a@b.com").is_empty());
+        assert_eq!(masked(&mentions, "@a @b @c @d"), "@a @b c d");
+
+        let repeats = RepeatedCharacters::new(3);
+        assert_eq!(masked(&repeats, "nooooo!!!!! ok"), "nooo!!! ok");
+        assert!(repeats.matches("aaa bbb").is_empty());
+        assert_eq!(masked(&repeats, "ééééé"), "ééé");
+    }
+
+    #[test]
+    fn test_chain_actions() {
+        let chain = FilterChain::new()
+            .with(Blocklist::new(["darn"]), FilterAction::Mask)
+            .with(RepeatedCharacters::new(3), FilterAction::Flag)
+            .with(MentionLimit::new(1), FilterAction::Reject);
+
+        let outcome = chain.apply("darn!!!!!");
+        assert_eq!(outcome.text, "****!!!!!");
+        assert_eq!(outcome.rejected_by(), None);
+        assert_eq!(
+            outcome.decision(),
+            Some(FilterDecision {
+                action: FilterAction::Mask,
+                filters: vec!["blocklist".to_string(), "repeated_characters".to_string()],
+            })
+        );
+
+        let outcome = chain.apply("@a @b");
+        assert_eq!(outcome.rejected_by(), Some("mentions"));
+
+        let outcome = chain.apply("hello");
+        assert_eq!(outcome.text, "hello");
+        assert_eq!(outcome.decision(), None);
+    }
+}
@@ -1,24 +1,154 @@
-use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient};
-use chrono::Utc;
-use std::{collections::HashMap, env};
-use tracing::info;
-use types::{ChatMessage, GetMessagesResponse, HealthCheck, HealthStatus, SendMessageRequest};
-use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{LazyLock, Mutex};
+use tracing::{info, warn};
+use types::{
+    ChatMessage, CreateRoomRequest, DeleteMessageQuery, DirectConversation, EditMessageRequest,
+    FilterDecision, GetMessagesQuery, GetMessagesResponse, GetThreadResponse, HealthCheck,
+    HealthStatus, InviteMemberRequest, ListDirectMessagesQuery, ListDirectMessagesResponse,
+    ListMembersQuery, ListMembersResponse, ListReadMarkersQuery, ListReadMarkersResponse,
+    ListReportsQuery, ListReportsResponse, ListRoomsQuery, ListRoomsResponse, ListSanctionsQuery,
+    ListSanctionsResponse, MarkReadRequest, MembershipRequest, MessageOrder, MessageReport,
+    ModerateRequest, ModerationAction, ModerationResponse, OnlineUser, OpenDirectMessageRequest,
+    ReactionQuery, ReadMarker, ReportMessageRequest, ReportResolution, ReportStatus,
+    ResolveReportRequest, ResolveReportResponse, Room, RoomKind, RoomMember, RoomPresenceQuery,
+    RoomPresenceResponse, RoomRole, RoomSanction, RoomVisibility, SendMessageRequest, UnreadCount,
+    UnreadCountsQuery, UnreadCountsResponse, UpdateRoomRequest,
+};
+use ulid::{Generator, Ulid};
 
-// Table names structure
-#[derive(Clone)]
-pub struct Tables {
-    pub rooms: String,
-    pub messages: String,
+use crate::content_filter::FilterChain;
+use crate::rate_limit::PostRateLimiter;
+use crate::store::{
+    dedup_key, ChatStore, Connection, MessageQuery, PutMessageOutcome, ReactionOutcome, StoreError,
+    DELETED_MESSAGE_TEXT,
+};
+use crate::MetricsHelper;
+
+// Page size for get_messages_handler when the client does not ask for one
+pub const DEFAULT_PAGE_SIZE: usize = 25;
+// Upper bound on any requested page size
+pub const MAX_PAGE_SIZE: usize = 100;
+// How long a client_message_id is remembered for deduplicating retried posts
+pub const IDEMPOTENCY_WINDOW_SECS: i64 = 60 * 60 * 24;
+// Room ids are URL path segments and partition keys: short lowercase slugs
+pub const MAX_ROOM_ID_LEN: usize = 64;
+// Slugs that would be confusing as rooms or collide with routes
+pub const RESERVED_ROOM_IDS: &[&str] = &[
+    "admin",
+    "api",
+    "dev",
+    "health",
+    "messages",
+    "new",
+    "null",
+    "rooms",
+    "system",
+    "undefined",
+    "ws",
+];
+// Distinct emoji a single message can collect
+pub const MAX_DISTINCT_REACTIONS: usize = 20;
+// Direct message room ids; no other room may be created under this prefix
+pub const DIRECT_ROOM_PREFIX: &str = "dm-";
+// Longest mute a moderator can hand out; longer means a ban
+pub const MAX_MUTE_SECS: u64 = 60 * 60 * 24 * 30;
+// Moderation and report reasons are shown to moderators, not the whole room
+pub const MAX_REASON_LEN: usize = 500;
+
+// Handler failure with the HTTP status it should map to
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandlerError {
+    pub status: u16,
+    pub message: String,
+    /// For 429s, how long the client should wait before trying again
+    pub retry_after_secs: Option<u64>,
 }
 
-impl Tables {
-    pub fn from_env() -> Self {
-        Self {
-            rooms: env::var("CHAT_ROOMS_TABLE").expect("CHAT_ROOMS_TABLE must be set"),
-            messages: env::var("CHAT_MESSAGES_TABLE").expect("CHAT_MESSAGES_TABLE must be set"),
-        }
+impl HandlerError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self { status, message: message.into(), retry_after_secs: None }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(400, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(401, message)
     }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(403, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(404, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(409, message)
+    }
+
+    pub fn too_many_requests(message: impl Into<String>, retry_after_secs: u64) -> Self {
+        Self { retry_after_secs: Some(retry_after_secs), ..Self::new(429, message) }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(500, message)
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// Validation functions report plain strings; those are the caller's fault
+impl From<String> for HandlerError {
+    fn from(message: String) -> Self {
+        Self::bad_request(message)
+    }
+}
+
+impl From<StoreError> for HandlerError {
+    fn from(err: StoreError) -> Self {
+        Self::internal(err.to_string())
+    }
+}
+
+// Outcome of post_message_handler
+#[derive(Debug, Clone)]
+pub struct PostedMessage {
+    pub message: ChatMessage,
+    /// True when this was a retry and `message` is the originally stored one
+    pub replayed: bool,
+}
+
+// Outcome of mark_read_handler
+#[derive(Debug, Clone)]
+pub struct MarkedRead {
+    /// The user's marker as it now stands
+    pub marker: ReadMarker,
+    /// False when the marker was already at or past the message, and `marker` is the stored one
+    pub advanced: bool,
+}
+
+// Content filters for posted messages, configured once per process from the environment
+static CONTENT_FILTERS: LazyLock<FilterChain> = LazyLock::new(FilterChain::from_env);
+
+// One generator per process so ids minted in the same millisecond still sort in order
+static MESSAGE_IDS: LazyLock<Mutex<Generator>> = LazyLock::new(|| Mutex::new(Generator::new()));
+
+/// Next message id: a ULID, strictly increasing within this process
+pub fn new_message_id() -> Ulid {
+    let mut generator = MESSAGE_IDS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    // Overflow needs 2^80 ids in one millisecond; fall back to a fresh random ULID
+    generator.generate().unwrap_or_else(|_| Ulid::new())
 }
 
 // Shared validation functions
@@ -33,6 +163,17 @@ pub fn validate_username(username: &str) -> Result<String, String> {
     Ok(trimmed.to_string())
 }
 
+pub fn validate_user_id(user_id: &str) -> Result<String, String> {
+    let trimmed = user_id.trim();
+    if trimmed.is_empty() {
+        return Err("User ID cannot be empty".to_string());
+    }
+    if trimmed.len() > 128 {
+        return Err("User ID cannot be longer than 128 characters".to_string());
+    }
+    Ok(trimmed.to_string())
+}
+
 pub fn validate_message_text(message_text: &str) -> Result<String, String> {
     let trimmed = message_text.trim();
     if trimmed.is_empty() {
@@ -44,12 +185,171 @@ pub fn validate_message_text(message_text: &str) -> Result<String, String> {
     Ok(trimmed.to_string())
 }
 
+/// Room ids are slugs: lowercase ASCII letters and digits in hyphen-separated words.
+/// Surrounding whitespace and upper case are accepted and normalized away.
 pub fn validate_room_id(room_id: &str) -> Result<String, String> {
-    let trimmed = room_id.trim();
-    if trimmed.is_empty() {
+    let slug = room_id.trim().to_ascii_lowercase();
+    if slug.is_empty() {
         return Err("Room ID cannot be empty".to_string());
     }
-    Ok(trimmed.to_lowercase())
+    if slug.len() > MAX_ROOM_ID_LEN {
+        return Err(format!("Room ID cannot be longer than {} characters", MAX_ROOM_ID_LEN));
+    }
+    let well_formed = slug.split('-').all(|word| {
+        !word.is_empty() && word.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+    });
+    if !well_formed {
+        return Err(
+            "Room ID may only contain lowercase letters, digits and single hyphens between words"
+                .to_string(),
+        );
+    }
+    if RESERVED_ROOM_IDS.contains(&slug.as_str()) {
+        return Err(format!("Room ID '{}' is reserved", slug));
+    }
+    Ok(slug)
+}
+
+/// Derive a room id from a display name, e.g. "Rust & Go!" becomes "rust-go".
+/// Anything that is not an ASCII letter or digit separates words.
+pub fn room_slug_from_name(name: &str) -> Result<String, String> {
+    let mut slug = String::new();
+    for word in name.split(|c: char| !c.is_ascii_alphanumeric()).filter(|w| !w.is_empty()) {
+        // Stop at a word boundary rather than cutting a word in half
+        if !slug.is_empty() && slug.len() + 1 + word.len() > MAX_ROOM_ID_LEN {
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&word.to_ascii_lowercase());
+    }
+    slug.truncate(MAX_ROOM_ID_LEN);
+    if slug.is_empty() {
+        return Err("Room name must contain letters or digits to derive an ID".to_string());
+    }
+    if RESERVED_ROOM_IDS.contains(&slug.as_str()) {
+        slug.push_str("-room");
+    }
+    validate_room_id(&slug)
+}
+
+/// Room id of the direct conversation between two users, the same whichever of them asks.
+/// User ids are free-form, so the id is a hash of the ordered pair rather than the ids themselves.
+pub fn direct_room_id(user_id: &str, other_user_id: &str) -> Result<String, String> {
+    let user_id = validate_user_id(user_id)?;
+    let other_user_id = validate_user_id(other_user_id)?;
+    if user_id == other_user_id {
+        return Err("Cannot open a direct message with yourself".to_string());
+    }
+    let (first, second) =
+        if user_id < other_user_id { (user_id, other_user_id) } else { (other_user_id, user_id) };
+
+    let mut hasher = Sha256::new();
+    hasher.update(first.as_bytes());
+    hasher.update([0]);
+    hasher.update(second.as_bytes());
+    let digest = hasher.finalize();
+    let hex: String = digest[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(format!("{}{}", DIRECT_ROOM_PREFIX, hex))
+}
+
+pub fn validate_room_name(name: &str) -> Result<String, String> {
+    let trimmed = name.trim();
+    if trimmed.is_empty() {
+        return Err("Room name cannot be empty".to_string());
+    }
+    if trimmed.len() > 80 {
+        return Err("Room name cannot be longer than 80 characters".to_string());
+    }
+    Ok(trimmed.to_string())
+}
+
+// Optional free text on a room (description, topic); blank means none
+fn validate_room_text(
+    value: Option<&str>,
+    field: &str,
+    max_len: usize,
+) -> Result<Option<String>, String> {
+    let Some(trimmed) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    if trimmed.len() > max_len {
+        return Err(format!("{} cannot be longer than {} characters", field, max_len));
+    }
+    Ok(Some(trimmed.to_string()))
+}
+
+pub fn validate_client_message_id(
+    client_message_id: Option<&str>,
+) -> Result<Option<String>, String> {
+    let Some(trimmed) = client_message_id.map(str::trim).filter(|id| !id.is_empty()) else {
+        return Ok(None);
+    };
+    if trimmed.len() > 100 {
+        return Err("Client message ID cannot be longer than 100 characters".to_string());
+    }
+    Ok(Some(trimmed.to_string()))
+}
+
+// Reactions are a single emoji (possibly a multi-codepoint sequence), not free text
+pub fn validate_emoji(emoji: &str) -> Result<String, String> {
+    let trimmed = emoji.trim();
+    if trimmed.is_empty() {
+        return Err("Emoji cannot be empty".to_string());
+    }
+    if trimmed.chars().count() > 16 {
+        return Err("Emoji cannot be longer than 16 characters".to_string());
+    }
+    if trimmed.chars().any(char::is_whitespace) || trimmed.is_ascii() {
+        return Err("Reaction must be an emoji".to_string());
+    }
+    Ok(trimmed.to_string())
+}
+
+// Cursors are opaque to clients; today they carry the message id (the sort key)
+pub fn parse_cursor(cursor: &str) -> Result<String, String> {
+    Ulid::from_string(cursor.trim())
+        .map(|id| id.to_string())
+        .map_err(|_| "Invalid cursor".to_string())
+}
+
+pub fn validate_page_size(limit: Option<u32>) -> Result<usize, String> {
+    match limit {
+        None => Ok(DEFAULT_PAGE_SIZE),
+        Some(0) => Err("Limit must be at least 1".to_string()),
+        Some(limit) => Ok((limit as usize).min(MAX_PAGE_SIZE)),
+    }
+}
+
+// Page of messages described by the shared ?before=&after=&limit=&order= parameters
+fn message_query(
+    query: &GetMessagesQuery,
+    parent_id: Option<String>,
+) -> Result<MessageQuery, String> {
+    Ok(MessageQuery {
+        before: query.before.as_deref().map(parse_cursor).transpose()?,
+        after: query.after.as_deref().map(parse_cursor).transpose()?,
+        limit: validate_page_size(query.limit)?,
+        newest_first: query.order.unwrap_or_default() == MessageOrder::Desc,
+        parent_id,
+    })
+}
+
+// Tombstones keep their place in the timeline but never their content
+fn prepare_for_viewer(messages: &mut [ChatMessage], viewer: Option<&str>) {
+    for message in messages.iter_mut().filter(|m| m.deleted_at.is_some()) {
+        message.message_text = DELETED_MESSAGE_TEXT.to_string();
+    }
+    mark_own_reactions(messages, viewer);
+}
+
+/// Fill in `reacted_by_me` for the user the response is for
+pub fn mark_own_reactions(messages: &mut [ChatMessage], viewer: Option<&str>) {
+    for reaction in messages.iter_mut().flat_map(|m| m.reactions.iter_mut()) {
+        reaction.reacted_by_me =
+            viewer.is_some_and(|viewer| reaction.user_ids.iter().any(|u| u == viewer));
+    }
 }
 
 // Shared business logic functions
@@ -62,160 +362,1417 @@ pub async fn health_handler() -> Result<HealthCheck, String> {
     Ok(health_check)
 }
 
-pub async fn ensure_room_exists(
-    ddb: &DynamoDbClient,
-    tables: &Tables,
+// The public channel a first post to `room_id` creates
+fn implicit_room(room_id: &str) -> Result<Room, HandlerError> {
+    // Direct message rooms only come from open_direct_message_handler
+    if room_id.starts_with(DIRECT_ROOM_PREFIX) {
+        return Err(HandlerError::not_found("Room not found"));
+    }
+    let room_name = if room_id == "general" { "General".to_string() } else { room_id.to_string() };
+    Ok(Room {
+        id: room_id.to_string(),
+        name: room_name,
+        created_at: Utc::now(),
+        description: None,
+        topic: None,
+        archived: false,
+        visibility: RoomVisibility::Public,
+        kind: RoomKind::Channel,
+        last_message_at: None,
+    })
+}
+
+/// Store `room` unless it exists by now; returns the stored room either way
+pub async fn ensure_room_exists(store: &dyn ChatStore, room: &Room) -> Result<Room, HandlerError> {
+    match store.create_room(room).await {
+        Ok(()) => {
+            info!("Created new room: {}", room.id);
+            Ok(room.clone())
+        }
+        // Another request created it first
+        Err(StoreError::ConditionFailed) => store
+            .get_room(&room.id)
+            .await?
+            .ok_or_else(|| HandlerError::internal("Room vanished after creation")),
+        Err(e) => Err(HandlerError::internal(format!("Failed to create room: {}", e))),
+    }
+}
+
+// Private rooms only serve their members, and no room serves users banned from it
+async fn authorize_room(
+    store: &dyn ChatStore,
+    room: &Room,
+    user_id: Option<&str>,
+) -> Result<(), HandlerError> {
+    if let Some(user_id) = user_id {
+        if store.get_sanction(&room.id, user_id).await?.is_some_and(|s| s.banned) {
+            return Err(HandlerError::forbidden("You are banned from this room"));
+        }
+    }
+    if room.visibility == RoomVisibility::Public {
+        return Ok(());
+    }
+    let member = match user_id {
+        Some(user_id) => store.get_member(&room.id, user_id).await?,
+        None => None,
+    };
+    if member.is_none() {
+        return Err(HandlerError::forbidden("This room is private"));
+    }
+    Ok(())
+}
+
+// Muted users keep reading but write nothing: no posts, and no edits to what they already posted
+async fn check_not_muted(
+    store: &dyn ChatStore,
     room_id: &str,
-) -> Result<(), String> {
-    let get_item_result = ddb
-        .get_item()
-        .table_name(&tables.rooms)
-        .key("id", AttributeValue::S(room_id.to_string()))
-        .send()
-        .await;
+    user_id: &str,
+) -> Result<(), HandlerError> {
+    if let Some(until) = store
+        .get_sanction(room_id, user_id)
+        .await?
+        .filter(|s| s.is_muted_at(Utc::now()))
+        .and_then(|s| s.muted_until)
+    {
+        return Err(HandlerError::forbidden(format!(
+            "You are muted in this room until {}",
+            until.to_rfc3339()
+        )));
+    }
+    Ok(())
+}
 
-    match get_item_result {
-        Ok(output) => {
-            if output.item.is_none() {
-                // Room doesn't exist, create it
-                let now = Utc::now();
-                let room_name =
-                    if room_id == "general" { "General".to_string() } else { room_id.to_string() };
-
-                let mut item = HashMap::new();
-                item.insert("id".to_string(), AttributeValue::S(room_id.to_string()));
-                item.insert("name".to_string(), AttributeValue::S(room_name));
-                item.insert("created_at_iso".to_string(), AttributeValue::S(now.to_rfc3339()));
-                item.insert(
-                    "created_at_epoch".to_string(),
-                    AttributeValue::N(now.timestamp().to_string()),
-                );
-
-                ddb.put_item()
-                    .table_name(&tables.rooms)
-                    .set_item(Some(item))
-                    .condition_expression("attribute_not_exists(id)")
-                    .send()
-                    .await
-                    .map_err(|e| format!("Failed to create room: {:?}", e))?;
-
-                info!("Created new room: {}", room_id);
+// Filters may refuse the text, mask parts of it or flag it for moderators. Returns the text
+// to store and the decision to store with it.
+async fn filter_content(
+    filters: &FilterChain,
+    message_text: &str,
+    user_id: &str,
+    room_id: &str,
+) -> Result<(String, Option<FilterDecision>), HandlerError> {
+    let filtered = filters.apply(message_text);
+    if !filtered.hits.is_empty() {
+        let metrics = MetricsHelper::new().await;
+        for (filter, action) in &filtered.hits {
+            metrics.emit_content_filtered(filter, action.as_str(), room_id).await;
+        }
+    }
+    if let Some(filter) = filtered.rejected_by() {
+        info!("The {} filter rejected a message from {} in room {}", filter, user_id, room_id);
+        return Err(HandlerError::bad_request(format!(
+            "Message rejected by the {} filter",
+            filter
+        )));
+    }
+    let decision = filtered.decision();
+    Ok((filtered.text, decision))
+}
+
+/// Check that `user_id` may read and post in a room. Rooms that do not exist yet are
+/// created public on first post, so they are open to everyone.
+pub async fn authorize_room_access(
+    store: &dyn ChatStore,
+    room_id: &str,
+    user_id: Option<&str>,
+) -> Result<(), HandlerError> {
+    match store.get_room(room_id).await? {
+        Some(room) => authorize_room(store, &room, user_id).await,
+        None => Ok(()),
+    }
+}
+
+/// Drop connections that may not receive a room's events: in a private room, those of
+/// users who are not (or are no longer) members
+pub async fn room_audience(
+    store: &dyn ChatStore,
+    room_id: &str,
+    connections: Vec<Connection>,
+) -> Result<Vec<Connection>, StoreError> {
+    let private =
+        store.get_room(room_id).await?.is_some_and(|r| r.visibility == RoomVisibility::Private);
+    if !private {
+        return Ok(connections);
+    }
+    let members = store.room_members(room_id).await?;
+    Ok(connections.into_iter().filter(|c| members.iter().any(|m| m.user_id == c.user_id)).collect())
+}
+
+// The caller's membership, if it lets them manage the room
+async fn require_moderator(
+    store: &dyn ChatStore,
+    room_id: &str,
+    user_id: Option<&str>,
+) -> Result<RoomMember, HandlerError> {
+    let member = match user_id {
+        Some(user_id) => store.get_member(room_id, user_id).await?,
+        None => None,
+    };
+    member
+        .filter(|m| m.role.can_moderate())
+        .ok_or_else(|| HandlerError::forbidden("Only room owners and moderators can do that"))
+}
+
+fn is_last_owner(members: &[RoomMember], user_id: &str) -> bool {
+    let mut owners = members.iter().filter(|m| m.role == RoomRole::Owner);
+    owners.clone().any(|m| m.user_id == user_id) && owners.all(|m| m.user_id == user_id)
+}
+
+pub async fn list_rooms_handler(
+    store: &dyn ChatStore,
+    query: ListRoomsQuery,
+) -> Result<ListRoomsResponse, HandlerError> {
+    let include_archived = query.include_archived.unwrap_or(false);
+    let mut rooms = Vec::new();
+    for room in store.list_rooms().await? {
+        // Direct messages are listed by list_direct_messages_handler
+        if room.kind == RoomKind::Direct || (room.archived && !include_archived) {
+            continue;
+        }
+        if authorize_room(store, &room, query.user_id.as_deref()).await.is_ok() {
+            rooms.push(room);
+        }
+    }
+    Ok(ListRoomsResponse { rooms })
+}
+
+pub async fn create_room_handler(
+    store: &dyn ChatStore,
+    request: CreateRoomRequest,
+) -> Result<Room, HandlerError> {
+    let name = validate_room_name(&request.name)?;
+    let id = match request.id.as_deref() {
+        Some(id) => validate_room_id(id)?,
+        None => room_slug_from_name(&name)?,
+    };
+    if id.starts_with(DIRECT_ROOM_PREFIX) {
+        return Err(HandlerError::bad_request(format!(
+            "Room IDs starting with '{}' are reserved for direct messages",
+            DIRECT_ROOM_PREFIX
+        )));
+    }
+    let owner_id = request.user_id.as_deref().map(validate_user_id).transpose()?;
+    if request.visibility == RoomVisibility::Private && owner_id.is_none() {
+        return Err(HandlerError::bad_request("Private rooms need a user_id for their owner"));
+    }
+    let room = Room {
+        id,
+        name,
+        created_at: Utc::now(),
+        description: validate_room_text(request.description.as_deref(), "Description", 500)?,
+        topic: validate_room_text(request.topic.as_deref(), "Topic", 250)?,
+        archived: false,
+        visibility: request.visibility,
+        kind: RoomKind::Channel,
+        last_message_at: None,
+    };
+
+    match store.create_room(&room).await {
+        Ok(()) => {
+            info!("Created {} room {} ({})", room.visibility.as_str(), room.id, room.name);
+            if let Some(owner_id) = owner_id {
+                let owner = RoomMember {
+                    room_id: room.id.clone(),
+                    user_id: owner_id,
+                    role: RoomRole::Owner,
+                    joined_at: room.created_at,
+                };
+                store.put_member(&owner).await?;
             }
-            Ok(())
+            Ok(room)
+        }
+        Err(StoreError::ConditionFailed) => Err(HandlerError::conflict("Room already exists")),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn update_room_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    request: UpdateRoomRequest,
+) -> Result<Room, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let mut room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+    let user_id = request.user_id.as_deref().map(validate_user_id).transpose()?;
+    if room.kind == RoomKind::Direct {
+        return Err(HandlerError::bad_request("Direct message rooms cannot be changed"));
+    }
+
+    // Rooms created before membership existed have no owner and stay open to edits
+    let members = store.room_members(&room_id).await?;
+    let managed = members.iter().any(|m| m.role.can_moderate());
+    if managed {
+        require_moderator(store, &room_id, user_id.as_deref()).await?;
+    }
+
+    if let Some(name) = &request.name {
+        room.name = validate_room_name(name)?;
+    }
+    if let Some(description) = &request.description {
+        room.description = validate_room_text(Some(description), "Description", 500)?;
+    }
+    if let Some(topic) = &request.topic {
+        room.topic = validate_room_text(Some(topic), "Topic", 250)?;
+    }
+    if let Some(archived) = request.archived {
+        room.archived = archived;
+    }
+    // A private room must have someone who can invite; without one, the caller takes it
+    let new_owner = match (request.visibility, managed, user_id) {
+        (Some(RoomVisibility::Private), false, None) => {
+            return Err(HandlerError::bad_request("Private rooms need a user_id for their owner"));
         }
-        Err(e) => Err(format!("DynamoDB error: {:?}", e)),
+        (Some(RoomVisibility::Private), false, Some(user_id)) => Some(RoomMember {
+            room_id: room_id.clone(),
+            user_id,
+            role: RoomRole::Owner,
+            joined_at: Utc::now(),
+        }),
+        _ => None,
+    };
+    if let Some(visibility) = request.visibility {
+        room.visibility = visibility;
+    }
+    if let Some(owner) = &new_owner {
+        store.put_member(owner).await?;
+    }
+
+    match store.update_room(&room).await {
+        Ok(()) => {
+            info!("Updated room {}", room.id);
+            Ok(room)
+        }
+        Err(StoreError::ConditionFailed) => Err(HandlerError::not_found("Room not found")),
+        Err(e) => Err(e.into()),
     }
 }
 
 pub async fn post_message_handler(
-    ddb: &DynamoDbClient,
-    tables: &Tables,
+    store: &dyn ChatStore,
+    limits: &PostRateLimiter,
     request: SendMessageRequest,
-) -> Result<ChatMessage, String> {
+) -> Result<PostedMessage, HandlerError> {
     // Validate input
     let room_id = validate_room_id(&request.room_id)?;
     let user_id = request.user_id.clone();
     let username = validate_username(&request.username)?;
     let message_text = validate_message_text(&request.message_text)?;
+    let client_message_id = validate_client_message_id(request.client_message_id.as_deref())?;
+    let parent_id =
+        request.parent_id.as_deref().map(str::trim).filter(|id| !id.is_empty()).map(str::to_string);
 
-    // Ensure room exists
-    ensure_room_exists(ddb, tables, &room_id).await?;
+    // A post to a room that does not exist yet creates it, once the post is accepted
+    let stored = store.get_room(&room_id).await?;
+    let room = match &stored {
+        Some(room) => room.clone(),
+        None => implicit_room(&room_id)?,
+    };
+    authorize_room(store, &room, Some(&user_id)).await?;
+    if room.archived {
+        return Err(HandlerError::forbidden("Room is archived"));
+    }
+    check_not_muted(store, &room_id, &user_id).await?;
 
-    // Create message
-    let now = Utc::now();
-    let message_id = Uuid::new_v4().to_string();
-    let timestamp_millis = now.timestamp_millis();
-
-    let mut item = HashMap::new();
-    item.insert("id".to_string(), AttributeValue::S(message_id.clone()));
-    item.insert("room_id".to_string(), AttributeValue::S(room_id.clone()));
-    item.insert("user_id".to_string(), AttributeValue::S(user_id.clone()));
-    item.insert("username".to_string(), AttributeValue::S(username.clone()));
-    item.insert("message_text".to_string(), AttributeValue::S(message_text.clone()));
-    item.insert("ts".to_string(), AttributeValue::N(timestamp_millis.to_string()));
-    item.insert("created_at_iso".to_string(), AttributeValue::S(now.to_rfc3339()));
-
-    // Store client_message_id if provided
-    if let Some(client_message_id) = &request.client_message_id {
-        item.insert("client_message_id".to_string(), AttributeValue::S(client_message_id.clone()));
-    }
-
-    // Store message in DynamoDB
-    ddb.put_item()
-        .table_name(&tables.messages)
-        .set_item(Some(item))
-        .send()
-        .await
-        .map_err(|e| format!("DynamoDB error: {:?}", e))?;
+    let (message_text, filter) =
+        filter_content(&CONTENT_FILTERS, &message_text, &user_id, &room_id).await?;
 
-    info!("Stored message {} in room {}", message_id, room_id);
+    // Replies go on a live, top-level message in the same room
+    if let Some(parent_id) = &parent_id {
+        let root = store
+            .get_message(&room_id, parent_id)
+            .await?
+            .ok_or_else(|| HandlerError::not_found("Thread not found"))?;
+        if root.parent_id.is_some() {
+            return Err(HandlerError::bad_request("Cannot reply to a reply"));
+        }
+        if root.deleted_at.is_some() {
+            return Err(HandlerError::conflict("Message has been deleted"));
+        }
+    }
 
-    // Create response message
+    // Create message; created_at is the id's timestamp so both orders agree
+    let id = new_message_id();
     let message = ChatMessage {
-        id: message_id.clone(),
-        room_id: room_id.clone(),
-        user_id: user_id.clone(),
-        username: username.clone(),
-        message_text: message_text.clone(),
-        created_at: now,
-        client_message_id: request.client_message_id.clone(),
+        id: id.to_string(),
+        room_id,
+        user_id,
+        username,
+        message_text,
+        created_at: DateTime::<Utc>::from(id.datetime()),
+        client_message_id,
+        edited_at: None,
+        deleted_at: None,
+        reactions: Vec::new(),
+        parent_id,
+        reply_count: 0,
+        last_reply_at: None,
+        filter,
     };
 
-    Ok(message)
+    // Retries carrying the same client_message_id get the original message back, without
+    // spending a token; put_message_once below still catches retries that race the original
+    if let Some(key) = dedup_key(&message) {
+        if let Some(original) = store.find_duplicate(&key).await? {
+            info!("Replayed message {} in room {}", original.id, original.room_id);
+            return Ok(PostedMessage { message: original, replayed: true });
+        }
+    }
+    // Only posts that would otherwise be accepted count against the limits
+    if let Err(limited) = limits.check(&message.user_id, &message.room_id).await {
+        warn!("Rate limited {} in room {}: {}", message.user_id, message.room_id, limited);
+        MetricsHelper::new()
+            .await
+            .emit_rate_limited(limited.scope.as_str(), &message.room_id)
+            .await;
+        return Err(limited.into());
+    }
+
+    if stored.is_none() {
+        // Whoever created it first may have made it private
+        let room = ensure_room_exists(store, &room).await?;
+        authorize_room(store, &room, Some(&message.user_id)).await?;
+    }
+
+    let dedup_expires_at = message.created_at.timestamp() + IDEMPOTENCY_WINDOW_SECS;
+    match store.put_message_once(&message, dedup_expires_at).await? {
+        PutMessageOutcome::Created => {
+            info!("Stored message {} in room {}", message.id, message.room_id);
+            // Only orders room listings; not worth failing the post over
+            if let Err(e) = store.record_room_activity(&message.room_id, message.created_at).await {
+                warn!("Failed to record activity in room {}: {}", message.room_id, e);
+            }
+            if let Some(parent_id) = &message.parent_id {
+                // The reply itself is stored; a stale summary is not worth failing the post
+                if let Err(e) =
+                    store.record_reply(&message.room_id, parent_id, message.created_at).await
+                {
+                    warn!("Failed to update thread {} for reply {}: {}", parent_id, message.id, e);
+                }
+            }
+            Ok(PostedMessage { message, replayed: false })
+        }
+        PutMessageOutcome::Duplicate(original) => {
+            info!("Replayed message {} in room {}", original.id, original.room_id);
+            Ok(PostedMessage { message: *original, replayed: true })
+        }
+    }
 }
 
 pub async fn get_messages_handler(
-    ddb: &DynamoDbClient,
-    tables: &Tables,
+    store: &dyn ChatStore,
+    room_id: String,
+    query: GetMessagesQuery,
+) -> Result<GetMessagesResponse, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let message_query = message_query(&query, None)?;
+    authorize_room_access(store, &room_id, query.user_id.as_deref()).await?;
+
+    let mut page = store.query_messages(&room_id, &message_query).await?;
+    prepare_for_viewer(&mut page.messages, query.user_id.as_deref());
+
+    info!("Retrieved {} messages for room {}", page.messages.len(), room_id);
+
+    let response =
+        GetMessagesResponse { room_id, messages: page.messages, next_cursor: page.next_cursor };
+    Ok(response)
+}
+
+pub async fn get_thread_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    message_id: String,
+    query: GetMessagesQuery,
+) -> Result<GetThreadResponse, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let message_query = message_query(&query, Some(message_id.clone()))?;
+    authorize_room_access(store, &room_id, query.user_id.as_deref()).await?;
+
+    let mut root = store
+        .get_message(&room_id, &message_id)
+        .await?
+        .ok_or_else(|| HandlerError::not_found("Thread not found"))?;
+    if root.parent_id.is_some() {
+        return Err(HandlerError::not_found("Thread not found"));
+    }
+
+    let mut page = store.query_messages(&room_id, &message_query).await?;
+    prepare_for_viewer(std::slice::from_mut(&mut root), query.user_id.as_deref());
+    prepare_for_viewer(&mut page.messages, query.user_id.as_deref());
+
+    info!("Retrieved {} replies to {} in room {}", page.messages.len(), message_id, room_id);
+
+    Ok(GetThreadResponse { room_id, root, replies: page.messages, next_cursor: page.next_cursor })
+}
+
+pub async fn edit_message_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    message_id: String,
+    request: EditMessageRequest,
+) -> Result<ChatMessage, HandlerError> {
+    edit_message(store, &CONTENT_FILTERS, room_id, message_id, request).await
+}
+
+// edit_message_handler with the content filters to hold the new text to
+async fn edit_message(
+    store: &dyn ChatStore,
+    filters: &FilterChain,
+    room_id: String,
+    message_id: String,
+    request: EditMessageRequest,
+) -> Result<ChatMessage, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let message_text = validate_message_text(&request.message_text)?;
+
+    // Held to the same rules as posting
+    let room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+    authorize_room(store, &room, Some(&request.user_id)).await?;
+    if room.archived {
+        return Err(HandlerError::forbidden("Room is archived"));
+    }
+    check_not_muted(store, &room_id, &request.user_id).await?;
+
+    let message = store
+        .get_message(&room_id, &message_id)
+        .await?
+        .ok_or_else(|| HandlerError::not_found("Message not found"))?;
+    if message.deleted_at.is_some() {
+        return Err(HandlerError::conflict("Message has been deleted"));
+    }
+    if message.user_id != request.user_id {
+        return Err(HandlerError::forbidden("Only the author can edit a message"));
+    }
+    // The same filters as a new post; the decision on the old text no longer applies
+    let (message_text, filter) =
+        filter_content(filters, &message_text, &request.user_id, &room_id).await?;
+    // Compared after filtering, since the stored text is the masked one
+    if message.message_text == message_text && message.filter == filter {
+        return Ok(message);
+    }
+
+    // Conditional on the text we just read, so concurrent edits cannot drop a revision
+    match store.edit_message(&message, &message_text, filter.as_ref(), Utc::now()).await {
+        Ok(edited) => {
+            info!("Edited message {} in room {}", edited.id, edited.room_id);
+            Ok(edited)
+        }
+        Err(StoreError::ConditionFailed) => {
+            Err(HandlerError::conflict("Message was changed by another request"))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn delete_message_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    message_id: String,
+    query: DeleteMessageQuery,
+) -> Result<ChatMessage, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+
+    // Banned or removed authors lose their messages to moderators, and archived rooms are
+    // frozen for everyone
+    let room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+    authorize_room(store, &room, Some(&query.user_id)).await?;
+    if room.archived {
+        return Err(HandlerError::forbidden("Room is archived"));
+    }
+
+    let message = store
+        .get_message(&room_id, &message_id)
+        .await?
+        .ok_or_else(|| HandlerError::not_found("Message not found"))?;
+    if message.deleted_at.is_some() {
+        return Ok(message);
+    }
+
+    // The author, or a room owner/moderator
+    if message.user_id != query.user_id {
+        let role = store.get_member(&room_id, &query.user_id).await?.map(|m| m.role);
+        if !role.is_some_and(|role| role.can_moderate()) {
+            return Err(HandlerError::forbidden(
+                "Only the author or a moderator can delete a message",
+            ));
+        }
+    }
+
+    match store.delete_message(&room_id, &message_id, Utc::now()).await {
+        Ok(deleted) => {
+            info!("Deleted message {} in room {} by {}", deleted.id, room_id, query.user_id);
+            Ok(deleted)
+        }
+        Err(StoreError::ConditionFailed) => Err(HandlerError::not_found("Message not found")),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn add_reaction_handler(
+    store: &dyn ChatStore,
     room_id: String,
-) -> Result<GetMessagesResponse, String> {
+    message_id: String,
+    emoji: String,
+    query: ReactionQuery,
+) -> Result<ChatMessage, HandlerError> {
     let room_id = validate_room_id(&room_id)?;
+    let emoji = validate_emoji(&emoji)?;
+    authorize_room_access(store, &room_id, Some(&query.user_id)).await?;
 
-    // Query messages from DynamoDB
-    let result = ddb
-        .query()
-        .table_name(&tables.messages)
-        .key_condition_expression("room_id = :room_id")
-        .expression_attribute_values(":room_id", AttributeValue::S(room_id.clone()))
-        .scan_index_forward(true) // Oldest first
-        .limit(25)
-        .send()
+    let message = store
+        .get_message(&room_id, &message_id)
+        .await?
+        .ok_or_else(|| HandlerError::not_found("Message not found"))?;
+    if message.deleted_at.is_some() {
+        return Err(HandlerError::conflict("Message has been deleted"));
+    }
+
+    match store
+        .add_reaction(&room_id, &message_id, &emoji, &query.user_id, MAX_DISTINCT_REACTIONS)
         .await
-        .map_err(|e| format!("DynamoDB error: {:?}", e))?;
-
-    let messages: Vec<ChatMessage> = result
-        .items
-        .unwrap_or_default()
-        .into_iter()
-        .filter_map(|item| {
-            // Convert DynamoDB item to ChatMessage struct
-            let id = item.get("id")?.as_s().ok()?.clone();
-            let user_id = item
-                .get("user_id")
-                .and_then(|v| v.as_s().ok())
-                .map(|s| s.to_string())
-                .unwrap_or_else(|| "unknown".to_string());
-            let username = item.get("username")?.as_s().ok()?.clone();
-            let message_text = item.get("message_text")?.as_s().ok()?.clone();
-            let ts = item.get("ts")?.as_n().ok()?.parse::<i64>().ok()?;
-            let created_at = chrono::DateTime::from_timestamp_millis(ts)?;
-            let client_message_id =
-                item.get("client_message_id").and_then(|v| v.as_s().ok()).cloned();
-
-            Some(ChatMessage {
-                id,
+    {
+        Ok(ReactionOutcome::Added) => {
+            info!(
+                "{} reacted {} to message {} in room {}",
+                query.user_id, emoji, message_id, room_id
+            )
+        }
+        Ok(ReactionOutcome::AlreadyPresent) => {}
+        Ok(ReactionOutcome::LimitReached) => {
+            return Err(HandlerError::conflict(format!(
+                "A message can have at most {} different reactions",
+                MAX_DISTINCT_REACTIONS
+            )));
+        }
+        // Deleted between the read and the write
+        Err(StoreError::ConditionFailed) => {
+            return Err(HandlerError::conflict("Message has been deleted"));
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    reacted_message(store, &room_id, &message_id, &query.user_id).await
+}
+
+pub async fn remove_reaction_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    message_id: String,
+    emoji: String,
+    query: ReactionQuery,
+) -> Result<ChatMessage, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let emoji = validate_emoji(&emoji)?;
+    authorize_room_access(store, &room_id, Some(&query.user_id)).await?;
+
+    // Removing a reaction that is not there is not an error
+    if store.remove_reaction(&room_id, &message_id, &emoji, &query.user_id).await? {
+        info!(
+            "{} removed {} from message {} in room {}",
+            query.user_id, emoji, message_id, room_id
+        );
+    }
+
+    reacted_message(store, &room_id, &message_id, &query.user_id).await
+}
+
+pub async fn list_members_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    query: ListMembersQuery,
+) -> Result<ListMembersResponse, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+    authorize_room(store, &room, query.user_id.as_deref()).await?;
+
+    let members = store.room_members(&room_id).await?;
+    Ok(ListMembersResponse { room_id, members })
+}
+
+pub async fn room_presence_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    query: RoomPresenceQuery,
+) -> Result<RoomPresenceResponse, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+    authorize_room(store, &room, query.user_id.as_deref()).await?;
+
+    let connections = live_connections(store, &room_id).await?;
+    let connections = room_audience(store, &room_id, connections).await?;
+    Ok(RoomPresenceResponse { room_id, users: online_users(&connections) })
+}
+
+/// A room's connections, less records left behind by sockets that closed without a
+/// disconnect and have outlived their TTL but not yet been swept
+pub async fn live_connections(
+    store: &dyn ChatStore,
+    room_id: &str,
+) -> Result<Vec<Connection>, StoreError> {
+    let now = Utc::now().timestamp();
+    let connections = store.room_connections(room_id).await?;
+    Ok(connections.into_iter().filter(|c| c.ttl > now).collect())
+}
+
+/// Each user with a connection once, however many tabs they have open, by username
+pub fn online_users(connections: &[Connection]) -> Vec<OnlineUser> {
+    let mut users: BTreeMap<&str, OnlineUser> = BTreeMap::new();
+    for connection in connections {
+        let connected_at =
+            DateTime::from_timestamp_millis(connection.connected_at).unwrap_or_default();
+        let user = users.entry(&connection.user_id).or_insert_with(|| OnlineUser {
+            user_id: connection.user_id.clone(),
+            username: connection.username.clone(),
+            connections: 0,
+            online_since: connected_at,
+        });
+        user.connections += 1;
+        user.online_since = user.online_since.min(connected_at);
+    }
+    let mut users: Vec<OnlineUser> = users.into_values().collect();
+    users.sort_by_cached_key(|user| (user.username.to_lowercase(), user.user_id.clone()));
+    users
+}
+
+pub async fn invite_member_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    request: InviteMemberRequest,
+) -> Result<RoomMember, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let member_id = validate_user_id(&request.member_id)?;
+    let room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+    if room.kind == RoomKind::Direct {
+        return Err(HandlerError::bad_request("Direct messages are between two people"));
+    }
+    let inviter = require_moderator(store, &room_id, Some(&request.user_id)).await?;
+
+    let existing = store.get_member(&room_id, &member_id).await?;
+    let role = match (request.role, &existing) {
+        (Some(role), _) => role,
+        // Re-inviting someone keeps their role
+        (None, Some(existing)) => existing.role,
+        (None, None) => RoomRole::Member,
+    };
+    let touches_staff =
+        role != RoomRole::Member || existing.as_ref().is_some_and(|m| m.role != RoomRole::Member);
+    if touches_staff && inviter.role != RoomRole::Owner {
+        return Err(HandlerError::forbidden(
+            "Only room owners can change moderator or owner roles",
+        ));
+    }
+    if let Some(existing) = &existing {
+        if existing.role == role {
+            return Ok(existing.clone());
+        }
+        if role != RoomRole::Owner
+            && is_last_owner(&store.room_members(&room_id).await?, &member_id)
+        {
+            return Err(HandlerError::conflict("A room must keep at least one owner"));
+        }
+    }
+
+    let member = RoomMember {
+        room_id: room_id.clone(),
+        user_id: member_id,
+        role,
+        joined_at: existing.map(|m| m.joined_at).unwrap_or_else(Utc::now),
+    };
+    store.put_member(&member).await?;
+    info!("{} added {} to room {} as {}", inviter.user_id, member.user_id, room_id, role.as_str());
+    Ok(member)
+}
+
+pub async fn join_room_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    request: MembershipRequest,
+) -> Result<RoomMember, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let user_id = validate_user_id(&request.user_id)?;
+    let room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+
+    if store.get_sanction(&room_id, &user_id).await?.is_some_and(|s| s.banned) {
+        return Err(HandlerError::forbidden("You are banned from this room"));
+    }
+    if let Some(existing) = store.get_member(&room_id, &user_id).await? {
+        return Ok(existing);
+    }
+    if room.visibility == RoomVisibility::Private {
+        return Err(HandlerError::forbidden("Private rooms can only be joined by invitation"));
+    }
+
+    let member = RoomMember { room_id, user_id, role: RoomRole::Member, joined_at: Utc::now() };
+    store.put_member(&member).await?;
+    info!("{} joined room {}", member.user_id, member.room_id);
+    Ok(member)
+}
+
+pub async fn leave_room_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    request: MembershipRequest,
+) -> Result<(), HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let user_id = validate_user_id(&request.user_id)?;
+
+    if room_id.starts_with(DIRECT_ROOM_PREFIX) {
+        return Err(HandlerError::bad_request("Cannot leave a direct message"));
+    }
+
+    let members = store.room_members(&room_id).await?;
+    if !members.iter().any(|m| m.user_id == user_id) {
+        return Err(HandlerError::not_found("Not a member of this room"));
+    }
+    if is_last_owner(&members, &user_id) {
+        return Err(HandlerError::conflict("The last owner cannot leave the room"));
+    }
+
+    store.delete_member(&room_id, &user_id).await?;
+    info!("{} left room {}", user_id, room_id);
+    Ok(())
+}
+
+/// A moderation action's result, with the target's connections that were dropped from the
+/// store. The caller closes them, since only it knows how to reach each transport.
+pub struct ModerationOutcome {
+    pub response: ModerationResponse,
+    pub closed: Vec<Connection>,
+}
+
+pub async fn moderate_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    request: ModerateRequest,
+) -> Result<ModerationOutcome, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let target_id = validate_user_id(&request.target_user_id)?;
+    let reason = request.reason.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    if reason.is_some_and(|r| r.chars().count() > MAX_REASON_LEN) {
+        return Err(HandlerError::bad_request(format!(
+            "Reason must be {} characters or less",
+            MAX_REASON_LEN
+        )));
+    }
+    let room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+    if room.kind == RoomKind::Direct {
+        return Err(HandlerError::bad_request("Direct messages cannot be moderated"));
+    }
+    let moderator = require_moderator(store, &room_id, Some(&request.user_id)).await?;
+    if target_id == moderator.user_id {
+        return Err(HandlerError::bad_request("Cannot moderate yourself"));
+    }
+    match store.get_member(&room_id, &target_id).await?.map(|m| m.role) {
+        Some(RoomRole::Owner) => {
+            return Err(HandlerError::forbidden("Room owners cannot be moderated"));
+        }
+        Some(RoomRole::Moderator) if moderator.role != RoomRole::Owner => {
+            return Err(HandlerError::forbidden("Only room owners can moderate moderators"));
+        }
+        _ => {}
+    }
+
+    let now = Utc::now();
+    let existing = store.get_sanction(&room_id, &target_id).await?;
+    let mut sanction = existing.clone().unwrap_or_else(|| RoomSanction {
+        room_id: room_id.clone(),
+        user_id: target_id.clone(),
+        muted_until: None,
+        banned: false,
+        reason: None,
+        moderator_id: moderator.user_id.clone(),
+        updated_at: now,
+    });
+    match request.action {
+        ModerationAction::Mute => {
+            let secs = request
+                .duration_secs
+                .filter(|secs| (1..=MAX_MUTE_SECS).contains(secs))
+                .ok_or_else(|| {
+                    HandlerError::bad_request(format!(
+                        "Mutes need a duration_secs between 1 and {}",
+                        MAX_MUTE_SECS
+                    ))
+                })?;
+            sanction.muted_until = Some(now + chrono::Duration::seconds(secs as i64));
+        }
+        ModerationAction::Unmute => sanction.muted_until = None,
+        ModerationAction::Ban => sanction.banned = true,
+        ModerationAction::Unban => sanction.banned = false,
+        ModerationAction::Kick => {}
+    }
+
+    // Kicks leave the standing sanction alone; everything else records who acted and why
+    let sanction = if request.action == ModerationAction::Kick {
+        existing.filter(|s| s.is_active_at(now))
+    } else {
+        sanction.moderator_id = moderator.user_id.clone();
+        sanction.updated_at = now;
+        if reason.is_some() {
+            sanction.reason = reason.map(str::to_string);
+        }
+        if sanction.is_active_at(now) {
+            store.put_sanction(&sanction).await?;
+            Some(sanction)
+        } else {
+            store.delete_sanction(&room_id, &target_id).await?;
+            None
+        }
+    };
+
+    let mut closed = Vec::new();
+    if matches!(request.action, ModerationAction::Kick | ModerationAction::Ban) {
+        store.delete_member(&room_id, &target_id).await?;
+        for connection in store.room_connections(&room_id).await? {
+            if connection.user_id == target_id {
+                store.delete_connection(&connection.connection_id).await?;
+                closed.push(connection);
+            }
+        }
+    }
+
+    info!(
+        "{} applied {:?} to {} in room {} ({} connection(s) closed)",
+        moderator.user_id,
+        request.action,
+        target_id,
+        room_id,
+        closed.len()
+    );
+    Ok(ModerationOutcome {
+        response: ModerationResponse {
+            room_id,
+            target_user_id: target_id,
+            action: request.action,
+            sanction,
+            disconnected: closed.len() as u32,
+        },
+        closed,
+    })
+}
+
+pub async fn list_sanctions_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    query: ListSanctionsQuery,
+) -> Result<ListSanctionsResponse, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    require_moderator(store, &room_id, Some(&query.user_id)).await?;
+
+    let now = Utc::now();
+    let mut sanctions = store.room_sanctions(&room_id).await?;
+    sanctions.retain(|s| s.is_active_at(now));
+    Ok(ListSanctionsResponse { room_id, sanctions })
+}
+
+pub async fn report_message_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    message_id: String,
+    request: ReportMessageRequest,
+) -> Result<MessageReport, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let reason = request.reason.trim();
+    if reason.is_empty() {
+        return Err(HandlerError::bad_request("A report needs a reason"));
+    }
+    if reason.chars().count() > MAX_REASON_LEN {
+        return Err(HandlerError::bad_request(format!(
+            "Reason must be {} characters or less",
+            MAX_REASON_LEN
+        )));
+    }
+
+    let room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+    authorize_room(store, &room, Some(&request.user_id)).await?;
+    let message = store
+        .get_message(&room_id, &message_id)
+        .await?
+        .ok_or_else(|| HandlerError::not_found("Message not found"))?;
+    if message.deleted_at.is_some() {
+        return Err(HandlerError::conflict("Message has been deleted"));
+    }
+    if message.user_id == request.user_id {
+        return Err(HandlerError::bad_request("Cannot report your own message"));
+    }
+    let open = store.room_reports(&room_id, Some(ReportStatus::Open)).await?;
+    if open.iter().any(|r| r.message_id == message.id && r.reporter_id == request.user_id) {
+        return Err(HandlerError::conflict("You already reported this message"));
+    }
+
+    let id = new_message_id();
+    let report = MessageReport {
+        id: id.to_string(),
+        room_id,
+        message_id: message.id,
+        reporter_id: request.user_id,
+        reason: reason.to_string(),
+        message_user_id: message.user_id,
+        message_text: message.message_text,
+        status: ReportStatus::Open,
+        created_at: DateTime::<Utc>::from(id.datetime()),
+        resolved_by: None,
+        resolved_at: None,
+    };
+    store.put_report(&report).await?;
+
+    info!(
+        "{} reported message {} in room {} ({})",
+        report.reporter_id, report.message_id, report.room_id, report.id
+    );
+    MetricsHelper::new().await.emit_message_reported(&report.room_id).await;
+    Ok(report)
+}
+
+pub async fn list_reports_handler(
+    store: &dyn ChatStore,
+    query: ListReportsQuery,
+) -> Result<ListReportsResponse, HandlerError> {
+    // One room if asked for, otherwise every room the caller moderates
+    let room_ids = match query.room_id.as_deref() {
+        Some(room_id) => {
+            let room_id = validate_room_id(room_id)?;
+            require_moderator(store, &room_id, Some(&query.user_id)).await?;
+            vec![room_id]
+        }
+        None => store
+            .user_memberships(&query.user_id)
+            .await?
+            .into_iter()
+            .filter(|m| m.role.can_moderate())
+            .map(|m| m.room_id)
+            .collect(),
+    };
+
+    let mut reports = Vec::new();
+    for room_id in &room_ids {
+        reports.extend(store.room_reports(room_id, query.status).await?);
+    }
+    reports.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(ListReportsResponse { reports })
+}
+
+/// Close a report, and every other open report of the same message, by dismissing them or
+/// deleting the message
+pub async fn resolve_report_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    report_id: String,
+    request: ResolveReportRequest,
+) -> Result<ResolveReportResponse, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let moderator = require_moderator(store, &room_id, Some(&request.user_id)).await?;
+    let report = store
+        .get_report(&room_id, &report_id)
+        .await?
+        .ok_or_else(|| HandlerError::not_found("Report not found"))?;
+    if report.status != ReportStatus::Open {
+        return Err(HandlerError::conflict("Report has already been resolved"));
+    }
+
+    let now = Utc::now();
+    let (status, message) = match request.resolution {
+        ReportResolution::Dismiss => (ReportStatus::Dismissed, None),
+        ReportResolution::Delete => {
+            match store.delete_message(&room_id, &report.message_id, now).await {
+                Ok(deleted) => (ReportStatus::Deleted, Some(deleted)),
+                // Gone already; the reports still need closing
+                Err(StoreError::ConditionFailed) => (ReportStatus::Deleted, None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    };
+
+    let mut resolved = Vec::new();
+    for mut open in store.room_reports(&room_id, Some(ReportStatus::Open)).await? {
+        if open.message_id != report.message_id {
+            continue;
+        }
+        open.status = status;
+        open.resolved_by = Some(moderator.user_id.clone());
+        open.resolved_at = Some(now);
+        store.put_report(&open).await?;
+        resolved.push(open);
+    }
+    let report = match resolved.iter().find(|r| r.id == report.id) {
+        Some(report) => report.clone(),
+        None => {
+            // Missed by an eventually consistent listing; close it on its own
+            let report = MessageReport {
+                status,
+                resolved_by: Some(moderator.user_id.clone()),
+                resolved_at: Some(now),
+                ..report
+            };
+            store.put_report(&report).await?;
+            resolved.push(report.clone());
+            report
+        }
+    };
+
+    info!(
+        "{} resolved {} report(s) of message {} in room {} as {}",
+        moderator.user_id,
+        resolved.len(),
+        report.message_id,
+        room_id,
+        status.as_str()
+    );
+    MetricsHelper::new()
+        .await
+        .emit_reports_resolved(status.as_str(), &room_id, resolved.len())
+        .await;
+    Ok(ResolveReportResponse { report, resolved: resolved.len() as u32, message })
+}
+
+pub async fn open_direct_message_handler(
+    store: &dyn ChatStore,
+    request: OpenDirectMessageRequest,
+) -> Result<DirectConversation, HandlerError> {
+    let room_id = direct_room_id(&request.user_id, &request.other_user_id)?;
+    let participants =
+        [validate_user_id(&request.user_id)?, validate_user_id(&request.other_user_id)?];
+
+    let room = match store.get_room(&room_id).await? {
+        Some(room) => room,
+        None => {
+            let room = Room {
+                id: room_id.clone(),
+                name: "Direct message".to_string(),
+                created_at: Utc::now(),
+                description: None,
+                topic: None,
+                archived: false,
+                visibility: RoomVisibility::Private,
+                kind: RoomKind::Direct,
+                last_message_at: None,
+            };
+            match store.create_room(&room).await {
+                Ok(()) => {
+                    info!("Opened direct message {} between {:?}", room_id, participants);
+                    room
+                }
+                // The other participant opened it at the same moment
+                Err(StoreError::ConditionFailed) => store
+                    .get_room(&room_id)
+                    .await?
+                    .ok_or_else(|| HandlerError::internal("Room vanished after creation"))?,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    };
+    if room.kind != RoomKind::Direct {
+        return Err(HandlerError::internal(format!("Room {} is not a direct message", room_id)));
+    }
+
+    // Written after the room, so an interrupted open is repaired by the next one
+    for user_id in &participants {
+        if store.get_member(&room_id, user_id).await?.is_none() {
+            let member = RoomMember {
                 room_id: room_id.clone(),
-                user_id,
-                username,
-                message_text,
-                created_at: created_at.with_timezone(&Utc),
-                client_message_id,
-            })
-        })
-        .collect();
-
-    info!("Retrieved {} messages for room {}", messages.len(), room_id);
-
-    let response = GetMessagesResponse { room_id, messages };
-    Ok(response)
+                user_id: user_id.clone(),
+                role: RoomRole::Member,
+                joined_at: room.created_at,
+            };
+            store.put_member(&member).await?;
+        }
+    }
+
+    let [_, other_user_id] = participants;
+    Ok(DirectConversation { room, other_user_id })
+}
+
+pub async fn list_direct_messages_handler(
+    store: &dyn ChatStore,
+    query: ListDirectMessagesQuery,
+) -> Result<ListDirectMessagesResponse, HandlerError> {
+    let user_id = validate_user_id(&query.user_id)?;
+
+    let mut conversations = Vec::new();
+    for membership in store.user_memberships(&user_id).await? {
+        if !membership.room_id.starts_with(DIRECT_ROOM_PREFIX) {
+            continue;
+        }
+        let Some(room) = store.get_room(&membership.room_id).await? else {
+            continue;
+        };
+        if room.kind != RoomKind::Direct {
+            continue;
+        }
+        let members = store.room_members(&room.id).await?;
+        if let Some(other) = members.into_iter().find(|m| m.user_id != user_id) {
+            conversations.push(DirectConversation { room, other_user_id: other.user_id });
+        }
+    }
+    // Conversations without messages yet sort by when they were opened
+    conversations.sort_by_key(|c| Reverse(c.room.last_message_at.unwrap_or(c.room.created_at)));
+
+    Ok(ListDirectMessagesResponse { conversations })
+}
+
+/// Move a user's read marker in a room up to a message. Marking an older message than the
+/// one already read leaves the marker where it is.
+pub async fn mark_read_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    request: MarkReadRequest,
+) -> Result<MarkedRead, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let message_id =
+        parse_cursor(&request.message_id).map_err(|_| "Invalid message id".to_string())?;
+
+    let room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+    authorize_room(store, &room, Some(&request.user_id)).await?;
+    if store.get_message(&room_id, &message_id).await?.is_none() {
+        return Err(HandlerError::not_found("Message not found"));
+    }
+
+    let marker = ReadMarker { room_id, user_id: request.user_id, message_id, read_at: Utc::now() };
+    if store.advance_read_marker(&marker).await? {
+        info!("{} read room {} up to {}", marker.user_id, marker.room_id, marker.message_id);
+        return Ok(MarkedRead { marker, advanced: true });
+    }
+    let stored = store
+        .get_read_marker(&marker.room_id, &marker.user_id)
+        .await?
+        .ok_or_else(|| HandlerError::internal("Read marker vanished"))?;
+    Ok(MarkedRead { marker: stored, advanced: false })
+}
+
+pub async fn list_read_markers_handler(
+    store: &dyn ChatStore,
+    room_id: String,
+    query: ListReadMarkersQuery,
+) -> Result<ListReadMarkersResponse, HandlerError> {
+    let room_id = validate_room_id(&room_id)?;
+    let room =
+        store.get_room(&room_id).await?.ok_or_else(|| HandlerError::not_found("Room not found"))?;
+    authorize_room(store, &room, query.user_id.as_deref()).await?;
+
+    let markers = store.room_read_markers(&room_id).await?;
+    Ok(ListReadMarkersResponse { room_id, markers })
+}
+
+/// Unread top-level messages from others in every room the user has marked read. Counts stop
+/// at one page of messages; rooms the user may no longer read are left out.
+pub async fn unread_counts_handler(
+    store: &dyn ChatStore,
+    query: UnreadCountsQuery,
+) -> Result<UnreadCountsResponse, HandlerError> {
+    let user_id = validate_user_id(&query.user_id)?;
+
+    let mut rooms = Vec::new();
+    for marker in store.user_read_markers(&user_id).await? {
+        let Some(room) = store.get_room(&marker.room_id).await? else {
+            continue;
+        };
+        match authorize_room(store, &room, Some(&user_id)).await {
+            Ok(()) => {}
+            Err(e) if e.status == 403 => continue,
+            Err(e) => return Err(e),
+        }
+
+        let unread_query = MessageQuery {
+            after: Some(marker.message_id.clone()),
+            limit: MAX_PAGE_SIZE,
+            ..Default::default()
+        };
+        let page = store.query_messages(&room.id, &unread_query).await?;
+        let unread =
+            page.messages.iter().filter(|m| m.deleted_at.is_none() && m.user_id != user_id).count();
+        rooms.push(UnreadCount {
+            room_id: room.id,
+            last_read_id: marker.message_id,
+            unread: unread as u32,
+            more: page.next_cursor.is_some(),
+        });
+    }
+    Ok(UnreadCountsResponse { rooms })
+}
+
+// The message as it stands after a reaction change, from the reacting user's point of view
+async fn reacted_message(
+    store: &dyn ChatStore,
+    room_id: &str,
+    message_id: &str,
+    user_id: &str,
+) -> Result<ChatMessage, HandlerError> {
+    let mut message = store
+        .get_message(room_id, message_id)
+        .await?
+        .ok_or_else(|| HandlerError::not_found("Message not found"))?;
+    mark_own_reactions(std::slice::from_mut(&mut message), Some(user_id));
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_filter::Blocklist;
+    use crate::store::MemoryStore;
+    use types::FilterAction;
+
+    #[test]
+    fn test_message_ids_are_monotonic() {
+        let ids: Vec<String> = (0..1000).map(|_| new_message_id().to_string()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_parse_cursor() {
+        let id = new_message_id().to_string();
+        assert_eq!(parse_cursor(&format!(" {} ", id.to_lowercase())), Ok(id));
+        assert!(parse_cursor("1700000000000").is_err());
+    }
+
+    #[test]
+    fn test_validate_room_id() {
+        assert_eq!(validate_room_id(" General "), Ok("general".to_string()));
+        assert_eq!(validate_room_id("team-42"), Ok("team-42".to_string()));
+        for invalid in
+            ["", "a/b", "two words", "-lead", "trail-", "double--hyphen", "caf\u{e9}", "ws"]
+        {
+            assert!(validate_room_id(invalid).is_err(), "{:?} should be rejected", invalid);
+        }
+        assert!(validate_room_id(&"a".repeat(MAX_ROOM_ID_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_room_slug_from_name() {
+        assert_eq!(room_slug_from_name("Rust & Go!"), Ok("rust-go".to_string()));
+        assert_eq!(room_slug_from_name("  Café  Chat "), Ok("caf-chat".to_string()));
+        assert_eq!(room_slug_from_name("Admin"), Ok("admin-room".to_string()));
+        assert!(room_slug_from_name("🎉🎉").is_err());
+
+        let long = room_slug_from_name(&"word ".repeat(40)).unwrap();
+        assert!(long.len() <= MAX_ROOM_ID_LEN && !long.ends_with('-'));
+    }
+
+    #[test]
+    fn test_direct_room_id() {
+        let id = direct_room_id("alice", "bob").unwrap();
+        assert_eq!(direct_room_id(" bob ", "alice"), Ok(id.clone()));
+        assert_eq!(validate_room_id(&id), Ok(id.clone()));
+        assert!(id.starts_with(DIRECT_ROOM_PREFIX));
+        assert_ne!(direct_room_id("alice", "carol").unwrap(), id);
+        assert!(direct_room_id("alice", "alice").is_err());
+        assert!(direct_room_id("alice", " ").is_err());
+    }
+
+    #[test]
+    fn test_validate_emoji() {
+        assert_eq!(validate_emoji(" 👍 "), Ok("👍".to_string()));
+        assert_eq!(validate_emoji("👨‍👩‍👧‍👦"), Ok("👨‍👩‍👧‍👦".to_string()));
+        assert!(validate_emoji("").is_err());
+        assert!(validate_emoji("+1").is_err());
+        assert!(validate_emoji("👍 👍").is_err());
+        assert!(validate_emoji(&"👍".repeat(17)).is_err());
+    }
+
+    #[test]
+    fn test_online_users() {
+        let connections = [
+            Connection::new("c1", "general", "u2", "bob", 3_000),
+            Connection::new("c2", "general", "u1", "Alice", 2_000),
+            Connection::new("c3", "general", "u2", "bob", 1_000),
+        ];
+        let users = online_users(&connections);
+        let summary: Vec<_> =
+            users.iter().map(|u| (u.user_id.as_str(), u.connections, u.online_since)).collect();
+        let at = |ms| DateTime::from_timestamp_millis(ms).unwrap();
+        assert_eq!(summary, [("u1", 1, at(2_000)), ("u2", 2, at(1_000))]);
+        assert!(online_users(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_edits_are_filtered() {
+        let store = MemoryStore::new();
+        let request = SendMessageRequest {
+            room_id: "general".to_string(),
+            user_id: "alice".to_string(),
+            username: "alice".to_string(),
+            message_text: "well done".to_string(),
+            client_message_id: None,
+            parent_id: None,
+        };
+        let id = post_message_handler(&store, &PostRateLimiter::unlimited(), request)
+            .await
+            .unwrap()
+            .message
+            .id;
+        let edit = |text: &str| EditMessageRequest {
+            user_id: "alice".to_string(),
+            message_text: text.to_string(),
+        };
+
+        // Clean text posted first cannot have blocked words edited in
+        let rejecting = FilterChain::new().with(Blocklist::new(["darn"]), FilterAction::Reject);
+        let err = edit_message(&store, &rejecting, "general".into(), id.clone(), edit("well darn"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status, 400);
+        assert_eq!(err.message, "Message rejected by the blocklist filter");
+        let stored = store.get_message("general", &id).await.unwrap().unwrap();
+        assert_eq!(stored.message_text, "well done");
+
+        // The stored decision follows the text
+        let masking = FilterChain::new().with(Blocklist::new(["darn"]), FilterAction::Mask);
+        let masked =
+            edit_message(&store, &masking, "general".into(), id.clone(), edit("well darn"))
+                .await
+                .unwrap();
+        assert_eq!(masked.message_text, "well ****");
+        let decision =
+            FilterDecision { action: FilterAction::Mask, filters: vec!["blocklist".to_string()] };
+        assert_eq!(masked.filter, Some(decision));
+        // Resending the same text masks to what is stored, so it is no new revision
+        edit_message(&store, &masking, "general".into(), id.clone(), edit("well darn"))
+            .await
+            .unwrap();
+        assert_eq!(store.message_revisions("general", &id).await.unwrap().len(), 1);
+        let clean = edit_message(&store, &masking, "general".into(), id.clone(), edit("well then"))
+            .await
+            .unwrap();
+        assert_eq!(clean.filter, None);
+        let stored = store.get_message("general", &id).await.unwrap().unwrap();
+        assert_eq!((stored.message_text.as_str(), stored.filter), ("well then", None));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_posts_create_no_room() {
+        let store = MemoryStore::new();
+        let request = |room_id: &str, parent_id: Option<&str>| SendMessageRequest {
+            room_id: room_id.to_string(),
+            user_id: "alice".to_string(),
+            username: "alice".to_string(),
+            message_text: "hello".to_string(),
+            client_message_id: None,
+            parent_id: parent_id.map(str::to_string),
+        };
+
+        let err = post_message_handler(
+            &store,
+            &PostRateLimiter::unlimited(),
+            request("fresh", Some("nope")),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!((err.status, err.message.as_str()), (404, "Thread not found"));
+        assert!(store.get_room("fresh").await.unwrap().is_none());
+
+        // The first accepted post does
+        post_message_handler(&store, &PostRateLimiter::unlimited(), request("fresh", None))
+            .await
+            .unwrap();
+        let room = store.get_room("fresh").await.unwrap().unwrap();
+        assert_eq!(room.visibility, RoomVisibility::Public);
+    }
 }
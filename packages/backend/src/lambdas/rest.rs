@@ -1,13 +1,178 @@
+use aws_config::SdkConfig;
 use aws_sdk_dynamodb::Client as DynamoDbClient;
-use lambda_http::{run, service_fn, Body, Error, Request, Response};
-use std::sync::LazyLock;
+use lambda_http::{run, service_fn, Body, Error, Request, RequestExt, Response};
+use percent_encoding::percent_decode_str;
+use serde::de::DeserializeOwned;
+use std::sync::{Arc, LazyLock};
 use tracing::{debug, error, info, warn, Level};
-use types::SendMessageRequest;
+use types::{
+    CreateRoomRequest, DeleteMessageQuery, EditMessageRequest, GetMessagesQuery,
+    InviteMemberRequest, ListDirectMessagesQuery, ListMembersQuery, ListReadMarkersQuery,
+    ListReportsQuery, ListRoomsQuery, ListSanctionsQuery, MarkReadRequest, MembershipRequest,
+    MessageOrder, ModerateRequest, OpenDirectMessageRequest, ReactionQuery, ReadMarker,
+    ReportMessageRequest, ReportStatus, ResolveReportRequest, RoomPresenceQuery,
+    SendMessageRequest, ServerEvent, UnreadCountsQuery, UpdateRoomRequest,
+};
 
-use backend::handlers;
+use backend::{
+    auth::{AuthChain, AuthError, Credentials, Identity},
+    handlers::{self, HandlerError},
+    push::ConnectionPusher,
+    rate_limit::{DynamoRateLimiter, MemoryRateLimiter, PostRateLimiter, RateLimiter},
+    store::{Connection, DynamoStore, Tables},
+};
 
 // Tables configuration
-static TABLES: LazyLock<handlers::Tables> = LazyLock::new(|| handlers::Tables::from_env());
+static TABLES: LazyLock<Tables> = LazyLock::new(Tables::from_env);
+
+// Authenticators, kept across invocations so the Cognito JWKS is only fetched once
+static AUTH: LazyLock<AuthChain> = LazyLock::new(AuthChain::from_env);
+
+// Fallback buckets when RATE_LIMIT_TABLE is unset; they only see this instance's traffic
+static LOCAL_BUCKETS: LazyLock<Arc<MemoryRateLimiter>> =
+    LazyLock::new(|| Arc::new(MemoryRateLimiter::new()));
+
+// Posting limits, with buckets in RATE_LIMIT_TABLE so every instance counts together
+fn post_rate_limiter(aws_config: &SdkConfig) -> PostRateLimiter {
+    let limiter: Arc<dyn RateLimiter> = match std::env::var("RATE_LIMIT_TABLE") {
+        Ok(table) => Arc::new(DynamoRateLimiter::new(DynamoDbClient::new(aws_config), table)),
+        Err(_) => {
+            warn!("RATE_LIMIT_TABLE unset; rate limiting per instance");
+            LOCAL_BUCKETS.clone()
+        }
+    };
+    PostRateLimiter::from_env(limiter)
+}
+
+// Build the messages query from ?before=&after=&limit=&order=
+fn messages_query(event: &Request, user_id: Option<String>) -> Result<GetMessagesQuery, String> {
+    let params = event.query_string_parameters();
+    let limit = params
+        .first("limit")
+        .map(|limit| limit.parse::<u32>().map_err(|_| "Invalid limit".to_string()))
+        .transpose()?;
+    let order = match params.first("order") {
+        None => None,
+        Some("asc") => Some(MessageOrder::Asc),
+        Some("desc") => Some(MessageOrder::Desc),
+        Some(other) => return Err(format!("Invalid order: {}", other)),
+    };
+
+    Ok(GetMessagesQuery {
+        before: params.first("before").map(str::to_string),
+        after: params.first("after").map(str::to_string),
+        limit,
+        order,
+        user_id,
+    })
+}
+
+// Build the reports query from ?room_id=&status=
+fn reports_query(event: &Request, user_id: String) -> Result<ListReportsQuery, String> {
+    let params = event.query_string_parameters();
+    let status = params
+        .first("status")
+        .map(|status| ReportStatus::parse(status).ok_or(format!("Invalid status: {}", status)))
+        .transpose()?;
+
+    Ok(ListReportsQuery { user_id, room_id: params.first("room_id").map(str::to_string), status })
+}
+
+// Split {prefix}{room_id}/{id}/{action}, e.g. /chat/messages/{room_id}/{message_id}/report
+fn item_action(path: &str, prefix: &str, action: &str) -> Option<(String, String)> {
+    let rest = path.strip_prefix(prefix)?.strip_suffix(action)?.strip_suffix('/')?;
+    let (room_id, id) = rest.split_once('/')?;
+    (!id.contains('/')).then(|| (room_id.to_string(), id.to_string()))
+}
+
+// Split /chat/messages/{room_id}/{message_id}/reactions/{emoji}; the emoji arrives percent-encoded
+fn reaction_path(path: &str) -> Option<(String, String, String)> {
+    let mut parts = path.strip_prefix("/chat/messages/")?.splitn(4, '/');
+    let (Some(room_id), Some(message_id), Some("reactions"), Some(emoji)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    let emoji = percent_decode_str(emoji).decode_utf8().ok()?;
+    Some((room_id.to_string(), message_id.to_string(), emoji.into_owned()))
+}
+
+// Split /chat/rooms/{room_id}/{action} (members, presence, read, join, leave, moderation)
+fn room_action(path: &str) -> Option<(String, &str)> {
+    let (room_id, action) = path.strip_prefix("/chat/rooms/")?.split_once('/')?;
+    Some((room_id.to_string(), action))
+}
+
+// The caller proven by the request's credentials
+async fn authenticate(event: &Request) -> Result<Identity, AuthError> {
+    let params = event.query_string_parameters();
+    let credentials = Credentials::collect(
+        |name| event.headers().get(name).and_then(|value| value.to_str().ok()),
+        |name| params.first(name),
+    );
+    AUTH.authenticate(&credentials).await
+}
+
+// Hang up on kicked or banned users through the WebSocket API's management endpoint
+// (WS_API_ID/WS_STAGE, as for the broadcaster). Their connection records are already gone,
+// so an unconfigured endpoint only delays the disconnect until the socket next idles out.
+async fn close_connections(aws_config: &SdkConfig, connections: &[Connection]) {
+    if connections.is_empty() {
+        return;
+    }
+    let (Ok(api_id), Ok(stage)) = (std::env::var("WS_API_ID"), std::env::var("WS_STAGE")) else {
+        warn!("WS_API_ID/WS_STAGE unset; not closing {} connection(s)", connections.len());
+        return;
+    };
+    ConnectionPusher::new(aws_config, &api_id, &stage).close(connections).await;
+}
+
+// Tell the room's sockets a read marker moved, through the same endpoint as
+// close_connections. The marker is stored either way; without one, only clients that
+// reload the room's markers see it.
+async fn push_read(aws_config: &SdkConfig, store: &DynamoStore, marker: &ReadMarker) {
+    let (Ok(api_id), Ok(stage)) = (std::env::var("WS_API_ID"), std::env::var("WS_STAGE")) else {
+        warn!("WS_API_ID/WS_STAGE unset; not announcing read marker in {}", marker.room_id);
+        return;
+    };
+    let pusher = ConnectionPusher::new(aws_config, &api_id, &stage);
+    let frame = ServerEvent::Read(marker.clone()).into();
+    match pusher.broadcast(store, &marker.room_id, &frame).await {
+        Ok(delivered) => info!("Announced read marker to {} connections", delivered),
+        Err(e) => error!("Failed to announce read marker in {}: {:?}", marker.room_id, e),
+    }
+}
+
+// A request's JSON body; one that does not parse is the client's error
+fn parse_body<T: DeserializeOwned>(event: &Request) -> Result<T, HandlerError> {
+    serde_json::from_slice(event.body().as_ref())
+        .map_err(|e| HandlerError::bad_request(format!("Invalid request body: {}", e)))
+}
+
+// A JSON body with the CORS headers every route answers with
+fn json_response(status: u16, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Headers", "*")
+        .body(Body::Text(body))
+        .unwrap()
+}
+
+// Client errors carry their message; anything else stays opaque
+fn error_response(err: &HandlerError) -> Response<Body> {
+    let body = if err.status >= 500 {
+        "Internal server error".to_string()
+    } else {
+        serde_json::json!({ "error": err.message, "code": err.status }).to_string()
+    };
+    let mut response = json_response(err.status, body);
+    if let Some(secs) = err.retry_after_secs {
+        response.headers_mut().insert("Retry-After", secs.into());
+    }
+    response
+}
 
 async fn handler(event: Request) -> Result<Response<Body>, Error> {
     let method = event.method().as_str();
@@ -17,8 +182,7 @@ async fn handler(event: Request) -> Result<Response<Body>, Error> {
     debug!("Full request: {:?}", event);
 
     let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-    let ddb = DynamoDbClient::new(&aws_config);
-    let tables = TABLES.clone();
+    let store = DynamoStore::new(DynamoDbClient::new(&aws_config), TABLES.clone());
 
     info!("Handler processing: {} {}", method, path);
 
@@ -47,55 +211,464 @@ async fn handler(event: Request) -> Result<Response<Body>, Error> {
 
     info!("Cleaned path: {}", clean_path);
 
+    // Everything but the health check and CORS preflight acts as the token's user
+    let identity = match (method, clean_path.as_str()) {
+        ("GET", "/health") | ("OPTIONS", _) => None,
+        _ => match authenticate(&event).await {
+            Ok(identity) => Some(identity),
+            Err(err) => {
+                warn!("Rejected {} {}: {}", method, clean_path, err);
+                return Ok(error_response(&err.into()));
+            }
+        },
+    };
+    // Requests act as the caller whatever user_id they carry
+    let caller_id = identity.as_ref().map(|identity| identity.user_id.clone());
+    let acting_user = caller_id.clone().unwrap_or_default();
+
     match (method, clean_path.as_str()) {
         ("GET", "/health") => {
             info!("Processing health endpoint");
             match handlers::health_handler().await {
                 Ok(health_check) => {
                     let body = serde_json::to_string(&health_check)?;
-                    Ok(Response::builder()
-                        .status(200)
-                        .header("Content-Type", "application/json")
-                        .header("Access-Control-Allow-Origin", "*")
-                        .header("Access-Control-Allow-Headers", "*")
-                        .body(Body::Text(body))
-                        .unwrap())
+                    Ok(json_response(200, body))
                 }
                 Err(err) => {
                     error!("Health check failed: {}", err);
-                    Ok(Response::builder()
-                        .status(500)
-                        .header("Access-Control-Allow-Origin", "*")
-                        .header("Access-Control-Allow-Headers", "*")
-                        .body(Body::Text("Internal server error".to_string()))
-                        .unwrap())
+                    Ok(error_response(&HandlerError::internal(err)))
+                }
+            }
+        }
+        ("POST", "/auth/token") => {
+            info!("Processing POST /auth/token");
+            let result = match &identity {
+                Some(identity) => AUTH.issue_token(identity),
+                None => Err(AuthError::Missing.into()),
+            };
+
+            match result {
+                Ok(issued) => {
+                    let body = serde_json::to_string(&issued)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to issue token: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("GET", "/chat/rooms") => {
+            info!("Processing GET /chat/rooms");
+            let include_archived = event
+                .query_string_parameters()
+                .first("include_archived")
+                .map(|value| value == "true");
+
+            let user_id = caller_id.clone();
+
+            match handlers::list_rooms_handler(&store, ListRoomsQuery { include_archived, user_id })
+                .await
+            {
+                Ok(response) => {
+                    let body = serde_json::to_string(&response)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to list rooms: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("POST", "/chat/rooms") => {
+            info!("Processing POST /chat/rooms");
+            let mut request: CreateRoomRequest = match parse_body(&event) {
+                Ok(request) => request,
+                Err(err) => return Ok(error_response(&err)),
+            };
+            request.user_id = caller_id.clone();
+
+            match handlers::create_room_handler(&store, request).await {
+                Ok(room) => {
+                    let body = serde_json::to_string(&room)?;
+                    Ok(json_response(201, body))
+                }
+                Err(err) => {
+                    error!("Failed to create room: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("GET" | "POST", path) if room_action(path).is_some() => {
+            info!("Processing {} for path: {}", method, path);
+            let Some((room_id, action)) = room_action(path) else {
+                return Ok(error_response(&HandlerError::not_found("Room not found")));
+            };
+
+            // A block, so a body that does not parse is answered like any other handler error
+            let result: Result<(u16, serde_json::Result<String>), HandlerError> = async {
+                match (method, action) {
+                    ("GET", "members") => {
+                        let user_id = caller_id.clone();
+                        handlers::list_members_handler(
+                            &store,
+                            room_id,
+                            ListMembersQuery { user_id },
+                        )
+                        .await
+                        .map(|response| (200, serde_json::to_string(&response)))
+                    }
+                    ("GET", "presence") => {
+                        let query = RoomPresenceQuery { user_id: caller_id.clone() };
+                        handlers::room_presence_handler(&store, room_id, query)
+                            .await
+                            .map(|response| (200, serde_json::to_string(&response)))
+                    }
+                    ("GET", "read") => {
+                        let query = ListReadMarkersQuery { user_id: caller_id.clone() };
+                        handlers::list_read_markers_handler(&store, room_id, query)
+                            .await
+                            .map(|response| (200, serde_json::to_string(&response)))
+                    }
+                    ("POST", "read") => {
+                        let mut request: MarkReadRequest = parse_body(&event)?;
+                        request.user_id = acting_user.clone();
+                        match handlers::mark_read_handler(&store, room_id, request).await {
+                            Ok(marked) => {
+                                if marked.advanced {
+                                    push_read(&aws_config, &store, &marked.marker).await;
+                                }
+                                Ok((200, serde_json::to_string(&marked.marker)))
+                            }
+                            Err(err) => Err(err),
+                        }
+                    }
+                    ("POST", "members") => {
+                        let mut request: InviteMemberRequest = parse_body(&event)?;
+                        request.user_id = acting_user.clone();
+                        handlers::invite_member_handler(&store, room_id, request)
+                            .await
+                            .map(|member| (200, serde_json::to_string(&member)))
+                    }
+                    ("POST", "join") => {
+                        let mut request: MembershipRequest = parse_body(&event)?;
+                        request.user_id = acting_user.clone();
+                        handlers::join_room_handler(&store, room_id, request)
+                            .await
+                            .map(|member| (200, serde_json::to_string(&member)))
+                    }
+                    ("POST", "leave") => {
+                        let mut request: MembershipRequest = parse_body(&event)?;
+                        request.user_id = acting_user.clone();
+                        handlers::leave_room_handler(&store, room_id, request)
+                            .await
+                            .map(|()| (204, Ok(String::new())))
+                    }
+                    ("GET", "moderation") => {
+                        let query = ListSanctionsQuery { user_id: acting_user.clone() };
+                        handlers::list_sanctions_handler(&store, room_id, query)
+                            .await
+                            .map(|response| (200, serde_json::to_string(&response)))
+                    }
+                    ("POST", "moderation") => {
+                        let mut request: ModerateRequest = parse_body(&event)?;
+                        request.user_id = acting_user.clone();
+                        match handlers::moderate_handler(&store, room_id, request).await {
+                            Ok(outcome) => {
+                                close_connections(&aws_config, &outcome.closed).await;
+                                Ok((200, serde_json::to_string(&outcome.response)))
+                            }
+                            Err(err) => Err(err),
+                        }
+                    }
+                    _ => Err(HandlerError::not_found("Not found")),
+                }
+            }
+            .await;
+
+            match result {
+                Ok((status, body)) => Ok(json_response(status, body?)),
+                Err(err) => {
+                    error!("Failed to handle room {}: {}", action, err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("PATCH", path) if path.starts_with("/chat/rooms/") => {
+            info!("Processing PATCH for path: {}", path);
+            let room_id = path.trim_start_matches("/chat/rooms/").to_string();
+            let mut request: UpdateRoomRequest = match parse_body(&event) {
+                Ok(request) => request,
+                Err(err) => return Ok(error_response(&err)),
+            };
+            request.user_id = caller_id.clone();
+
+            match handlers::update_room_handler(&store, room_id, request).await {
+                Ok(room) => {
+                    let body = serde_json::to_string(&room)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to update room: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("GET", "/chat/dms") => {
+            info!("Processing GET /chat/dms");
+            let user_id = acting_user.clone();
+
+            match handlers::list_direct_messages_handler(
+                &store,
+                ListDirectMessagesQuery { user_id },
+            )
+            .await
+            {
+                Ok(response) => {
+                    let body = serde_json::to_string(&response)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to list direct messages: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("POST", "/chat/dms") => {
+            info!("Processing POST /chat/dms");
+            let mut request: OpenDirectMessageRequest = match parse_body(&event) {
+                Ok(request) => request,
+                Err(err) => return Ok(error_response(&err)),
+            };
+            request.user_id = acting_user.clone();
+
+            match handlers::open_direct_message_handler(&store, request).await {
+                Ok(conversation) => {
+                    let body = serde_json::to_string(&conversation)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to open direct message: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("GET", "/chat/unread") => {
+            info!("Processing GET /chat/unread");
+            let user_id = acting_user.clone();
+
+            match handlers::unread_counts_handler(&store, UnreadCountsQuery { user_id }).await {
+                Ok(response) => {
+                    let body = serde_json::to_string(&response)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to count unread messages: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("GET", "/chat/reports") => {
+            info!("Processing GET /chat/reports");
+            let result = match reports_query(&event, acting_user.clone()) {
+                Ok(query) => handlers::list_reports_handler(&store, query).await,
+                Err(err) => Err(HandlerError::from(err)),
+            };
+
+            match result {
+                Ok(response) => {
+                    let body = serde_json::to_string(&response)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to list reports: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("POST", path) if item_action(path, "/chat/reports/", "resolve").is_some() => {
+            info!("Processing POST for path: {}", path);
+            let Some((room_id, report_id)) = item_action(path, "/chat/reports/", "resolve") else {
+                return Ok(error_response(&HandlerError::not_found("Report not found")));
+            };
+            let mut request: ResolveReportRequest = match parse_body(&event) {
+                Ok(request) => request,
+                Err(err) => return Ok(error_response(&err)),
+            };
+            request.user_id = acting_user.clone();
+
+            match handlers::resolve_report_handler(&store, room_id, report_id, request).await {
+                Ok(response) => {
+                    let body = serde_json::to_string(&response)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to resolve report: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("POST", path) if item_action(path, "/chat/messages/", "report").is_some() => {
+            info!("Processing POST for path: {}", path);
+            let Some((room_id, message_id)) = item_action(path, "/chat/messages/", "report") else {
+                return Ok(error_response(&HandlerError::not_found("Message not found")));
+            };
+            let mut request: ReportMessageRequest = match parse_body(&event) {
+                Ok(request) => request,
+                Err(err) => return Ok(error_response(&err)),
+            };
+            request.user_id = acting_user.clone();
+
+            match handlers::report_message_handler(&store, room_id, message_id, request).await {
+                Ok(report) => {
+                    let body = serde_json::to_string(&report)?;
+                    Ok(json_response(201, body))
+                }
+                Err(err) => {
+                    error!("Failed to report message: {}", err);
+                    Ok(error_response(&err))
                 }
             }
         }
         ("POST", "/chat/messages") => {
             info!("Processing POST /chat/messages");
-            let bytes = event.body().as_ref().to_owned();
-            let request: SendMessageRequest = serde_json::from_slice(&bytes)?;
+            let mut request: SendMessageRequest = match parse_body(&event) {
+                Ok(request) => request,
+                Err(err) => return Ok(error_response(&err)),
+            };
+            request.user_id = acting_user.clone();
+            if let Some(identity) = &identity {
+                request.username = identity.username.clone();
+            }
 
-            match handlers::post_message_handler(&ddb, &tables, request).await {
+            let limits = post_rate_limiter(&aws_config);
+            match handlers::post_message_handler(&store, &limits, request).await {
+                Ok(posted) => {
+                    // 200 when a retry replays the original message
+                    let status = if posted.replayed { 200 } else { 201 };
+                    let body = serde_json::to_string(&posted.message)?;
+                    Ok(json_response(status, body))
+                }
+                Err(err) => {
+                    error!("Failed to post message: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("POST" | "DELETE", path) if path.contains("/reactions/") => {
+            info!("Processing {} reaction for path: {}", method, path);
+            let Some((room_id, message_id, emoji)) = reaction_path(path) else {
+                return Ok(error_response(&HandlerError::not_found("Message not found")));
+            };
+            let user_id = acting_user.clone();
+
+            let query = ReactionQuery { user_id };
+            let result = if method == "POST" {
+                handlers::add_reaction_handler(&store, room_id, message_id, emoji, query).await
+            } else {
+                handlers::remove_reaction_handler(&store, room_id, message_id, emoji, query).await
+            };
+
+            match result {
                 Ok(message) => {
                     let body = serde_json::to_string(&message)?;
-                    Ok(Response::builder()
-                        .status(201)
-                        .header("Content-Type", "application/json")
-                        .header("Access-Control-Allow-Origin", "*")
-                        .header("Access-Control-Allow-Headers", "*")
-                        .body(Body::Text(body))
-                        .unwrap())
+                    Ok(json_response(200, body))
                 }
                 Err(err) => {
-                    error!("Failed to post message: {}", err);
-                    Ok(Response::builder()
-                        .status(500)
-                        .header("Access-Control-Allow-Origin", "*")
-                        .header("Access-Control-Allow-Headers", "*")
-                        .body(Body::Text("Internal server error".to_string()))
-                        .unwrap())
+                    error!("Failed to update reaction: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("PATCH", path) if path.starts_with("/chat/messages/") => {
+            info!("Processing PATCH for path: {}", path);
+            let Some((room_id, message_id)) =
+                path.trim_start_matches("/chat/messages/").split_once('/')
+            else {
+                return Ok(error_response(&HandlerError::not_found("Message not found")));
+            };
+            let mut request: EditMessageRequest = match parse_body(&event) {
+                Ok(request) => request,
+                Err(err) => return Ok(error_response(&err)),
+            };
+            request.user_id = acting_user.clone();
+
+            match handlers::edit_message_handler(
+                &store,
+                room_id.to_string(),
+                message_id.to_string(),
+                request,
+            )
+            .await
+            {
+                Ok(message) => {
+                    let body = serde_json::to_string(&message)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to edit message: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("DELETE", path) if path.starts_with("/chat/messages/") => {
+            info!("Processing DELETE for path: {}", path);
+            let Some((room_id, message_id)) =
+                path.trim_start_matches("/chat/messages/").split_once('/')
+            else {
+                return Ok(error_response(&HandlerError::not_found("Message not found")));
+            };
+            let user_id = acting_user.clone();
+
+            match handlers::delete_message_handler(
+                &store,
+                room_id.to_string(),
+                message_id.to_string(),
+                DeleteMessageQuery { user_id },
+            )
+            .await
+            {
+                Ok(message) => {
+                    let body = serde_json::to_string(&message)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to delete message: {}", err);
+                    Ok(error_response(&err))
+                }
+            }
+        }
+        ("GET", path) if path.starts_with("/chat/messages/") && path.ends_with("/replies") => {
+            info!("Processing GET thread for path: {}", path);
+            let Some((room_id, message_id)) = path
+                .trim_start_matches("/chat/messages/")
+                .trim_end_matches("/replies")
+                .split_once('/')
+            else {
+                return Ok(error_response(&HandlerError::not_found("Thread not found")));
+            };
+
+            let result = match messages_query(&event, caller_id.clone()) {
+                Ok(query) => {
+                    handlers::get_thread_handler(
+                        &store,
+                        room_id.to_string(),
+                        message_id.to_string(),
+                        query,
+                    )
+                    .await
+                }
+                Err(err) => Err(HandlerError::from(err)),
+            };
+
+            match result {
+                Ok(response) => {
+                    let body = serde_json::to_string(&response)?;
+                    Ok(json_response(200, body))
+                }
+                Err(err) => {
+                    error!("Failed to get thread: {}", err);
+                    Ok(error_response(&err))
                 }
             }
         }
@@ -104,25 +677,19 @@ async fn handler(event: Request) -> Result<Response<Body>, Error> {
             let room_id = path.trim_start_matches("/chat/messages/").to_string();
             info!("Extracted room_id: {}", room_id);
 
-            match handlers::get_messages_handler(&ddb, &tables, room_id).await {
+            let result = match messages_query(&event, caller_id.clone()) {
+                Ok(query) => handlers::get_messages_handler(&store, room_id, query).await,
+                Err(err) => Err(HandlerError::from(err)),
+            };
+
+            match result {
                 Ok(response) => {
                     let body = serde_json::to_string(&response)?;
-                    Ok(Response::builder()
-                        .status(200)
-                        .header("Content-Type", "application/json")
-                        .header("Access-Control-Allow-Origin", "*")
-                        .header("Access-Control-Allow-Headers", "*")
-                        .body(Body::Text(body))
-                        .unwrap())
+                    Ok(json_response(200, body))
                 }
                 Err(err) => {
                     error!("Failed to get messages: {}", err);
-                    Ok(Response::builder()
-                        .status(500)
-                        .header("Access-Control-Allow-Origin", "*")
-                        .header("Access-Control-Allow-Headers", "*")
-                        .body(Body::Text("Internal server error".to_string()))
-                        .unwrap())
+                    Ok(error_response(&err))
                 }
             }
         }
@@ -131,19 +698,14 @@ async fn handler(event: Request) -> Result<Response<Body>, Error> {
             Ok(Response::builder()
                 .status(204)
                 .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Methods", "GET,POST,OPTIONS")
-                .header("Access-Control-Allow-Headers", "content-type,authorization")
+                .header("Access-Control-Allow-Methods", "GET,POST,PATCH,DELETE,OPTIONS")
+                .header("Access-Control-Allow-Headers", "content-type,authorization,x-api-key")
                 .body(Body::Empty)
                 .unwrap())
         }
         _ => {
             warn!("No route matched for: {} {}", method, path);
-            Ok(Response::builder()
-                .status(404)
-                .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Headers", "*")
-                .body(Body::Empty)
-                .unwrap())
+            Ok(error_response(&HandlerError::not_found("Not found")))
         }
     }
 }
@@ -1,18 +1,22 @@
-use aws_sdk_apigatewaymanagement::{primitives::Blob, Client as ApiGatewayClient};
-use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient};
-use backend::MetricsHelper;
+use aws_sdk_dynamodb::Client as DynamoDbClient;
+use backend::{
+    push::ConnectionPusher,
+    store::{summarize_reactions, ChatStore, DynamoStore, Tables, DELETED_MESSAGE_TEXT},
+    ws, MetricsHelper,
+};
 use chrono::{DateTime, Utc};
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
-#[cfg(feature = "dev")]
-use reqwest::Client as HttpClient;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, env, sync::LazyLock};
+use std::{
+    collections::{BTreeSet, HashMap},
+    env,
+    sync::LazyLock,
+};
 use tracing::{error, info};
+use types::{ChatMessage, FilterAction, FilterDecision, ReactionChange, ServerEvent, ServerFrame};
 
 // Static constants for required environment variables - will panic at startup if not set
-static CONNECTIONS_TABLE: LazyLock<String> = LazyLock::new(|| {
-    env::var("CONNECTIONS_TABLE").expect("CONNECTIONS_TABLE environment variable must be set")
-});
+static TABLES: LazyLock<Tables> = LazyLock::new(Tables::from_env);
 
 static WS_API_ID: LazyLock<String> =
     LazyLock::new(|| env::var("WS_API_ID").expect("WS_API_ID environment variable must be set"));
@@ -20,9 +24,6 @@ static WS_API_ID: LazyLock<String> =
 static WS_STAGE: LazyLock<String> =
     LazyLock::new(|| env::var("WS_STAGE").expect("WS_STAGE environment variable must be set"));
 
-static AWS_REGION: LazyLock<String> =
-    LazyLock::new(|| env::var("AWS_REGION").expect("AWS_REGION environment variable must be set"));
-
 // Optional: when set, broadcast locally via HTTP to the dev server
 #[cfg(feature = "dev")]
 static DEV_BROADCAST_URL: LazyLock<Option<String>> =
@@ -44,27 +45,23 @@ struct DynamoDBRecord {
 #[derive(Deserialize)]
 struct DynamoDBStreamRecord {
     #[serde(rename = "NewImage")]
-    new_image: Option<HashMap<String, AttributeValueWrapper>>,
+    new_image: Option<Image>,
+    #[serde(rename = "OldImage")]
+    old_image: Option<Image>,
 }
 
+type Image = HashMap<String, AttributeValueWrapper>;
+
 #[derive(Deserialize)]
 struct AttributeValueWrapper {
     #[serde(rename = "S")]
     s: Option<String>,
     #[serde(rename = "N")]
     n: Option<String>,
-}
-
-#[derive(Debug, Serialize)]
-struct ChatMessage {
-    id: String,
-    room_id: String,
-    user_id: String,
-    username: String,
-    message_text: String,
-    created_at: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    client_message_id: Option<String>,
+    #[serde(rename = "SS")]
+    ss: Option<Vec<String>>,
+    #[serde(rename = "L")]
+    l: Option<Vec<AttributeValueWrapper>>,
 }
 
 #[derive(Serialize)]
@@ -82,204 +79,208 @@ async fn function_handler(
 
     // Initialize AWS clients
     let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-    let ddb = DynamoDbClient::new(&aws_config);
-
-    // Optional HTTP client for dev per-connection push
-    #[cfg(feature = "dev")]
-    let http_client = HttpClient::new();
-
-    // Build WebSocket management client using static constants for prod
-    let ws_endpoint = format!(
-        "https://{}.execute-api.{}.amazonaws.com/{}",
-        &*WS_API_ID, &*AWS_REGION, &*WS_STAGE
-    );
-    let api_gateway_config = aws_sdk_apigatewaymanagement::config::Builder::from(&aws_config)
-        .endpoint_url(ws_endpoint)
-        .build();
-    let api_gateway = ApiGatewayClient::from_conf(api_gateway_config);
+    let store = DynamoStore::new(DynamoDbClient::new(&aws_config), TABLES.clone());
+
+    // Pushes through the WebSocket API's management endpoint, or dev push URLs
+    let pusher = ConnectionPusher::new(&aws_config, &WS_API_ID, &WS_STAGE);
 
+    let mut posted_rooms = BTreeSet::new();
     for record in event.records {
-        if let Err(e) = process_record(&ddb, &api_gateway, &CONNECTIONS_TABLE, record).await {
-            error!("Failed to process record: {:?}", e);
+        match process_record(&store, &pusher, record).await {
+            Ok(Some(room_id)) => {
+                posted_rooms.insert(room_id);
+            }
+            Ok(None) => {}
             // Continue processing other records even if one fails
+            Err(e) => error!("Failed to process record: {:?}", e),
+        }
+    }
+
+    // Posting usually follows typing; lapsed indicators stop once per room in the batch
+    let now = Utc::now().timestamp();
+    for room_id in posted_rooms {
+        if let Err(e) = expire_typing(&store, &pusher, &room_id, now).await {
+            error!("Failed to expire typing in room {}: {:?}", room_id, e);
         }
     }
 
     Ok(LambdaResponse { status_code: 200 })
 }
 
+// Push one change to its room; the room is returned when the change was a new post
 async fn process_record(
-    ddb: &DynamoDbClient,
-    api_gateway: &ApiGatewayClient,
-    connections_table: &str,
+    store: &dyn ChatStore,
+    pusher: &ConnectionPusher,
     record: DynamoDBRecord,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
     // Initialize metrics helper
     let metrics = MetricsHelper::new().await;
-    // Only process INSERT events (new messages)
-    if record.event_name != "INSERT" {
-        info!("Skipping event: {}", record.event_name);
-        return Ok(());
-    }
 
     let stream_record = record.dynamodb.ok_or("No dynamodb data in record")?;
-    let image = stream_record.new_image.ok_or("No NewImage in record")?;
-
-    // Extract message data from DynamoDB stream record
-    let room_id = image.get("room_id").and_then(|v| v.s.as_ref()).ok_or("Missing room_id")?;
-    let message_id = image.get("id").and_then(|v| v.s.as_ref()).ok_or("Missing id")?;
-    let username = image.get("username").and_then(|v| v.s.as_ref()).ok_or("Missing username")?;
-    let message_text =
-        image.get("message_text").and_then(|v| v.s.as_ref()).ok_or("Missing message_text")?;
+    let old_image = stream_record.old_image.as_ref();
+    // REMOVE records only carry the old image
+    let image = match record.event_name.as_str() {
+        "REMOVE" => old_image.ok_or("No OldImage in record")?,
+        _ => stream_record.new_image.as_ref().ok_or("No NewImage in record")?,
+    };
+    let mut message_payload = message_from_image(image)?;
+    let room_id = message_payload.room_id.clone();
+    let message_id = message_payload.id.clone();
+
+    // Each change goes out as a typed frame so clients can tell them apart
+    let old_value = |key: &str| old_image.and_then(|old| old.get(key)).and_then(|v| v.s.clone());
+    let events = match record.event_name.as_str() {
+        "INSERT" => {
+            // Emit message sent metrics
+            metrics.emit_message_sent(&room_id, message_payload.message_text.len()).await;
+            vec![ServerEvent::MessageNew { message: message_payload.clone() }]
+        }
+        "MODIFY" if message_payload.deleted_at.is_some() => {
+            if old_value("deleted_at_iso").is_some() {
+                info!("Skipping MODIFY of already deleted message {}", message_id);
+                return Ok(None);
+            }
+            vec![ServerEvent::MessageDeleted { message: message_payload.clone() }]
+        }
+        "MODIFY" if old_value("message_text").as_ref() != Some(&message_payload.message_text) => {
+            vec![ServerEvent::MessageEdited { message: message_payload.clone() }]
+        }
+        "MODIFY" => {
+            let events = reaction_events(&message_payload, old_image, Some(image));
+            // Thread summary updates land here too; the reply's own INSERT already went out
+            if events.is_empty() {
+                info!("Skipping MODIFY without a visible change for message {}", message_id);
+                return Ok(None);
+            }
+            events
+        }
+        "REMOVE" => {
+            // Hard deletes look the same to clients as tombstones
+            message_payload.message_text = DELETED_MESSAGE_TEXT.to_string();
+            message_payload.deleted_at.get_or_insert_with(Utc::now);
+            vec![ServerEvent::MessageDeleted { message: message_payload.clone() }]
+        }
+        other => {
+            info!("Skipping event: {}", other);
+            return Ok(None);
+        }
+    };
+
+    info!("Broadcasting {} to room {}: {:?}", record.event_name, room_id, message_payload);
+
+    for event in events {
+        let frame = ServerFrame::from(event);
+        // Live connections of the room's audience, as for every other push
+        let connections = ws::recipients(store, &room_id, &frame).await?;
+        info!("Found {} connections in room {}", connections.len(), room_id);
+        let payload = &serde_json::to_string(&frame)?;
+        let successful_sends = pusher.send(store, &connections, payload).await;
+
+        // Emit broadcast metrics
+        metrics.emit_message_broadcast(&room_id, connections.len() as i32, successful_sends).await;
+    }
+
+    info!("Finished broadcasting message {} to room {}", message_id, room_id);
+    Ok((record.event_name == "INSERT").then_some(room_id))
+}
+
+async fn expire_typing(
+    store: &dyn ChatStore,
+    pusher: &ConnectionPusher,
+    room_id: &str,
+    now: i64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for stop in ws::expire_typing(store, room_id, now).await? {
+        pusher.broadcast(store, room_id, &stop).await?;
+    }
+    Ok(())
+}
+
+// One event per reaction added or removed between the two images
+fn reaction_events(
+    message: &ChatMessage,
+    old_image: Option<&Image>,
+    new_image: Option<&Image>,
+) -> Vec<ServerEvent> {
+    let before = reaction_entries(old_image);
+    let after = reaction_entries(new_image);
+    let change = |entry: &String| {
+        split_reaction(entry).map(|(emoji, user_id)| ReactionChange {
+            room_id: message.room_id.clone(),
+            message_id: message.id.clone(),
+            emoji,
+            user_id,
+        })
+    };
+    let added = after.difference(&before).filter_map(change).map(ServerEvent::ReactionAdded);
+    let removed = before.difference(&after).filter_map(change).map(ServerEvent::ReactionRemoved);
+    added.chain(removed).collect()
+}
+
+// Build the broadcast payload from a stream image of a messages table item
+fn message_from_image(
+    image: &Image,
+) -> Result<ChatMessage, Box<dyn std::error::Error + Send + Sync>> {
+    let s = |key: &str| image.get(key).and_then(|v| v.s.clone());
+    let time = |key: &str| {
+        s(key)
+            .and_then(|at| DateTime::parse_from_rfc3339(&at).ok())
+            .map(|at| at.with_timezone(&Utc))
+    };
+
     let ts = image
         .get("ts")
         .and_then(|v| v.n.as_ref())
         .and_then(|n| n.parse::<i64>().ok())
         .ok_or("Missing or invalid ts")?;
 
-    // Extract user_id and client_message_id (may be missing for older messages)
-    let user_id = image
-        .get("user_id")
-        .and_then(|v| v.s.as_ref())
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| "unknown".to_string());
-
-    let client_message_id = image.get("client_message_id").and_then(|v| v.s.as_ref()).cloned();
-
-    // Create the message payload to broadcast
-    let message_payload = ChatMessage {
-        id: message_id.clone(),
-        room_id: room_id.clone(),
-        user_id,
-        username: username.clone(),
-        message_text: message_text.clone(),
-        created_at: DateTime::from_timestamp_millis(ts).unwrap_or_else(Utc::now).to_rfc3339(),
-        client_message_id,
-    };
+    Ok(ChatMessage {
+        id: s("id").ok_or("Missing id")?,
+        room_id: s("room_id").ok_or("Missing room_id")?,
+        // May be missing for older messages
+        user_id: s("user_id").unwrap_or_else(|| "unknown".to_string()),
+        username: s("username").ok_or("Missing username")?,
+        message_text: s("message_text").ok_or("Missing message_text")?,
+        created_at: DateTime::from_timestamp_millis(ts).unwrap_or_else(Utc::now),
+        client_message_id: s("client_message_id"),
+        edited_at: time("edited_at_iso"),
+        deleted_at: time("deleted_at_iso"),
+        reactions: summarize_reactions(
+            reaction_entries(Some(image)).iter().filter_map(|entry| split_reaction(entry)),
+        ),
+        // Replies carry their thread root; clients bump the root's summary from these
+        parent_id: s("parent_id"),
+        reply_count: image
+            .get("reply_count")
+            .and_then(|v| v.n.as_ref())
+            .and_then(|n| n.parse().ok())
+            .unwrap_or_default(),
+        last_reply_at: time("last_reply_at_iso"),
+        filter: filter_from_image(image),
+    })
+}
 
-    info!("Broadcasting message to room {}: {:?}", room_id, message_payload);
-
-    // Query for all connections in this room using GSI
-    let connections_result = ddb
-        .query()
-        .table_name(connections_table)
-        .index_name("room-index")
-        .key_condition_expression("room_id = :room_id")
-        .expression_attribute_values(":room_id", AttributeValue::S(room_id.clone()))
-        .send()
-        .await?;
-
-    let connections = connections_result.items.unwrap_or_default();
-    info!("Found {} connections in room {}", connections.len(), room_id);
-
-    // Broadcast to each connection and track metrics
-    let message_json = serde_json::to_string(&message_payload)?;
-    let message_blob = Blob::new(message_json.as_bytes());
-
-    let total_connections = connections.len() as i32;
-    let mut successful_sends = 0;
-
-    // Emit message sent metrics
-    metrics.emit_message_sent(room_id, message_text.len()).await;
-
-    // Send per connection according to its transport
-    for connection in connections {
-        // Determine transport; default to apigw if missing
-        let transport = connection
-            .get("transport")
-            .and_then(|v| v.as_s().ok())
-            .map(|s| s.as_str())
-            .unwrap_or("apigw");
-
-        match transport {
-            "apigw" => {
-                if let Some(AttributeValue::S(connection_id)) = connection.get("connection_id") {
-                    match api_gateway
-                        .post_to_connection()
-                        .connection_id(connection_id)
-                        .data(message_blob.clone())
-                        .send()
-                        .await
-                    {
-                        Ok(_) => {
-                            info!("Sent via API Gateway to connection {}", connection_id);
-                            successful_sends += 1;
-                        }
-                        Err(e) => {
-                            error!("Failed to send via API Gateway to {}: {:?}", connection_id, e);
-                            if let Some(service_err) = e.as_service_error() {
-                                if service_err.is_gone_exception() {
-                                    info!("Removing stale connection {}", connection_id);
-                                    if let Err(delete_err) = ddb
-                                        .delete_item()
-                                        .table_name(connections_table)
-                                        .key(
-                                            "connection_id",
-                                            AttributeValue::S(connection_id.clone()),
-                                        )
-                                        .send()
-                                        .await
-                                    {
-                                        error!(
-                                            "Failed to delete stale connection {}: {:?}",
-                                            connection_id, delete_err
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            #[cfg(feature = "dev")]
-            "dev" => {
-                // Use per-connection push_url
-                if let Some(AttributeValue::S(push_url)) = connection.get("push_url") {
-                    match http_client.post(push_url).json(&message_payload).send().await {
-                        Ok(resp) => {
-                            if resp.status().is_success() {
-                                info!("Sent via dev push_url to {}", push_url);
-                                successful_sends += 1;
-                            } else if resp.status().as_u16() == 404 || resp.status().as_u16() == 410
-                            {
-                                // Remove stale connection
-                                if let Some(AttributeValue::S(connection_id)) =
-                                    connection.get("connection_id")
-                                {
-                                    let _ = ddb
-                                        .delete_item()
-                                        .table_name(connections_table)
-                                        .key(
-                                            "connection_id",
-                                            AttributeValue::S(connection_id.clone()),
-                                        )
-                                        .send()
-                                        .await;
-                                }
-                            } else {
-                                error!("Dev push_url responded with status {}", resp.status());
-                            }
-                        }
-                        Err(e) => {
-                            error!("HTTP error sending to dev push_url {}: {:?}", push_url, e);
-                        }
-                    }
-                } else {
-                    error!("Missing push_url for dev transport connection");
-                }
-            }
-            _ => {
-                // Unknown transport; skip
-                info!("Skipping connection with unknown transport: {}", transport);
-            }
-        }
-    }
+fn filter_from_image(image: &Image) -> Option<FilterDecision> {
+    let action = FilterAction::parse(image.get("filter_action")?.s.as_deref()?)?;
+    let filters = image
+        .get("filter_names")
+        .and_then(|v| v.l.as_ref())
+        .map(|names| names.iter().filter_map(|name| name.s.clone()).collect())
+        .unwrap_or_default();
+    Some(FilterDecision { action, filters })
+}
 
-    // Emit broadcast metrics
-    metrics.emit_message_broadcast(room_id, total_connections, successful_sends).await;
+// Reactions are stored as a string set of "<emoji> <user_id>" entries
+fn reaction_entries(image: Option<&Image>) -> BTreeSet<String> {
+    image
+        .and_then(|image| image.get("reactions"))
+        .and_then(|v| v.ss.clone())
+        .map(|entries| entries.into_iter().collect())
+        .unwrap_or_default()
+}
 
-    info!("Finished broadcasting message {} to room {}", message_id, room_id);
-    Ok(())
+fn split_reaction(entry: &str) -> Option<(String, String)> {
+    entry.split_once(' ').map(|(emoji, user_id)| (emoji.to_string(), user_id.to_string()))
 }
 
 #[tokio::main]
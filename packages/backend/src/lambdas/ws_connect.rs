@@ -1,14 +1,21 @@
-use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient};
-use backend::MetricsHelper;
+use aws_sdk_dynamodb::Client as DynamoDbClient;
+use backend::{
+    auth::{AuthChain, Credentials},
+    handlers,
+    push::ConnectionPusher,
+    store::{ChatStore, Connection, DynamoStore, Tables},
+    ws, MetricsHelper,
+};
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, env, sync::LazyLock};
+use std::{collections::HashMap, sync::LazyLock};
 use tracing::{error, info};
 
-// Static constant for required environment variable - will panic at startup if not set
-static CONNECTIONS_TABLE: LazyLock<String> = LazyLock::new(|| {
-    env::var("CONNECTIONS_TABLE").expect("CONNECTIONS_TABLE environment variable must be set")
-});
+// Tables configuration - will panic at startup if not set
+static TABLES: LazyLock<Tables> = LazyLock::new(Tables::from_env);
+
+// Authenticators, kept across invocations so the Cognito JWKS is only fetched once
+static AUTH: LazyLock<AuthChain> = LazyLock::new(AuthChain::from_env);
 
 #[derive(Debug, Deserialize, Serialize)]
 struct WebSocketEvent {
@@ -16,12 +23,15 @@ struct WebSocketEvent {
     request_context: RequestContext,
     #[serde(rename = "queryStringParameters")]
     query_string_parameters: Option<HashMap<String, String>>,
+    headers: Option<HashMap<String, String>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 struct RequestContext {
     #[serde(rename = "connectionId")]
     connection_id: String,
+    #[serde(rename = "apiId")]
+    api_id: Option<String>,
     #[serde(rename = "domainName")]
     domain_name: Option<String>,
     stage: Option<String>,
@@ -36,11 +46,12 @@ struct LambdaResponse {
 async fn function_handler(event: LambdaEvent<WebSocketEvent>) -> Result<LambdaResponse, Error> {
     let (event, _context) = event.into_parts();
 
-    info!("WebSocket connection event: {:?}", event);
+    // Not the whole event: the query string may carry a token
+    info!("WebSocket connection event for {}", event.request_context.connection_id);
 
-    // Initialize AWS config, DynamoDB client, and metrics helper
+    // Initialize AWS config, store, and metrics helper
     let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-    let ddb = DynamoDbClient::new(&aws_config);
+    let store = DynamoStore::new(DynamoDbClient::new(&aws_config), TABLES.clone());
     let metrics = MetricsHelper::new().await;
 
     let connection_id = &event.request_context.connection_id;
@@ -54,45 +65,62 @@ async fn function_handler(event: LambdaEvent<WebSocketEvent>) -> Result<LambdaRe
         .and_then(|params| params.get("room_id"))
         .map(|s| s.as_str())
         .unwrap_or("general");
-
-    let username = event
-        .query_string_parameters
-        .as_ref()
-        .and_then(|params| params.get("username"))
-        .map(|s| s.as_str())
-        .unwrap_or("anon");
-
-    let user_id = event
-        .query_string_parameters
-        .as_ref()
-        .and_then(|params| params.get("userId"))
-        .map(|s| s.as_str())
-        .unwrap_or("anon");
+    // Same slug rules as the REST API; a rejected $connect never opens the socket
+    let room_id = match handlers::validate_room_id(room_id) {
+        Ok(room_id) => room_id,
+        Err(err) => {
+            info!("Rejecting connection {}: {}", connection_id, err);
+            return Ok(LambdaResponse { status_code: 400 });
+        }
+    };
+    let room_id = room_id.as_str();
+
+    // Browsers cannot set headers on a WebSocket handshake, so ?token= works too
+    let credentials = Credentials::collect(
+        |name| {
+            event
+                .headers
+                .as_ref()?
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        },
+        |name| event.query_string_parameters.as_ref()?.get(name).map(String::as_str),
+    );
+    let identity = match AUTH.authenticate(&credentials).await {
+        Ok(identity) => identity,
+        Err(err) => {
+            info!("Rejecting connection {}: {}", connection_id, err);
+            let status = handlers::HandlerError::from(err).status;
+            return Ok(LambdaResponse { status_code: status as i32 });
+        }
+    };
+    let (user_id, username) = (identity.user_id.as_str(), identity.username.as_str());
+
+    // Private rooms only accept their members
+    if let Err(err) = handlers::authorize_room_access(&store, room_id, Some(user_id)).await {
+        info!(
+            "Rejecting connection {} for {} in room {}: {}",
+            connection_id, user_id, room_id, err
+        );
+        return Ok(LambdaResponse { status_code: err.status as i32 });
+    }
 
     let now = chrono::Utc::now().timestamp_millis();
-    let ttl = now / 1000 + (60 * 60 * 24); // 24 hours from now
 
     info!(
         "Connecting user '{}' to room '{}' with connectionId: {}",
         username, room_id, connection_id
     );
 
-    // Store connection in DynamoDB using static constant
-    let connections_table = &*CONNECTIONS_TABLE;
-
-    let mut item = HashMap::new();
-    item.insert("connection_id".to_string(), AttributeValue::S(connection_id.clone()));
-    item.insert("room_id".to_string(), AttributeValue::S(room_id.to_string()));
-    item.insert("user_id".to_string(), AttributeValue::S(user_id.to_string())); // Store user_id
-    item.insert("username".to_string(), AttributeValue::S(username.to_string()));
-    item.insert("connected_at".to_string(), AttributeValue::N(now.to_string()));
-    item.insert("domain".to_string(), AttributeValue::S(domain_name.to_string()));
-    item.insert("stage".to_string(), AttributeValue::S(stage.to_string()));
-    // Explicitly mark transport for broadcaster
-    item.insert("transport".to_string(), AttributeValue::S("apigw".to_string()));
-    item.insert("ttl".to_string(), AttributeValue::N(ttl.to_string()));
-
-    match ddb.put_item().table_name(connections_table).set_item(Some(item)).send().await {
+    // Store connection (expires 24 hours from now)
+    let connection = Connection {
+        domain: domain_name.to_string(),
+        stage: stage.to_string(),
+        ..Connection::new(connection_id, room_id, user_id, username, now)
+    };
+
+    match store.put_connection(&connection).await {
         Ok(_) => {
             info!(
                 "Successfully stored connection {} for user {} in room {}",
@@ -102,6 +130,20 @@ async fn function_handler(event: LambdaEvent<WebSocketEvent>) -> Result<LambdaRe
             // Emit connection metrics
             metrics.emit_connection_event("connect", room_id, None).await;
 
+            // The new socket is not open until this returns; everyone else hears of it now
+            if let Some(api_id) = &event.request_context.api_id {
+                let pusher = ConnectionPusher::new(&aws_config, api_id, stage);
+                match ws::presence_change(&store, &connection, true).await {
+                    Ok(Some(joined)) => {
+                        if let Err(e) = pusher.broadcast(&store, room_id, &joined).await {
+                            error!("Failed to announce {} in room {}: {:?}", user_id, room_id, e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => error!("Failed to check presence in room {}: {:?}", room_id, e),
+                }
+            }
+
             Ok(LambdaResponse { status_code: 200 })
         }
         Err(e) => {
@@ -1,6 +1,25 @@
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::Client as DynamoDbClient;
+use backend::{
+    push::ConnectionPusher,
+    rate_limit::{
+        DynamoRateLimiter, MemoryRateLimiter, PostRateLimiter, RateLimiter, TypingThrottle,
+    },
+    store::{ChatStore, DynamoStore, Tables},
+    ws,
+};
+use chrono::Utc;
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use std::sync::{Arc, LazyLock};
+use tracing::{error, info, warn};
+
+// Tables configuration - will panic at startup if not set
+static TABLES: LazyLock<Tables> = LazyLock::new(Tables::from_env);
+
+// Fallback buckets when RATE_LIMIT_TABLE is unset; they only see this instance's traffic
+static LOCAL_BUCKETS: LazyLock<Arc<MemoryRateLimiter>> =
+    LazyLock::new(|| Arc::new(MemoryRateLimiter::new()));
 
 #[derive(Debug, Deserialize, Serialize)]
 struct WebSocketEvent {
@@ -13,6 +32,9 @@ struct WebSocketEvent {
 struct RequestContext {
     #[serde(rename = "connectionId")]
     connection_id: String,
+    #[serde(rename = "apiId")]
+    api_id: Option<String>,
+    stage: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -21,20 +43,85 @@ struct LambdaResponse {
     status_code: i32,
 }
 
+// Buckets in RATE_LIMIT_TABLE, shared with the rest lambda's posting limits
+fn rate_limiter(aws_config: &SdkConfig) -> Arc<dyn RateLimiter> {
+    match std::env::var("RATE_LIMIT_TABLE") {
+        Ok(table) => Arc::new(DynamoRateLimiter::new(DynamoDbClient::new(aws_config), table)),
+        Err(_) => {
+            warn!("RATE_LIMIT_TABLE unset; rate limiting per instance");
+            LOCAL_BUCKETS.clone()
+        }
+    }
+}
+
 async fn function_handler(event: LambdaEvent<WebSocketEvent>) -> Result<LambdaResponse, Error> {
     let (event, _context) = event.into_parts();
 
     let connection_id = &event.request_context.connection_id;
     let body = event.body.as_deref().unwrap_or("");
 
-    info!("WebSocket default route - connectionId: {}, message: {}", connection_id, body);
+    info!("WebSocket frame on connectionId: {} ({} bytes)", connection_id, body.len());
+
+    let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    let store = DynamoStore::new(DynamoDbClient::new(&aws_config), TABLES.clone());
 
-    // For now, this is a no-op handler that just logs the message
-    // In the future, this could handle specific message types or echo back
+    // $connect stored who is on the other end and which room they joined
+    let connection = match store.get_connection(connection_id).await {
+        Ok(Some(connection)) => connection,
+        Ok(None) => {
+            warn!("Frame on unknown connection {}", connection_id);
+            return Ok(LambdaResponse { status_code: 410 });
+        }
+        Err(e) => {
+            error!("Failed to load connection {}: {:?}", connection_id, e);
+            return Ok(LambdaResponse { status_code: 500 });
+        }
+    };
+
+    let limiter = rate_limiter(&aws_config);
+    let limits = PostRateLimiter::from_env(limiter.clone());
+    let typing = TypingThrottle::new(limiter, TypingThrottle::DEFAULT);
+    let handled = ws::handle_frame(&store, &limits, &typing, &connection, body).await;
+
+    // Everything goes out through the management endpoint of the API the frame arrived on
+    let (Some(api_id), Some(stage)) = (&event.request_context.api_id, &event.request_context.stage)
+    else {
+        warn!("No apiId/stage on the request; not answering {}", connection_id);
+        return Ok(LambdaResponse { status_code: 200 });
+    };
+    let pusher = ConnectionPusher::new(&aws_config, api_id, stage);
+
+    if let Some(reply) = handled.reply {
+        let payload = serde_json::to_string(&reply)?;
+        if pusher.send(&store, std::slice::from_ref(&connection), &payload).await == 0 {
+            error!("Failed to reply to connection {}", connection_id);
+        }
+    }
+    if let Some(broadcast) = handled.broadcast {
+        let delivered = pusher.broadcast(&store, &connection.room_id, &broadcast).await?;
+        info!("Relayed frame from {} to {} connections", connection_id, delivered);
+    }
+    expire_typing(&store, &pusher, &connection.room_id).await;
 
     Ok(LambdaResponse { status_code: 200 })
 }
 
+// Nothing runs when an indicator lapses, so each frame in the room sweeps for those that have
+async fn expire_typing(store: &DynamoStore, pusher: &ConnectionPusher, room_id: &str) {
+    let stopped = match ws::expire_typing(store, room_id, Utc::now().timestamp()).await {
+        Ok(stopped) => stopped,
+        Err(e) => {
+            error!("Failed to check typing in room {}: {:?}", room_id, e);
+            return;
+        }
+    };
+    for stop in stopped {
+        if let Err(e) = pusher.broadcast(store, room_id, &stop).await {
+            error!("Failed to send typing stop in room {}: {:?}", room_id, e);
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     // Initialize tracing with JSON format for CloudWatch
@@ -1,14 +1,16 @@
-use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient};
-use backend::MetricsHelper;
+use aws_sdk_dynamodb::Client as DynamoDbClient;
+use backend::{
+    push::ConnectionPusher,
+    store::{ChatStore, DynamoStore, Tables},
+    ws, MetricsHelper,
+};
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, env, sync::LazyLock};
+use std::{collections::HashMap, sync::LazyLock};
 use tracing::{error, info};
 
-// Static constant for required environment variable - will panic at startup if not set
-static CONNECTIONS_TABLE: LazyLock<String> = LazyLock::new(|| {
-    env::var("CONNECTIONS_TABLE").expect("CONNECTIONS_TABLE environment variable must be set")
-});
+// Tables configuration - will panic at startup if not set
+static TABLES: LazyLock<Tables> = LazyLock::new(Tables::from_env);
 
 #[derive(Debug, Deserialize, Serialize)]
 struct WebSocketEvent {
@@ -20,6 +22,9 @@ struct WebSocketEvent {
 struct RequestContext {
     #[serde(rename = "connectionId")]
     connection_id: String,
+    #[serde(rename = "apiId")]
+    api_id: Option<String>,
+    stage: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -33,44 +38,51 @@ async fn function_handler(event: LambdaEvent<WebSocketEvent>) -> Result<LambdaRe
 
     info!("WebSocket disconnection event: {:?}", event);
 
-    // Initialize AWS config, DynamoDB client, and metrics helper
+    // Initialize AWS config, store, and metrics helper
     let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-    let ddb = DynamoDbClient::new(&aws_config);
+    let store = DynamoStore::new(DynamoDbClient::new(&aws_config), TABLES.clone());
     let metrics = MetricsHelper::new().await;
 
     let connection_id = &event.request_context.connection_id;
 
     info!("Disconnecting connectionId: {}", connection_id);
 
-    // First, get connection info to extract room_id for metrics
-    let mut key = HashMap::new();
-    key.insert("connection_id".to_string(), AttributeValue::S(connection_id.clone()));
-
-    let room_id = match ddb
-        .get_item()
-        .table_name(&*CONNECTIONS_TABLE)
-        .set_key(Some(key.clone()))
-        .send()
-        .await
-    {
-        Ok(response) => response
-            .item
-            .as_ref()
-            .and_then(|item| item.get("room_id"))
-            .and_then(|attr| attr.as_s().ok())
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| "unknown".to_string()),
-        Err(_) => "unknown".to_string(),
-    };
-
-    // Delete connection from DynamoDB using static constant
-    match ddb.delete_item().table_name(&*CONNECTIONS_TABLE).set_key(Some(key)).send().await {
+    // First, get connection info: the room for metrics, the user for presence
+    let connection = store.get_connection(connection_id).await.ok().flatten();
+    let room_id = connection.as_ref().map_or("unknown", |c| c.room_id.as_str()).to_string();
+
+    // Delete connection from the store
+    match store.delete_connection(connection_id).await {
         Ok(_) => {
             info!("Successfully removed connection {}", connection_id);
 
             // Emit disconnection metrics
             metrics.emit_connection_event("disconnect", &room_id, None).await;
 
+            // Closing the user's last socket in the room takes them offline
+            let context = &event.request_context;
+            if let (Some(connection), Some(api_id), Some(stage)) =
+                (&connection, &context.api_id, &context.stage)
+            {
+                let pusher = ConnectionPusher::new(&aws_config, api_id, stage);
+                // Its record is gone, so no sweep will stop a typing indicator left behind
+                if connection.typing_until.is_some() {
+                    let stop = ws::typing_frame(connection, false);
+                    if let Err(e) = pusher.broadcast(&store, &room_id, &stop).await {
+                        error!("Failed to stop {} typing: {:?}", connection.user_id, e);
+                    }
+                }
+                match ws::presence_change(&store, connection, false).await {
+                    Ok(Some(left)) => {
+                        if let Err(e) = pusher.broadcast(&store, &room_id, &left).await {
+                            error!("Failed to announce {} leaving: {:?}", connection.user_id, e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => error!("Failed to check presence in room {}: {:?}", room_id, e),
+                }
+            }
+
             Ok(LambdaResponse { status_code: 200 })
         }
         Err(e) => {
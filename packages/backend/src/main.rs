@@ -1,29 +1,34 @@
 use axum::{
+    async_trait,
     extract::{
         ws::{Message, WebSocket},
-        Path, Query, State, WebSocketUpgrade,
+        FromRequestParts, Path, Query, State, WebSocketUpgrade,
     },
-    http::StatusCode,
+    http::{header, request::Parts, StatusCode},
     response::{IntoResponse, Json, Response},
-    routing::{get, post},
+    routing::{get, patch, post},
     Router,
 };
-#[cfg(feature = "dev")]
-use types::ChatMessage;
-#[cfg(feature = "dev")]
+use types::ServerFrame;
 use uuid::Uuid;
 // WebSocket support imports - will be used for message handling
 // use futures_util::{sink::SinkExt, stream::StreamExt};
 
 use std::net::SocketAddr;
-#[cfg(feature = "dev")]
 use std::sync::Arc;
 #[cfg(feature = "dev")]
 use tokio::sync::{broadcast, RwLock};
 use tower_http::cors::CorsLayer;
 // use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use types::{HealthCheck, SendMessageRequest};
+use types::{
+    CreateRoomRequest, DeleteMessageQuery, EditMessageRequest, GetMessagesQuery, HealthCheck,
+    InviteMemberRequest, ListDirectMessagesQuery, ListMembersQuery, ListReadMarkersQuery,
+    ListReportsQuery, ListRoomsQuery, ListSanctionsQuery, MarkReadRequest, MembershipRequest,
+    ModerateRequest, OpenDirectMessageRequest, ReactionQuery, ReportMessageRequest,
+    ResolveReportRequest, RoomPresenceQuery, SendMessageRequest, UnreadCountsQuery,
+    UpdateRoomRequest,
+};
 // use tower::ServiceExt; // Unused for now, but will be needed for Lambda
 use aws_sdk_dynamodb::Client as DynamoDbClient;
 use serde::Deserialize;
@@ -31,16 +36,19 @@ use serde_json::json;
 use std::{env, sync::LazyLock};
 #[cfg(feature = "dev")]
 use tokio::sync::mpsc;
+#[cfg(feature = "dev")]
+use types::{ReactionChange, ServerEvent};
 
-use backend::handlers;
+use backend::{
+    auth::{AuthChain, Credentials, Identity},
+    handlers::{self, HandlerError},
+    rate_limit::{MemoryRateLimiter, PostRateLimiter, TypingThrottle},
+    store::{ChatStore, Connection, DynamoStore, MemoryStore, SqliteStore, Tables},
+    ws,
+};
 
 // Tables configuration
-static TABLES: LazyLock<handlers::Tables> = LazyLock::new(|| handlers::Tables::from_env());
-
-#[cfg(feature = "dev")]
-static CHAT_CONNECTIONS_TABLE: LazyLock<String> = LazyLock::new(|| {
-    env::var("CONNECTIONS_TABLE").expect("CONNECTIONS_TABLE environment variable must be set")
-});
+static TABLES: LazyLock<Tables> = LazyLock::new(Tables::from_env);
 
 #[cfg(feature = "dev")]
 static DEV_PUBLIC_BASE_URL: LazyLock<Option<String>> =
@@ -48,15 +56,20 @@ static DEV_PUBLIC_BASE_URL: LazyLock<Option<String>> =
 
 #[derive(Clone)]
 struct AppState {
-    ddb: DynamoDbClient,
-    tables: handlers::Tables,
+    store: Arc<dyn ChatStore>,
     metrics: backend::MetricsHelper,
+    // Authenticators every route but /health goes through
+    auth: AuthChain,
+    // Per-user and per-room limits on posting
+    rate_limits: PostRateLimiter,
+    // Per-connection limit on relaying typing indicators
+    typing: TypingThrottle,
     // In-memory broadcast channels keyed by room id (dev only)
     #[cfg(feature = "dev")]
-    channels: Arc<RwLock<std::collections::HashMap<String, broadcast::Sender<String>>>>,
+    channels: Arc<RwLock<std::collections::HashMap<String, broadcast::Sender<ServerFrame>>>>,
     // Per-connection senders for targeted push (dev only)
     #[cfg(feature = "dev")]
-    conn_senders: Arc<RwLock<std::collections::HashMap<String, mpsc::Sender<String>>>>,
+    conn_senders: Arc<RwLock<std::collections::HashMap<String, mpsc::Sender<ServerFrame>>>>,
 }
 
 // Error handling for the API
@@ -64,6 +77,7 @@ struct AppState {
 struct AppError {
     message: String,
     status_code: StatusCode,
+    retry_after_secs: Option<u64>,
 }
 
 impl IntoResponse for AppError {
@@ -73,21 +87,59 @@ impl IntoResponse for AppError {
             "code": self.status_code.as_u16()
         });
 
-        (self.status_code, Json(body)).into_response()
+        let mut response = (self.status_code, Json(body)).into_response();
+        if let Some(secs) = self.retry_after_secs {
+            response.headers_mut().insert(header::RETRY_AFTER, secs.into());
+        }
+        response
     }
 }
 
-// Helper to create AppError from any error
-impl AppError {
-    fn from_error<E: std::fmt::Debug>(err: E) -> Self {
-        tracing::error!("DynamoDB error: {:?}", err);
+impl From<HandlerError> for AppError {
+    fn from(err: HandlerError) -> Self {
         Self {
-            message: "Internal server error".to_string(),
-            status_code: StatusCode::INTERNAL_SERVER_ERROR,
+            status_code: StatusCode::from_u16(err.status)
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            message: err.message,
+            retry_after_secs: err.retry_after_secs,
         }
     }
 }
 
+// The authenticated caller. Requests act as this user whatever user_id they carry.
+struct Caller(Identity);
+
+impl Caller {
+    fn user_id(&self) -> String {
+        self.0.user_id.clone()
+    }
+
+    // Read endpoints take an optional viewer
+    fn viewer(&self) -> Option<String> {
+        Some(self.user_id())
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Caller {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        let params = Query::<std::collections::HashMap<String, String>>::try_from_uri(&parts.uri)
+            .map(|query| query.0)
+            .unwrap_or_default();
+        let credentials = Credentials::collect(
+            |name| parts.headers.get(name).and_then(|value| value.to_str().ok()),
+            |name| params.get(name).map(String::as_str),
+        );
+
+        state.auth.authenticate(&credentials).await.map(Caller).map_err(|err| {
+            tracing::warn!("Rejected request to {}: {}", parts.uri.path(), err);
+            HandlerError::from(err).into()
+        })
+    }
+}
+
 #[tokio::main]
 async fn main() {
     // Initialize tracing
@@ -99,33 +151,19 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    // Initialize AWS config and DynamoDB client
-    let aws_config = if let Ok(endpoint) = env::var("DYNAMODB_ENDPOINT") {
-        // Use local DynamoDB for development
-        tracing::info!("Using local DynamoDB endpoint: {}", endpoint);
-        aws_config::defaults(aws_config::BehaviorVersion::latest())
-            .endpoint_url(endpoint)
-            .load()
-            .await
-    } else {
-        // Use AWS DynamoDB
-        aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await
-    };
-
-    let ddb_client = DynamoDbClient::new(&aws_config);
-
-    // Use static constants for table names - will panic at startup if not set
-    let tables = TABLES.clone();
-
-    tracing::info!("Using tables: rooms={}, messages={}", tables.rooms, tables.messages);
+    // Storage backend - DynamoDB unless CHAT_STORE says otherwise
+    let store = build_store().await;
 
     // Initialize metrics helper
     let metrics = backend::MetricsHelper::new().await;
 
     let state = AppState {
-        ddb: ddb_client,
-        tables,
+        store,
         metrics,
+        auth: AuthChain::from_env(),
+        // One process serves every request, so its buckets can live in memory
+        rate_limits: PostRateLimiter::from_env(Arc::new(MemoryRateLimiter::new())),
+        typing: TypingThrottle::new(Arc::new(MemoryRateLimiter::new()), TypingThrottle::DEFAULT),
         #[cfg(feature = "dev")]
         channels: Arc::new(RwLock::new(std::collections::HashMap::new())),
         #[cfg(feature = "dev")]
@@ -145,11 +183,84 @@ async fn main() {
     axum::Server::bind(&addr).serve(app.into_make_service()).await.unwrap();
 }
 
+// Select the storage backend from CHAT_STORE ("dynamodb" by default, "memory" or "sqlite")
+async fn build_store() -> Arc<dyn ChatStore> {
+    match env::var("CHAT_STORE").as_deref() {
+        Ok("memory") => {
+            tracing::info!("Using in-memory store; data will not survive a restart");
+            Arc::new(MemoryStore::new())
+        }
+        Ok("sqlite") => {
+            let path = env::var("SQLITE_PATH").unwrap_or_else(|_| "chat.db".to_string());
+            tracing::info!("Using SQLite store at {}", path);
+            Arc::new(SqliteStore::open(&path).expect("Failed to open SQLite database"))
+        }
+        Ok("dynamodb") | Err(_) => {
+            // Initialize AWS config and DynamoDB client
+            let aws_config = if let Ok(endpoint) = env::var("DYNAMODB_ENDPOINT") {
+                // Use local DynamoDB for development
+                tracing::info!("Using local DynamoDB endpoint: {}", endpoint);
+                aws_config::defaults(aws_config::BehaviorVersion::latest())
+                    .endpoint_url(endpoint)
+                    .load()
+                    .await
+            } else {
+                // Use AWS DynamoDB
+                aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await
+            };
+
+            let ddb_client = DynamoDbClient::new(&aws_config);
+
+            // Use static constants for table names - will panic at startup if not set
+            let tables = TABLES.clone();
+
+            tracing::info!(
+                "Using tables: rooms={}, messages={}, connections={}",
+                tables.rooms,
+                tables.messages,
+                tables.connections
+            );
+
+            Arc::new(DynamoStore::new(ddb_client, tables))
+        }
+        Ok(other) => panic!("Unsupported CHAT_STORE: {}", other),
+    }
+}
+
 fn create_app(state: AppState) -> Router {
     let base = Router::new()
         .route("/health", get(health_handler))
+        .route("/auth/token", post(issue_token_handler))
+        .route("/chat/rooms", get(list_rooms_handler).post(create_room_handler))
+        .route("/chat/rooms/:room_id", patch(update_room_handler))
+        .route(
+            "/chat/rooms/:room_id/members",
+            get(list_members_handler).post(invite_member_handler),
+        )
+        .route("/chat/rooms/:room_id/presence", get(room_presence_handler))
+        .route("/chat/rooms/:room_id/read", get(list_read_markers_handler).post(mark_read_handler))
+        .route("/chat/unread", get(unread_counts_handler))
+        .route("/chat/rooms/:room_id/join", post(join_room_handler))
+        .route("/chat/rooms/:room_id/leave", post(leave_room_handler))
+        .route(
+            "/chat/rooms/:room_id/moderation",
+            get(list_sanctions_handler).post(moderate_handler),
+        )
+        .route("/chat/reports", get(list_reports_handler))
+        .route("/chat/reports/:room_id/:report_id/resolve", post(resolve_report_handler))
+        .route("/chat/dms", get(list_direct_messages_handler).post(open_direct_message_handler))
         .route("/chat/messages", post(post_message_handler))
         .route("/chat/messages/:room_id", get(get_messages_handler))
+        .route(
+            "/chat/messages/:room_id/:message_id",
+            patch(edit_message_handler).delete(delete_message_handler),
+        )
+        .route("/chat/messages/:room_id/:message_id/replies", get(get_thread_handler))
+        .route("/chat/messages/:room_id/:message_id/report", post(report_message_handler))
+        .route(
+            "/chat/messages/:room_id/:message_id/reactions/:emoji",
+            post(add_reaction_handler).delete(remove_reaction_handler),
+        )
         .route("/ws", get(websocket_handler));
 
     #[cfg(feature = "dev")]
@@ -172,305 +283,2214 @@ async fn health_handler() -> Result<Json<HealthCheck>, StatusCode> {
     }
 }
 
-// POST /chat/messages - Send a new message
-async fn post_message_handler(
+// POST /auth/token - A short-lived backend token for the caller (e.g. a bot's WebSocket)
+async fn issue_token_handler(
     State(state): State<AppState>,
-    Json(request): Json<SendMessageRequest>,
+    caller: Caller,
 ) -> Result<impl IntoResponse, AppError> {
-    tracing::info!("Received message request for room: {}", request.room_id);
+    tracing::info!("Issuing token for {}", caller.0.user_id);
+    Ok(Json(state.auth.issue_token(&caller.0)?))
+}
 
-    match handlers::post_message_handler(&state.ddb, &state.tables, request).await {
-        Ok(message) => {
-            // Emit metrics for REST message post
-            state.metrics.emit_message_sent(&message.room_id, message.message_text.len()).await;
-            Ok((StatusCode::CREATED, Json(message)))
+// GET /chat/rooms - List rooms (?include_archived=true to see archived ones too)
+async fn list_rooms_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(mut query): Query<ListRoomsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.viewer();
+    match handlers::list_rooms_handler(state.store.as_ref(), query).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            tracing::error!("Failed to list rooms: {}", err);
+            Err(err.into())
         }
+    }
+}
+
+// POST /chat/rooms - Create a room
+async fn create_room_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(mut request): Json<CreateRoomRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.viewer();
+    tracing::info!("Creating room: {}", request.name);
+
+    match handlers::create_room_handler(state.store.as_ref(), request).await {
+        Ok(room) => Ok((StatusCode::CREATED, Json(room))),
         Err(err) => {
-            tracing::error!("Failed to post message: {}", err);
-            Err(AppError { message: err, status_code: StatusCode::INTERNAL_SERVER_ERROR })
+            tracing::error!("Failed to create room: {}", err);
+            Err(err.into())
         }
     }
 }
 
-// GET /chat/messages/:room_id - Retrieve last 25 messages
-async fn get_messages_handler(
+// PATCH /chat/rooms/:room_id - Rename, change description/topic, archive or unarchive
+async fn update_room_handler(
     State(state): State<AppState>,
+    caller: Caller,
     Path(room_id): Path<String>,
+    Json(mut request): Json<UpdateRoomRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    tracing::info!("Retrieving messages for room: {}", room_id);
+    request.user_id = caller.viewer();
+    tracing::info!("Updating room: {}", room_id);
 
-    match handlers::get_messages_handler(&state.ddb, &state.tables, room_id).await {
-        Ok(response) => Ok(Json(response)),
+    match handlers::update_room_handler(state.store.as_ref(), room_id, request).await {
+        Ok(room) => Ok(Json(room)),
         Err(err) => {
-            tracing::error!("Failed to get messages: {}", err);
-            Err(AppError { message: err, status_code: StatusCode::INTERNAL_SERVER_ERROR })
+            tracing::error!("Failed to update room: {}", err);
+            Err(err.into())
         }
     }
 }
 
-// WebSocket query parameters
-#[derive(Debug, Deserialize)]
-struct WebSocketParams {
-    room_id: Option<String>,
-    #[serde(rename = "userId")]
-    user_id: Option<String>,
-    username: Option<String>,
+// GET /chat/rooms/:room_id/members?user_id= - List a room's members and their roles
+async fn list_members_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(room_id): Path<String>,
+    Query(mut query): Query<ListMembersQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.viewer();
+    match handlers::list_members_handler(state.store.as_ref(), room_id, query).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            tracing::error!("Failed to list members: {}", err);
+            Err(err.into())
+        }
+    }
 }
 
-// WebSocket handler for development
-async fn websocket_handler(
-    ws: WebSocketUpgrade,
-    Query(params): Query<WebSocketParams>,
-    #[cfg(feature = "dev")] State(state): State<AppState>,
-) -> Response {
-    let room_id = params.room_id.unwrap_or_else(|| "general".to_string());
-    let user_id = params.user_id.unwrap_or_else(|| "dev-user".to_string());
-    let username = params.username.unwrap_or_else(|| "Developer".to_string());
-
-    tracing::info!(
-        "WebSocket connection request: room={}, user={}, username={}",
-        room_id,
-        user_id,
-        username
-    );
+// GET /chat/rooms/:room_id/presence - Users with a socket open in the room
+async fn room_presence_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(room_id): Path<String>,
+    Query(mut query): Query<RoomPresenceQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.viewer();
+    match handlers::room_presence_handler(state.store.as_ref(), room_id, query).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            tracing::error!("Failed to list presence: {}", err);
+            Err(err.into())
+        }
+    }
+}
 
-    ws.on_upgrade(move |socket| {
-        handle_websocket(
-            socket,
-            room_id,
-            user_id,
-            username,
+// POST /chat/rooms/:room_id/read - Move the caller's read marker up to a message
+async fn mark_read_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(room_id): Path<String>,
+    Json(mut request): Json<MarkReadRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.user_id();
+    match handlers::mark_read_handler(state.store.as_ref(), room_id, request).await {
+        Ok(marked) => {
+            // Sockets in the room hear about it, as if it came over one of them
             #[cfg(feature = "dev")]
-            state,
-        )
-    })
+            if marked.advanced {
+                if let Some(tx) = state.channels.read().await.get(&marked.marker.room_id) {
+                    let _ = tx.send(ServerEvent::Read(marked.marker.clone()).into());
+                }
+            }
+            Ok(Json(marked.marker))
+        }
+        Err(err) => {
+            tracing::error!("Failed to mark read: {}", err);
+            Err(err.into())
+        }
+    }
 }
 
-// WebSocket connection handler
-async fn handle_websocket(
-    mut socket: WebSocket,
-    room_id: String,
-    user_id: String,
-    username: String,
-    #[cfg(feature = "dev")] state: AppState,
-) {
-    tracing::info!("WebSocket connected: {} ({}) in room {}", username, user_id, room_id);
-
-    #[cfg(feature = "dev")]
-    let tx = {
-        let mut channels = state.channels.write().await;
-        if let Some(existing) = channels.get(&room_id) {
-            existing.clone()
-        } else {
-            let (tx, _rx) = broadcast::channel::<String>(100);
-            channels.insert(room_id.clone(), tx.clone());
-            tx
+// GET /chat/rooms/:room_id/read - Where everyone has read the room up to
+async fn list_read_markers_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(room_id): Path<String>,
+    Query(mut query): Query<ListReadMarkersQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.viewer();
+    match handlers::list_read_markers_handler(state.store.as_ref(), room_id, query).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            tracing::error!("Failed to list read markers: {}", err);
+            Err(err.into())
         }
-    };
-
-    #[cfg(feature = "dev")]
-    let mut rx = tx.subscribe();
-
-    // For development, create a per-connection sender and store connection in DynamoDB
-    #[cfg(feature = "dev")]
-    let connection_id = Uuid::new_v4().to_string();
-    #[cfg(feature = "dev")]
-    let (conn_tx, mut conn_rx) = mpsc::channel::<String>(100);
-    #[cfg(feature = "dev")]
-    {
-        use std::collections::HashMap;
-
-        use aws_sdk_dynamodb::types::AttributeValue;
+    }
+}
 
-        state.conn_senders.write().await.insert(connection_id.clone(), conn_tx);
+// GET /chat/unread - Unread counts in every room the caller has read
+async fn unread_counts_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(mut query): Query<UnreadCountsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.user_id();
+    match handlers::unread_counts_handler(state.store.as_ref(), query).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            tracing::error!("Failed to count unread messages: {}", err);
+            Err(err.into())
+        }
+    }
+}
 
-        // Compute public push URL (for broadcaster Lambda to call)
-        let base = DEV_PUBLIC_BASE_URL.clone();
-        let base = base.as_deref().unwrap_or("http://localhost:3001");
-        let push_url = format!("{}/dev/conn/{}/send", base.trim_end_matches('/'), connection_id);
+// POST /chat/rooms/:room_id/members - Invite a user or change their role (owners/moderators)
+async fn invite_member_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(room_id): Path<String>,
+    Json(mut request): Json<InviteMemberRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.user_id();
+    tracing::info!("Inviting {} to room {}", request.member_id, room_id);
 
-        // Write connection record to DynamoDB
-        let now = chrono::Utc::now().timestamp_millis();
-        let ttl = now / 1000 + (60 * 60 * 24);
-
-        let mut item = HashMap::new();
-        item.insert("connection_id".to_string(), AttributeValue::S(connection_id.clone()));
-        item.insert("room_id".to_string(), AttributeValue::S(room_id.clone()));
-        item.insert("user_id".to_string(), AttributeValue::S(user_id.clone()));
-        item.insert("username".to_string(), AttributeValue::S(username.clone()));
-        item.insert("connected_at".to_string(), AttributeValue::N(now.to_string()));
-        item.insert("domain".to_string(), AttributeValue::S("local".to_string()));
-        item.insert("stage".to_string(), AttributeValue::S("local".to_string()));
-        item.insert("transport".to_string(), AttributeValue::S("dev".to_string()));
-        item.insert("push_url".to_string(), AttributeValue::S(push_url));
-        item.insert("ttl".to_string(), AttributeValue::N(ttl.to_string()));
-
-        if let Err(e) = state
-            .ddb
-            .put_item()
-            .table_name(&*CHAT_CONNECTIONS_TABLE)
-            .set_item(Some(item))
-            .send()
-            .await
-        {
-            tracing::error!("Failed to write dev connection record: {:?}", e);
+    match handlers::invite_member_handler(state.store.as_ref(), room_id, request).await {
+        Ok(member) => Ok(Json(member)),
+        Err(err) => {
+            tracing::error!("Failed to invite member: {}", err);
+            Err(err.into())
         }
     }
+}
 
-    // Handle incoming messages
-    #[cfg(feature = "dev")]
-    {
-        loop {
-            tokio::select! {
-                // Outbound server -> client messages (room fan-out)
-                received = rx.recv() => {
-                    match received {
-                        Ok(payload) => {
-                            if let Err(e) = socket.send(Message::Text(payload)).await {
-                                tracing::warn!("Failed to send to {} in room {}: {}", username, room_id, e);
-                                break;
-                            }
-                        }
-                        Err(broadcast::error::RecvError::Closed) => {
-                            tracing::info!("Broadcast channel closed for room {}", room_id);
-                            break;
-                        }
-                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
-                            tracing::warn!("WebSocket for user {} lagged by {} messages in room {}", username, skipped, room_id);
-                        }
-                    }
-                }
-                // Targeted per-connection push
-                msg_to_send = conn_rx.recv() => {
-                    if let Some(payload) = msg_to_send {
-                        if let Err(e) = socket.send(Message::Text(payload)).await {
-                            tracing::warn!("Failed to send targeted message to {}: {}", username, e);
-                            break;
-                        }
-                    } else {
-                        // Sender dropped
-                        break;
-                    }
-                }
-                // Inbound client -> server messages (ignored in dev)
-                msg = socket.recv() => {
-                    match msg {
-                        Some(Ok(Message::Text(text))) => {
-                            tracing::info!("Received WebSocket message from {}: {}", username, text);
-                        }
-                        Some(Ok(Message::Close(_))) | None => {
-                            tracing::info!("WebSocket connection closed for user {}", username);
-                            break;
-                        }
-                        Some(Err(e)) => {
-                            tracing::error!("WebSocket error for user {}: {}", username, e);
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
-            }
+// POST /chat/rooms/:room_id/join - Join a public room
+async fn join_room_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(room_id): Path<String>,
+    Json(mut request): Json<MembershipRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.user_id();
+    match handlers::join_room_handler(state.store.as_ref(), room_id, request).await {
+        Ok(member) => Ok(Json(member)),
+        Err(err) => {
+            tracing::error!("Failed to join room: {}", err);
+            Err(err.into())
         }
     }
+}
 
-    #[cfg(not(feature = "dev"))]
-    {
-        // Minimal loop: only consume client messages and close
-        while let Some(msg) = socket.recv().await {
-            match msg {
-                Ok(Message::Text(text)) => {
-                    tracing::info!("Received WebSocket message from {}: {}", username, text);
-                }
-                Ok(Message::Close(_)) => {
-                    tracing::info!("WebSocket connection closed for user {}", username);
-                    break;
-                }
-                Err(e) => {
-                    tracing::error!("WebSocket error for user {}: {}", username, e);
-                    break;
-                }
-                _ => {}
-            }
+// POST /chat/rooms/:room_id/leave - Leave a room
+async fn leave_room_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(room_id): Path<String>,
+    Json(mut request): Json<MembershipRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.user_id();
+    match handlers::leave_room_handler(state.store.as_ref(), room_id, request).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => {
+            tracing::error!("Failed to leave room: {}", err);
+            Err(err.into())
         }
     }
+}
 
-    tracing::info!("WebSocket disconnected: {} ({}) from room {}", username, user_id, room_id);
+// POST /chat/rooms/:room_id/moderation - Mute, kick or ban a user (owners/moderators)
+async fn moderate_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(room_id): Path<String>,
+    Json(mut request): Json<ModerateRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.user_id();
+    let outcome = match handlers::moderate_handler(state.store.as_ref(), room_id, request).await {
+        Ok(outcome) => outcome,
+        Err(err) => {
+            tracing::error!("Failed to moderate: {}", err);
+            return Err(err.into());
+        }
+    };
 
-    // Cleanup dev connection mapping and DynamoDB record
+    // Dropping a dev connection's sender ends its socket loop
     #[cfg(feature = "dev")]
     {
-        use aws_sdk_dynamodb::types::AttributeValue;
-        state.conn_senders.write().await.remove(&connection_id);
-        if let Err(e) = state
-            .ddb
-            .delete_item()
-            .table_name(&*CHAT_CONNECTIONS_TABLE)
-            .key("connection_id", AttributeValue::S(connection_id))
-            .send()
-            .await
-        {
-            tracing::warn!("Failed to delete dev connection record: {:?}", e);
+        let mut senders = state.conn_senders.write().await;
+        for connection in &outcome.closed {
+            senders.remove(&connection.connection_id);
         }
     }
+    Ok(Json(outcome.response))
 }
 
-// Dev-only: Per-connection send endpoint for broadcaster Lambda to push to a specific connection
-#[cfg(feature = "dev")]
-async fn dev_conn_send_handler(
+// GET /chat/rooms/:room_id/moderation - A room's active mutes and bans (owners/moderators)
+async fn list_sanctions_handler(
     State(state): State<AppState>,
-    Path(connection_id): Path<String>,
-    Json(message): Json<ChatMessage>,
+    caller: Caller,
+    Path(room_id): Path<String>,
+    Query(mut query): Query<ListSanctionsQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    let payload = serde_json::to_string(&message).map_err(AppError::from_error)?;
-
-    let maybe_sender = { state.conn_senders.read().await.get(&connection_id).cloned() };
-    if let Some(sender) = maybe_sender {
-        if let Err(_e) = sender.send(payload).await {
-            return Ok((StatusCode::GONE, Json(json!({ "status": "gone" }))));
+    query.user_id = caller.user_id();
+    match handlers::list_sanctions_handler(state.store.as_ref(), room_id, query).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            tracing::error!("Failed to list sanctions: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// GET /chat/reports?room_id=&status= - Reports in the rooms the caller moderates, oldest first
+async fn list_reports_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(mut query): Query<ListReportsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.user_id();
+    match handlers::list_reports_handler(state.store.as_ref(), query).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            tracing::error!("Failed to list reports: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// POST /chat/reports/:room_id/:report_id/resolve - Dismiss a report or delete the message
+// (owners/moderators)
+async fn resolve_report_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path((room_id, report_id)): Path<(String, String)>,
+    Json(mut request): Json<ResolveReportRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.user_id();
+    match handlers::resolve_report_handler(state.store.as_ref(), room_id, report_id, request).await
+    {
+        Ok(response) => {
+            #[cfg(feature = "dev")]
+            if let Some(message) = &response.message {
+                relay_change(&state, ServerEvent::MessageDeleted { message: message.clone() })
+                    .await;
+            }
+            Ok(Json(response))
+        }
+        Err(err) => {
+            tracing::error!("Failed to resolve report: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// GET /chat/dms?user_id= - A user's direct messages, most recently active first
+async fn list_direct_messages_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(mut query): Query<ListDirectMessagesQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.user_id();
+    match handlers::list_direct_messages_handler(state.store.as_ref(), query).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            tracing::error!("Failed to list direct messages: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// POST /chat/dms - Open (or fetch) the direct message room between two users
+async fn open_direct_message_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(mut request): Json<OpenDirectMessageRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.user_id();
+    tracing::info!(
+        "Opening direct message between {} and {}",
+        request.user_id,
+        request.other_user_id
+    );
+
+    match handlers::open_direct_message_handler(state.store.as_ref(), request).await {
+        Ok(conversation) => Ok(Json(conversation)),
+        Err(err) => {
+            tracing::error!("Failed to open direct message: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// POST /chat/messages - Send a new message
+async fn post_message_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(mut request): Json<SendMessageRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.user_id();
+    request.username = caller.0.username.clone();
+    tracing::info!("Received message request for room: {}", request.room_id);
+
+    match handlers::post_message_handler(state.store.as_ref(), &state.rate_limits, request).await {
+        // Retried post - return the original without counting it again
+        Ok(posted) if posted.replayed => Ok((StatusCode::OK, Json(posted.message))),
+        Ok(posted) => {
+            let message = posted.message;
+            // Emit metrics for REST message post
+            state.metrics.emit_message_sent(&message.room_id, message.message_text.len()).await;
+            #[cfg(feature = "dev")]
+            relay_change(&state, ServerEvent::MessageNew { message: message.clone() }).await;
+            Ok((StatusCode::CREATED, Json(message)))
+        }
+        Err(err) => {
+            tracing::error!("Failed to post message: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// GET /chat/messages/:room_id - Retrieve a page of messages (?before=&after=&limit=&order=)
+async fn get_messages_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(room_id): Path<String>,
+    Query(mut query): Query<GetMessagesQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.viewer();
+    tracing::info!("Retrieving messages for room: {}", room_id);
+
+    match handlers::get_messages_handler(state.store.as_ref(), room_id, query).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            tracing::error!("Failed to get messages: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// GET /chat/messages/:room_id/:message_id/replies - A thread root and a page of its replies
+async fn get_thread_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path((room_id, message_id)): Path<(String, String)>,
+    Query(mut query): Query<GetMessagesQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.viewer();
+    tracing::info!("Retrieving thread {} in room {}", message_id, room_id);
+
+    match handlers::get_thread_handler(state.store.as_ref(), room_id, message_id, query).await {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            tracing::error!("Failed to get thread: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// PATCH /chat/messages/:room_id/:message_id - Edit a message's text (author only)
+async fn edit_message_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path((room_id, message_id)): Path<(String, String)>,
+    Json(mut request): Json<EditMessageRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.user_id();
+    tracing::info!("Editing message {} in room {}", message_id, room_id);
+
+    match handlers::edit_message_handler(state.store.as_ref(), room_id, message_id, request).await {
+        Ok(message) => {
+            #[cfg(feature = "dev")]
+            relay_change(&state, ServerEvent::MessageEdited { message: message.clone() }).await;
+            Ok(Json(message))
+        }
+        Err(err) => {
+            tracing::error!("Failed to edit message: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// DELETE /chat/messages/:room_id/:message_id?user_id= - Tombstone a message
+// (author, or a room owner/moderator)
+async fn delete_message_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path((room_id, message_id)): Path<(String, String)>,
+    Query(mut query): Query<DeleteMessageQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.user_id();
+    tracing::info!("Deleting message {} in room {}", message_id, room_id);
+
+    match handlers::delete_message_handler(state.store.as_ref(), room_id, message_id, query).await {
+        Ok(message) => {
+            #[cfg(feature = "dev")]
+            relay_change(&state, ServerEvent::MessageDeleted { message: message.clone() }).await;
+            Ok(Json(message))
+        }
+        Err(err) => {
+            tracing::error!("Failed to delete message: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// POST /chat/messages/:room_id/:message_id/report - Report a message to the room's moderators
+async fn report_message_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path((room_id, message_id)): Path<(String, String)>,
+    Json(mut request): Json<ReportMessageRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request.user_id = caller.user_id();
+    match handlers::report_message_handler(state.store.as_ref(), room_id, message_id, request).await
+    {
+        Ok(report) => Ok((StatusCode::CREATED, Json(report))),
+        Err(err) => {
+            tracing::error!("Failed to report message: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// POST /chat/messages/:room_id/:message_id/reactions/:emoji?user_id= - React to a message
+async fn add_reaction_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path((room_id, message_id, emoji)): Path<(String, String, String)>,
+    Query(mut query): Query<ReactionQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.user_id();
+    tracing::info!("Adding reaction {} to message {} in room {}", emoji, message_id, room_id);
+    #[cfg(feature = "dev")]
+    let change = reaction_change(&room_id, &message_id, &emoji, &query.user_id);
+
+    match handlers::add_reaction_handler(state.store.as_ref(), room_id, message_id, emoji, query)
+        .await
+    {
+        Ok(message) => {
+            #[cfg(feature = "dev")]
+            relay_change(&state, ServerEvent::ReactionAdded(change)).await;
+            Ok(Json(message))
+        }
+        Err(err) => {
+            tracing::error!("Failed to add reaction: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// DELETE /chat/messages/:room_id/:message_id/reactions/:emoji?user_id= - Take a reaction back
+async fn remove_reaction_handler(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path((room_id, message_id, emoji)): Path<(String, String, String)>,
+    Query(mut query): Query<ReactionQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    query.user_id = caller.user_id();
+    tracing::info!("Removing reaction {} from message {} in room {}", emoji, message_id, room_id);
+    #[cfg(feature = "dev")]
+    let change = reaction_change(&room_id, &message_id, &emoji, &query.user_id);
+
+    match handlers::remove_reaction_handler(state.store.as_ref(), room_id, message_id, emoji, query)
+        .await
+    {
+        Ok(message) => {
+            #[cfg(feature = "dev")]
+            relay_change(&state, ServerEvent::ReactionRemoved(change)).await;
+            Ok(Json(message))
+        }
+        Err(err) => {
+            tracing::error!("Failed to remove reaction: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// Deployed, the broadcast lambda tells sockets about message changes off the table's
+// stream. The memory and SQLite stores have none, so the route that made the change
+// sends it to the room's dev channel instead.
+#[cfg(feature = "dev")]
+async fn relay_change(state: &AppState, event: ServerEvent) {
+    if state.store.streams_changes() {
+        return;
+    }
+    let room_id = match &event {
+        ServerEvent::MessageNew { message }
+        | ServerEvent::MessageEdited { message }
+        | ServerEvent::MessageDeleted { message } => &message.room_id,
+        ServerEvent::ReactionAdded(change) | ServerEvent::ReactionRemoved(change) => {
+            &change.room_id
+        }
+        _ => return,
+    };
+    if let Some(tx) = state.channels.read().await.get(room_id) {
+        let _ = tx.send(event.into());
+    }
+}
+
+// The reaction frame for a request, emoji trimmed as the handler stores it
+#[cfg(feature = "dev")]
+fn reaction_change(room_id: &str, message_id: &str, emoji: &str, user_id: &str) -> ReactionChange {
+    ReactionChange {
+        room_id: room_id.to_string(),
+        message_id: message_id.to_string(),
+        emoji: emoji.trim().to_string(),
+        user_id: user_id.to_string(),
+    }
+}
+
+// WebSocket query parameters
+#[derive(Debug, Deserialize)]
+struct WebSocketParams {
+    room_id: Option<String>,
+}
+
+// WebSocket handler for development
+async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    Query(params): Query<WebSocketParams>,
+    State(state): State<AppState>,
+    caller: Caller,
+) -> Response {
+    let room_id = match handlers::validate_room_id(params.room_id.as_deref().unwrap_or("general")) {
+        Ok(room_id) => room_id,
+        Err(err) => return AppError::from(HandlerError::from(err)).into_response(),
+    };
+    let Identity { user_id, username } = caller.0;
+
+    // Refuse before upgrading so private rooms never see a non-member socket
+    if let Err(err) =
+        handlers::authorize_room_access(state.store.as_ref(), &room_id, Some(&user_id)).await
+    {
+        tracing::warn!("Rejected WebSocket for {} in room {}: {}", user_id, room_id, err);
+        return AppError::from(err).into_response();
+    }
+
+    tracing::info!(
+        "WebSocket connection request: room={}, user={}, username={}",
+        room_id,
+        user_id,
+        username
+    );
+
+    ws.on_upgrade(move |socket| handle_websocket(socket, room_id, user_id, username, state))
+}
+
+// WebSocket connection handler
+async fn handle_websocket(
+    mut socket: WebSocket,
+    room_id: String,
+    user_id: String,
+    username: String,
+    state: AppState,
+) {
+    tracing::info!("WebSocket connected: {} ({}) in room {}", username, user_id, room_id);
+
+    // Who frames on this socket come from, and the room they go to
+    let connection_id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().timestamp_millis();
+    let connection = Connection::new(&connection_id, &room_id, &user_id, &username, now);
+
+    #[cfg(feature = "dev")]
+    let tx = {
+        let mut channels = state.channels.write().await;
+        if let Some(existing) = channels.get(&room_id) {
+            existing.clone()
+        } else {
+            let (tx, _rx) = broadcast::channel::<ServerFrame>(100);
+            channels.insert(room_id.clone(), tx.clone());
+            tx
+        }
+    };
+
+    #[cfg(feature = "dev")]
+    let mut rx = tx.subscribe();
+
+    // For development, create a per-connection sender and store connection in DynamoDB
+    #[cfg(feature = "dev")]
+    let (conn_tx, mut conn_rx) = mpsc::channel::<ServerFrame>(100);
+    #[cfg(feature = "dev")]
+    {
+        state.conn_senders.write().await.insert(connection_id.clone(), conn_tx);
+
+        // Compute public push URL (for broadcaster Lambda to call)
+        let base = DEV_PUBLIC_BASE_URL.clone();
+        let base = base.as_deref().unwrap_or("http://localhost:3001");
+        let push_url = format!("{}/dev/conn/{}/send", base.trim_end_matches('/'), connection_id);
+
+        // Write connection record to the store
+        let record = Connection {
+            domain: "local".to_string(),
+            stage: "local".to_string(),
+            transport: "dev".to_string(),
+            push_url: Some(push_url),
+            ..connection.clone()
+        };
+
+        if let Err(e) = state.store.put_connection(&record).await {
+            tracing::error!("Failed to write dev connection record: {:?}", e);
+        }
+
+        // Everyone else in the room sees a first tab come online
+        match ws::presence_change(state.store.as_ref(), &connection, true).await {
+            Ok(Some(joined)) => {
+                let _ = tx.send(joined);
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to check presence in room {}: {:?}", room_id, e),
+        }
+    }
+
+    // Handle incoming messages
+    #[cfg(feature = "dev")]
+    {
+        // When this user's typing indicator lapses unless they keep typing
+        let mut typing_until: Option<tokio::time::Instant> = None;
+
+        loop {
+            tokio::select! {
+                // Outbound server -> client messages (room fan-out)
+                received = rx.recv() => {
+                    match received {
+                        Ok(frame) if ws::skips(&frame, &connection) => {}
+                        Ok(frame) => {
+                            // Held to the same audience as the lambdas' pushes, so a socket left
+                            // open after leaving a private room hears nothing more from it
+                            let audience = handlers::room_audience(state.store.as_ref(), &room_id, vec![connection.clone()]).await;
+                            match audience {
+                                Ok(audience) if audience.is_empty() => {}
+                                Ok(_) => {
+                                    if let Err(e) = send_frame(&mut socket, &frame).await {
+                                        tracing::warn!("Failed to send to {} in room {}: {}", username, room_id, e);
+                                        break;
+                                    }
+                                }
+                                Err(e) => tracing::warn!("Failed to check audience of room {}: {:?}", room_id, e),
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            tracing::info!("Broadcast channel closed for room {}", room_id);
+                            break;
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            tracing::warn!("WebSocket for user {} lagged by {} messages in room {}", username, skipped, room_id);
+                        }
+                    }
+                }
+                // Targeted per-connection push
+                msg_to_send = conn_rx.recv() => {
+                    if let Some(frame) = msg_to_send {
+                        if let Err(e) = send_frame(&mut socket, &frame).await {
+                            tracing::warn!("Failed to send targeted message to {}: {}", username, e);
+                            break;
+                        }
+                    } else {
+                        // Sender dropped
+                        break;
+                    }
+                }
+                // Inbound client -> server frames, each answered on this socket
+                msg = socket.recv() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            let handled = ws::handle_frame(state.store.as_ref(), &state.rate_limits, &state.typing, &connection, &text).await;
+                            // Nothing streams the messages table here, so count posts as REST does
+                            if let Some(message) = handled.posted {
+                                state.metrics.emit_message_sent(&message.room_id, message.message_text.len()).await;
+                                relay_change(&state, ServerEvent::MessageNew { message }).await;
+                            }
+                            if let Some(frame) = handled.broadcast {
+                                if let ServerEvent::Typing(typing) = &frame.event {
+                                    typing_until = typing.expires_in_secs.map(|secs| {
+                                        tokio::time::Instant::now() + std::time::Duration::from_secs(secs)
+                                    });
+                                }
+                                // No receivers just means nobody else is here
+                                let _ = tx.send(frame);
+                            }
+                            if let Some(reply) = handled.reply {
+                                if let Err(e) = send_frame(&mut socket, &reply).await {
+                                    tracing::warn!("Failed to reply to {}: {}", username, e);
+                                    break;
+                                }
+                            }
+                        }
+                        Some(Ok(Message::Close(_))) | None => {
+                            tracing::info!("WebSocket connection closed for user {}", username);
+                            break;
+                        }
+                        Some(Err(e)) => {
+                            tracing::error!("WebSocket error for user {}: {}", username, e);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                // No typing.start for a while: tell the room they stopped
+                _ = tokio::time::sleep_until(typing_until.unwrap_or_else(tokio::time::Instant::now)), if typing_until.is_some() => {
+                    typing_until = None;
+                    let _ = tx.send(ws::typing_frame(&connection, false));
+                }
+            }
+        }
+
+        // Leaving mid-sentence ends the indicator too
+        if typing_until.is_some() {
+            let _ = tx.send(ws::typing_frame(&connection, false));
+        }
+    }
+
+    #[cfg(not(feature = "dev"))]
+    {
+        // Minimal loop: answer client frames until the socket closes. Without the dev
+        // channels there is no room to relay typing indicators to.
+        while let Some(msg) = socket.recv().await {
+            match msg {
+                Ok(Message::Text(text)) => {
+                    let handled = ws::handle_frame(
+                        state.store.as_ref(),
+                        &state.rate_limits,
+                        &state.typing,
+                        &connection,
+                        &text,
+                    )
+                    .await;
+                    if let Some(message) = &handled.posted {
+                        state
+                            .metrics
+                            .emit_message_sent(&message.room_id, message.message_text.len())
+                            .await;
+                    }
+                    let Some(reply) = handled.reply else {
+                        continue;
+                    };
+                    if let Err(e) = send_frame(&mut socket, &reply).await {
+                        tracing::warn!("Failed to reply to {}: {}", username, e);
+                        break;
+                    }
+                }
+                Ok(Message::Close(_)) => {
+                    tracing::info!("WebSocket connection closed for user {}", username);
+                    break;
+                }
+                Err(e) => {
+                    tracing::error!("WebSocket error for user {}: {}", username, e);
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    tracing::info!("WebSocket disconnected: {} ({}) from room {}", username, user_id, room_id);
+
+    // Cleanup dev connection mapping and connection record
+    #[cfg(feature = "dev")]
+    {
+        state.conn_senders.write().await.remove(&connection_id);
+        if let Err(e) = state.store.delete_connection(&connection_id).await {
+            tracing::warn!("Failed to delete dev connection record: {:?}", e);
+        }
+        // Closing the user's last tab in the room takes them offline
+        match ws::presence_change(state.store.as_ref(), &connection, false).await {
+            Ok(Some(left)) => {
+                let _ = tx.send(left);
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to check presence in room {}: {:?}", room_id, e),
+        }
+    }
+}
+
+// Push one protocol frame to a client
+async fn send_frame(socket: &mut WebSocket, frame: &ServerFrame) -> Result<(), axum::Error> {
+    let payload = serde_json::to_string(frame).map_err(axum::Error::new)?;
+    socket.send(Message::Text(payload)).await
+}
+
+// Dev-only: Per-connection send endpoint for broadcaster Lambda to push to a specific connection
+#[cfg(feature = "dev")]
+async fn dev_conn_send_handler(
+    State(state): State<AppState>,
+    Path(connection_id): Path<String>,
+    Json(frame): Json<ServerFrame>,
+) -> Result<impl IntoResponse, AppError> {
+    let maybe_sender = { state.conn_senders.read().await.get(&connection_id).cloned() };
+    if let Some(sender) = maybe_sender {
+        if let Err(_e) = sender.send(frame).await {
+            return Ok((StatusCode::GONE, Json(json!({ "status": "gone" }))));
         }
         Ok((StatusCode::OK, Json(json!({ "status": "ok" }))))
     } else {
         Ok((StatusCode::NOT_FOUND, Json(json!({ "status": "not_found" }))))
     }
-}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::{Body, HttpBody},
+        http::{Method, Request, StatusCode},
+    };
+    use backend::auth::{ApiKeys, JwtVerifier, TokenIssuer};
+    use backend::rate_limit::RateLimit;
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+    use types::{GetMessagesResponse, GetThreadResponse, RoomMember, RoomRole};
+
+    const TEST_SECRET: &[u8] = b"test-secret";
+
+    async fn test_state() -> AppState {
+        let metrics = backend::MetricsHelper::new().await;
+        AppState {
+            store: Arc::new(MemoryStore::new()),
+            metrics,
+            auth: AuthChain::new().with_tokens(TokenIssuer::new(TEST_SECRET, 300)),
+            rate_limits: PostRateLimiter::unlimited(),
+            typing: TypingThrottle::new(
+                Arc::new(MemoryRateLimiter::new()),
+                TypingThrottle::DEFAULT,
+            ),
+            #[cfg(feature = "dev")]
+            channels: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            #[cfg(feature = "dev")]
+            conn_senders: Arc::new(RwLock::new(std::collections::HashMap::new())),
+        }
+    }
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    fn json_request(method: Method, uri: &str, body: serde_json::Value) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn post_json(uri: &str, body: serde_json::Value) -> Request<Body> {
+        json_request(Method::POST, uri, body)
+    }
+
+    // Most tests say who they act as through a user_id in the body or query string, the way
+    // clients did before authentication. Sign those requests in as that user.
+    fn signed_in(app: Router) -> Router {
+        app.layer(axum::middleware::map_request(sign_in_claimed_user))
+    }
+
+    async fn sign_in_claimed_user(request: Request<Body>) -> Request<Body> {
+        if request.headers().contains_key("authorization") {
+            return request;
+        }
+        let (mut parts, mut body) = request.into_parts();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap_or_default();
+        let params = Query::<HashMap<String, String>>::try_from_uri(&parts.uri)
+            .map(|query| query.0)
+            .unwrap_or_default();
+        let user_id = json["user_id"]
+            .as_str()
+            .or(params.get("user_id").map(String::as_str))
+            .unwrap_or("tester")
+            .to_string();
+        let username = json["username"].as_str().unwrap_or(&user_id).to_string();
+
+        let token = TokenIssuer::new(TEST_SECRET, 300)
+            .issue(&Identity { user_id, username })
+            .unwrap()
+            .token;
+        parts.headers.insert("authorization", format!("Bearer {}", token).parse().unwrap());
+        Request::from_parts(parts, Body::from(bytes))
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoint() {
+        let app = signed_in(create_app(test_state().await));
+
+        let response = app
+            .oneshot(
+                Request::builder().method(Method::GET).uri("/health").body(Body::empty()).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let health: HealthCheck = serde_json::from_value(body_json(response).await).unwrap();
+        assert_eq!(health.version, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[tokio::test]
+    async fn test_post_then_get_messages() {
+        let app = signed_in(create_app(test_state().await));
+
+        for text in ["first", "second"] {
+            let response = app
+                .clone()
+                .oneshot(post_json(
+                    "/chat/messages",
+                    json!({
+                        "room_id": " General ",
+                        "user_id": "user-1",
+                        "username": "alice",
+                        "message_text": text,
+                        "client_message_id": null
+                    }),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri("/chat/messages/general")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let page: GetMessagesResponse = serde_json::from_value(body_json(response).await).unwrap();
+        assert_eq!(page.room_id, "general");
+        let texts: Vec<&str> = page.messages.iter().map(|m| m.message_text.as_str()).collect();
+        assert_eq!(texts, vec!["first", "second"]);
+        assert_eq!(page.next_cursor, None);
+
+        // Latest first, one per page
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri("/chat/messages/general?limit=1&order=desc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let page: GetMessagesResponse = serde_json::from_value(body_json(response).await).unwrap();
+        assert_eq!(page.messages[0].message_text, "second");
+        let cursor = page.next_cursor.expect("expected another page");
+
+        let uri = format!("/chat/messages/general?limit=1&order=desc&before={}", cursor);
+        let response = app
+            .oneshot(Request::builder().method(Method::GET).uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let page: GetMessagesResponse = serde_json::from_value(body_json(response).await).unwrap();
+        assert_eq!(page.messages[0].message_text, "first");
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn test_retried_post_is_deduplicated() {
+        let app = signed_in(create_app(test_state().await));
+        let request = json!({
+            "room_id": "general",
+            "user_id": "user-1",
+            "username": "alice",
+            "message_text": "hello",
+            "client_message_id": "client-1"
+        });
+
+        let response =
+            app.clone().oneshot(post_json("/chat/messages", request.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let original = body_json(response).await;
+
+        let response = app.clone().oneshot(post_json("/chat/messages", request)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["id"], original["id"]);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri("/chat/messages/general")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let page: GetMessagesResponse = serde_json::from_value(body_json(response).await).unwrap();
+        assert_eq!(page.messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_posting_is_rate_limited() {
+        let state = AppState {
+            rate_limits: PostRateLimiter::new(
+                Arc::new(MemoryRateLimiter::new()),
+                Some(RateLimit::new(2, 1)),
+                None,
+            ),
+            ..test_state().await
+        };
+        let app = signed_in(create_app(state));
+        let post = |user_id: &str, client_message_id: Option<&str>, parent_id: Option<&str>| {
+            post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": "general",
+                    "user_id": user_id,
+                    "username": user_id,
+                    "message_text": "hi",
+                    "client_message_id": client_message_id,
+                    "parent_id": parent_id
+                }),
+            )
+        };
+        let post_as = |user_id: &str| post(user_id, None, None);
+
+        // Retries and rejected posts cost nothing
+        let response = app.clone().oneshot(post("alice", Some("m-1"), None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let retry = || post("alice", Some("m-1"), None);
+        let response = app.clone().oneshot(retry()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(post("alice", None, Some("nope"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app.clone().oneshot(post_as("alice")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = app.clone().oneshot(post_as("alice")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "60");
+        // Even out of tokens, a retry gets its original back
+        let response = app.clone().oneshot(retry()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Each user has their own bucket
+        let response = app.oneshot(post_as("bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_content_filters() {
+        let app = signed_in(create_app(test_state().await));
+        let post = |text: &str| {
+            post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": "general",
+                    "user_id": "alice",
+                    "username": "alice",
+                    "message_text": text,
+                    "client_message_id": null
+                }),
+            )
+        };
+
+        // The default chain shortens long runs of one character and records that it did
+        let response = app.clone().oneshot(post(&format!("no{}", "o".repeat(40)))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let message = body_json(response).await;
+        assert_eq!(message["message_text"], format!("n{}", "o".repeat(20)));
+        assert_eq!(
+            message["filter"],
+            json!({ "action": "mask", "filters": ["repeated_characters"] })
+        );
+
+        // ... and refuses mass mentions outright
+        let mentions: Vec<String> = (0..11).map(|i| format!("@user{}", i)).collect();
+        let response = app.clone().oneshot(post(&mentions.join(" "))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_json(response).await["error"], "Message rejected by the mentions filter");
+
+        let response = app.oneshot(post("hello")).await.unwrap();
+        assert_eq!(body_json(response).await["filter"], json!(null));
+    }
+
+    #[tokio::test]
+    async fn test_post_message_rejects_empty_text() {
+        let app = signed_in(create_app(test_state().await));
+
+        let response = app
+            .oneshot(post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": "general",
+                    "user_id": "user-1",
+                    "username": "alice",
+                    "message_text": "   ",
+                    "client_message_id": null
+                }),
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_json(response).await["error"], "Message text cannot be empty");
+    }
+
+    #[tokio::test]
+    async fn test_edit_message() {
+        let app = signed_in(create_app(test_state().await));
+        let response = app
+            .clone()
+            .oneshot(post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": "general",
+                    "user_id": "user-1",
+                    "username": "alice",
+                    "message_text": "helo",
+                    "client_message_id": null
+                }),
+            ))
+            .await
+            .unwrap();
+        let id = body_json(response).await["id"].as_str().unwrap().to_string();
+        let uri = format!("/chat/messages/general/{}", id);
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                Method::PATCH,
+                &uri,
+                json!({ "user_id": "user-2", "message_text": "hijacked" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                Method::PATCH,
+                "/chat/messages/general/01ARZ3NDEKTSV4RRFFQ69G5FAV",
+                json!({ "user_id": "user-1", "message_text": "hello" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                Method::PATCH,
+                &uri,
+                json!({ "user_id": "user-1", "message_text": " hello " }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let edited = body_json(response).await;
+        assert_eq!(edited["message_text"], "hello");
+        assert!(edited["edited_at"].is_string());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri("/chat/messages/general")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let page: GetMessagesResponse = serde_json::from_value(body_json(response).await).unwrap();
+        assert_eq!(page.messages[0].message_text, "hello");
+        assert!(page.messages[0].edited_at.is_some());
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::{
-        body::Body,
-        http::{Method, Request, StatusCode},
-    };
-    use backend::handlers::Tables;
-    // use http_body_util::BodyExt; // Unused due to test simplification
-    use tower::ServiceExt;
+    #[tokio::test]
+    async fn test_delete_message_by_author_or_moderator() {
+        let state = test_state().await;
+        let store = state.store.clone();
+        let app = signed_in(create_app(state));
+
+        let mut ids = Vec::new();
+        for text in ["first", "second"] {
+            let response = app
+                .clone()
+                .oneshot(post_json(
+                    "/chat/messages",
+                    json!({
+                        "room_id": "general",
+                        "user_id": "user-1",
+                        "username": "alice",
+                        "message_text": text,
+                        "client_message_id": null
+                    }),
+                ))
+                .await
+                .unwrap();
+            ids.push(body_json(response).await["id"].as_str().unwrap().to_string());
+        }
+        let delete = |id: &str, user_id: &str| {
+            Request::builder()
+                .method(Method::DELETE)
+                .uri(format!("/chat/messages/general/{}?user_id={}", id, user_id))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Another member cannot delete
+        let response = app.clone().oneshot(delete(&ids[0], "user-2")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // The author can
+        let response = app.clone().oneshot(delete(&ids[0], "user-1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_json(response).await["deleted_at"].is_string());
+
+        // So can a moderator
+        store
+            .put_member(&RoomMember {
+                room_id: "general".to_string(),
+                user_id: "mod-1".to_string(),
+                role: RoomRole::Moderator,
+                joined_at: chrono::Utc::now(),
+            })
+            .await
+            .unwrap();
+        let response = app.clone().oneshot(delete(&ids[1], "mod-1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri("/chat/messages/general")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let page: GetMessagesResponse = serde_json::from_value(body_json(response).await).unwrap();
+        assert_eq!(page.messages.len(), 2);
+        assert!(page
+            .messages
+            .iter()
+            .all(|m| m.deleted_at.is_some() && m.message_text == "[deleted]"));
+    }
 
     #[tokio::test]
-    #[ignore] // TODO: Fix body collection issue
-    async fn test_health_endpoint() {
-        // Create a mock DynamoDB client for testing
-        let aws_config = aws_config::defaults(aws_config::BehaviorVersion::latest()).load().await;
-        let ddb_client = DynamoDbClient::new(&aws_config);
-        let metrics = backend::MetricsHelper::new().await;
-        let state = AppState {
-            ddb: ddb_client,
-            tables: Tables {
-                messages: "chat-messages".to_string(),
-                rooms: "chat-rooms".to_string(),
-            },
-            metrics,
+    async fn test_reactions() {
+        let app = signed_in(create_app(test_state().await));
+
+        let response = app
+            .clone()
+            .oneshot(post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": "general",
+                    "user_id": "user-1",
+                    "username": "alice",
+                    "message_text": "hello",
+                    "client_message_id": null
+                }),
+            ))
+            .await
+            .unwrap();
+        let id = body_json(response).await["id"].as_str().unwrap().to_string();
+        let react = |method: Method, emoji: &str, user_id: &str| {
+            Request::builder()
+                .method(method)
+                .uri(format!(
+                    "/chat/messages/general/{}/reactions/{}?user_id={}",
+                    id, emoji, user_id
+                ))
+                .body(Body::empty())
+                .unwrap()
         };
 
-        let app = create_app(state);
+        // 👍 percent-encoded, as clients send it
+        let thumbs_up = "%F0%9F%91%8D";
+        for user_id in ["user-1", "user-2", "user-2"] {
+            let response =
+                app.clone().oneshot(react(Method::POST, thumbs_up, user_id)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = app.clone().oneshot(react(Method::POST, "plus1", "user-1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response =
+            app.clone().oneshot(react(Method::DELETE, thumbs_up, "user-1")).await.unwrap();
+        let message = body_json(response).await;
+        assert_eq!(message["reactions"][0]["count"], 1);
+        assert_eq!(message["reactions"][0]["reacted_by_me"], false);
 
         let response = app
             .oneshot(
-                Request::builder().method(Method::GET).uri("/health").body(Body::empty()).unwrap(),
+                Request::builder()
+                    .method(Method::GET)
+                    .uri("/chat/messages/general?user_id=user-2")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let page: GetMessagesResponse = serde_json::from_value(body_json(response).await).unwrap();
+        let reaction = &page.messages[0].reactions[0];
+        assert_eq!((reaction.emoji.as_str(), reaction.count), ("👍", 1));
+        assert!(reaction.reacted_by_me);
+    }
+
+    #[tokio::test]
+    async fn test_thread_replies() {
+        let app = signed_in(create_app(test_state().await));
+        let post = |text: &str, parent_id: Option<&str>| {
+            post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": "general",
+                    "user_id": "user-1",
+                    "username": "alice",
+                    "message_text": text,
+                    "client_message_id": null,
+                    "parent_id": parent_id
+                }),
+            )
+        };
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(post("root", None)).await.unwrap();
+        let root_id = body_json(response).await["id"].as_str().unwrap().to_string();
+        let mut reply_ids = Vec::new();
+        for text in ["one", "two"] {
+            let response = app.clone().oneshot(post(text, Some(&root_id))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            reply_ids.push(body_json(response).await["id"].as_str().unwrap().to_string());
+        }
+
+        // No replies to replies, and no replies to unknown messages
+        let response = app.clone().oneshot(post("nested", Some(&reply_ids[0]))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = app.clone().oneshot(post("lost", Some("01ARZ3NDEKTSV4RRFFQ69G5FAV"))).await;
+        assert_eq!(response.unwrap().status(), StatusCode::NOT_FOUND);
+
+        // The room timeline only has the root, with its thread summary
+        let response = app.clone().oneshot(get("/chat/messages/general")).await.unwrap();
+        let page: GetMessagesResponse = serde_json::from_value(body_json(response).await).unwrap();
+        assert_eq!(page.messages.len(), 1);
+        assert_eq!(page.messages[0].reply_count, 2);
+        assert_eq!(page.messages[0].last_reply_at, Some(ulid_time(&reply_ids[1])));
+
+        let uri = format!("/chat/messages/general/{}/replies?limit=1", root_id);
+        let response = app.clone().oneshot(get(&uri)).await.unwrap();
+        let thread: GetThreadResponse = serde_json::from_value(body_json(response).await).unwrap();
+        assert_eq!(thread.root.id, root_id);
+        assert_eq!(thread.replies[0].id, reply_ids[0]);
+        assert_eq!(thread.next_cursor.as_ref(), Some(&reply_ids[0]));
+
+        let uri = format!("/chat/messages/general/{}/replies", reply_ids[0]);
+        let response = app.oneshot(get(&uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn ulid_time(id: &str) -> chrono::DateTime<chrono::Utc> {
+        ulid::Ulid::from_string(id).unwrap().datetime().into()
+    }
+
+    #[tokio::test]
+    async fn test_room_management() {
+        let app = signed_in(create_app(test_state().await));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(post_json(
+                "/chat/rooms",
+                json!({ "id": "rust", "name": "Rust", "description": null, "topic": "Borrowck" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = app
+            .clone()
+            .oneshot(post_json(
+                "/chat/rooms",
+                json!({ "id": "rust", "name": "Again", "description": null, "topic": null }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // Without an id the slug comes from the name; malformed ids are rejected
+        let response = app
+            .clone()
+            .oneshot(post_json("/chat/rooms", json!({ "name": "Go & Zig!", "topic": null })))
+            .await
+            .unwrap();
+        assert_eq!(body_json(response).await["id"], "go-zig");
+        let response = app.clone().oneshot(get("/chat/messages/not%20a%20slug")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let post = || {
+            post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": "rust",
+                    "user_id": "user-1",
+                    "username": "alice",
+                    "message_text": "hello?",
+                    "client_message_id": null
+                }),
+            )
+        };
+        let response = app.clone().oneshot(post()).await.unwrap();
+        let id = body_json(response).await["id"].as_str().unwrap().to_string();
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                Method::PATCH,
+                "/chat/rooms/rust",
+                json!({ "name": "Rustaceans", "topic": "", "archived": true }),
+            ))
+            .await
+            .unwrap();
+        let room = body_json(response).await;
+        assert_eq!(room["name"], "Rustaceans");
+        assert_eq!(room["topic"], serde_json::Value::Null);
+
+        // Archived rooms take no posts, edits or deletes and drop out of the default listing
+        let response = app.clone().oneshot(post()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                Method::PATCH,
+                &format!("/chat/messages/rust/{}", id),
+                json!({ "user_id": "user-1", "message_text": "hello!" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let delete = Request::builder()
+            .method(Method::DELETE)
+            .uri(format!("/chat/messages/rust/{}?user_id=user-1", id))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(delete).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app.clone().oneshot(get("/chat/rooms")).await.unwrap();
+        assert_eq!(body_json(response).await["rooms"][0]["id"], "go-zig");
+        let response = app.oneshot(get("/chat/rooms?include_archived=true")).await.unwrap();
+        assert_eq!(body_json(response).await["rooms"][1]["id"], "rust");
+    }
+
+    #[tokio::test]
+    async fn test_private_room_membership() {
+        let app = signed_in(create_app(test_state().await));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let post_as = |user_id: &str| {
+            post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": "staff",
+                    "user_id": user_id,
+                    "username": user_id,
+                    "message_text": "hi",
+                    "client_message_id": null
+                }),
+            )
+        };
+
+        let response = app
+            .clone()
+            .oneshot(post_json(
+                "/chat/rooms",
+                json!({ "name": "Staff", "visibility": "private", "user_id": "owner" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // Outsiders can neither read, post, join nor see the room listed
+        let response =
+            app.clone().oneshot(get("/chat/messages/staff?user_id=outsider")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(post_as("outsider")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app
+            .clone()
+            .oneshot(post_json("/chat/rooms/staff/join", json!({ "user_id": "outsider" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(get("/chat/rooms?user_id=outsider")).await.unwrap();
+        assert_eq!(body_json(response).await["rooms"], json!([]));
+
+        // Members only get in by invitation, and only staff can invite
+        let invite = |inviter: &str, role: Option<&str>| {
+            post_json(
+                "/chat/rooms/staff/members",
+                json!({ "user_id": inviter, "member_id": "bob", "role": role }),
+            )
+        };
+        let response = app.clone().oneshot(invite("outsider", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(invite("owner", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["role"], "member");
+
+        let response = app.clone().oneshot(post_as("bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = app.clone().oneshot(get("/chat/messages/staff?user_id=bob")).await.unwrap();
+        assert_eq!(body_json(response).await["messages"][0]["message_text"], "hi");
+        let response =
+            app.clone().oneshot(get("/chat/rooms/staff/members?user_id=bob")).await.unwrap();
+        let members = body_json(response).await["members"].clone();
+        assert_eq!(members[0]["user_id"], "bob");
+        assert_eq!(members[1]["role"], "owner");
+
+        // The last owner has to stay; anyone else can go and loses access
+        let response = app
+            .clone()
+            .oneshot(post_json("/chat/rooms/staff/leave", json!({ "user_id": "owner" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = app
+            .clone()
+            .oneshot(post_json("/chat/rooms/staff/leave", json!({ "user_id": "bob" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = app.oneshot(get("/chat/messages/staff?user_id=bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_room_presence() {
+        let state = test_state().await;
+        let now = chrono::Utc::now().timestamp_millis();
+        let stale = Connection::new("c4", "general", "carol", "carol", now - 25 * 60 * 60 * 1000);
+        for connection in [
+            Connection::new("c1", "general", "bob", "bob", now),
+            Connection::new("c2", "general", "alice", "alice", now - 1000),
+            Connection::new("c3", "general", "alice", "alice", now),
+            stale,
+            Connection::new("c5", "random", "dave", "dave", now),
+        ] {
+            state.store.put_connection(&connection).await.unwrap();
+        }
+        let app = signed_in(create_app(state));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let post = json!({ "room_id": "general", "user_id": "bob", "username": "bob",
+            "message_text": "hi", "client_message_id": null });
+        let response = app.clone().oneshot(post_json("/chat/messages", post)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // Two tabs are one user; an expired record is nobody
+        let response =
+            app.clone().oneshot(get("/chat/rooms/general/presence?user_id=bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let users = body_json(response).await["users"].clone();
+        assert_eq!(users.as_array().unwrap().len(), 2);
+        assert_eq!((&users[0]["user_id"], &users[0]["connections"]), (&json!("alice"), &json!(2)));
+        assert_eq!(users[1]["user_id"], "bob");
+
+        let response = app.clone().oneshot(get("/chat/rooms/nowhere/presence")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Private rooms only show who is online to members
+        let response = app
+            .clone()
+            .oneshot(post_json(
+                "/chat/rooms",
+                json!({ "name": "Staff", "visibility": "private", "user_id": "owner" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response =
+            app.clone().oneshot(get("/chat/rooms/staff/presence?user_id=bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.oneshot(get("/chat/rooms/staff/presence?user_id=owner")).await.unwrap();
+        assert_eq!(body_json(response).await["users"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_read_markers_and_unread_counts() {
+        let app = signed_in(create_app(test_state().await));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let post = |app: Router, user_id: &'static str, text: &'static str| async move {
+            let post = json!({ "room_id": "general", "user_id": user_id, "username": user_id,
+                "message_text": text, "client_message_id": null });
+            let response = app.oneshot(post_json("/chat/messages", post)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            body_json(response).await["id"].as_str().unwrap().to_string()
+        };
+        let mark = |message_id: &str| {
+            post_json(
+                "/chat/rooms/general/read",
+                json!({ "user_id": "alice", "message_id": message_id }),
+            )
+        };
+
+        let first = post(app.clone(), "alice", "one").await;
+        post(app.clone(), "bob", "two").await;
+        let third = post(app.clone(), "bob", "three").await;
+
+        // Rooms only count once the user has read them
+        let response = app.clone().oneshot(get("/chat/unread?user_id=alice")).await.unwrap();
+        assert_eq!(body_json(response).await["rooms"], json!([]));
+
+        let response = app.clone().oneshot(mark(&first)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["message_id"], json!(first));
+        let response = app.clone().oneshot(get("/chat/unread?user_id=alice")).await.unwrap();
+        let rooms = body_json(response).await["rooms"].clone();
+        assert_eq!(rooms[0]["room_id"], "general");
+        assert_eq!((&rooms[0]["unread"], &rooms[0]["more"]), (&json!(2), &json!(false)));
+
+        // The user's own messages are never unread
+        post(app.clone(), "alice", "four").await;
+        let response = app.clone().oneshot(mark(&third)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(get("/chat/unread?user_id=alice")).await.unwrap();
+        assert_eq!(body_json(response).await["rooms"][0]["unread"], json!(0));
+
+        // Marking an older message leaves the marker where it is
+        let response = app.clone().oneshot(mark(&first)).await.unwrap();
+        assert_eq!(body_json(response).await["message_id"], json!(third));
+        let response =
+            app.clone().oneshot(get("/chat/rooms/general/read?user_id=bob")).await.unwrap();
+        let markers = body_json(response).await["markers"].clone();
+        assert_eq!(markers.as_array().unwrap().len(), 1);
+        assert_eq!(
+            (&markers[0]["user_id"], &markers[0]["message_id"]),
+            (&json!("alice"), &json!(third))
+        );
+
+        let response = app.clone().oneshot(mark("not-an-id")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = app.oneshot(mark(&ulid::Ulid::new().to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_moderation() {
+        let state = test_state().await;
+        let store = state.store.clone();
+        let app = signed_in(create_app(state));
+        let post_as = |user_id: &str| {
+            post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": "lobby",
+                    "user_id": user_id,
+                    "username": user_id,
+                    "message_text": "hi",
+                    "client_message_id": null
+                }),
+            )
+        };
+        let moderate = |actor: &str, target: &str, action: &str, duration: Option<u64>| {
+            post_json(
+                "/chat/rooms/lobby/moderation",
+                json!({
+                    "user_id": actor,
+                    "target_user_id": target,
+                    "action": action,
+                    "duration_secs": duration,
+                }),
+            )
+        };
+
+        let response = app
+            .clone()
+            .oneshot(post_json("/chat/rooms", json!({ "name": "Lobby", "user_id": "owner" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = app
+            .clone()
+            .oneshot(post_json(
+                "/chat/rooms/lobby/members",
+                json!({ "user_id": "owner", "member_id": "mod", "role": "moderator" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(post_as("troll")).await.unwrap();
+        let id = body_json(response).await["id"].as_str().unwrap().to_string();
+        let edit = || {
+            json_request(
+                Method::PATCH,
+                &format!("/chat/messages/lobby/{}", id),
+                json!({ "user_id": "troll", "message_text": "edited" }),
+            )
+        };
+
+        // Only staff moderate, and never the room's owners
+        let response = app.clone().oneshot(moderate("troll", "mod", "ban", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(moderate("mod", "owner", "ban", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(moderate("mod", "troll", "mute", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Muted users can neither post nor edit until unmuted
+        let response =
+            app.clone().oneshot(moderate("mod", "troll", "mute", Some(600))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_json(response).await["sanction"]["muted_until"].is_string());
+        let response = app.clone().oneshot(post_as("troll")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(edit()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(moderate("mod", "troll", "unmute", None)).await.unwrap();
+        assert_eq!(body_json(response).await["sanction"], json!(null));
+        let response = app.clone().oneshot(post_as("troll")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // Kicks close the target's connections and nobody else's
+        for (connection_id, user_id) in [("c1", "troll"), ("c2", "troll"), ("c3", "mod")] {
+            let connection =
+                backend::store::Connection::new(connection_id, "lobby", user_id, user_id, 0);
+            store.put_connection(&connection).await.unwrap();
+        }
+        let response = app.clone().oneshot(moderate("mod", "troll", "kick", None)).await.unwrap();
+        assert_eq!(body_json(response).await["disconnected"], 2);
+        let remaining = store.room_connections("lobby").await.unwrap();
+        assert_eq!(remaining.iter().map(|c| c.user_id.as_str()).collect::<Vec<_>>(), ["mod"]);
+
+        // Banned users can neither post, edit, delete nor rejoin until unbanned
+        let response = app.clone().oneshot(moderate("mod", "troll", "ban", None)).await.unwrap();
+        assert_eq!(body_json(response).await["sanction"]["banned"], true);
+        let response = app.clone().oneshot(post_as("troll")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(edit()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let delete = Request::builder()
+            .method(Method::DELETE)
+            .uri(format!("/chat/messages/lobby/{}?user_id=troll", id))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(delete).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app
+            .clone()
+            .oneshot(post_json("/chat/rooms/lobby/join", json!({ "user_id": "troll" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let list = |user_id: &str| {
+            Request::builder()
+                .uri(format!("/chat/rooms/lobby/moderation?user_id={}", user_id))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = app.clone().oneshot(list("troll")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(list("owner")).await.unwrap();
+        let sanctions = body_json(response).await["sanctions"].clone();
+        assert_eq!(sanctions[0]["user_id"], "troll");
+        assert_eq!(sanctions[0]["moderator_id"], "mod");
+
+        let response =
+            app.clone().oneshot(moderate("owner", "troll", "unban", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.oneshot(post_as("troll")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_reports() {
+        let app = signed_in(create_app(test_state().await));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let post_as = |user_id: &str| {
+            post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": "lobby",
+                    "user_id": user_id,
+                    "username": user_id,
+                    "message_text": "buy now",
+                    "client_message_id": null
+                }),
+            )
+        };
+        let report = |reporter: &str, message_id: &str, reason: &str| {
+            post_json(
+                &format!("/chat/messages/lobby/{}/report", message_id),
+                json!({ "user_id": reporter, "reason": reason }),
+            )
+        };
+        let resolve = |moderator: &str, report_id: &str, resolution: &str| {
+            post_json(
+                &format!("/chat/reports/lobby/{}/resolve", report_id),
+                json!({ "user_id": moderator, "resolution": resolution }),
             )
+        };
+
+        let response = app
+            .clone()
+            .oneshot(post_json("/chat/rooms", json!({ "name": "Lobby", "user_id": "owner" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = app
+            .clone()
+            .oneshot(post_json(
+                "/chat/rooms/lobby/members",
+                json!({ "user_id": "owner", "member_id": "mod", "role": "moderator" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let spam = body_json(app.clone().oneshot(post_as("troll")).await.unwrap()).await;
+        let spam_id = spam["id"].as_str().unwrap();
+
+        // Reports need a reason, and nobody reports their own message or reports twice
+        let response = app.clone().oneshot(report("troll", spam_id, "oops")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = app.clone().oneshot(report("alice", spam_id, "  ")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = app.clone().oneshot(report("alice", spam_id, "spam")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let first = body_json(response).await;
+        assert_eq!(first["status"], "open");
+        assert_eq!(first["message_user_id"], "troll");
+        let response = app.clone().oneshot(report("alice", spam_id, "spam")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = app.clone().oneshot(report("bob", spam_id, "ads")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // Only staff see the queue
+        let response = app.clone().oneshot(get("/chat/reports?user_id=alice")).await.unwrap();
+        assert_eq!(body_json(response).await["reports"], json!([]));
+        let response =
+            app.clone().oneshot(get("/chat/reports?user_id=alice&room_id=lobby")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response =
+            app.clone().oneshot(get("/chat/reports?user_id=mod&status=open")).await.unwrap();
+        let reports = body_json(response).await["reports"].clone();
+        assert_eq!(reports.as_array().unwrap().len(), 2);
+        assert_eq!(reports[0]["reporter_id"], "alice");
+
+        // Deleting the message closes every open report of it
+        let first_id = first["id"].as_str().unwrap();
+        let response = app.clone().oneshot(resolve("alice", first_id, "delete")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(resolve("mod", first_id, "delete")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let resolved = body_json(response).await;
+        assert_eq!(resolved["resolved"], 2);
+        assert_eq!(resolved["report"]["status"], "deleted");
+        assert_eq!(resolved["report"]["resolved_by"], "mod");
+        assert!(resolved["message"]["deleted_at"].is_string());
+        let response = app.clone().oneshot(resolve("mod", first_id, "dismiss")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = app.clone().oneshot(report("carol", spam_id, "spam")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // Dismissing leaves the message up
+        let fine = body_json(app.clone().oneshot(post_as("dave")).await.unwrap()).await;
+        let fine_id = fine["id"].as_str().unwrap();
+        let response = app.clone().oneshot(report("alice", fine_id, "rude")).await.unwrap();
+        let report_id = body_json(response).await["id"].as_str().unwrap().to_string();
+        let response = app.clone().oneshot(resolve("owner", &report_id, "dismiss")).await.unwrap();
+        let resolved = body_json(response).await;
+        assert_eq!(resolved["report"]["status"], "dismissed");
+        assert_eq!(resolved["message"], json!(null));
+
+        let response =
+            app.clone().oneshot(get("/chat/reports?user_id=owner&status=open")).await.unwrap();
+        assert_eq!(body_json(response).await["reports"], json!([]));
+        let response = app.oneshot(get("/chat/reports?user_id=mod&room_id=lobby")).await.unwrap();
+        let statuses: Vec<_> = body_json(response).await["reports"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["status"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(statuses, ["deleted", "deleted", "dismissed"]);
+    }
+
+    #[tokio::test]
+    async fn test_direct_messages() {
+        let state = test_state().await;
+        let store = state.store.clone();
+        let app = signed_in(create_app(state));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let open = |user_id: &str, other_user_id: &str| {
+            post_json("/chat/dms", json!({ "user_id": user_id, "other_user_id": other_user_id }))
+        };
+
+        let response = app.clone().oneshot(open("alice", "bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let conversation = body_json(response).await;
+        let room_id = conversation["room"]["id"].as_str().unwrap().to_string();
+        assert_eq!(conversation["room"]["kind"], "direct");
+        assert_eq!(conversation["other_user_id"], "bob");
+
+        // Either side gets the same room
+        let response = app.clone().oneshot(open("bob", "alice")).await.unwrap();
+        assert_eq!(body_json(response).await["room"]["id"], room_id.as_str());
+        app.clone().oneshot(open("alice", "carol")).await.unwrap();
+        // Message times have millisecond precision; keep them clear of the room creation times
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        let response = app
+            .clone()
+            .oneshot(post_json(
+                "/chat/messages",
+                json!({
+                    "room_id": room_id,
+                    "user_id": "bob",
+                    "username": "bob",
+                    "message_text": "hey alice",
+                    "client_message_id": null
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = app
+            .clone()
+            .oneshot(get(&format!("/chat/messages/{}?user_id=carol", room_id)))
             .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Most recent activity first, and never in the room listing
+        let response = app.clone().oneshot(get("/chat/dms?user_id=alice")).await.unwrap();
+        let conversations = body_json(response).await["conversations"].clone();
+        assert_eq!(conversations.as_array().unwrap().len(), 2);
+        assert_eq!(conversations[0]["other_user_id"], "bob");
+        assert_eq!(conversations[1]["other_user_id"], "carol");
+        let response = app.oneshot(get("/chat/rooms?user_id=alice")).await.unwrap();
+        assert_eq!(body_json(response).await["rooms"], json!([]));
+
+        // Fan-out reaches only the two participants
+        for (connection_id, user_id) in [("c1", "alice"), ("c2", "bob"), ("c3", "carol")] {
+            let connection =
+                backend::store::Connection::new(connection_id, &room_id, user_id, user_id, 0);
+            store.put_connection(&connection).await.unwrap();
+        }
+        let connections = store.room_connections(&room_id).await.unwrap();
+        let audience =
+            handlers::room_audience(store.as_ref(), &room_id, connections).await.unwrap();
+        let mut users: Vec<_> = audience.iter().map(|c| c.user_id.as_str()).collect();
+        users.sort();
+        assert_eq!(users, ["alice", "bob"]);
+    }
+
+    #[tokio::test]
+    async fn test_identity_comes_from_credentials() {
+        let mut state = test_state().await;
+        let bot = Identity { user_id: "bot".to_string(), username: "Bot".to_string() };
+        state.auth = state
+            .auth
+            .with(ApiKeys::new().with_key("bot-key", bot))
+            .with(JwtVerifier::with_secret(b"cognito", None, None));
+        let app = create_app(state);
+        let body = json!({
+            "room_id": "general",
+            "user_id": "mallory",
+            "username": "mallory",
+            "message_text": "hello",
+            "client_message_id": null
+        });
+        let post_with = |header: &'static str, value: String| {
+            let mut request = post_json("/chat/messages", body.clone());
+            request.headers_mut().insert(header, value.parse().unwrap());
+            request
+        };
+
+        // Claimed user ids are not credentials
+        let response =
+            app.clone().oneshot(post_json("/chat/messages", body.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response =
+            app.clone().oneshot(post_with("x-user-id", "mallory".to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response =
+            app.clone().oneshot(post_with("x-api-key", "guess".to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // A Cognito token
+        let claims = json!({
+            "sub": "u-1",
+            "cognito:username": "alice",
+            "exp": chrono::Utc::now().timestamp() + 300,
+        });
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(b"cognito"),
+        )
+        .unwrap();
+        let response =
+            app.clone().oneshot(post_with("authorization", format!("Bearer {}", token))).await;
+        let response = response.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let message = body_json(response).await;
+        assert_eq!(message["user_id"], "u-1");
+        assert_eq!(message["username"], "alice");
 
+        // An API key, exchanged for a backend token
+        let mut request =
+            Request::builder().method(Method::POST).uri("/auth/token").body(Body::empty()).unwrap();
+        request.headers_mut().insert("x-api-key", "bot-key".parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-        // TODO: Add body deserialization test when body collection is fixed
+        let issued = body_json(response).await;
+        assert_eq!(issued["user_id"], "bot");
+
+        let token = issued["token"].as_str().unwrap();
+        let response = app.oneshot(post_with("authorization", format!("Bearer {}", token))).await;
+        let message = body_json(response.unwrap()).await;
+        assert_eq!(message["user_id"], "bot");
+        assert_eq!(message["username"], "Bot");
+    }
+
+    // Over real sockets: with the memory store nothing streams the table, so the server
+    // relays posts to the room itself
+    #[cfg(feature = "dev")]
+    #[tokio::test]
+    async fn test_dev_sockets_hear_each_others_posts() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener).unwrap();
+        tokio::spawn(server.serve(create_app(test_state().await).into_make_service()));
+
+        let token = |user_id: &str| {
+            let identity = Identity { user_id: user_id.to_string(), username: user_id.to_string() };
+            TokenIssuer::new(TEST_SECRET, 300).issue(&identity).unwrap().token
+        };
+        let connect = |user_id: &str| {
+            let url = format!("ws://{}/ws?room_id=general&token={}", addr, token(user_id));
+            async move { tokio_tungstenite::connect_async(url).await.unwrap().0 }
+        };
+        // The next frame matching `wanted`, skipping the rest
+        async fn next_event<S>(socket: &mut S, wanted: fn(&ServerEvent) -> bool) -> ServerEvent
+        where
+            S: futures_util::Stream<
+                    Item = Result<WsMessage, tokio_tungstenite::tungstenite::Error>,
+                > + Unpin,
+        {
+            let wait = async {
+                loop {
+                    if let WsMessage::Text(text) = socket.next().await.unwrap().unwrap() {
+                        let frame: ServerFrame = serde_json::from_str(&text).unwrap();
+                        if wanted(&frame.event) {
+                            return frame.event;
+                        }
+                    }
+                }
+            };
+            tokio::time::timeout(std::time::Duration::from_secs(5), wait).await.unwrap()
+        }
+
+        let mut alice = connect("alice").await;
+        let mut bob = connect("bob").await;
+        // Bob's socket is subscribed to the room once alice hears him join
+        next_event(&mut alice, |e| matches!(e, ServerEvent::PresenceJoin(_))).await;
+
+        let send = json!({ "type": "send_message", "message_text": "over the socket" });
+        alice.send(WsMessage::Text(send.to_string())).await.unwrap();
+        let ServerEvent::MessageNew { message } =
+            next_event(&mut bob, |e| matches!(e, ServerEvent::MessageNew { .. })).await
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            (message.user_id.as_str(), message.message_text.as_str()),
+            ("alice", "over the socket")
+        );
+
+        // Posts and edits over REST reach the room's sockets too
+        let client = reqwest::Client::new();
+        let posted: serde_json::Value = client
+            .post(format!("http://{}/chat/messages", addr))
+            .bearer_auth(token("alice"))
+            .json(&json!({
+                "room_id": "general",
+                "user_id": "alice",
+                "username": "alice",
+                "message_text": "over REST",
+                "client_message_id": null
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let ServerEvent::MessageNew { message } =
+            next_event(&mut bob, |e| matches!(e, ServerEvent::MessageNew { .. })).await
+        else {
+            unreachable!()
+        };
+        assert_eq!(message.id, posted["id"].as_str().unwrap());
+
+        let response = client
+            .patch(format!("http://{}/chat/messages/general/{}", addr, message.id))
+            .bearer_auth(token("alice"))
+            .json(&json!({ "user_id": "alice", "message_text": "edited over REST" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let ServerEvent::MessageEdited { message } =
+            next_event(&mut bob, |e| matches!(e, ServerEvent::MessageEdited { .. })).await
+        else {
+            unreachable!()
+        };
+        assert_eq!(message.message_text, "edited over REST");
+
+        // A socket left open after leaving a private room hears nothing more from it
+        let response = client
+            .post(format!("http://{}/chat/rooms", addr))
+            .bearer_auth(token("alice"))
+            .json(&json!({ "name": "Staff", "visibility": "private", "user_id": "alice" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 201);
+        let response = client
+            .post(format!("http://{}/chat/rooms/staff/members", addr))
+            .bearer_auth(token("alice"))
+            .json(&json!({ "user_id": "alice", "member_id": "bob", "role": null }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let connect_staff = |user_id: &str| {
+            let url = format!("ws://{}/ws?room_id=staff&token={}", addr, token(user_id));
+            async move { tokio_tungstenite::connect_async(url).await.unwrap().0 }
+        };
+        let mut alice = connect_staff("alice").await;
+        let mut bob = connect_staff("bob").await;
+        next_event(&mut alice, |e| matches!(e, ServerEvent::PresenceJoin(_))).await;
+
+        let response = client
+            .post(format!("http://{}/chat/rooms/staff/leave", addr))
+            .bearer_auth(token("bob"))
+            .json(&json!({ "user_id": "bob" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 204);
+        alice.send(WsMessage::Text(json!({ "type": "typing_start" }).to_string())).await.unwrap();
+        let send = json!({ "type": "send_message", "message_text": "members only" });
+        alice.send(WsMessage::Text(send.to_string())).await.unwrap();
+        // Alice getting the post back means the room's channel has carried it past bob too
+        next_event(&mut alice, |e| matches!(e, ServerEvent::MessageNew { .. })).await;
+        let heard = tokio::time::timeout(std::time::Duration::from_millis(300), bob.next()).await;
+        assert!(heard.is_err(), "bob heard {:?}", heard);
     }
 }
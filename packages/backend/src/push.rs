@@ -0,0 +1,162 @@
+use aws_config::SdkConfig;
+use aws_sdk_apigatewaymanagement::{primitives::Blob, Client as ApiGatewayClient};
+#[cfg(feature = "dev")]
+use reqwest::Client as HttpClient;
+use tracing::{error, info};
+use types::ServerFrame;
+
+use crate::store::{ChatStore, Connection};
+use crate::ws;
+
+/// Delivers frames to stored connections over their transport: the WebSocket API's
+/// management endpoint for "apigw" connections, and the dev server's push URL for "dev" ones
+pub struct ConnectionPusher {
+    api_gateway: ApiGatewayClient,
+    #[cfg(feature = "dev")]
+    http_client: HttpClient,
+}
+
+impl ConnectionPusher {
+    /// Push through the management endpoint of WebSocket API `api_id` at `stage`
+    pub fn new(aws_config: &SdkConfig, api_id: &str, stage: &str) -> Self {
+        let region = aws_config.region().map(|region| region.to_string()).unwrap_or_default();
+        let endpoint = format!("https://{}.execute-api.{}.amazonaws.com/{}", api_id, region, stage);
+        let config = aws_sdk_apigatewaymanagement::config::Builder::from(aws_config)
+            .endpoint_url(endpoint)
+            .build();
+        Self {
+            api_gateway: ApiGatewayClient::from_conf(config),
+            #[cfg(feature = "dev")]
+            http_client: HttpClient::new(),
+        }
+    }
+
+    /// Send one payload to every connection; returns the number delivered. Connections
+    /// that turn out to be gone are removed from the store.
+    pub async fn send(
+        &self,
+        store: &dyn ChatStore,
+        connections: &[Connection],
+        payload: &str,
+    ) -> i32 {
+        let message_blob = Blob::new(payload.as_bytes());
+        let mut successful_sends = 0;
+
+        for connection in connections {
+            let connection_id = &connection.connection_id;
+            match connection.transport.as_str() {
+                "apigw" => {
+                    match self
+                        .api_gateway
+                        .post_to_connection()
+                        .connection_id(connection_id)
+                        .data(message_blob.clone())
+                        .send()
+                        .await
+                    {
+                        Ok(_) => {
+                            info!("Sent via API Gateway to connection {}", connection_id);
+                            successful_sends += 1;
+                        }
+                        Err(e) => {
+                            error!("Failed to send via API Gateway to {}: {:?}", connection_id, e);
+                            if let Some(service_err) = e.as_service_error() {
+                                if service_err.is_gone_exception() {
+                                    info!("Removing stale connection {}", connection_id);
+                                    if let Err(delete_err) =
+                                        store.delete_connection(connection_id).await
+                                    {
+                                        error!(
+                                            "Failed to delete stale connection {}: {:?}",
+                                            connection_id, delete_err
+                                        );
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                #[cfg(feature = "dev")]
+                "dev" => {
+                    // Use per-connection push_url
+                    if let Some(push_url) = &connection.push_url {
+                        match self
+                            .http_client
+                            .post(push_url)
+                            .header("content-type", "application/json")
+                            .body(payload.to_string())
+                            .send()
+                            .await
+                        {
+                            Ok(resp) => {
+                                if resp.status().is_success() {
+                                    info!("Sent via dev push_url to {}", push_url);
+                                    successful_sends += 1;
+                                } else if resp.status().as_u16() == 404
+                                    || resp.status().as_u16() == 410
+                                {
+                                    // Remove stale connection
+                                    let _ = store.delete_connection(connection_id).await;
+                                } else {
+                                    error!("Dev push_url responded with status {}", resp.status());
+                                }
+                            }
+                            Err(e) => {
+                                error!("HTTP error sending to dev push_url {}: {:?}", push_url, e);
+                            }
+                        }
+                    } else {
+                        error!("Missing push_url for dev transport connection");
+                    }
+                }
+                transport => {
+                    // Unknown transport; skip
+                    info!("Skipping connection with unknown transport: {}", transport);
+                }
+            }
+        }
+
+        successful_sends
+    }
+
+    /// Hang up on every "apigw" connection; other transports have no way to be closed
+    /// from here and are skipped. Their records are left to the caller.
+    pub async fn close(&self, connections: &[Connection]) {
+        for connection in connections {
+            if connection.transport != "apigw" {
+                info!(
+                    "Not closing {} connection {}",
+                    connection.transport, connection.connection_id
+                );
+                continue;
+            }
+            match self
+                .api_gateway
+                .delete_connection()
+                .connection_id(&connection.connection_id)
+                .send()
+                .await
+            {
+                Ok(_) => info!("Closed connection {}", connection.connection_id),
+                // Already gone is as good as closed
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_gone_exception()) => {}
+                Err(e) => {
+                    error!("Failed to close connection {}: {:?}", connection.connection_id, e)
+                }
+            }
+        }
+    }
+
+    /// Send `frame` to the connections in `room_id` it is meant for, found through the
+    /// room-index GSI; returns the number delivered
+    pub async fn broadcast(
+        &self,
+        store: &dyn ChatStore,
+        room_id: &str,
+        frame: &ServerFrame,
+    ) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+        let recipients = ws::recipients(store, room_id, frame).await?;
+        let payload = serde_json::to_string(frame)?;
+        Ok(self.send(store, &recipients, &payload).await)
+    }
+}
@@ -0,0 +1,110 @@
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient};
+use chrono::Utc;
+
+use super::{Bucket, Decision, RateLimit, RateLimiter};
+use crate::store::StoreError;
+
+// Attempts at a bucket write before letting a contended request through
+const WRITE_ATTEMPTS: usize = 3;
+
+/// Buckets in a DynamoDB table keyed by `bucket_key`, shared by every Lambda instance.
+/// Each write bumps an atomic `version` counter and is conditional on the version it read,
+/// so concurrent takes cannot spend the same token. Items expire through `ttl` once their
+/// bucket would be full again.
+#[derive(Clone)]
+pub struct DynamoRateLimiter {
+    ddb: DynamoDbClient,
+    table: String,
+}
+
+impl DynamoRateLimiter {
+    pub fn new(ddb: DynamoDbClient, table: impl Into<String>) -> Self {
+        Self { ddb, table: table.into() }
+    }
+}
+
+fn backend_error<E: std::fmt::Debug>(err: E) -> StoreError {
+    StoreError::Backend(format!("DynamoDB error: {:?}", err))
+}
+
+fn number<T: std::str::FromStr>(value: Option<&AttributeValue>) -> Option<T> {
+    value.and_then(|v| v.as_n().ok()).and_then(|n| n.parse().ok())
+}
+
+#[async_trait]
+impl RateLimiter for DynamoRateLimiter {
+    async fn acquire(&self, key: &str, limit: &RateLimit) -> Result<Decision, StoreError> {
+        for _ in 0..WRITE_ATTEMPTS {
+            let now_ms = Utc::now().timestamp_millis();
+            let item = self
+                .ddb
+                .get_item()
+                .table_name(&self.table)
+                .key("bucket_key", AttributeValue::S(key.to_string()))
+                .consistent_read(true)
+                .send()
+                .await
+                .map_err(backend_error)?
+                .item;
+
+            // An item past its ttl that has not been swept yet is a full bucket
+            let live = item.as_ref().filter(|item| {
+                number::<i64>(item.get("ttl")).is_some_and(|ttl| ttl * 1_000 > now_ms)
+            });
+            let mut bucket = match live {
+                Some(item) => Bucket {
+                    tokens: number(item.get("tokens")).unwrap_or(0.0),
+                    refilled_at_ms: number(item.get("refilled_at_ms")).unwrap_or(now_ms),
+                },
+                None => Bucket::full(limit, now_ms),
+            };
+            if let Err(retry_after_secs) = bucket.take(limit, now_ms) {
+                return Ok(Decision::Limited { retry_after_secs });
+            }
+
+            let update = self
+                .ddb
+                .update_item()
+                .table_name(&self.table)
+                .key("bucket_key", AttributeValue::S(key.to_string()))
+                .update_expression(
+                    "SET tokens = :tokens, refilled_at_ms = :refilled_at_ms, #ttl = :ttl \
+                     ADD version :one",
+                )
+                .expression_attribute_names("#ttl", "ttl")
+                .expression_attribute_values(
+                    ":tokens",
+                    AttributeValue::N(bucket.tokens.to_string()),
+                )
+                .expression_attribute_values(
+                    ":refilled_at_ms",
+                    AttributeValue::N(bucket.refilled_at_ms.to_string()),
+                )
+                .expression_attribute_values(
+                    ":ttl",
+                    AttributeValue::N((now_ms / 1_000 + limit.refill_secs()).to_string()),
+                )
+                .expression_attribute_values(":one", AttributeValue::N("1".to_string()));
+            // Nobody may have written the bucket since we read it
+            let update = match item.as_ref().and_then(|item| item.get("version")) {
+                Some(version) => update
+                    .condition_expression("version = :version")
+                    .expression_attribute_values(":version", version.clone()),
+                None => update.condition_expression("attribute_not_exists(bucket_key)"),
+            };
+
+            match update.send().await {
+                Ok(_) => return Ok(Decision::Allowed),
+                Err(e)
+                    if e.as_service_error()
+                        .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(backend_error(e)),
+            }
+        }
+        Err(StoreError::ConditionFailed)
+    }
+}
@@ -0,0 +1,40 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+use super::{Bucket, Decision, RateLimit, RateLimiter};
+use crate::store::StoreError;
+
+/// Buckets held in process; right for a single server, not for Lambdas
+#[derive(Default)]
+pub struct MemoryRateLimiter {
+    // Each bucket with the epoch millis at which it will be full again
+    buckets: Mutex<HashMap<String, (Bucket, i64)>>,
+}
+
+impl MemoryRateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RateLimiter for MemoryRateLimiter {
+    async fn acquire(&self, key: &str, limit: &RateLimit) -> Result<Decision, StoreError> {
+        let now_ms = Utc::now().timestamp_millis();
+        let mut buckets = self.buckets.lock().await;
+        // Full buckets hold nothing worth keeping
+        buckets.retain(|_, (_, full_at_ms)| *full_at_ms > now_ms);
+
+        let (bucket, full_at_ms) =
+            buckets.entry(key.to_string()).or_insert_with(|| (Bucket::full(limit, now_ms), now_ms));
+        Ok(match bucket.take(limit, now_ms) {
+            Ok(()) => {
+                *full_at_ms = now_ms + limit.refill_secs() * 1_000;
+                Decision::Allowed
+            }
+            Err(retry_after_secs) => Decision::Limited { retry_after_secs },
+        })
+    }
+}
@@ -0,0 +1,269 @@
+use async_trait::async_trait;
+use std::{env, fmt, sync::Arc};
+use tracing::warn;
+
+use crate::handlers::{validate_room_id, HandlerError};
+use crate::store::StoreError;
+
+pub mod dynamo;
+pub mod memory;
+
+pub use dynamo::DynamoRateLimiter;
+pub use memory::MemoryRateLimiter;
+
+/// A token bucket: holds up to `burst` tokens and regains `per_minute` of them a minute
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl RateLimit {
+    pub const fn new(burst: u32, per_minute: u32) -> Self {
+        Self { burst, per_minute }
+    }
+
+    fn tokens_per_ms(&self) -> f64 {
+        self.per_minute as f64 / 60_000.0
+    }
+
+    /// How long an untouched bucket takes to fill up again, after which it can be forgotten
+    pub fn refill_secs(&self) -> i64 {
+        (self.burst as f64 / self.tokens_per_ms() / 1_000.0).ceil() as i64
+    }
+}
+
+// A bucket's state as last written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    pub refilled_at_ms: i64, // epoch millis
+}
+
+impl Bucket {
+    pub fn full(limit: &RateLimit, now_ms: i64) -> Self {
+        Self { tokens: limit.burst as f64, refilled_at_ms: now_ms }
+    }
+
+    /// Refill for the time since the last take, then take a token. When the bucket is
+    /// empty, returns how many seconds until the next token and leaves it untouched.
+    pub fn take(&mut self, limit: &RateLimit, now_ms: i64) -> Result<(), u64> {
+        let elapsed_ms = (now_ms - self.refilled_at_ms).max(0) as f64;
+        let tokens = (self.tokens + elapsed_ms * limit.tokens_per_ms()).min(limit.burst as f64);
+        if tokens < 1.0 {
+            let wait_ms = (1.0 - tokens) / limit.tokens_per_ms();
+            return Err(((wait_ms / 1_000.0).ceil() as u64).max(1));
+        }
+        *self = Self { tokens: tokens - 1.0, refilled_at_ms: now_ms.max(self.refilled_at_ms) };
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    Allowed,
+    Limited { retry_after_secs: u64 },
+}
+
+/// Where token buckets live: in process for the dev server, in DynamoDB for the Lambdas,
+/// whose instances would otherwise each keep their own count
+#[async_trait]
+pub trait RateLimiter: Send + Sync {
+    /// Take a token from the bucket named `key`
+    async fn acquire(&self, key: &str, limit: &RateLimit) -> Result<Decision, StoreError>;
+}
+
+/// Which bucket ran dry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    User,
+    Room,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::User => "user",
+            Scope::Room => "room",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimited {
+    pub scope: Scope,
+    pub retry_after_secs: u64,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.scope {
+            Scope::User => write!(f, "You are posting too fast"),
+            Scope::Room => write!(f, "This room is receiving too many messages"),
+        }?;
+        write!(f, "; try again in {}s", self.retry_after_secs)
+    }
+}
+
+impl From<RateLimited> for HandlerError {
+    fn from(limited: RateLimited) -> Self {
+        Self::too_many_requests(limited.to_string(), limited.retry_after_secs)
+    }
+}
+
+/// Limits on posting messages, per user and per room. None leaves that side unlimited.
+#[derive(Clone)]
+pub struct PostRateLimiter {
+    limiter: Arc<dyn RateLimiter>,
+    per_user: Option<RateLimit>,
+    per_room: Option<RateLimit>,
+}
+
+impl PostRateLimiter {
+    pub const DEFAULT_PER_USER: RateLimit = RateLimit::new(10, 30);
+    pub const DEFAULT_PER_ROOM: RateLimit = RateLimit::new(60, 300);
+
+    pub fn new(
+        limiter: Arc<dyn RateLimiter>,
+        per_user: Option<RateLimit>,
+        per_room: Option<RateLimit>,
+    ) -> Self {
+        Self { limiter, per_user, per_room }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(Arc::new(MemoryRateLimiter::new()), None, None)
+    }
+
+    /// The limits in `RATE_LIMIT_USER_PER_MIN`/`RATE_LIMIT_USER_BURST` and
+    /// `RATE_LIMIT_ROOM_PER_MIN`/`RATE_LIMIT_ROOM_BURST`; a rate of 0 turns that limit off
+    pub fn from_env(limiter: Arc<dyn RateLimiter>) -> Self {
+        let limit = |prefix: &str, default: RateLimit| {
+            let var = |name: &str, default: u32| {
+                env::var(format!("{}_{}", prefix, name))
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(default)
+            };
+            let limit = RateLimit::new(
+                var("BURST", default.burst).max(1),
+                var("PER_MIN", default.per_minute),
+            );
+            (limit.per_minute > 0).then_some(limit)
+        };
+        Self::new(
+            limiter,
+            limit("RATE_LIMIT_USER", Self::DEFAULT_PER_USER),
+            limit("RATE_LIMIT_ROOM", Self::DEFAULT_PER_ROOM),
+        )
+    }
+
+    /// Take a token from the user's bucket, then the room's, so one user's flood is stopped
+    /// before it drains the room. A limiter that cannot be reached lets the post through
+    /// rather than taking posting down with it.
+    pub async fn check(&self, user_id: &str, room_id: &str) -> Result<(), RateLimited> {
+        let room_id = validate_room_id(room_id).ok();
+        let buckets = [
+            (Scope::User, Some(format!("user#{}", user_id)), self.per_user),
+            (Scope::Room, room_id.map(|room_id| format!("room#{}", room_id)), self.per_room),
+        ];
+        for (scope, key, limit) in buckets {
+            let (Some(key), Some(limit)) = (key, limit) else {
+                continue;
+            };
+            match self.limiter.acquire(&key, &limit).await {
+                Ok(Decision::Allowed) => {}
+                Ok(Decision::Limited { retry_after_secs }) => {
+                    return Err(RateLimited { scope, retry_after_secs });
+                }
+                Err(e) => warn!("Rate limiter unavailable for {}: {}", key, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Limits how often one connection's typing indicator is relayed to its room. Clients
+/// repeat `typing.start` while the user types; the repeats in between are dropped.
+#[derive(Clone)]
+pub struct TypingThrottle {
+    limiter: Arc<dyn RateLimiter>,
+    limit: RateLimit,
+}
+
+impl TypingThrottle {
+    /// One relayed start every 3 seconds
+    pub const DEFAULT: RateLimit = RateLimit::new(1, 20);
+
+    pub fn new(limiter: Arc<dyn RateLimiter>, limit: RateLimit) -> Self {
+        Self { limiter, limit }
+    }
+
+    /// Whether to relay this start. Like posting, an unreachable limiter lets it through.
+    pub async fn allow(&self, connection_id: &str) -> bool {
+        let key = format!("typing#{}", connection_id);
+        match self.limiter.acquire(&key, &self.limit).await {
+            Ok(decision) => decision == Decision::Allowed,
+            Err(e) => {
+                warn!("Rate limiter unavailable for {}: {}", key, e);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        // 2 tokens, one more every 30s
+        let limit = RateLimit::new(2, 2);
+        let mut bucket = Bucket::full(&limit, 0);
+        assert_eq!(bucket.take(&limit, 0), Ok(()));
+        assert_eq!(bucket.take(&limit, 0), Ok(()));
+        assert_eq!(bucket.take(&limit, 0), Err(30));
+        assert_eq!(bucket.take(&limit, 20_000), Err(10));
+        assert_eq!(bucket.take(&limit, 30_000), Ok(()));
+
+        // A long pause never fills past the burst
+        assert_eq!(bucket.take(&limit, 600_000), Ok(()));
+        assert_eq!(bucket.take(&limit, 600_000), Ok(()));
+        assert!(bucket.take(&limit, 600_000).is_err());
+        assert_eq!(limit.refill_secs(), 60);
+    }
+
+    #[tokio::test]
+    async fn test_post_limits_per_user_and_room() {
+        let limiter = PostRateLimiter::new(
+            Arc::new(MemoryRateLimiter::new()),
+            Some(RateLimit::new(1, 1)),
+            Some(RateLimit::new(2, 1)),
+        );
+        assert_eq!(limiter.check("alice", "general").await, Ok(()));
+        let limited = limiter.check("alice", "General ").await.unwrap_err();
+        assert_eq!(limited.scope, Scope::User);
+        assert_eq!(limited.retry_after_secs, 60);
+
+        assert_eq!(limiter.check("bob", "general").await, Ok(()));
+        assert_eq!(limiter.check("carol", "general").await.unwrap_err().scope, Scope::Room);
+        assert_eq!(limiter.check("dave", "random").await, Ok(()));
+        assert_eq!(HandlerError::from(limited).retry_after_secs, Some(60));
+
+        let unlimited = PostRateLimiter::unlimited();
+        for _ in 0..100 {
+            assert_eq!(unlimited.check("alice", "general").await, Ok(()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_typing_throttle_per_connection() {
+        let throttle =
+            TypingThrottle::new(Arc::new(MemoryRateLimiter::new()), TypingThrottle::DEFAULT);
+        assert!(throttle.allow("c1").await);
+        assert!(!throttle.allow("c1").await);
+        // A second tab is its own connection
+        assert!(throttle.allow("c2").await);
+    }
+}
@@ -0,0 +1,1495 @@
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{
+    operation::transact_write_items::TransactWriteItemsError,
+    types::{AttributeValue, Put, ReturnValue, TransactWriteItem},
+    Client as DynamoDbClient,
+};
+use chrono::{DateTime, Utc};
+use std::{collections::HashMap, env};
+use tracing::warn;
+
+use super::{
+    dedup_key, legacy_message_id, summarize_reactions, ChatStore, Connection, MessagePage,
+    MessageQuery, MessageRevision, PutMessageOutcome, ReactionOutcome, StoreError,
+    DELETED_MESSAGE_TEXT, MAX_REVISIONS,
+};
+use types::{
+    ChatMessage, FilterAction, FilterDecision, MessageReport, ReadMarker, ReportStatus, Room,
+    RoomKind, RoomMember, RoomRole, RoomSanction, RoomVisibility,
+};
+
+type Item = HashMap<String, AttributeValue>;
+
+// Sparse GSI on the messages table: (parent_id, id), so only replies are indexed
+const THREAD_INDEX: &str = "thread-index";
+
+// GSI on the members and read markers tables: (user_id, room_id), for the rooms a user
+// belongs to or has read
+const USER_INDEX: &str = "user-index";
+
+// Optimistic attempts at a reaction write before giving up on a busy message
+const REACTION_WRITE_ATTEMPTS: usize = 3;
+
+// Table names structure
+#[derive(Clone, Default)]
+pub struct Tables {
+    pub rooms: String,
+    pub messages: String,
+    pub connections: String,
+    pub dedup: String,
+    pub members: String,
+    pub sanctions: String,
+    pub reports: String,
+    pub reads: String,
+}
+
+impl Tables {
+    pub fn from_env() -> Self {
+        Self {
+            rooms: env::var("CHAT_ROOMS_TABLE").expect("CHAT_ROOMS_TABLE must be set"),
+            messages: env::var("CHAT_MESSAGES_TABLE").expect("CHAT_MESSAGES_TABLE must be set"),
+            connections: env::var("CONNECTIONS_TABLE").expect("CONNECTIONS_TABLE must be set"),
+            dedup: env::var("CHAT_DEDUP_TABLE").expect("CHAT_DEDUP_TABLE must be set"),
+            members: env::var("CHAT_MEMBERS_TABLE").expect("CHAT_MEMBERS_TABLE must be set"),
+            sanctions: env::var("CHAT_SANCTIONS_TABLE").expect("CHAT_SANCTIONS_TABLE must be set"),
+            reports: env::var("CHAT_REPORTS_TABLE").expect("CHAT_REPORTS_TABLE must be set"),
+            reads: env::var("CHAT_READS_TABLE").expect("CHAT_READS_TABLE must be set"),
+        }
+    }
+
+    /// Only the messages and dedup tables, all that `migrate_legacy_messages` touches.
+    /// A store built on these has no other tables to use.
+    pub fn messages_from_env() -> Self {
+        Self {
+            messages: env::var("CHAT_MESSAGES_TABLE").expect("CHAT_MESSAGES_TABLE must be set"),
+            dedup: env::var("CHAT_DEDUP_TABLE").expect("CHAT_DEDUP_TABLE must be set"),
+            ..Self::default()
+        }
+    }
+}
+
+// DynamoDB-backed ChatStore
+#[derive(Clone)]
+pub struct DynamoStore {
+    ddb: DynamoDbClient,
+    tables: Tables,
+}
+
+impl DynamoStore {
+    pub fn new(ddb: DynamoDbClient, tables: Tables) -> Self {
+        Self { ddb, tables }
+    }
+
+    // Drop the oldest revisions beyond MAX_REVISIONS. Only if there are still `count`, so a
+    // concurrent edit cannot make this drop a revision it did not see; that edit trims instead.
+    async fn trim_revisions(&self, message: &ChatMessage, count: usize) {
+        let excess = (0..count - MAX_REVISIONS).map(|i| format!("revisions[{}]", i));
+        let result = self
+            .ddb
+            .update_item()
+            .table_name(&self.tables.messages)
+            .key("room_id", AttributeValue::S(message.room_id.clone()))
+            .key("id", AttributeValue::S(message.id.clone()))
+            .update_expression(format!("REMOVE {}", excess.collect::<Vec<_>>().join(", ")))
+            .condition_expression("size(revisions) = :count")
+            .expression_attribute_values(":count", AttributeValue::N(count.to_string()))
+            .send()
+            .await;
+        if let Err(e) = result {
+            if !e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) {
+                warn!("Failed to trim revisions of message {}: {:?}", message.id, e);
+            }
+        }
+    }
+
+    /// Copy messages from a legacy table keyed by (room_id, ts) into the messages table,
+    /// giving each one a deterministic ULID so the migration can be re-run safely.
+    /// Dedup markers that still point at a ts are re-pointed at the new id.
+    /// Returns the number of messages copied.
+    pub async fn migrate_legacy_messages(&self, legacy_table: &str) -> Result<usize, StoreError> {
+        let mut copied = 0;
+        let mut start_key = None;
+        loop {
+            let page = self
+                .ddb
+                .scan()
+                .table_name(legacy_table)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(backend_error)?;
+
+            for legacy in page.items.unwrap_or_default() {
+                let Some(mut message) = message_from_item(&legacy) else {
+                    continue;
+                };
+                message.id = legacy_message_id(message.created_at.timestamp_millis(), &message.id);
+                match self.put_message(&message).await {
+                    Ok(()) => copied += 1,
+                    // Already migrated on a previous run
+                    Err(StoreError::ConditionFailed) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            start_key = page.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+
+        let mut start_key = None;
+        loop {
+            let page = self
+                .ddb
+                .scan()
+                .table_name(&self.tables.dedup)
+                .filter_expression("attribute_not_exists(message_id)")
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(backend_error)?;
+
+            for marker in page.items.unwrap_or_default() {
+                let (Some(key), Some(room_id), Some(ts)) =
+                    (marker.get("dedup_key"), marker.get("room_id"), marker.get("ts"))
+                else {
+                    continue;
+                };
+                let legacy = self
+                    .ddb
+                    .get_item()
+                    .table_name(legacy_table)
+                    .key("room_id", room_id.clone())
+                    .key("ts", ts.clone())
+                    .send()
+                    .await
+                    .map_err(backend_error)?
+                    .item;
+                let Some(legacy) = legacy.as_ref().and_then(message_from_item) else {
+                    continue;
+                };
+                let message_id =
+                    legacy_message_id(legacy.created_at.timestamp_millis(), &legacy.id);
+                self.ddb
+                    .update_item()
+                    .table_name(&self.tables.dedup)
+                    .key("dedup_key", key.clone())
+                    .update_expression("SET message_id = :message_id")
+                    .expression_attribute_values(":message_id", AttributeValue::S(message_id))
+                    .send()
+                    .await
+                    .map_err(backend_error)?;
+            }
+
+            start_key = page.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+
+        Ok(copied)
+    }
+}
+
+fn backend_error<E: std::fmt::Debug>(err: E) -> StoreError {
+    StoreError::Backend(format!("DynamoDB error: {:?}", err))
+}
+
+fn get_s(item: &Item, key: &str) -> Option<String> {
+    item.get(key).and_then(|v| v.as_s().ok()).cloned()
+}
+
+fn get_n(item: &Item, key: &str) -> Option<i64> {
+    item.get(key).and_then(|v| v.as_n().ok()).and_then(|n| n.parse::<i64>().ok())
+}
+
+fn get_time(item: &Item, key: &str) -> Option<DateTime<Utc>> {
+    get_s(item, key)
+        .and_then(|iso| DateTime::parse_from_rfc3339(&iso).ok())
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+// Reactions live on the message item as a string set of "<emoji> <user_id>" entries.
+// Emoji never contain whitespace, so the first space separates the two.
+fn reaction_entry(emoji: &str, user_id: &str) -> String {
+    format!("{} {}", emoji, user_id)
+}
+
+fn reaction_entries(item: &Item) -> Vec<(String, String)> {
+    item.get("reactions")
+        .and_then(|v| v.as_ss().ok())
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| entry.split_once(' '))
+                .map(|(emoji, user_id)| (emoji.to_string(), user_id.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn room_to_item(room: &Room) -> Item {
+    let mut item = HashMap::new();
+    item.insert("id".to_string(), AttributeValue::S(room.id.clone()));
+    item.insert("name".to_string(), AttributeValue::S(room.name.clone()));
+    item.insert("created_at_iso".to_string(), AttributeValue::S(room.created_at.to_rfc3339()));
+    item.insert(
+        "created_at_epoch".to_string(),
+        AttributeValue::N(room.created_at.timestamp().to_string()),
+    );
+    if let Some(description) = &room.description {
+        item.insert("description".to_string(), AttributeValue::S(description.clone()));
+    }
+    if let Some(topic) = &room.topic {
+        item.insert("topic".to_string(), AttributeValue::S(topic.clone()));
+    }
+    item.insert("archived".to_string(), AttributeValue::Bool(room.archived));
+    item.insert("visibility".to_string(), AttributeValue::S(room.visibility.as_str().to_string()));
+    item.insert("kind".to_string(), AttributeValue::S(room.kind.as_str().to_string()));
+    if let Some(last_message_at) = room.last_message_at {
+        item.insert(
+            "last_message_at_iso".to_string(),
+            AttributeValue::S(last_message_at.to_rfc3339()),
+        );
+    }
+    item
+}
+
+fn room_from_item(item: &Item) -> Option<Room> {
+    let id = get_s(item, "id")?;
+    let name = get_s(item, "name").unwrap_or_else(|| id.clone());
+    let created_at = get_s(item, "created_at_iso")
+        .and_then(|iso| DateTime::parse_from_rfc3339(&iso).ok())
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now);
+    let archived = item.get("archived").and_then(|v| v.as_bool().ok()).copied().unwrap_or(false);
+    // Rooms written before private rooms existed are public; unknown values fail closed
+    let visibility = match get_s(item, "visibility") {
+        Some(value) => RoomVisibility::parse(&value).unwrap_or(RoomVisibility::Private),
+        None => RoomVisibility::Public,
+    };
+    Some(Room {
+        id,
+        name,
+        created_at,
+        description: get_s(item, "description"),
+        topic: get_s(item, "topic"),
+        archived,
+        visibility,
+        kind: get_s(item, "kind").and_then(|kind| RoomKind::parse(&kind)).unwrap_or_default(),
+        last_message_at: get_time(item, "last_message_at_iso"),
+    })
+}
+
+fn message_to_item(message: &ChatMessage) -> Item {
+    let mut item = HashMap::new();
+    item.insert("id".to_string(), AttributeValue::S(message.id.clone()));
+    item.insert("room_id".to_string(), AttributeValue::S(message.room_id.clone()));
+    item.insert("user_id".to_string(), AttributeValue::S(message.user_id.clone()));
+    item.insert("username".to_string(), AttributeValue::S(message.username.clone()));
+    item.insert("message_text".to_string(), AttributeValue::S(message.message_text.clone()));
+    item.insert(
+        "ts".to_string(),
+        AttributeValue::N(message.created_at.timestamp_millis().to_string()),
+    );
+    item.insert("created_at_iso".to_string(), AttributeValue::S(message.created_at.to_rfc3339()));
+
+    // Store client_message_id if provided
+    if let Some(client_message_id) = &message.client_message_id {
+        item.insert("client_message_id".to_string(), AttributeValue::S(client_message_id.clone()));
+    }
+    if let Some(edited_at) = &message.edited_at {
+        item.insert("edited_at_iso".to_string(), AttributeValue::S(edited_at.to_rfc3339()));
+    }
+    if let Some(deleted_at) = &message.deleted_at {
+        item.insert("deleted_at_iso".to_string(), AttributeValue::S(deleted_at.to_rfc3339()));
+    }
+    if let Some(parent_id) = &message.parent_id {
+        item.insert("parent_id".to_string(), AttributeValue::S(parent_id.clone()));
+    }
+    if message.reply_count > 0 {
+        item.insert("reply_count".to_string(), AttributeValue::N(message.reply_count.to_string()));
+    }
+    if let Some(last_reply_at) = &message.last_reply_at {
+        item.insert("last_reply_at_iso".to_string(), AttributeValue::S(last_reply_at.to_rfc3339()));
+    }
+    if let Some(filter) = &message.filter {
+        item.insert("filter_action".to_string(), AttributeValue::S(filter.action.as_str().into()));
+        item.insert(
+            "filter_names".to_string(),
+            AttributeValue::L(filter.filters.iter().cloned().map(AttributeValue::S).collect()),
+        );
+    }
+    item
+}
+
+fn message_from_item(item: &Item) -> Option<ChatMessage> {
+    let id = get_s(item, "id")?;
+    let room_id = get_s(item, "room_id")?;
+    let user_id = get_s(item, "user_id").unwrap_or_else(|| "unknown".to_string());
+    let username = get_s(item, "username")?;
+    let message_text = get_s(item, "message_text")?;
+    let created_at = DateTime::from_timestamp_millis(get_n(item, "ts")?)?;
+    let client_message_id = get_s(item, "client_message_id");
+    let edited_at = get_time(item, "edited_at_iso");
+    let deleted_at = get_time(item, "deleted_at_iso");
+
+    Some(ChatMessage {
+        id,
+        room_id,
+        user_id,
+        username,
+        message_text,
+        created_at: created_at.with_timezone(&Utc),
+        client_message_id,
+        edited_at,
+        deleted_at,
+        reactions: summarize_reactions(reaction_entries(item)),
+        parent_id: get_s(item, "parent_id"),
+        reply_count: get_n(item, "reply_count").unwrap_or_default() as u32,
+        last_reply_at: get_time(item, "last_reply_at_iso"),
+        filter: filter_from_item(item),
+    })
+}
+
+fn filter_from_item(item: &Item) -> Option<FilterDecision> {
+    let action = FilterAction::parse(&get_s(item, "filter_action")?)?;
+    let filters = item
+        .get("filter_names")
+        .and_then(|v| v.as_l().ok())
+        .map(|names| names.iter().filter_map(|name| name.as_s().ok().cloned()).collect())
+        .unwrap_or_default();
+    Some(FilterDecision { action, filters })
+}
+
+fn member_to_item(member: &RoomMember) -> Item {
+    let mut item = HashMap::new();
+    item.insert("room_id".to_string(), AttributeValue::S(member.room_id.clone()));
+    item.insert("user_id".to_string(), AttributeValue::S(member.user_id.clone()));
+    item.insert("role".to_string(), AttributeValue::S(member.role.as_str().to_string()));
+    item.insert("joined_at_iso".to_string(), AttributeValue::S(member.joined_at.to_rfc3339()));
+    item
+}
+
+fn member_from_item(item: &Item) -> Option<RoomMember> {
+    Some(RoomMember {
+        room_id: get_s(item, "room_id")?,
+        user_id: get_s(item, "user_id")?,
+        // Unknown roles get the least privilege
+        role: get_s(item, "role")
+            .and_then(|role| RoomRole::parse(&role))
+            .unwrap_or(RoomRole::Member),
+        joined_at: get_time(item, "joined_at_iso").unwrap_or_else(Utc::now),
+    })
+}
+
+fn sanction_to_item(sanction: &RoomSanction) -> Item {
+    let mut item = HashMap::new();
+    item.insert("room_id".to_string(), AttributeValue::S(sanction.room_id.clone()));
+    item.insert("user_id".to_string(), AttributeValue::S(sanction.user_id.clone()));
+    item.insert("banned".to_string(), AttributeValue::Bool(sanction.banned));
+    item.insert("moderator_id".to_string(), AttributeValue::S(sanction.moderator_id.clone()));
+    item.insert("updated_at_iso".to_string(), AttributeValue::S(sanction.updated_at.to_rfc3339()));
+    if let Some(until) = sanction.muted_until {
+        item.insert("muted_until_iso".to_string(), AttributeValue::S(until.to_rfc3339()));
+        // A mute on its own expires with the item; bans stay until lifted
+        if !sanction.banned {
+            item.insert("ttl".to_string(), AttributeValue::N(until.timestamp().to_string()));
+        }
+    }
+    if let Some(reason) = &sanction.reason {
+        item.insert("reason".to_string(), AttributeValue::S(reason.clone()));
+    }
+    item
+}
+
+fn sanction_from_item(item: &Item) -> Option<RoomSanction> {
+    Some(RoomSanction {
+        room_id: get_s(item, "room_id")?,
+        user_id: get_s(item, "user_id")?,
+        muted_until: get_time(item, "muted_until_iso"),
+        banned: item.get("banned").and_then(|v| v.as_bool().ok()).copied().unwrap_or(false),
+        reason: get_s(item, "reason"),
+        moderator_id: get_s(item, "moderator_id").unwrap_or_default(),
+        updated_at: get_time(item, "updated_at_iso").unwrap_or_else(Utc::now),
+    })
+}
+
+fn report_to_item(report: &MessageReport) -> Item {
+    let mut item = HashMap::new();
+    item.insert("room_id".to_string(), AttributeValue::S(report.room_id.clone()));
+    item.insert("report_id".to_string(), AttributeValue::S(report.id.clone()));
+    item.insert("message_id".to_string(), AttributeValue::S(report.message_id.clone()));
+    item.insert("reporter_id".to_string(), AttributeValue::S(report.reporter_id.clone()));
+    item.insert("reason".to_string(), AttributeValue::S(report.reason.clone()));
+    item.insert("message_user_id".to_string(), AttributeValue::S(report.message_user_id.clone()));
+    item.insert("message_text".to_string(), AttributeValue::S(report.message_text.clone()));
+    item.insert("status".to_string(), AttributeValue::S(report.status.as_str().to_string()));
+    item.insert("created_at_iso".to_string(), AttributeValue::S(report.created_at.to_rfc3339()));
+    if let Some(resolved_by) = &report.resolved_by {
+        item.insert("resolved_by".to_string(), AttributeValue::S(resolved_by.clone()));
+    }
+    if let Some(resolved_at) = report.resolved_at {
+        item.insert("resolved_at_iso".to_string(), AttributeValue::S(resolved_at.to_rfc3339()));
+    }
+    item
+}
+
+fn report_from_item(item: &Item) -> Option<MessageReport> {
+    Some(MessageReport {
+        id: get_s(item, "report_id")?,
+        room_id: get_s(item, "room_id")?,
+        message_id: get_s(item, "message_id")?,
+        reporter_id: get_s(item, "reporter_id").unwrap_or_default(),
+        reason: get_s(item, "reason").unwrap_or_default(),
+        message_user_id: get_s(item, "message_user_id").unwrap_or_default(),
+        message_text: get_s(item, "message_text").unwrap_or_default(),
+        status: get_s(item, "status")
+            .and_then(|status| ReportStatus::parse(&status))
+            .unwrap_or(ReportStatus::Open),
+        created_at: get_time(item, "created_at_iso").unwrap_or_else(Utc::now),
+        resolved_by: get_s(item, "resolved_by"),
+        resolved_at: get_time(item, "resolved_at_iso"),
+    })
+}
+
+fn read_marker_to_item(marker: &ReadMarker) -> Item {
+    let mut item = HashMap::new();
+    item.insert("room_id".to_string(), AttributeValue::S(marker.room_id.clone()));
+    item.insert("user_id".to_string(), AttributeValue::S(marker.user_id.clone()));
+    item.insert("message_id".to_string(), AttributeValue::S(marker.message_id.clone()));
+    item.insert("read_at_iso".to_string(), AttributeValue::S(marker.read_at.to_rfc3339()));
+    item
+}
+
+fn read_marker_from_item(item: &Item) -> Option<ReadMarker> {
+    Some(ReadMarker {
+        room_id: get_s(item, "room_id")?,
+        user_id: get_s(item, "user_id")?,
+        message_id: get_s(item, "message_id")?,
+        read_at: get_time(item, "read_at_iso").unwrap_or_else(Utc::now),
+    })
+}
+
+fn connection_to_item(connection: &Connection) -> Item {
+    let mut item = HashMap::new();
+    item.insert("connection_id".to_string(), AttributeValue::S(connection.connection_id.clone()));
+    item.insert("room_id".to_string(), AttributeValue::S(connection.room_id.clone()));
+    item.insert("user_id".to_string(), AttributeValue::S(connection.user_id.clone()));
+    item.insert("username".to_string(), AttributeValue::S(connection.username.clone()));
+    item.insert("connected_at".to_string(), AttributeValue::N(connection.connected_at.to_string()));
+    item.insert("domain".to_string(), AttributeValue::S(connection.domain.clone()));
+    item.insert("stage".to_string(), AttributeValue::S(connection.stage.clone()));
+    // Explicitly mark transport for broadcaster
+    item.insert("transport".to_string(), AttributeValue::S(connection.transport.clone()));
+    if let Some(push_url) = &connection.push_url {
+        item.insert("push_url".to_string(), AttributeValue::S(push_url.clone()));
+    }
+    item.insert("ttl".to_string(), AttributeValue::N(connection.ttl.to_string()));
+    if let Some(typing_until) = connection.typing_until {
+        item.insert("typing_until".to_string(), AttributeValue::N(typing_until.to_string()));
+    }
+    item
+}
+
+fn connection_from_item(item: &Item) -> Option<Connection> {
+    Some(Connection {
+        connection_id: get_s(item, "connection_id")?,
+        room_id: get_s(item, "room_id").unwrap_or_else(|| "unknown".to_string()),
+        user_id: get_s(item, "user_id").unwrap_or_else(|| "unknown".to_string()),
+        username: get_s(item, "username").unwrap_or_default(),
+        connected_at: get_n(item, "connected_at").unwrap_or_default(),
+        domain: get_s(item, "domain").unwrap_or_else(|| "unknown".to_string()),
+        stage: get_s(item, "stage").unwrap_or_else(|| "unknown".to_string()),
+        // Default to apigw if missing
+        transport: get_s(item, "transport").unwrap_or_else(|| "apigw".to_string()),
+        push_url: get_s(item, "push_url"),
+        ttl: get_n(item, "ttl").unwrap_or_default(),
+        typing_until: get_n(item, "typing_until"),
+    })
+}
+
+#[async_trait]
+impl ChatStore for DynamoStore {
+    async fn get_room(&self, room_id: &str) -> Result<Option<Room>, StoreError> {
+        let output = self
+            .ddb
+            .get_item()
+            .table_name(&self.tables.rooms)
+            .key("id", AttributeValue::S(room_id.to_string()))
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(output.item.as_ref().and_then(room_from_item))
+    }
+
+    async fn create_room(&self, room: &Room) -> Result<(), StoreError> {
+        self.ddb
+            .put_item()
+            .table_name(&self.tables.rooms)
+            .set_item(Some(room_to_item(room)))
+            .condition_expression("attribute_not_exists(id)")
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(se) if se.is_conditional_check_failed_exception() => {
+                    StoreError::ConditionFailed
+                }
+                _ => backend_error(e),
+            })?;
+        Ok(())
+    }
+
+    async fn update_room(&self, room: &Room) -> Result<(), StoreError> {
+        self.ddb
+            .put_item()
+            .table_name(&self.tables.rooms)
+            .set_item(Some(room_to_item(room)))
+            .condition_expression("attribute_exists(id)")
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(se) if se.is_conditional_check_failed_exception() => {
+                    StoreError::ConditionFailed
+                }
+                _ => backend_error(e),
+            })?;
+        Ok(())
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<Room>, StoreError> {
+        // Rooms are few; a paginated scan is enough
+        let mut rooms = Vec::new();
+        let mut start_key = None;
+        loop {
+            let page = self
+                .ddb
+                .scan()
+                .table_name(&self.tables.rooms)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(backend_error)?;
+            rooms.extend(page.items.unwrap_or_default().iter().filter_map(room_from_item));
+
+            start_key = page.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+        rooms.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(rooms)
+    }
+
+    async fn record_room_activity(
+        &self,
+        room_id: &str,
+        last_message_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        self.ddb
+            .update_item()
+            .table_name(&self.tables.rooms)
+            .key("id", AttributeValue::S(room_id.to_string()))
+            .update_expression("SET last_message_at_iso = :at")
+            .condition_expression("attribute_exists(id)")
+            .expression_attribute_values(":at", AttributeValue::S(last_message_at.to_rfc3339()))
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(se) if se.is_conditional_check_failed_exception() => {
+                    StoreError::ConditionFailed
+                }
+                _ => backend_error(e),
+            })?;
+        Ok(())
+    }
+
+    async fn put_message(&self, message: &ChatMessage) -> Result<(), StoreError> {
+        self.ddb
+            .put_item()
+            .table_name(&self.tables.messages)
+            .set_item(Some(message_to_item(message)))
+            .condition_expression("attribute_not_exists(id)")
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(se) if se.is_conditional_check_failed_exception() => {
+                    StoreError::ConditionFailed
+                }
+                _ => backend_error(e),
+            })?;
+        Ok(())
+    }
+
+    async fn put_message_once(
+        &self,
+        message: &ChatMessage,
+        dedup_expires_at: i64,
+    ) -> Result<PutMessageOutcome, StoreError> {
+        let Some(key) = dedup_key(message) else {
+            self.put_message(message).await?;
+            return Ok(PutMessageOutcome::Created);
+        };
+        let mut marker = HashMap::new();
+        marker.insert("dedup_key".to_string(), AttributeValue::S(key.clone()));
+        marker.insert("room_id".to_string(), AttributeValue::S(message.room_id.clone()));
+        marker.insert("message_id".to_string(), AttributeValue::S(message.id.clone()));
+        marker.insert("expires_at".to_string(), AttributeValue::N(dedup_expires_at.to_string()));
+        // TTL for automatic cleanup of old markers
+        marker.insert("ttl".to_string(), AttributeValue::N(dedup_expires_at.to_string()));
+
+        // Claim the marker (unless a live one exists) and write the message atomically
+        let claim = Put::builder()
+            .table_name(&self.tables.dedup)
+            .set_item(Some(marker))
+            .condition_expression("attribute_not_exists(dedup_key) OR expires_at < :now")
+            .expression_attribute_values(
+                ":now",
+                AttributeValue::N(Utc::now().timestamp().to_string()),
+            )
+            .build()
+            .map_err(backend_error)?;
+        let write = Put::builder()
+            .table_name(&self.tables.messages)
+            .set_item(Some(message_to_item(message)))
+            .condition_expression("attribute_not_exists(id)")
+            .build()
+            .map_err(backend_error)?;
+
+        let result = self
+            .ddb
+            .transact_write_items()
+            .transact_items(TransactWriteItem::builder().put(claim).build())
+            .transact_items(TransactWriteItem::builder().put(write).build())
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(PutMessageOutcome::Created),
+            Err(e) => {
+                // Cancellation reasons line up with the transaction items: [claim, write]
+                let failed: Vec<bool> = match e.as_service_error() {
+                    Some(TransactWriteItemsError::TransactionCanceledException(canceled)) => {
+                        canceled
+                            .cancellation_reasons()
+                            .iter()
+                            .map(|reason| reason.code() == Some("ConditionalCheckFailed"))
+                            .collect()
+                    }
+                    _ => Vec::new(),
+                };
+                match failed.as_slice() {
+                    [true, ..] => {}
+                    [false, true] => return Err(StoreError::ConditionFailed),
+                    _ => return Err(backend_error(e)),
+                }
+
+                // A live marker exists; load the message it points at
+                let original =
+                    self.find_duplicate(&key).await?.ok_or(StoreError::ConditionFailed)?;
+                Ok(PutMessageOutcome::Duplicate(Box::new(original)))
+            }
+        }
+    }
+
+    async fn find_duplicate(&self, key: &str) -> Result<Option<ChatMessage>, StoreError> {
+        let marker = self
+            .ddb
+            .get_item()
+            .table_name(&self.tables.dedup)
+            .key("dedup_key", AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(backend_error)?
+            .item;
+        // Expired by the same test put_message_once claims it with
+        let Some(marker) =
+            marker.filter(|m| get_n(m, "expires_at").unwrap_or_default() >= Utc::now().timestamp())
+        else {
+            return Ok(None);
+        };
+        let (Some(room_id), Some(message_id)) = (marker.get("room_id"), marker.get("message_id"))
+        else {
+            return Ok(None);
+        };
+        let output = self
+            .ddb
+            .get_item()
+            .table_name(&self.tables.messages)
+            .key("room_id", room_id.clone())
+            .key("id", message_id.clone())
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(backend_error)?;
+        Ok(output.item.as_ref().and_then(message_from_item))
+    }
+
+    async fn get_message(
+        &self,
+        room_id: &str,
+        message_id: &str,
+    ) -> Result<Option<ChatMessage>, StoreError> {
+        let output = self
+            .ddb
+            .get_item()
+            .table_name(&self.tables.messages)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("id", AttributeValue::S(message_id.to_string()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(output.item.as_ref().and_then(message_from_item))
+    }
+
+    async fn edit_message(
+        &self,
+        message: &ChatMessage,
+        message_text: &str,
+        filter: Option<&FilterDecision>,
+        edited_at: DateTime<Utc>,
+    ) -> Result<ChatMessage, StoreError> {
+        // Prior texts are kept on the item as a list of {message_text, replaced_at_iso}
+        let revision = AttributeValue::M(HashMap::from([
+            ("message_text".to_string(), AttributeValue::S(message.message_text.clone())),
+            ("replaced_at_iso".to_string(), AttributeValue::S(edited_at.to_rfc3339())),
+        ]));
+
+        let mut request = self
+            .ddb
+            .update_item()
+            .table_name(&self.tables.messages)
+            .key("room_id", AttributeValue::S(message.room_id.clone()))
+            .key("id", AttributeValue::S(message.id.clone()));
+        // The filters' decision follows the text, and goes away when the new text is clean
+        request = match filter {
+            Some(filter) => request
+                .update_expression(
+                    "SET message_text = :text, edited_at_iso = :edited_at, \
+                     revisions = list_append(if_not_exists(revisions, :empty), :revision), \
+                     filter_action = :filter_action, filter_names = :filter_names",
+                )
+                .expression_attribute_values(
+                    ":filter_action",
+                    AttributeValue::S(filter.action.as_str().into()),
+                )
+                .expression_attribute_values(
+                    ":filter_names",
+                    AttributeValue::L(
+                        filter.filters.iter().cloned().map(AttributeValue::S).collect(),
+                    ),
+                ),
+            None => request.update_expression(
+                "SET message_text = :text, edited_at_iso = :edited_at, \
+                 revisions = list_append(if_not_exists(revisions, :empty), :revision) \
+                 REMOVE filter_action, filter_names",
+            ),
+        };
+
+        let output = request
+            .condition_expression("message_text = :expected")
+            .expression_attribute_values(":text", AttributeValue::S(message_text.to_string()))
+            .expression_attribute_values(":edited_at", AttributeValue::S(edited_at.to_rfc3339()))
+            .expression_attribute_values(":empty", AttributeValue::L(Vec::new()))
+            .expression_attribute_values(":revision", AttributeValue::L(vec![revision]))
+            .expression_attribute_values(
+                ":expected",
+                AttributeValue::S(message.message_text.clone()),
+            )
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(se) if se.is_conditional_check_failed_exception() => {
+                    StoreError::ConditionFailed
+                }
+                _ => backend_error(e),
+            })?;
+
+        let item = output.attributes.ok_or_else(|| {
+            StoreError::Backend("Edited message missing from response".to_string())
+        })?;
+        let revisions = item.get("revisions").and_then(|v| v.as_l().ok()).map_or(0, Vec::len);
+        if revisions > MAX_REVISIONS {
+            self.trim_revisions(message, revisions).await;
+        }
+        message_from_item(&item)
+            .ok_or_else(|| StoreError::Backend("Edited message missing from response".to_string()))
+    }
+
+    async fn record_reply(
+        &self,
+        room_id: &str,
+        parent_id: &str,
+        replied_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        self.ddb
+            .update_item()
+            .table_name(&self.tables.messages)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("id", AttributeValue::S(parent_id.to_string()))
+            .update_expression("ADD reply_count :one SET last_reply_at_iso = :replied_at")
+            .condition_expression("attribute_exists(id)")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":replied_at", AttributeValue::S(replied_at.to_rfc3339()))
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(se) if se.is_conditional_check_failed_exception() => {
+                    StoreError::ConditionFailed
+                }
+                _ => backend_error(e),
+            })?;
+        Ok(())
+    }
+
+    async fn message_revisions(
+        &self,
+        room_id: &str,
+        message_id: &str,
+    ) -> Result<Vec<MessageRevision>, StoreError> {
+        let output = self
+            .ddb
+            .get_item()
+            .table_name(&self.tables.messages)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("id", AttributeValue::S(message_id.to_string()))
+            .projection_expression("revisions")
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        let revisions = output
+            .item
+            .as_ref()
+            .and_then(|item| item.get("revisions"))
+            .and_then(|v| v.as_l().ok())
+            .map(|list| {
+                list.iter()
+                    .filter_map(|v| v.as_m().ok())
+                    .filter_map(|revision| {
+                        Some(MessageRevision {
+                            message_text: get_s(revision, "message_text")?,
+                            replaced_at: get_time(revision, "replaced_at_iso")?,
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(revisions)
+    }
+
+    async fn delete_message(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        deleted_at: DateTime<Utc>,
+    ) -> Result<ChatMessage, StoreError> {
+        let output = self
+            .ddb
+            .update_item()
+            .table_name(&self.tables.messages)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("id", AttributeValue::S(message_id.to_string()))
+            .update_expression(
+                "SET message_text = :text, deleted_at_iso = :deleted_at \
+                 REMOVE revisions, reactions",
+            )
+            .condition_expression("attribute_exists(id)")
+            .expression_attribute_values(":text", AttributeValue::S(DELETED_MESSAGE_TEXT.into()))
+            .expression_attribute_values(":deleted_at", AttributeValue::S(deleted_at.to_rfc3339()))
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(se) if se.is_conditional_check_failed_exception() => {
+                    StoreError::ConditionFailed
+                }
+                _ => backend_error(e),
+            })?;
+
+        output
+            .attributes
+            .as_ref()
+            .and_then(message_from_item)
+            .ok_or_else(|| StoreError::Backend("Deleted message missing from response".to_string()))
+    }
+
+    async fn query_messages(
+        &self,
+        room_id: &str,
+        query: &MessageQuery,
+    ) -> Result<MessagePage, StoreError> {
+        let mut request = self
+            .ddb
+            .query()
+            .table_name(&self.tables.messages)
+            .expression_attribute_values(":room_id", AttributeValue::S(room_id.to_string()));
+        // Threads come from the reply index; the room timeline skips replies
+        let mut key_condition = match &query.parent_id {
+            Some(parent_id) => {
+                request = request
+                    .index_name(THREAD_INDEX)
+                    .filter_expression("room_id = :room_id")
+                    .expression_attribute_values(
+                        ":parent_id",
+                        AttributeValue::S(parent_id.clone()),
+                    );
+                "parent_id = :parent_id".to_string()
+            }
+            None => {
+                request = request.filter_expression("attribute_not_exists(parent_id)");
+                "room_id = :room_id".to_string()
+            }
+        };
+
+        // Cursors are exclusive bounds on the id sort key
+        match (&query.after, &query.before) {
+            (Some(after), Some(before)) => {
+                if after >= before {
+                    return Ok(MessagePage::default());
+                }
+                // BETWEEN is inclusive; the bounds themselves are dropped below
+                key_condition.push_str(" AND id BETWEEN :after AND :before");
+                request = request
+                    .expression_attribute_values(":after", AttributeValue::S(after.clone()))
+                    .expression_attribute_values(":before", AttributeValue::S(before.clone()));
+            }
+            (Some(after), None) => {
+                key_condition.push_str(" AND id > :after");
+                request =
+                    request.expression_attribute_values(":after", AttributeValue::S(after.clone()));
+            }
+            (None, Some(before)) => {
+                key_condition.push_str(" AND id < :before");
+                request = request
+                    .expression_attribute_values(":before", AttributeValue::S(before.clone()));
+            }
+            (None, None) => {}
+        }
+
+        let result = request
+            .key_condition_expression(key_condition)
+            .scan_index_forward(!query.newest_first)
+            .limit(query.limit as i32)
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        let messages = result
+            .items
+            .unwrap_or_default()
+            .iter()
+            .filter_map(message_from_item)
+            .filter(|m| query.after.as_ref() != Some(&m.id) && query.before.as_ref() != Some(&m.id))
+            .collect();
+        // Filtered-out items still count towards the limit, so a page can come back short.
+        // LastEvaluatedKey always carries the id sort key, which is all a client needs to resume
+        let next_cursor = result.last_evaluated_key.as_ref().and_then(|key| get_s(key, "id"));
+
+        Ok(MessagePage { messages, next_cursor })
+    }
+
+    async fn add_reaction(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+        max_distinct: usize,
+    ) -> Result<ReactionOutcome, StoreError> {
+        for _ in 0..REACTION_WRITE_ATTEMPTS {
+            let item = self
+                .ddb
+                .get_item()
+                .table_name(&self.tables.messages)
+                .key("room_id", AttributeValue::S(room_id.to_string()))
+                .key("id", AttributeValue::S(message_id.to_string()))
+                .projection_expression("id, deleted_at_iso, reactions")
+                .consistent_read(true)
+                .send()
+                .await
+                .map_err(backend_error)?
+                .item
+                .filter(|item| !item.contains_key("deleted_at_iso"))
+                .ok_or(StoreError::ConditionFailed)?;
+
+            let entries = reaction_entries(&item);
+            if entries.iter().any(|(e, u)| e == emoji && u == user_id) {
+                return Ok(ReactionOutcome::AlreadyPresent);
+            }
+            let distinct = summarize_reactions(entries.iter().cloned());
+            if !distinct.iter().any(|r| r.emoji == emoji) && distinct.len() >= max_distinct {
+                return Ok(ReactionOutcome::LimitReached);
+            }
+
+            // Only apply if the set is still the one the limit was checked against
+            let unchanged = if entries.is_empty() {
+                "attribute_not_exists(reactions)"
+            } else {
+                "size(reactions) = :size"
+            };
+            let mut request = self
+                .ddb
+                .update_item()
+                .table_name(&self.tables.messages)
+                .key("room_id", AttributeValue::S(room_id.to_string()))
+                .key("id", AttributeValue::S(message_id.to_string()))
+                .update_expression("ADD reactions :entry")
+                .condition_expression(format!(
+                    "attribute_exists(id) AND attribute_not_exists(deleted_at_iso) AND {}",
+                    unchanged
+                ))
+                .expression_attribute_values(
+                    ":entry",
+                    AttributeValue::Ss(vec![reaction_entry(emoji, user_id)]),
+                );
+            if !entries.is_empty() {
+                request = request.expression_attribute_values(
+                    ":size",
+                    AttributeValue::N(entries.len().to_string()),
+                );
+            }
+
+            match request.send().await {
+                Ok(_) => return Ok(ReactionOutcome::Added),
+                Err(e) => match e.as_service_error() {
+                    // Changed (or deleted) since we read it; re-check
+                    Some(se) if se.is_conditional_check_failed_exception() => continue,
+                    _ => return Err(backend_error(e)),
+                },
+            }
+        }
+        Err(StoreError::Backend("Reactions changed too often to update".to_string()))
+    }
+
+    async fn remove_reaction(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> Result<bool, StoreError> {
+        let entry = reaction_entry(emoji, user_id);
+        let result = self
+            .ddb
+            .update_item()
+            .table_name(&self.tables.messages)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("id", AttributeValue::S(message_id.to_string()))
+            .update_expression("DELETE reactions :entries")
+            .condition_expression("contains(reactions, :entry)")
+            .expression_attribute_values(":entries", AttributeValue::Ss(vec![entry.clone()]))
+            .expression_attribute_values(":entry", AttributeValue::S(entry))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) => match e.as_service_error() {
+                Some(se) if se.is_conditional_check_failed_exception() => Ok(false),
+                _ => Err(backend_error(e)),
+            },
+        }
+    }
+
+    async fn get_member(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<RoomMember>, StoreError> {
+        let output = self
+            .ddb
+            .get_item()
+            .table_name(&self.tables.members)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("user_id", AttributeValue::S(user_id.to_string()))
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(output.item.as_ref().and_then(member_from_item))
+    }
+
+    async fn put_member(&self, member: &RoomMember) -> Result<(), StoreError> {
+        self.ddb
+            .put_item()
+            .table_name(&self.tables.members)
+            .set_item(Some(member_to_item(member)))
+            .send()
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    async fn delete_member(&self, room_id: &str, user_id: &str) -> Result<bool, StoreError> {
+        let output = self
+            .ddb
+            .delete_item()
+            .table_name(&self.tables.members)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("user_id", AttributeValue::S(user_id.to_string()))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await
+            .map_err(backend_error)?;
+        Ok(output.attributes.is_some_and(|item| !item.is_empty()))
+    }
+
+    async fn room_members(&self, room_id: &str) -> Result<Vec<RoomMember>, StoreError> {
+        // The members table is keyed (room_id, user_id), so pages come back sorted by user
+        let mut members = Vec::new();
+        let mut start_key = None;
+        loop {
+            let page = self
+                .ddb
+                .query()
+                .table_name(&self.tables.members)
+                .key_condition_expression("room_id = :room_id")
+                .expression_attribute_values(":room_id", AttributeValue::S(room_id.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(backend_error)?;
+            members.extend(page.items.unwrap_or_default().iter().filter_map(member_from_item));
+
+            start_key = page.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+        Ok(members)
+    }
+
+    async fn user_memberships(&self, user_id: &str) -> Result<Vec<RoomMember>, StoreError> {
+        let mut memberships = Vec::new();
+        let mut start_key = None;
+        loop {
+            let page = self
+                .ddb
+                .query()
+                .table_name(&self.tables.members)
+                .index_name(USER_INDEX)
+                .key_condition_expression("user_id = :user_id")
+                .expression_attribute_values(":user_id", AttributeValue::S(user_id.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(backend_error)?;
+            memberships.extend(page.items.unwrap_or_default().iter().filter_map(member_from_item));
+
+            start_key = page.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+        Ok(memberships)
+    }
+
+    async fn get_sanction(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<RoomSanction>, StoreError> {
+        let output = self
+            .ddb
+            .get_item()
+            .table_name(&self.tables.sanctions)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("user_id", AttributeValue::S(user_id.to_string()))
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(output.item.as_ref().and_then(sanction_from_item))
+    }
+
+    async fn put_sanction(&self, sanction: &RoomSanction) -> Result<(), StoreError> {
+        self.ddb
+            .put_item()
+            .table_name(&self.tables.sanctions)
+            .set_item(Some(sanction_to_item(sanction)))
+            .send()
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    async fn delete_sanction(&self, room_id: &str, user_id: &str) -> Result<bool, StoreError> {
+        let output = self
+            .ddb
+            .delete_item()
+            .table_name(&self.tables.sanctions)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("user_id", AttributeValue::S(user_id.to_string()))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await
+            .map_err(backend_error)?;
+        Ok(output.attributes.is_some_and(|item| !item.is_empty()))
+    }
+
+    async fn room_sanctions(&self, room_id: &str) -> Result<Vec<RoomSanction>, StoreError> {
+        let mut sanctions = Vec::new();
+        let mut start_key = None;
+        loop {
+            let page = self
+                .ddb
+                .query()
+                .table_name(&self.tables.sanctions)
+                .key_condition_expression("room_id = :room_id")
+                .expression_attribute_values(":room_id", AttributeValue::S(room_id.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(backend_error)?;
+            sanctions.extend(page.items.unwrap_or_default().iter().filter_map(sanction_from_item));
+
+            start_key = page.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+        Ok(sanctions)
+    }
+
+    async fn get_report(
+        &self,
+        room_id: &str,
+        report_id: &str,
+    ) -> Result<Option<MessageReport>, StoreError> {
+        let output = self
+            .ddb
+            .get_item()
+            .table_name(&self.tables.reports)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("report_id", AttributeValue::S(report_id.to_string()))
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(output.item.as_ref().and_then(report_from_item))
+    }
+
+    async fn put_report(&self, report: &MessageReport) -> Result<(), StoreError> {
+        self.ddb
+            .put_item()
+            .table_name(&self.tables.reports)
+            .set_item(Some(report_to_item(report)))
+            .send()
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    async fn room_reports(
+        &self,
+        room_id: &str,
+        status: Option<ReportStatus>,
+    ) -> Result<Vec<MessageReport>, StoreError> {
+        let mut reports = Vec::new();
+        let mut start_key = None;
+        loop {
+            let mut request = self
+                .ddb
+                .query()
+                .table_name(&self.tables.reports)
+                .key_condition_expression("room_id = :room_id")
+                .expression_attribute_values(":room_id", AttributeValue::S(room_id.to_string()))
+                .set_exclusive_start_key(start_key);
+            if let Some(status) = status {
+                request = request
+                    .filter_expression("#status = :status")
+                    .expression_attribute_names("#status", "status")
+                    .expression_attribute_values(
+                        ":status",
+                        AttributeValue::S(status.as_str().to_string()),
+                    );
+            }
+            let page = request.send().await.map_err(backend_error)?;
+            reports.extend(page.items.unwrap_or_default().iter().filter_map(report_from_item));
+
+            start_key = page.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+        Ok(reports)
+    }
+
+    async fn get_read_marker(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<ReadMarker>, StoreError> {
+        let output = self
+            .ddb
+            .get_item()
+            .table_name(&self.tables.reads)
+            .key("room_id", AttributeValue::S(room_id.to_string()))
+            .key("user_id", AttributeValue::S(user_id.to_string()))
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(output.item.as_ref().and_then(read_marker_from_item))
+    }
+
+    async fn advance_read_marker(&self, marker: &ReadMarker) -> Result<bool, StoreError> {
+        let result = self
+            .ddb
+            .put_item()
+            .table_name(&self.tables.reads)
+            .set_item(Some(read_marker_to_item(marker)))
+            .condition_expression("attribute_not_exists(room_id) OR message_id < :message_id")
+            .expression_attribute_values(
+                ":message_id",
+                AttributeValue::S(marker.message_id.clone()),
+            )
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) => match e.as_service_error() {
+                Some(se) if se.is_conditional_check_failed_exception() => Ok(false),
+                _ => Err(backend_error(e)),
+            },
+        }
+    }
+
+    async fn room_read_markers(&self, room_id: &str) -> Result<Vec<ReadMarker>, StoreError> {
+        let mut markers = Vec::new();
+        let mut start_key = None;
+        loop {
+            let page = self
+                .ddb
+                .query()
+                .table_name(&self.tables.reads)
+                .key_condition_expression("room_id = :room_id")
+                .expression_attribute_values(":room_id", AttributeValue::S(room_id.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(backend_error)?;
+            markers.extend(page.items.unwrap_or_default().iter().filter_map(read_marker_from_item));
+
+            start_key = page.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+        Ok(markers)
+    }
+
+    async fn user_read_markers(&self, user_id: &str) -> Result<Vec<ReadMarker>, StoreError> {
+        let mut markers = Vec::new();
+        let mut start_key = None;
+        loop {
+            let page = self
+                .ddb
+                .query()
+                .table_name(&self.tables.reads)
+                .index_name(USER_INDEX)
+                .key_condition_expression("user_id = :user_id")
+                .expression_attribute_values(":user_id", AttributeValue::S(user_id.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(backend_error)?;
+            markers.extend(page.items.unwrap_or_default().iter().filter_map(read_marker_from_item));
+
+            start_key = page.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+        Ok(markers)
+    }
+
+    async fn put_connection(&self, connection: &Connection) -> Result<(), StoreError> {
+        self.ddb
+            .put_item()
+            .table_name(&self.tables.connections)
+            .set_item(Some(connection_to_item(connection)))
+            .send()
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    async fn get_connection(&self, connection_id: &str) -> Result<Option<Connection>, StoreError> {
+        let output = self
+            .ddb
+            .get_item()
+            .table_name(&self.tables.connections)
+            .key("connection_id", AttributeValue::S(connection_id.to_string()))
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(output.item.as_ref().and_then(connection_from_item))
+    }
+
+    async fn delete_connection(&self, connection_id: &str) -> Result<(), StoreError> {
+        self.ddb
+            .delete_item()
+            .table_name(&self.tables.connections)
+            .key("connection_id", AttributeValue::S(connection_id.to_string()))
+            .send()
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    async fn set_typing(
+        &self,
+        connection_id: &str,
+        typing_until: Option<i64>,
+    ) -> Result<(), StoreError> {
+        let request = self
+            .ddb
+            .update_item()
+            .table_name(&self.tables.connections)
+            .key("connection_id", AttributeValue::S(connection_id.to_string()))
+            // Updating a deleted connection would bring back a stub of it
+            .condition_expression("attribute_exists(connection_id)");
+        let request = match typing_until {
+            Some(until) => request
+                .update_expression("SET typing_until = :until")
+                .expression_attribute_values(":until", AttributeValue::N(until.to_string())),
+            None => request.update_expression("REMOVE typing_until"),
+        };
+        match request.send().await {
+            Ok(_) => Ok(()),
+            Err(e) => match e.as_service_error() {
+                Some(se) if se.is_conditional_check_failed_exception() => Ok(()),
+                _ => Err(backend_error(e)),
+            },
+        }
+    }
+
+    async fn room_connections(&self, room_id: &str) -> Result<Vec<Connection>, StoreError> {
+        // Query for all connections in this room using GSI
+        let result = self
+            .ddb
+            .query()
+            .table_name(&self.tables.connections)
+            .index_name("room-index")
+            .key_condition_expression("room_id = :room_id")
+            .expression_attribute_values(":room_id", AttributeValue::S(room_id.to_string()))
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(result.items.unwrap_or_default().iter().filter_map(connection_from_item).collect())
+    }
+
+    fn streams_changes(&self) -> bool {
+        true
+    }
+}
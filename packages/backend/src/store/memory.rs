@@ -0,0 +1,774 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use tokio::sync::RwLock;
+
+use super::{
+    dedup_key, summarize_reactions, ChatStore, Connection, MessagePage, MessageQuery,
+    MessageRevision, PutMessageOutcome, ReactionOutcome, StoreError, DELETED_MESSAGE_TEXT,
+    MAX_REVISIONS,
+};
+use types::{
+    ChatMessage, FilterDecision, MessageReport, ReadMarker, ReportStatus, Room, RoomMember,
+    RoomSanction,
+};
+
+#[derive(Default)]
+struct Inner {
+    rooms: HashMap<String, Room>,
+    // Messages per room keyed by id, mirroring the room_id + id table key
+    messages: HashMap<String, BTreeMap<String, ChatMessage>>,
+    // Dedup markers: dedup key -> (expires_at epoch secs, room_id, message id)
+    dedup: HashMap<String, (i64, String, String)>,
+    // Prior texts keyed by (room_id, message id)
+    revisions: HashMap<(String, String), Vec<MessageRevision>>,
+    // Reactions keyed by (room_id, message id), as (emoji, user_id) pairs
+    reactions: HashMap<(String, String), BTreeSet<(String, String)>>,
+    // Room members keyed by (room_id, user_id)
+    members: HashMap<(String, String), RoomMember>,
+    // Mutes and bans keyed by (room_id, user_id)
+    sanctions: HashMap<(String, String), RoomSanction>,
+    // Message reports keyed by (room_id, report_id)
+    reports: BTreeMap<(String, String), MessageReport>,
+    // Read markers keyed by (room_id, user_id)
+    read_markers: BTreeMap<(String, String), ReadMarker>,
+    connections: HashMap<String, Connection>,
+}
+
+impl Inner {
+    // Conditional insert, like a put with attribute_not_exists(id)
+    fn insert_message(&mut self, message: &ChatMessage) -> Result<(), StoreError> {
+        let room = self.messages.entry(message.room_id.clone()).or_default();
+        if room.contains_key(&message.id) {
+            return Err(StoreError::ConditionFailed);
+        }
+        room.insert(message.id.clone(), message.clone());
+        Ok(())
+    }
+
+    // Stored message with its reactions filled in, as the other backends return it
+    fn load_message(&self, message: &ChatMessage) -> ChatMessage {
+        let key = (message.room_id.clone(), message.id.clone());
+        let mut message = message.clone();
+        if let Some(reactions) = self.reactions.get(&key) {
+            message.reactions = summarize_reactions(reactions.iter().cloned());
+        }
+        message
+    }
+}
+
+// In-memory ChatStore for tests and offline development
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: RwLock<Inner>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ChatStore for MemoryStore {
+    async fn get_room(&self, room_id: &str) -> Result<Option<Room>, StoreError> {
+        Ok(self.inner.read().await.rooms.get(room_id).cloned())
+    }
+
+    async fn create_room(&self, room: &Room) -> Result<(), StoreError> {
+        let mut inner = self.inner.write().await;
+        if inner.rooms.contains_key(&room.id) {
+            return Err(StoreError::ConditionFailed);
+        }
+        inner.rooms.insert(room.id.clone(), room.clone());
+        Ok(())
+    }
+
+    async fn update_room(&self, room: &Room) -> Result<(), StoreError> {
+        let mut inner = self.inner.write().await;
+        let stored = inner.rooms.get_mut(&room.id).ok_or(StoreError::ConditionFailed)?;
+        *stored = room.clone();
+        Ok(())
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<Room>, StoreError> {
+        let mut rooms: Vec<Room> = self.inner.read().await.rooms.values().cloned().collect();
+        rooms.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(rooms)
+    }
+
+    async fn record_room_activity(
+        &self,
+        room_id: &str,
+        last_message_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        let mut inner = self.inner.write().await;
+        let room = inner.rooms.get_mut(room_id).ok_or(StoreError::ConditionFailed)?;
+        room.last_message_at = Some(last_message_at);
+        Ok(())
+    }
+
+    async fn put_message(&self, message: &ChatMessage) -> Result<(), StoreError> {
+        self.inner.write().await.insert_message(message)
+    }
+
+    async fn put_message_once(
+        &self,
+        message: &ChatMessage,
+        dedup_expires_at: i64,
+    ) -> Result<PutMessageOutcome, StoreError> {
+        let Some(key) = dedup_key(message) else {
+            self.put_message(message).await?;
+            return Ok(PutMessageOutcome::Created);
+        };
+
+        let mut inner = self.inner.write().await;
+        let now = Utc::now().timestamp();
+        if let Some((expires_at, room_id, id)) = inner.dedup.get(&key) {
+            if *expires_at > now {
+                if let Some(original) = inner.messages.get(room_id).and_then(|room| room.get(id)) {
+                    return Ok(PutMessageOutcome::Duplicate(Box::new(
+                        inner.load_message(original),
+                    )));
+                }
+            }
+        }
+
+        inner.insert_message(message)?;
+        inner.dedup.insert(key, (dedup_expires_at, message.room_id.clone(), message.id.clone()));
+        Ok(PutMessageOutcome::Created)
+    }
+
+    async fn find_duplicate(&self, key: &str) -> Result<Option<ChatMessage>, StoreError> {
+        let inner = self.inner.read().await;
+        let now = Utc::now().timestamp();
+        Ok(inner
+            .dedup
+            .get(key)
+            .filter(|(expires_at, _, _)| *expires_at > now)
+            .and_then(|(_, room_id, id)| inner.messages.get(room_id)?.get(id))
+            .map(|original| inner.load_message(original)))
+    }
+
+    async fn get_message(
+        &self,
+        room_id: &str,
+        message_id: &str,
+    ) -> Result<Option<ChatMessage>, StoreError> {
+        let inner = self.inner.read().await;
+        Ok(inner
+            .messages
+            .get(room_id)
+            .and_then(|room| room.get(message_id))
+            .map(|m| inner.load_message(m)))
+    }
+
+    async fn edit_message(
+        &self,
+        message: &ChatMessage,
+        message_text: &str,
+        filter: Option<&FilterDecision>,
+        edited_at: DateTime<Utc>,
+    ) -> Result<ChatMessage, StoreError> {
+        let mut inner = self.inner.write().await;
+        let stored = inner
+            .messages
+            .get_mut(&message.room_id)
+            .and_then(|room| room.get_mut(&message.id))
+            .filter(|stored| stored.message_text == message.message_text)
+            .ok_or(StoreError::ConditionFailed)?;
+
+        let previous = std::mem::replace(&mut stored.message_text, message_text.to_string());
+        stored.filter = filter.cloned();
+        stored.edited_at = Some(edited_at);
+        let edited = stored.clone();
+        let edited = inner.load_message(&edited);
+        let revisions =
+            inner.revisions.entry((message.room_id.clone(), message.id.clone())).or_default();
+        revisions.push(MessageRevision { message_text: previous, replaced_at: edited_at });
+        let excess = revisions.len().saturating_sub(MAX_REVISIONS);
+        revisions.drain(..excess);
+        Ok(edited)
+    }
+
+    async fn record_reply(
+        &self,
+        room_id: &str,
+        parent_id: &str,
+        replied_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        let mut inner = self.inner.write().await;
+        let root = inner
+            .messages
+            .get_mut(room_id)
+            .and_then(|room| room.get_mut(parent_id))
+            .ok_or(StoreError::ConditionFailed)?;
+        root.reply_count += 1;
+        root.last_reply_at = Some(replied_at);
+        Ok(())
+    }
+
+    async fn message_revisions(
+        &self,
+        room_id: &str,
+        message_id: &str,
+    ) -> Result<Vec<MessageRevision>, StoreError> {
+        let key = (room_id.to_string(), message_id.to_string());
+        Ok(self.inner.read().await.revisions.get(&key).cloned().unwrap_or_default())
+    }
+
+    async fn delete_message(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        deleted_at: DateTime<Utc>,
+    ) -> Result<ChatMessage, StoreError> {
+        let mut inner = self.inner.write().await;
+        let stored = inner
+            .messages
+            .get_mut(room_id)
+            .and_then(|room| room.get_mut(message_id))
+            .ok_or(StoreError::ConditionFailed)?;
+
+        stored.message_text = DELETED_MESSAGE_TEXT.to_string();
+        stored.deleted_at = Some(deleted_at);
+        let deleted = stored.clone();
+        let key = (room_id.to_string(), message_id.to_string());
+        inner.revisions.remove(&key);
+        inner.reactions.remove(&key);
+        Ok(deleted)
+    }
+
+    async fn add_reaction(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+        max_distinct: usize,
+    ) -> Result<ReactionOutcome, StoreError> {
+        let mut inner = self.inner.write().await;
+        inner
+            .messages
+            .get(room_id)
+            .and_then(|room| room.get(message_id))
+            .filter(|m| m.deleted_at.is_none())
+            .ok_or(StoreError::ConditionFailed)?;
+
+        let reactions =
+            inner.reactions.entry((room_id.to_string(), message_id.to_string())).or_default();
+        let entry = (emoji.to_string(), user_id.to_string());
+        if reactions.contains(&entry) {
+            return Ok(ReactionOutcome::AlreadyPresent);
+        }
+        let distinct: BTreeSet<&str> = reactions.iter().map(|(e, _)| e.as_str()).collect();
+        if !distinct.contains(emoji) && distinct.len() >= max_distinct {
+            return Ok(ReactionOutcome::LimitReached);
+        }
+        reactions.insert(entry);
+        Ok(ReactionOutcome::Added)
+    }
+
+    async fn remove_reaction(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> Result<bool, StoreError> {
+        let mut inner = self.inner.write().await;
+        let key = (room_id.to_string(), message_id.to_string());
+        let entry = (emoji.to_string(), user_id.to_string());
+        Ok(inner.reactions.get_mut(&key).is_some_and(|reactions| reactions.remove(&entry)))
+    }
+
+    async fn query_messages(
+        &self,
+        room_id: &str,
+        query: &MessageQuery,
+    ) -> Result<MessagePage, StoreError> {
+        let inner = self.inner.read().await;
+        let Some(room) = inner.messages.get(room_id) else {
+            return Ok(MessagePage::default());
+        };
+
+        let lower = query.after.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        let upper = query.before.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        if let (Some(after), Some(before)) = (&query.after, &query.before) {
+            if after >= before {
+                return Ok(MessagePage::default());
+            }
+        }
+        let range =
+            room.range::<str, _>((lower, upper)).filter(|(_, m)| m.parent_id == query.parent_id);
+
+        // Fetch one extra message to know whether another page exists
+        let mut messages: Vec<ChatMessage> = if query.newest_first {
+            range.rev().take(query.limit + 1).map(|(_, m)| inner.load_message(m)).collect()
+        } else {
+            range.take(query.limit + 1).map(|(_, m)| inner.load_message(m)).collect()
+        };
+
+        let next_cursor = if messages.len() > query.limit {
+            messages.truncate(query.limit);
+            messages.last().map(|m| m.id.clone())
+        } else {
+            None
+        };
+
+        Ok(MessagePage { messages, next_cursor })
+    }
+
+    async fn get_member(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<RoomMember>, StoreError> {
+        let key = (room_id.to_string(), user_id.to_string());
+        Ok(self.inner.read().await.members.get(&key).cloned())
+    }
+
+    async fn put_member(&self, member: &RoomMember) -> Result<(), StoreError> {
+        let key = (member.room_id.clone(), member.user_id.clone());
+        self.inner.write().await.members.insert(key, member.clone());
+        Ok(())
+    }
+
+    async fn delete_member(&self, room_id: &str, user_id: &str) -> Result<bool, StoreError> {
+        let key = (room_id.to_string(), user_id.to_string());
+        Ok(self.inner.write().await.members.remove(&key).is_some())
+    }
+
+    async fn room_members(&self, room_id: &str) -> Result<Vec<RoomMember>, StoreError> {
+        let inner = self.inner.read().await;
+        let mut members: Vec<RoomMember> =
+            inner.members.values().filter(|m| m.room_id == room_id).cloned().collect();
+        members.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        Ok(members)
+    }
+
+    async fn user_memberships(&self, user_id: &str) -> Result<Vec<RoomMember>, StoreError> {
+        let inner = self.inner.read().await;
+        let mut memberships: Vec<RoomMember> =
+            inner.members.values().filter(|m| m.user_id == user_id).cloned().collect();
+        memberships.sort_by(|a, b| a.room_id.cmp(&b.room_id));
+        Ok(memberships)
+    }
+
+    async fn get_sanction(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<RoomSanction>, StoreError> {
+        let key = (room_id.to_string(), user_id.to_string());
+        Ok(self.inner.read().await.sanctions.get(&key).cloned())
+    }
+
+    async fn put_sanction(&self, sanction: &RoomSanction) -> Result<(), StoreError> {
+        let key = (sanction.room_id.clone(), sanction.user_id.clone());
+        self.inner.write().await.sanctions.insert(key, sanction.clone());
+        Ok(())
+    }
+
+    async fn delete_sanction(&self, room_id: &str, user_id: &str) -> Result<bool, StoreError> {
+        let key = (room_id.to_string(), user_id.to_string());
+        Ok(self.inner.write().await.sanctions.remove(&key).is_some())
+    }
+
+    async fn room_sanctions(&self, room_id: &str) -> Result<Vec<RoomSanction>, StoreError> {
+        let inner = self.inner.read().await;
+        let mut sanctions: Vec<RoomSanction> =
+            inner.sanctions.values().filter(|s| s.room_id == room_id).cloned().collect();
+        sanctions.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        Ok(sanctions)
+    }
+
+    async fn get_report(
+        &self,
+        room_id: &str,
+        report_id: &str,
+    ) -> Result<Option<MessageReport>, StoreError> {
+        let key = (room_id.to_string(), report_id.to_string());
+        Ok(self.inner.read().await.reports.get(&key).cloned())
+    }
+
+    async fn put_report(&self, report: &MessageReport) -> Result<(), StoreError> {
+        let key = (report.room_id.clone(), report.id.clone());
+        self.inner.write().await.reports.insert(key, report.clone());
+        Ok(())
+    }
+
+    async fn room_reports(
+        &self,
+        room_id: &str,
+        status: Option<ReportStatus>,
+    ) -> Result<Vec<MessageReport>, StoreError> {
+        let inner = self.inner.read().await;
+        Ok(inner
+            .reports
+            .values()
+            .filter(|r| r.room_id == room_id && status.is_none_or(|status| r.status == status))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_read_marker(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<ReadMarker>, StoreError> {
+        let key = (room_id.to_string(), user_id.to_string());
+        Ok(self.inner.read().await.read_markers.get(&key).cloned())
+    }
+
+    async fn advance_read_marker(&self, marker: &ReadMarker) -> Result<bool, StoreError> {
+        let key = (marker.room_id.clone(), marker.user_id.clone());
+        let mut inner = self.inner.write().await;
+        if inner.read_markers.get(&key).is_some_and(|stored| stored.message_id >= marker.message_id)
+        {
+            return Ok(false);
+        }
+        inner.read_markers.insert(key, marker.clone());
+        Ok(true)
+    }
+
+    async fn room_read_markers(&self, room_id: &str) -> Result<Vec<ReadMarker>, StoreError> {
+        let inner = self.inner.read().await;
+        Ok(inner.read_markers.values().filter(|m| m.room_id == room_id).cloned().collect())
+    }
+
+    async fn user_read_markers(&self, user_id: &str) -> Result<Vec<ReadMarker>, StoreError> {
+        let inner = self.inner.read().await;
+        Ok(inner.read_markers.values().filter(|m| m.user_id == user_id).cloned().collect())
+    }
+
+    async fn put_connection(&self, connection: &Connection) -> Result<(), StoreError> {
+        self.inner
+            .write()
+            .await
+            .connections
+            .insert(connection.connection_id.clone(), connection.clone());
+        Ok(())
+    }
+
+    async fn get_connection(&self, connection_id: &str) -> Result<Option<Connection>, StoreError> {
+        Ok(self.inner.read().await.connections.get(connection_id).cloned())
+    }
+
+    async fn delete_connection(&self, connection_id: &str) -> Result<(), StoreError> {
+        self.inner.write().await.connections.remove(connection_id);
+        Ok(())
+    }
+
+    async fn set_typing(
+        &self,
+        connection_id: &str,
+        typing_until: Option<i64>,
+    ) -> Result<(), StoreError> {
+        if let Some(connection) = self.inner.write().await.connections.get_mut(connection_id) {
+            connection.typing_until = typing_until;
+        }
+        Ok(())
+    }
+
+    async fn room_connections(&self, room_id: &str) -> Result<Vec<Connection>, StoreError> {
+        // Same shape as the room-index GSI: partitioned by room, sorted by connected_at
+        let inner = self.inner.read().await;
+        let mut connections: Vec<Connection> =
+            inner.connections.values().filter(|c| c.room_id == room_id).cloned().collect();
+        connections.sort_by_key(|c| c.connected_at);
+        Ok(connections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn message(room_id: &str, id: &str, ts: i64) -> ChatMessage {
+        ChatMessage {
+            id: id.to_string(),
+            room_id: room_id.to_string(),
+            user_id: "user-1".to_string(),
+            username: "alice".to_string(),
+            message_text: format!("message {}", id),
+            created_at: DateTime::from_timestamp_millis(ts).unwrap(),
+            client_message_id: None,
+            edited_at: None,
+            deleted_at: None,
+            reactions: Vec::new(),
+            parent_id: None,
+            reply_count: 0,
+            last_reply_at: None,
+            filter: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_room_is_conditional() {
+        let store = MemoryStore::new();
+        let mut room = Room {
+            id: "general".to_string(),
+            name: "General".to_string(),
+            created_at: Utc::now(),
+            description: None,
+            topic: None,
+            archived: false,
+            visibility: Default::default(),
+            kind: Default::default(),
+            last_message_at: None,
+        };
+
+        assert_eq!(store.create_room(&room).await, Ok(()));
+        assert_eq!(store.create_room(&room).await, Err(StoreError::ConditionFailed));
+        assert_eq!(store.get_room("general").await.unwrap().unwrap().name, "General");
+
+        room.archived = true;
+        store.update_room(&room).await.unwrap();
+        assert!(store.list_rooms().await.unwrap()[0].archived);
+        let at = DateTime::from_timestamp_millis(5_000).unwrap();
+        store.record_room_activity("general", at).await.unwrap();
+        assert_eq!(store.get_room("general").await.unwrap().unwrap().last_message_at, Some(at));
+        assert_eq!(
+            store.record_room_activity("missing", at).await,
+            Err(StoreError::ConditionFailed)
+        );
+        room.id = "missing".to_string();
+        assert_eq!(store.update_room(&room).await, Err(StoreError::ConditionFailed));
+    }
+
+    #[tokio::test]
+    async fn test_messages_sorted_by_id_per_room() {
+        let store = MemoryStore::new();
+        store.put_message(&message("general", "b", 2_000)).await.unwrap();
+        store.put_message(&message("general", "a", 1_000)).await.unwrap();
+        store.put_message(&message("general", "c", 3_000)).await.unwrap();
+        store.put_message(&message("other", "x", 500)).await.unwrap();
+        assert_eq!(
+            store.put_message(&message("general", "a", 4_000)).await,
+            Err(StoreError::ConditionFailed)
+        );
+
+        let ids = |page: MessagePage| page.messages.into_iter().map(|m| m.id).collect::<Vec<_>>();
+
+        let page = store
+            .query_messages("general", &MessageQuery { limit: 2, ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(page.next_cursor.as_deref(), Some("b"));
+        assert_eq!(ids(page), vec!["a", "b"]);
+
+        let page = store
+            .query_messages(
+                "general",
+                &MessageQuery { after: Some("b".to_string()), limit: 2, ..Default::default() },
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.next_cursor, None);
+        assert_eq!(ids(page), vec!["c"]);
+
+        let page = store
+            .query_messages(
+                "general",
+                &MessageQuery {
+                    before: Some("c".to_string()),
+                    limit: 1,
+                    newest_first: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.next_cursor.as_deref(), Some("b"));
+        assert_eq!(ids(page), vec!["b"]);
+
+        let query = MessageQuery { limit: 25, ..Default::default() };
+        assert!(store.query_messages("empty", &query).await.unwrap().messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_put_message_once_deduplicates() {
+        let store = MemoryStore::new();
+        let expires_at = Utc::now().timestamp() + 60;
+        let mut first = message("general", "a", 1_000);
+        first.client_message_id = Some("client-1".to_string());
+        let mut retry = message("general", "b", 2_000);
+        retry.client_message_id = Some("client-1".to_string());
+
+        assert!(matches!(
+            store.put_message_once(&first, expires_at).await.unwrap(),
+            PutMessageOutcome::Created
+        ));
+        match store.put_message_once(&retry, expires_at).await.unwrap() {
+            PutMessageOutcome::Duplicate(original) => assert_eq!(original.id, "a"),
+            PutMessageOutcome::Created => panic!("retry should be deduplicated"),
+        }
+
+        // Expired markers no longer deduplicate
+        let mut later = message("general", "c", 3_000);
+        later.client_message_id = Some("client-2".to_string());
+        store.put_message_once(&later, Utc::now().timestamp() - 1).await.unwrap();
+        later.id = "d".to_string();
+        later.created_at = DateTime::from_timestamp_millis(4_000).unwrap();
+        assert!(matches!(
+            store.put_message_once(&later, expires_at).await.unwrap(),
+            PutMessageOutcome::Created
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_edit_message_keeps_revisions() {
+        let store = MemoryStore::new();
+        let original = message("general", "a", 1_000);
+        store.put_message(&original).await.unwrap();
+
+        let edited_at = DateTime::from_timestamp_millis(5_000).unwrap();
+        let edited = store.edit_message(&original, "fixed", None, edited_at).await.unwrap();
+        assert_eq!(edited.message_text, "fixed");
+        assert_eq!(edited.edited_at, Some(edited_at));
+
+        // A stale copy no longer matches the stored text
+        assert_eq!(
+            store.edit_message(&original, "again", None, edited_at).await.unwrap_err(),
+            StoreError::ConditionFailed
+        );
+
+        let revisions = store.message_revisions("general", "a").await.unwrap();
+        assert_eq!(
+            revisions,
+            vec![MessageRevision { message_text: "message a".to_string(), replaced_at: edited_at }]
+        );
+        let stored = store.get_message("general", "a").await.unwrap().unwrap();
+        assert_eq!(stored.message_text, "fixed");
+    }
+
+    #[tokio::test]
+    async fn test_delete_message_leaves_tombstone() {
+        let store = MemoryStore::new();
+        let original = message("general", "a", 1_000);
+        store.put_message(&original).await.unwrap();
+        let at = DateTime::from_timestamp_millis(5_000).unwrap();
+        store.edit_message(&original, "secret", None, at).await.unwrap();
+
+        let deleted = store.delete_message("general", "a", at).await.unwrap();
+        assert_eq!(deleted.message_text, DELETED_MESSAGE_TEXT);
+        assert_eq!(deleted.deleted_at, Some(at));
+        assert!(store.message_revisions("general", "a").await.unwrap().is_empty());
+        assert_eq!(
+            store.delete_message("general", "missing", at).await.unwrap_err(),
+            StoreError::ConditionFailed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_thread_replies_are_listed_apart() {
+        let store = MemoryStore::new();
+        store.put_message(&message("general", "a", 1_000)).await.unwrap();
+        for (id, ts) in [("b", 2_000), ("c", 3_000)] {
+            let mut reply = message("general", id, ts);
+            reply.parent_id = Some("a".to_string());
+            store.put_message(&reply).await.unwrap();
+            store.record_reply("general", "a", reply.created_at).await.unwrap();
+        }
+        store.put_message(&message("general", "d", 4_000)).await.unwrap();
+
+        let ids = |page: MessagePage| page.messages.into_iter().map(|m| m.id).collect::<Vec<_>>();
+        let query = MessageQuery { limit: 10, ..Default::default() };
+        let page = store.query_messages("general", &query).await.unwrap();
+        assert_eq!(page.messages[0].reply_count, 2);
+        assert_eq!(page.messages[0].last_reply_at, DateTime::from_timestamp_millis(3_000));
+        assert_eq!(ids(page), vec!["a", "d"]);
+
+        let thread = MessageQuery { limit: 1, parent_id: Some("a".to_string()), ..query };
+        let page = store.query_messages("general", &thread).await.unwrap();
+        assert_eq!(page.next_cursor.as_deref(), Some("b"));
+        assert_eq!(ids(page), vec!["b"]);
+
+        assert_eq!(
+            store.record_reply("general", "missing", Utc::now()).await,
+            Err(StoreError::ConditionFailed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reactions_are_summarized_per_emoji() {
+        let store = MemoryStore::new();
+        store.put_message(&message("general", "a", 1_000)).await.unwrap();
+
+        let react = |emoji: &'static str, user_id: &'static str| {
+            store.add_reaction("general", "a", emoji, user_id, 2)
+        };
+        assert_eq!(react("👍", "u2").await, Ok(ReactionOutcome::Added));
+        assert_eq!(react("👍", "u1").await, Ok(ReactionOutcome::Added));
+        assert_eq!(react("👍", "u1").await, Ok(ReactionOutcome::AlreadyPresent));
+        assert_eq!(react("🎉", "u1").await, Ok(ReactionOutcome::Added));
+        assert_eq!(react("🔥", "u1").await, Ok(ReactionOutcome::LimitReached));
+
+        let stored = store.get_message("general", "a").await.unwrap().unwrap();
+        let summary: Vec<_> =
+            stored.reactions.iter().map(|r| (r.emoji.as_str(), r.user_ids.clone())).collect();
+        assert_eq!(
+            summary,
+            vec![("🎉", vec!["u1".to_string()]), ("👍", vec!["u1".to_string(), "u2".to_string()])]
+        );
+
+        assert_eq!(store.remove_reaction("general", "a", "🎉", "u1").await, Ok(true));
+        assert_eq!(store.remove_reaction("general", "a", "🎉", "u1").await, Ok(false));
+        assert_eq!(react("🔥", "u1").await, Ok(ReactionOutcome::Added));
+
+        // Tombstones lose their reactions and take no new ones
+        let deleted = store.delete_message("general", "a", Utc::now()).await.unwrap();
+        assert!(deleted.reactions.is_empty());
+        assert_eq!(react("👍", "u3").await, Err(StoreError::ConditionFailed));
+    }
+
+    #[tokio::test]
+    async fn test_room_members() {
+        let store = MemoryStore::new();
+        for (user_id, role) in [("u2", types::RoomRole::Member), ("u1", types::RoomRole::Owner)] {
+            let member = RoomMember {
+                room_id: "general".to_string(),
+                user_id: user_id.to_string(),
+                role,
+                joined_at: Utc::now(),
+            };
+            store.put_member(&member).await.unwrap();
+        }
+
+        let members = store.room_members("general").await.unwrap();
+        assert_eq!(members.iter().map(|m| m.user_id.as_str()).collect::<Vec<_>>(), ["u1", "u2"]);
+        assert!(store.room_members("other").await.unwrap().is_empty());
+        assert_eq!(store.user_memberships("u2").await.unwrap()[0].room_id, "general");
+
+        assert!(store.delete_member("general", "u2").await.unwrap());
+        assert!(!store.delete_member("general", "u2").await.unwrap());
+        assert!(store.get_member("general", "u2").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_room_connections() {
+        let store = MemoryStore::new();
+        store.put_connection(&Connection::new("c2", "general", "u2", "bob", 2_000)).await.unwrap();
+        store
+            .put_connection(&Connection::new("c1", "general", "u1", "alice", 1_000))
+            .await
+            .unwrap();
+        store.put_connection(&Connection::new("c3", "random", "u3", "carol", 500)).await.unwrap();
+
+        let ids: Vec<String> = store
+            .room_connections("general")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.connection_id)
+            .collect();
+        assert_eq!(ids, vec!["c1", "c2"]);
+
+        store.delete_connection("c1").await.unwrap();
+        assert!(store.get_connection("c1").await.unwrap().is_none());
+        assert_eq!(store.room_connections("general").await.unwrap().len(), 1);
+    }
+}
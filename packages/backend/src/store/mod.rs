@@ -0,0 +1,369 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use types::{
+    ChatMessage, FilterDecision, MessageReport, ReactionSummary, ReadMarker, ReportStatus, Room,
+    RoomMember, RoomSanction,
+};
+use ulid::Ulid;
+
+pub mod dynamo;
+pub mod memory;
+pub mod sqlite;
+
+pub use dynamo::{DynamoStore, Tables};
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+// Storage errors surfaced by every ChatStore backend
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreError {
+    /// A conditional write was rejected (e.g. the item already exists)
+    ConditionFailed,
+    /// Any other backend failure
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::ConditionFailed => write!(f, "Conditional write failed"),
+            StoreError::Backend(err) => write!(f, "Storage error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+// A live WebSocket connection registered in a room
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connection {
+    pub connection_id: String,
+    pub room_id: String,
+    pub user_id: String,
+    pub username: String,
+    pub connected_at: i64, // epoch millis
+    pub domain: String,
+    pub stage: String,
+    pub transport: String, // "apigw" or "dev"
+    pub push_url: Option<String>,
+    pub ttl: i64,                  // epoch seconds
+    pub typing_until: Option<i64>, // epoch seconds, while the user types on this connection
+}
+
+impl Connection {
+    /// Build a connection record that expires 24 hours after `now_millis`
+    pub fn new(
+        connection_id: &str,
+        room_id: &str,
+        user_id: &str,
+        username: &str,
+        now_millis: i64,
+    ) -> Self {
+        Self {
+            connection_id: connection_id.to_string(),
+            room_id: room_id.to_string(),
+            user_id: user_id.to_string(),
+            username: username.to_string(),
+            connected_at: now_millis,
+            domain: "unknown".to_string(),
+            stage: "unknown".to_string(),
+            transport: "apigw".to_string(),
+            push_url: None,
+            ttl: now_millis / 1000 + (60 * 60 * 24),
+            typing_until: None,
+        }
+    }
+}
+
+// Range over a room's messages. Cursors are message ids (ULIDs, which sort by time)
+// and both bounds are exclusive. Without a parent_id only top-level messages are listed;
+// with one, only that thread's replies.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageQuery {
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub limit: usize,
+    pub newest_first: bool,
+    pub parent_id: Option<String>,
+}
+
+// Result of an idempotent message write
+#[derive(Debug, Clone)]
+pub enum PutMessageOutcome {
+    Created,
+    /// Same (room_id, user_id, client_message_id) already stored; carries the original
+    Duplicate(Box<ChatMessage>),
+}
+
+/// Key used to deduplicate retried posts, if the message carries a client_message_id
+pub fn dedup_key(message: &ChatMessage) -> Option<String> {
+    message
+        .client_message_id
+        .as_ref()
+        .map(|client_id| format!("{}#{}#{}", message.room_id, message.user_id, client_id))
+}
+
+/// Text stored in place of a deleted message's content
+pub const DELETED_MESSAGE_TEXT: &str = "[deleted]";
+
+/// Prior texts kept per message; older ones are dropped as new edits come in. DynamoDB keeps
+/// them on the message item, which must stay under its 400KB limit.
+pub const MAX_REVISIONS: usize = 100;
+
+// Text a message had before an edit replaced it
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageRevision {
+    pub message_text: String,
+    pub replaced_at: DateTime<Utc>,
+}
+
+// Result of adding a reaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReactionOutcome {
+    Added,
+    /// The user had already reacted with this emoji
+    AlreadyPresent,
+    /// The emoji is new to the message and it already has the maximum distinct emoji
+    LimitReached,
+}
+
+/// Group (emoji, user_id) pairs into per-emoji summaries sorted by emoji.
+/// `reacted_by_me` is left false; handlers fill it in for the viewer.
+pub fn summarize_reactions<I>(pairs: I) -> Vec<ReactionSummary>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut by_emoji: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for (emoji, user_id) in pairs {
+        by_emoji.entry(emoji).or_default().insert(user_id);
+    }
+    by_emoji
+        .into_iter()
+        .map(|(emoji, users)| ReactionSummary {
+            emoji,
+            count: users.len() as u32,
+            user_ids: users.into_iter().collect(),
+            reacted_by_me: false,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MessagePage {
+    pub messages: Vec<ChatMessage>,
+    /// Sort key of the last returned message when more may follow
+    pub next_cursor: Option<String>,
+}
+
+/// Deterministic ULID for a legacy `ts`-keyed message, so re-running a migration is a no-op.
+/// The random half comes from the legacy UUID (or a hash of the id if it is not one).
+pub fn legacy_message_id(ts: i64, legacy_id: &str) -> String {
+    let random = match uuid::Uuid::parse_str(legacy_id) {
+        Ok(uuid) => uuid.as_u128(),
+        Err(_) => {
+            // FNV-1a, stable across builds unlike DefaultHasher
+            legacy_id.bytes().fold(0xcbf29ce484222325u128, |hash, byte| {
+                (hash ^ byte as u128).wrapping_mul(0x100000001b3)
+            })
+        }
+    };
+    Ulid::from_parts(ts.max(0) as u64, random).to_string()
+}
+
+/// Persistence for rooms, messages and WebSocket connections.
+///
+/// Handlers, the dev server and the WebSocket lambdas only talk to storage through this trait.
+#[async_trait]
+pub trait ChatStore: Send + Sync {
+    async fn get_room(&self, room_id: &str) -> Result<Option<Room>, StoreError>;
+
+    /// Create a room, failing with `ConditionFailed` if it already exists
+    async fn create_room(&self, room: &Room) -> Result<(), StoreError>;
+
+    /// Overwrite an existing room, failing with `ConditionFailed` if it does not exist
+    async fn update_room(&self, room: &Room) -> Result<(), StoreError>;
+
+    /// Every room, sorted by id
+    async fn list_rooms(&self) -> Result<Vec<Room>, StoreError>;
+
+    /// Set a room's `last_message_at`. Fails with `ConditionFailed` if the room does not exist.
+    async fn record_room_activity(
+        &self,
+        room_id: &str,
+        last_message_at: DateTime<Utc>,
+    ) -> Result<(), StoreError>;
+
+    /// Store a new message, failing with `ConditionFailed` if its id is already taken
+    async fn put_message(&self, message: &ChatMessage) -> Result<(), StoreError>;
+
+    /// Store a message unless one with the same dedup key was stored and its marker has not
+    /// expired yet. The new marker expires at `dedup_expires_at` (epoch seconds).
+    async fn put_message_once(
+        &self,
+        message: &ChatMessage,
+        dedup_expires_at: i64,
+    ) -> Result<PutMessageOutcome, StoreError>;
+
+    /// The message stored under dedup key `key` (see `dedup_key`), while its marker is live
+    async fn find_duplicate(&self, key: &str) -> Result<Option<ChatMessage>, StoreError>;
+
+    async fn get_message(
+        &self,
+        room_id: &str,
+        message_id: &str,
+    ) -> Result<Option<ChatMessage>, StoreError>;
+
+    /// Replace the text of `message` and the filters' decision on it, keeping its current
+    /// text as a revision (up to the latest `MAX_REVISIONS`). Fails with `ConditionFailed`
+    /// if the stored text is no longer `message.message_text`.
+    async fn edit_message(
+        &self,
+        message: &ChatMessage,
+        message_text: &str,
+        filter: Option<&FilterDecision>,
+        edited_at: DateTime<Utc>,
+    ) -> Result<ChatMessage, StoreError>;
+
+    /// Count a new reply on a thread root and set its `last_reply_at`. Fails with
+    /// `ConditionFailed` if the root does not exist.
+    async fn record_reply(
+        &self,
+        room_id: &str,
+        parent_id: &str,
+        replied_at: DateTime<Utc>,
+    ) -> Result<(), StoreError>;
+
+    /// Earlier texts of a message, oldest first
+    async fn message_revisions(
+        &self,
+        room_id: &str,
+        message_id: &str,
+    ) -> Result<Vec<MessageRevision>, StoreError>;
+
+    /// Turn a message into a tombstone: its text becomes `DELETED_MESSAGE_TEXT`, its
+    /// revisions and reactions are dropped and `deleted_at` is set. Fails with `ConditionFailed` if the
+    /// message does not exist.
+    async fn delete_message(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        deleted_at: DateTime<Utc>,
+    ) -> Result<ChatMessage, StoreError>;
+
+    /// Record `user_id` reacting with `emoji`. Fails with `ConditionFailed` if the message
+    /// does not exist or has been deleted.
+    async fn add_reaction(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+        max_distinct: usize,
+    ) -> Result<ReactionOutcome, StoreError>;
+
+    /// Remove a reaction, returning whether it existed
+    async fn remove_reaction(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> Result<bool, StoreError>;
+
+    /// One page of a room's messages in sort key order
+    async fn query_messages(
+        &self,
+        room_id: &str,
+        query: &MessageQuery,
+    ) -> Result<MessagePage, StoreError>;
+
+    async fn get_member(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<RoomMember>, StoreError>;
+
+    /// Add a member to a room or change their role
+    async fn put_member(&self, member: &RoomMember) -> Result<(), StoreError>;
+
+    /// Remove a member from a room, returning whether they were one
+    async fn delete_member(&self, room_id: &str, user_id: &str) -> Result<bool, StoreError>;
+
+    /// Every member of a room, sorted by user id
+    async fn room_members(&self, room_id: &str) -> Result<Vec<RoomMember>, StoreError>;
+
+    /// Every room a user is a member of, sorted by room id
+    async fn user_memberships(&self, user_id: &str) -> Result<Vec<RoomMember>, StoreError>;
+
+    async fn get_sanction(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<RoomSanction>, StoreError>;
+
+    /// Record a user's mute/ban in a room, replacing any earlier one
+    async fn put_sanction(&self, sanction: &RoomSanction) -> Result<(), StoreError>;
+
+    /// Lift a user's sanction in a room, returning whether there was one
+    async fn delete_sanction(&self, room_id: &str, user_id: &str) -> Result<bool, StoreError>;
+
+    /// Every sanction recorded in a room (including lapsed mutes), sorted by user id
+    async fn room_sanctions(&self, room_id: &str) -> Result<Vec<RoomSanction>, StoreError>;
+
+    async fn get_report(
+        &self,
+        room_id: &str,
+        report_id: &str,
+    ) -> Result<Option<MessageReport>, StoreError>;
+
+    /// Record a new report or overwrite one being resolved
+    async fn put_report(&self, report: &MessageReport) -> Result<(), StoreError>;
+
+    /// A room's reports, optionally only those with `status`, oldest first
+    async fn room_reports(
+        &self,
+        room_id: &str,
+        status: Option<ReportStatus>,
+    ) -> Result<Vec<MessageReport>, StoreError>;
+
+    async fn get_read_marker(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<ReadMarker>, StoreError>;
+
+    /// Store a user's read marker unless the one stored is already at or past its message,
+    /// returning whether it moved. Markers never go backwards, whichever tab reports last.
+    async fn advance_read_marker(&self, marker: &ReadMarker) -> Result<bool, StoreError>;
+
+    /// Every read marker in a room, sorted by user id
+    async fn room_read_markers(&self, room_id: &str) -> Result<Vec<ReadMarker>, StoreError>;
+
+    /// Every room a user has read, sorted by room id
+    async fn user_read_markers(&self, user_id: &str) -> Result<Vec<ReadMarker>, StoreError>;
+
+    async fn put_connection(&self, connection: &Connection) -> Result<(), StoreError>;
+
+    async fn get_connection(&self, connection_id: &str) -> Result<Option<Connection>, StoreError>;
+
+    async fn delete_connection(&self, connection_id: &str) -> Result<(), StoreError>;
+
+    /// Set or clear when the typing indicator of a connection's user lapses. A connection
+    /// that is already gone stays gone.
+    async fn set_typing(
+        &self,
+        connection_id: &str,
+        typing_until: Option<i64>,
+    ) -> Result<(), StoreError>;
+
+    /// All connections currently registered in a room
+    async fn room_connections(&self, room_id: &str) -> Result<Vec<Connection>, StoreError>;
+
+    /// Whether changes to messages reach the broadcast lambda through a table stream.
+    /// Without one, whoever makes a change tells the room's sockets about it.
+    fn streams_changes(&self) -> bool {
+        false
+    }
+}
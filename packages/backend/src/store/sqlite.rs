@@ -0,0 +1,1739 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension, Row, Transaction};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+use super::{
+    dedup_key, legacy_message_id, summarize_reactions, ChatStore, Connection, MessagePage,
+    MessageQuery, MessageRevision, PutMessageOutcome, ReactionOutcome, StoreError,
+    DELETED_MESSAGE_TEXT, MAX_REVISIONS,
+};
+use types::{
+    ChatMessage, FilterAction, FilterDecision, MessageReport, ReadMarker, ReportStatus, Room,
+    RoomKind, RoomMember, RoomRole, RoomSanction, RoomVisibility,
+};
+
+enum Migration {
+    Sql(&'static str),
+    // Migrations that need to compute values in Rust
+    Code(fn(&Transaction) -> rusqlite::Result<()>),
+}
+
+// Schema migrations, applied in order and tracked with PRAGMA user_version.
+// Messages keep the DynamoDB key design: partitioned by room_id, sorted by id (a ULID).
+const MIGRATIONS: &[Migration] = &[
+    // v1: rooms, messages, connections
+    Migration::Sql(
+        "CREATE TABLE rooms (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE messages (
+        room_id TEXT NOT NULL,
+        ts INTEGER NOT NULL,
+        id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        username TEXT NOT NULL,
+        message_text TEXT NOT NULL,
+        client_message_id TEXT,
+        PRIMARY KEY (room_id, ts)
+    );
+    CREATE TABLE connections (
+        connection_id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        username TEXT NOT NULL,
+        connected_at INTEGER NOT NULL,
+        domain TEXT NOT NULL,
+        stage TEXT NOT NULL,
+        transport TEXT NOT NULL,
+        push_url TEXT,
+        ttl INTEGER NOT NULL
+    );
+    CREATE INDEX connections_room_index ON connections (room_id, connected_at);",
+    ),
+    // v2: idempotency markers for retried posts
+    Migration::Sql(
+        "CREATE TABLE message_dedup (
+        dedup_key TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        ts INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );",
+    ),
+    // v3: re-key messages (and dedup markers) by ULID instead of ts
+    Migration::Code(rekey_messages_by_ulid),
+    // v4: message edits and their prior texts
+    Migration::Sql(
+        "ALTER TABLE messages ADD COLUMN edited_at TEXT;
+    CREATE TABLE message_revisions (
+        room_id TEXT NOT NULL,
+        message_id TEXT NOT NULL,
+        revision INTEGER NOT NULL,
+        message_text TEXT NOT NULL,
+        replaced_at TEXT NOT NULL,
+        PRIMARY KEY (room_id, message_id, revision)
+    );",
+    ),
+    // v5: tombstones and room roles
+    Migration::Sql(
+        "ALTER TABLE messages ADD COLUMN deleted_at TEXT;
+    CREATE TABLE room_members (
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        role TEXT NOT NULL,
+        joined_at TEXT NOT NULL,
+        PRIMARY KEY (room_id, user_id)
+    );",
+    ),
+    // v6: emoji reactions
+    Migration::Sql(
+        "CREATE TABLE message_reactions (
+        room_id TEXT NOT NULL,
+        message_id TEXT NOT NULL,
+        emoji TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        PRIMARY KEY (room_id, message_id, emoji, user_id)
+    );",
+    ),
+    // v7: threads
+    Migration::Sql(
+        "ALTER TABLE messages ADD COLUMN parent_id TEXT;
+    ALTER TABLE messages ADD COLUMN reply_count INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE messages ADD COLUMN last_reply_at TEXT;
+    CREATE INDEX messages_thread_index ON messages (room_id, parent_id, id);",
+    ),
+    // v8: room details and archiving
+    Migration::Sql(
+        "ALTER TABLE rooms ADD COLUMN description TEXT;
+    ALTER TABLE rooms ADD COLUMN topic TEXT;
+    ALTER TABLE rooms ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;",
+    ),
+    // v9: private rooms
+    Migration::Sql("ALTER TABLE rooms ADD COLUMN visibility TEXT NOT NULL DEFAULT 'public';"),
+    // v10: direct messages and per-room activity
+    Migration::Sql(
+        "ALTER TABLE rooms ADD COLUMN kind TEXT NOT NULL DEFAULT 'channel';
+    ALTER TABLE rooms ADD COLUMN last_message_at TEXT;
+    CREATE INDEX room_members_user_index ON room_members (user_id, room_id);",
+    ),
+    // v11: moderation
+    Migration::Sql(
+        "CREATE TABLE room_sanctions (
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        muted_until TEXT,
+        banned INTEGER NOT NULL DEFAULT 0,
+        reason TEXT,
+        moderator_id TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        PRIMARY KEY (room_id, user_id)
+    );",
+    ),
+    // v12: content filter decisions
+    Migration::Sql(
+        "ALTER TABLE messages ADD COLUMN filter_action TEXT;
+    ALTER TABLE messages ADD COLUMN filter_names TEXT;",
+    ),
+    // v13: message reports
+    Migration::Sql(
+        "CREATE TABLE message_reports (
+        room_id TEXT NOT NULL,
+        report_id TEXT NOT NULL,
+        message_id TEXT NOT NULL,
+        reporter_id TEXT NOT NULL,
+        reason TEXT NOT NULL,
+        message_user_id TEXT NOT NULL,
+        message_text TEXT NOT NULL,
+        status TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        resolved_by TEXT,
+        resolved_at TEXT,
+        PRIMARY KEY (room_id, report_id)
+    );",
+    ),
+    // v14: read markers
+    Migration::Sql(
+        "CREATE TABLE read_markers (
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        message_id TEXT NOT NULL,
+        read_at TEXT NOT NULL,
+        PRIMARY KEY (room_id, user_id)
+    );
+    CREATE INDEX read_markers_user_index ON read_markers (user_id, room_id);",
+    ),
+    // v15: typing indicators
+    Migration::Sql("ALTER TABLE connections ADD COLUMN typing_until INTEGER;"),
+];
+
+fn rekey_messages_by_ulid(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE messages_v3 (
+            room_id TEXT NOT NULL,
+            id TEXT NOT NULL,
+            ts INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            username TEXT NOT NULL,
+            message_text TEXT NOT NULL,
+            client_message_id TEXT,
+            PRIMARY KEY (room_id, id)
+        );",
+    )?;
+
+    {
+        let mut select = tx.prepare(
+            "SELECT room_id, ts, id, user_id, username, message_text, client_message_id
+             FROM messages",
+        )?;
+        let mut insert = tx.prepare(
+            "INSERT INTO messages_v3
+                (room_id, id, ts, user_id, username, message_text, client_message_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            let ts: i64 = row.get(1)?;
+            let legacy_id: String = row.get(2)?;
+            insert.execute(params![
+                row.get::<_, String>(0)?,
+                legacy_message_id(ts, &legacy_id),
+                ts,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, Option<String>>(6)?,
+            ])?;
+        }
+    }
+
+    // Markers pointed at (room_id, ts), which was unique under the old key
+    tx.execute_batch(
+        "CREATE TABLE message_dedup_v3 (
+            dedup_key TEXT PRIMARY KEY,
+            room_id TEXT NOT NULL,
+            message_id TEXT NOT NULL,
+            expires_at INTEGER NOT NULL
+        );
+        INSERT INTO message_dedup_v3 (dedup_key, room_id, message_id, expires_at)
+            SELECT d.dedup_key, d.room_id, m.id, d.expires_at
+            FROM message_dedup d JOIN messages_v3 m ON m.room_id = d.room_id AND m.ts = d.ts;
+        DROP TABLE message_dedup;
+        ALTER TABLE message_dedup_v3 RENAME TO message_dedup;
+        DROP TABLE messages;
+        ALTER TABLE messages_v3 RENAME TO messages;",
+    )
+}
+
+// SQLite-backed ChatStore for self-hosted deployments
+#[derive(Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<rusqlite::Connection>>,
+}
+
+impl SqliteStore {
+    /// Open (or create) the database at `path` and run pending migrations
+    pub fn open(path: &str) -> Result<Self, StoreError> {
+        let conn = rusqlite::Connection::open(path).map_err(backend_error)?;
+        Self::from_connection(conn)
+    }
+
+    /// Private in-memory database, mainly for tests
+    pub fn open_in_memory() -> Result<Self, StoreError> {
+        let conn = rusqlite::Connection::open_in_memory().map_err(backend_error)?;
+        Self::from_connection(conn)
+    }
+
+    fn from_connection(mut conn: rusqlite::Connection) -> Result<Self, StoreError> {
+        migrate(&mut conn).map_err(backend_error)?;
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    // Run a closure against the connection on the blocking thread pool
+    async fn call<T, F>(&self, f: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&rusqlite::Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().map_err(|e| StoreError::Backend(e.to_string()))?;
+            f(&conn).map_err(backend_error)
+        })
+        .await
+        .map_err(|e| StoreError::Backend(e.to_string()))?
+    }
+}
+
+fn migrate(conn: &mut rusqlite::Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        match migration {
+            Migration::Sql(sql) => tx.execute_batch(sql)?,
+            Migration::Code(apply) => apply(&tx)?,
+        }
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+        info!("Applied SQLite migration v{}", index + 1);
+    }
+    Ok(())
+}
+
+fn backend_error<E: std::fmt::Debug>(err: E) -> StoreError {
+    StoreError::Backend(format!("SQLite error: {:?}", err))
+}
+
+fn is_constraint_violation(err: &rusqlite::Error) -> bool {
+    matches!(err.sqlite_error_code(), Some(rusqlite::ErrorCode::ConstraintViolation))
+}
+
+fn parse_time(value: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now())
+}
+
+const MESSAGE_COLUMNS: &str = "id, room_id, user_id, username, message_text, ts, \
+    client_message_id, edited_at, deleted_at, parent_id, reply_count, last_reply_at, \
+    filter_action, filter_names";
+
+// Filter names are plain identifiers, so a comma-separated column holds them
+fn filter_from_columns(action: Option<String>, names: Option<String>) -> Option<FilterDecision> {
+    Some(FilterDecision {
+        action: FilterAction::parse(&action?)?,
+        filters: names.unwrap_or_default().split(',').map(str::to_string).collect(),
+    })
+}
+
+fn message_from_row(row: &Row) -> rusqlite::Result<ChatMessage> {
+    let ts: i64 = row.get(5)?;
+    Ok(ChatMessage {
+        id: row.get(0)?,
+        room_id: row.get(1)?,
+        user_id: row.get(2)?,
+        username: row.get(3)?,
+        message_text: row.get(4)?,
+        created_at: DateTime::from_timestamp_millis(ts).unwrap_or_default(),
+        client_message_id: row.get(6)?,
+        edited_at: row.get::<_, Option<String>>(7)?.as_deref().map(parse_time),
+        deleted_at: row.get::<_, Option<String>>(8)?.as_deref().map(parse_time),
+        reactions: Vec::new(),
+        parent_id: row.get(9)?,
+        reply_count: row.get(10)?,
+        last_reply_at: row.get::<_, Option<String>>(11)?.as_deref().map(parse_time),
+        filter: filter_from_columns(row.get(12)?, row.get(13)?),
+    })
+}
+
+// Fill in each message's reaction summaries
+fn load_reactions(
+    conn: &rusqlite::Connection,
+    messages: &mut [ChatMessage],
+) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT emoji, user_id FROM message_reactions WHERE room_id = ?1 AND message_id = ?2",
+    )?;
+    for message in messages.iter_mut() {
+        let rows = stmt.query_map(params![message.room_id, message.id], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        message.reactions = summarize_reactions(rows.collect::<rusqlite::Result<Vec<_>>>()?);
+    }
+    Ok(())
+}
+
+// Conditional write: the (room_id, id) primary key rejects an existing id
+fn insert_message(conn: &rusqlite::Connection, message: &ChatMessage) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO messages
+            (id, room_id, user_id, username, message_text, ts, client_message_id, edited_at,
+             deleted_at, parent_id, reply_count, last_reply_at, filter_action, filter_names)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        params![
+            message.id,
+            message.room_id,
+            message.user_id,
+            message.username,
+            message.message_text,
+            message.created_at.timestamp_millis(),
+            message.client_message_id,
+            message.edited_at.map(|at| at.to_rfc3339()),
+            message.deleted_at.map(|at| at.to_rfc3339()),
+            message.parent_id,
+            message.reply_count,
+            message.last_reply_at.map(|at| at.to_rfc3339()),
+            message.filter.as_ref().map(|filter| filter.action.as_str()),
+            message.filter.as_ref().map(|filter| filter.filters.join(",")),
+        ],
+    )
+}
+
+const ROOM_COLUMNS: &str =
+    "id, name, created_at, description, topic, archived, visibility, kind, last_message_at";
+
+fn room_from_row(row: &Row) -> rusqlite::Result<Room> {
+    let created_at: String = row.get(2)?;
+    let visibility: String = row.get(6)?;
+    let kind: String = row.get(7)?;
+    Ok(Room {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        created_at: parse_time(&created_at),
+        description: row.get(3)?,
+        topic: row.get(4)?,
+        archived: row.get(5)?,
+        // Unknown values fail closed
+        visibility: RoomVisibility::parse(&visibility).unwrap_or(RoomVisibility::Private),
+        kind: RoomKind::parse(&kind).unwrap_or_default(),
+        last_message_at: row.get::<_, Option<String>>(8)?.as_deref().map(parse_time),
+    })
+}
+
+fn member_from_row(row: &Row) -> rusqlite::Result<RoomMember> {
+    let role: String = row.get(2)?;
+    let joined_at: String = row.get(3)?;
+    Ok(RoomMember {
+        room_id: row.get(0)?,
+        user_id: row.get(1)?,
+        // Unknown roles get the least privilege
+        role: RoomRole::parse(&role).unwrap_or(RoomRole::Member),
+        joined_at: parse_time(&joined_at),
+    })
+}
+
+const SANCTION_COLUMNS: &str =
+    "room_id, user_id, muted_until, banned, reason, moderator_id, updated_at";
+
+fn sanction_from_row(row: &Row) -> rusqlite::Result<RoomSanction> {
+    let updated_at: String = row.get(6)?;
+    Ok(RoomSanction {
+        room_id: row.get(0)?,
+        user_id: row.get(1)?,
+        muted_until: row.get::<_, Option<String>>(2)?.as_deref().map(parse_time),
+        banned: row.get(3)?,
+        reason: row.get(4)?,
+        moderator_id: row.get(5)?,
+        updated_at: parse_time(&updated_at),
+    })
+}
+
+const REPORT_COLUMNS: &str =
+    "room_id, report_id, message_id, reporter_id, reason, message_user_id, message_text, \
+    status, created_at, resolved_by, resolved_at";
+
+fn report_from_row(row: &Row) -> rusqlite::Result<MessageReport> {
+    let status: String = row.get(7)?;
+    let created_at: String = row.get(8)?;
+    Ok(MessageReport {
+        room_id: row.get(0)?,
+        id: row.get(1)?,
+        message_id: row.get(2)?,
+        reporter_id: row.get(3)?,
+        reason: row.get(4)?,
+        message_user_id: row.get(5)?,
+        message_text: row.get(6)?,
+        status: ReportStatus::parse(&status).unwrap_or(ReportStatus::Open),
+        created_at: parse_time(&created_at),
+        resolved_by: row.get(9)?,
+        resolved_at: row.get::<_, Option<String>>(10)?.as_deref().map(parse_time),
+    })
+}
+
+const READ_MARKER_COLUMNS: &str = "room_id, user_id, message_id, read_at";
+
+fn read_marker_from_row(row: &Row) -> rusqlite::Result<ReadMarker> {
+    let read_at: String = row.get(3)?;
+    Ok(ReadMarker {
+        room_id: row.get(0)?,
+        user_id: row.get(1)?,
+        message_id: row.get(2)?,
+        read_at: parse_time(&read_at),
+    })
+}
+
+// The message a dedup marker live at `now` points at
+fn find_duplicate(
+    conn: &rusqlite::Connection,
+    key: &str,
+    now: i64,
+) -> rusqlite::Result<Option<ChatMessage>> {
+    let marker: Option<(String, String)> = conn
+        .query_row(
+            "SELECT room_id, message_id FROM message_dedup
+             WHERE dedup_key = ?1 AND expires_at > ?2",
+            params![key, now],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((room_id, message_id)) = marker else {
+        return Ok(None);
+    };
+    let original = conn
+        .query_row(
+            &format!("SELECT {} FROM messages WHERE room_id = ?1 AND id = ?2", MESSAGE_COLUMNS),
+            params![room_id, message_id],
+            message_from_row,
+        )
+        .optional()?;
+    let Some(mut original) = original else {
+        return Ok(None);
+    };
+    load_reactions(conn, std::slice::from_mut(&mut original))?;
+    Ok(Some(original))
+}
+
+const CONNECTION_COLUMNS: &str =
+    "connection_id, room_id, user_id, username, connected_at, domain, \
+    stage, transport, push_url, ttl, typing_until";
+
+fn connection_from_row(row: &Row) -> rusqlite::Result<Connection> {
+    Ok(Connection {
+        connection_id: row.get(0)?,
+        room_id: row.get(1)?,
+        user_id: row.get(2)?,
+        username: row.get(3)?,
+        connected_at: row.get(4)?,
+        domain: row.get(5)?,
+        stage: row.get(6)?,
+        transport: row.get(7)?,
+        push_url: row.get(8)?,
+        ttl: row.get(9)?,
+        typing_until: row.get(10)?,
+    })
+}
+
+#[async_trait]
+impl ChatStore for SqliteStore {
+    async fn get_room(&self, room_id: &str) -> Result<Option<Room>, StoreError> {
+        let room_id = room_id.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM rooms WHERE id = ?1", ROOM_COLUMNS),
+                params![room_id],
+                room_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn create_room(&self, room: &Room) -> Result<(), StoreError> {
+        let room = room.clone();
+        let result = self
+            .call(move |conn| {
+                match conn.execute(
+                    &format!(
+                        "INSERT INTO rooms ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                        ROOM_COLUMNS
+                    ),
+                    params![
+                        room.id,
+                        room.name,
+                        room.created_at.to_rfc3339(),
+                        room.description,
+                        room.topic,
+                        room.archived,
+                        room.visibility.as_str(),
+                        room.kind.as_str(),
+                        room.last_message_at.map(|at| at.to_rfc3339())
+                    ],
+                ) {
+                    Ok(_) => Ok(true),
+                    Err(e) if is_constraint_violation(&e) => Ok(false),
+                    Err(e) => Err(e),
+                }
+            })
+            .await?;
+        if result {
+            Ok(())
+        } else {
+            Err(StoreError::ConditionFailed)
+        }
+    }
+
+    async fn update_room(&self, room: &Room) -> Result<(), StoreError> {
+        let room = room.clone();
+        let changed = self
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE rooms SET name = ?2, description = ?3, topic = ?4, archived = ?5,
+                        visibility = ?6, kind = ?7, last_message_at = ?8
+                     WHERE id = ?1",
+                    params![
+                        room.id,
+                        room.name,
+                        room.description,
+                        room.topic,
+                        room.archived,
+                        room.visibility.as_str(),
+                        room.kind.as_str(),
+                        room.last_message_at.map(|at| at.to_rfc3339())
+                    ],
+                )
+            })
+            .await?;
+        if changed == 0 {
+            return Err(StoreError::ConditionFailed);
+        }
+        Ok(())
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<Room>, StoreError> {
+        self.call(|conn| {
+            let mut stmt =
+                conn.prepare(&format!("SELECT {} FROM rooms ORDER BY id ASC", ROOM_COLUMNS))?;
+            let rows = stmt.query_map([], room_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn record_room_activity(
+        &self,
+        room_id: &str,
+        last_message_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        let room_id = room_id.to_string();
+        let changed = self
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE rooms SET last_message_at = ?2 WHERE id = ?1",
+                    params![room_id, last_message_at.to_rfc3339()],
+                )
+            })
+            .await?;
+        if changed == 0 {
+            return Err(StoreError::ConditionFailed);
+        }
+        Ok(())
+    }
+
+    async fn put_message(&self, message: &ChatMessage) -> Result<(), StoreError> {
+        let message = message.clone();
+        let inserted = self
+            .call(move |conn| match insert_message(conn, &message) {
+                Ok(_) => Ok(true),
+                Err(e) if is_constraint_violation(&e) => Ok(false),
+                Err(e) => Err(e),
+            })
+            .await?;
+        if inserted {
+            Ok(())
+        } else {
+            Err(StoreError::ConditionFailed)
+        }
+    }
+
+    async fn put_message_once(
+        &self,
+        message: &ChatMessage,
+        dedup_expires_at: i64,
+    ) -> Result<PutMessageOutcome, StoreError> {
+        let Some(key) = dedup_key(message) else {
+            self.put_message(message).await?;
+            return Ok(PutMessageOutcome::Created);
+        };
+
+        let message = message.clone();
+        let now = Utc::now().timestamp();
+        let outcome = self
+            .call(move |conn| {
+                let tx = conn.unchecked_transaction()?;
+                if let Some(original) = find_duplicate(&tx, &key, now)? {
+                    return Ok(Some(PutMessageOutcome::Duplicate(Box::new(original))));
+                }
+
+                tx.execute(
+                    "INSERT OR REPLACE INTO message_dedup
+                        (dedup_key, room_id, message_id, expires_at)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![key, message.room_id, message.id, dedup_expires_at],
+                )?;
+                match insert_message(&tx, &message) {
+                    Ok(_) => {}
+                    Err(e) if is_constraint_violation(&e) => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+                tx.commit()?;
+                Ok(Some(PutMessageOutcome::Created))
+            })
+            .await?;
+        outcome.ok_or(StoreError::ConditionFailed)
+    }
+
+    async fn find_duplicate(&self, key: &str) -> Result<Option<ChatMessage>, StoreError> {
+        let key = key.to_string();
+        let now = Utc::now().timestamp();
+        self.call(move |conn| find_duplicate(conn, &key, now)).await
+    }
+
+    async fn get_message(
+        &self,
+        room_id: &str,
+        message_id: &str,
+    ) -> Result<Option<ChatMessage>, StoreError> {
+        let room_id = room_id.to_string();
+        let message_id = message_id.to_string();
+        self.call(move |conn| {
+            let mut message = conn
+                .query_row(
+                    &format!(
+                        "SELECT {} FROM messages WHERE room_id = ?1 AND id = ?2",
+                        MESSAGE_COLUMNS
+                    ),
+                    params![room_id, message_id],
+                    message_from_row,
+                )
+                .optional()?;
+            if let Some(message) = message.as_mut() {
+                load_reactions(conn, std::slice::from_mut(message))?;
+            }
+            Ok(message)
+        })
+        .await
+    }
+
+    async fn edit_message(
+        &self,
+        message: &ChatMessage,
+        message_text: &str,
+        filter: Option<&FilterDecision>,
+        edited_at: DateTime<Utc>,
+    ) -> Result<ChatMessage, StoreError> {
+        let mut edited = message.clone();
+        edited.message_text = message_text.to_string();
+        edited.filter = filter.cloned();
+        edited.edited_at = Some(edited_at);
+
+        let message = message.clone();
+        let updated = edited.clone();
+        let applied = self
+            .call(move |conn| {
+                let tx = conn.unchecked_transaction()?;
+                let changed = tx.execute(
+                    "UPDATE messages
+                     SET message_text = ?1, edited_at = ?2, filter_action = ?3, filter_names = ?4
+                     WHERE room_id = ?5 AND id = ?6 AND message_text = ?7",
+                    params![
+                        updated.message_text,
+                        edited_at.to_rfc3339(),
+                        updated.filter.as_ref().map(|filter| filter.action.as_str()),
+                        updated.filter.as_ref().map(|filter| filter.filters.join(",")),
+                        message.room_id,
+                        message.id,
+                        message.message_text,
+                    ],
+                )?;
+                if changed == 0 {
+                    return Ok(false);
+                }
+                tx.execute(
+                    "INSERT INTO message_revisions
+                        (room_id, message_id, revision, message_text, replaced_at)
+                     SELECT ?1, ?2, COALESCE(MAX(revision), 0) + 1, ?3, ?4
+                     FROM message_revisions WHERE room_id = ?1 AND message_id = ?2",
+                    params![
+                        message.room_id,
+                        message.id,
+                        message.message_text,
+                        edited_at.to_rfc3339()
+                    ],
+                )?;
+                // Numbers keep counting up past the ones dropped here
+                tx.execute(
+                    "DELETE FROM message_revisions
+                     WHERE room_id = ?1 AND message_id = ?2 AND revision <= (
+                         SELECT MAX(revision) - ?3 FROM message_revisions
+                         WHERE room_id = ?1 AND message_id = ?2
+                     )",
+                    params![message.room_id, message.id, MAX_REVISIONS as i64],
+                )?;
+                tx.commit()?;
+                Ok(true)
+            })
+            .await?;
+        if applied {
+            Ok(edited)
+        } else {
+            Err(StoreError::ConditionFailed)
+        }
+    }
+
+    async fn record_reply(
+        &self,
+        room_id: &str,
+        parent_id: &str,
+        replied_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        let room_id = room_id.to_string();
+        let parent_id = parent_id.to_string();
+        let changed = self
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE messages SET reply_count = reply_count + 1, last_reply_at = ?1
+                     WHERE room_id = ?2 AND id = ?3",
+                    params![replied_at.to_rfc3339(), room_id, parent_id],
+                )
+            })
+            .await?;
+        if changed == 0 {
+            return Err(StoreError::ConditionFailed);
+        }
+        Ok(())
+    }
+
+    async fn message_revisions(
+        &self,
+        room_id: &str,
+        message_id: &str,
+    ) -> Result<Vec<MessageRevision>, StoreError> {
+        let room_id = room_id.to_string();
+        let message_id = message_id.to_string();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT message_text, replaced_at FROM message_revisions
+                 WHERE room_id = ?1 AND message_id = ?2 ORDER BY revision ASC",
+            )?;
+            let rows = stmt.query_map(params![room_id, message_id], |row| {
+                let replaced_at: String = row.get(1)?;
+                Ok(MessageRevision {
+                    message_text: row.get(0)?,
+                    replaced_at: parse_time(&replaced_at),
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn delete_message(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        deleted_at: DateTime<Utc>,
+    ) -> Result<ChatMessage, StoreError> {
+        let room_id = room_id.to_string();
+        let message_id = message_id.to_string();
+        let deleted = self
+            .call(move |conn| {
+                let tx = conn.unchecked_transaction()?;
+                let changed = tx.execute(
+                    "UPDATE messages SET message_text = ?1, deleted_at = ?2
+                     WHERE room_id = ?3 AND id = ?4",
+                    params![DELETED_MESSAGE_TEXT, deleted_at.to_rfc3339(), room_id, message_id],
+                )?;
+                if changed == 0 {
+                    return Ok(None);
+                }
+                tx.execute(
+                    "DELETE FROM message_revisions WHERE room_id = ?1 AND message_id = ?2",
+                    params![room_id, message_id],
+                )?;
+                tx.execute(
+                    "DELETE FROM message_reactions WHERE room_id = ?1 AND message_id = ?2",
+                    params![room_id, message_id],
+                )?;
+                let deleted = tx.query_row(
+                    &format!(
+                        "SELECT {} FROM messages WHERE room_id = ?1 AND id = ?2",
+                        MESSAGE_COLUMNS
+                    ),
+                    params![room_id, message_id],
+                    message_from_row,
+                )?;
+                tx.commit()?;
+                Ok(Some(deleted))
+            })
+            .await?;
+        deleted.ok_or(StoreError::ConditionFailed)
+    }
+
+    async fn query_messages(
+        &self,
+        room_id: &str,
+        query: &MessageQuery,
+    ) -> Result<MessagePage, StoreError> {
+        let room_id = room_id.to_string();
+        let query = query.clone();
+        self.call(move |conn| {
+            let order = if query.newest_first { "DESC" } else { "ASC" };
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM messages
+                 WHERE room_id = ?1 AND (?2 IS NULL OR id < ?2) AND (?3 IS NULL OR id > ?3)
+                   AND parent_id IS ?5
+                 ORDER BY id {} LIMIT ?4",
+                MESSAGE_COLUMNS, order
+            ))?;
+            // Fetch one extra row to know whether another page exists
+            let rows = stmt.query_map(
+                params![
+                    room_id,
+                    query.before,
+                    query.after,
+                    query.limit as i64 + 1,
+                    query.parent_id
+                ],
+                message_from_row,
+            )?;
+            let mut messages = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+            load_reactions(conn, &mut messages)?;
+
+            let next_cursor = if messages.len() > query.limit {
+                messages.truncate(query.limit);
+                messages.last().map(|m| m.id.clone())
+            } else {
+                None
+            };
+            Ok(MessagePage { messages, next_cursor })
+        })
+        .await
+    }
+
+    async fn add_reaction(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+        max_distinct: usize,
+    ) -> Result<ReactionOutcome, StoreError> {
+        let (room_id, message_id) = (room_id.to_string(), message_id.to_string());
+        let (emoji, user_id) = (emoji.to_string(), user_id.to_string());
+        let outcome = self
+            .call(move |conn| {
+                let tx = conn.unchecked_transaction()?;
+                let live: Option<bool> = tx
+                    .query_row(
+                        "SELECT deleted_at IS NULL FROM messages WHERE room_id = ?1 AND id = ?2",
+                        params![room_id, message_id],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                if live != Some(true) {
+                    return Ok(None);
+                }
+
+                let (distinct, has_emoji, has_reaction): (usize, bool, bool) = tx.query_row(
+                    "SELECT COUNT(DISTINCT emoji),
+                            COALESCE(MAX(emoji = ?3), 0),
+                            COALESCE(MAX(emoji = ?3 AND user_id = ?4), 0)
+                     FROM message_reactions WHERE room_id = ?1 AND message_id = ?2",
+                    params![room_id, message_id, emoji, user_id],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )?;
+                if has_reaction {
+                    return Ok(Some(ReactionOutcome::AlreadyPresent));
+                }
+                if !has_emoji && distinct >= max_distinct {
+                    return Ok(Some(ReactionOutcome::LimitReached));
+                }
+
+                tx.execute(
+                    "INSERT INTO message_reactions (room_id, message_id, emoji, user_id, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![room_id, message_id, emoji, user_id, Utc::now().to_rfc3339()],
+                )?;
+                tx.commit()?;
+                Ok(Some(ReactionOutcome::Added))
+            })
+            .await?;
+        outcome.ok_or(StoreError::ConditionFailed)
+    }
+
+    async fn remove_reaction(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> Result<bool, StoreError> {
+        let (room_id, message_id) = (room_id.to_string(), message_id.to_string());
+        let (emoji, user_id) = (emoji.to_string(), user_id.to_string());
+        let removed = self
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM message_reactions
+                     WHERE room_id = ?1 AND message_id = ?2 AND emoji = ?3 AND user_id = ?4",
+                    params![room_id, message_id, emoji, user_id],
+                )
+            })
+            .await?;
+        Ok(removed > 0)
+    }
+
+    async fn get_member(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<RoomMember>, StoreError> {
+        let room_id = room_id.to_string();
+        let user_id = user_id.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                "SELECT room_id, user_id, role, joined_at FROM room_members
+                 WHERE room_id = ?1 AND user_id = ?2",
+                params![room_id, user_id],
+                member_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn put_member(&self, member: &RoomMember) -> Result<(), StoreError> {
+        let member = member.clone();
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO room_members (room_id, user_id, role, joined_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    member.room_id,
+                    member.user_id,
+                    member.role.as_str(),
+                    member.joined_at.to_rfc3339()
+                ],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_member(&self, room_id: &str, user_id: &str) -> Result<bool, StoreError> {
+        let room_id = room_id.to_string();
+        let user_id = user_id.to_string();
+        let removed = self
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM room_members WHERE room_id = ?1 AND user_id = ?2",
+                    params![room_id, user_id],
+                )
+            })
+            .await?;
+        Ok(removed > 0)
+    }
+
+    async fn room_members(&self, room_id: &str) -> Result<Vec<RoomMember>, StoreError> {
+        let room_id = room_id.to_string();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT room_id, user_id, role, joined_at FROM room_members
+                 WHERE room_id = ?1 ORDER BY user_id ASC",
+            )?;
+            let rows = stmt.query_map(params![room_id], member_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn user_memberships(&self, user_id: &str) -> Result<Vec<RoomMember>, StoreError> {
+        let user_id = user_id.to_string();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT room_id, user_id, role, joined_at FROM room_members
+                 WHERE user_id = ?1 ORDER BY room_id ASC",
+            )?;
+            let rows = stmt.query_map(params![user_id], member_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn get_sanction(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<RoomSanction>, StoreError> {
+        let room_id = room_id.to_string();
+        let user_id = user_id.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM room_sanctions WHERE room_id = ?1 AND user_id = ?2",
+                    SANCTION_COLUMNS
+                ),
+                params![room_id, user_id],
+                sanction_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn put_sanction(&self, sanction: &RoomSanction) -> Result<(), StoreError> {
+        let sanction = sanction.clone();
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO room_sanctions ({})
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    SANCTION_COLUMNS
+                ),
+                params![
+                    sanction.room_id,
+                    sanction.user_id,
+                    sanction.muted_until.map(|at| at.to_rfc3339()),
+                    sanction.banned,
+                    sanction.reason,
+                    sanction.moderator_id,
+                    sanction.updated_at.to_rfc3339()
+                ],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_sanction(&self, room_id: &str, user_id: &str) -> Result<bool, StoreError> {
+        let room_id = room_id.to_string();
+        let user_id = user_id.to_string();
+        let removed = self
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM room_sanctions WHERE room_id = ?1 AND user_id = ?2",
+                    params![room_id, user_id],
+                )
+            })
+            .await?;
+        Ok(removed > 0)
+    }
+
+    async fn room_sanctions(&self, room_id: &str) -> Result<Vec<RoomSanction>, StoreError> {
+        let room_id = room_id.to_string();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM room_sanctions WHERE room_id = ?1 ORDER BY user_id ASC",
+                SANCTION_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![room_id], sanction_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn get_report(
+        &self,
+        room_id: &str,
+        report_id: &str,
+    ) -> Result<Option<MessageReport>, StoreError> {
+        let room_id = room_id.to_string();
+        let report_id = report_id.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM message_reports WHERE room_id = ?1 AND report_id = ?2",
+                    REPORT_COLUMNS
+                ),
+                params![room_id, report_id],
+                report_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn put_report(&self, report: &MessageReport) -> Result<(), StoreError> {
+        let report = report.clone();
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO message_reports ({})
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    REPORT_COLUMNS
+                ),
+                params![
+                    report.room_id,
+                    report.id,
+                    report.message_id,
+                    report.reporter_id,
+                    report.reason,
+                    report.message_user_id,
+                    report.message_text,
+                    report.status.as_str(),
+                    report.created_at.to_rfc3339(),
+                    report.resolved_by,
+                    report.resolved_at.map(|at| at.to_rfc3339())
+                ],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn room_reports(
+        &self,
+        room_id: &str,
+        status: Option<ReportStatus>,
+    ) -> Result<Vec<MessageReport>, StoreError> {
+        let room_id = room_id.to_string();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM message_reports
+                 WHERE room_id = ?1 AND (?2 IS NULL OR status = ?2)
+                 ORDER BY report_id ASC",
+                REPORT_COLUMNS
+            ))?;
+            let rows =
+                stmt.query_map(params![room_id, status.map(|s| s.as_str())], report_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn get_read_marker(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<ReadMarker>, StoreError> {
+        let room_id = room_id.to_string();
+        let user_id = user_id.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM read_markers WHERE room_id = ?1 AND user_id = ?2",
+                    READ_MARKER_COLUMNS
+                ),
+                params![room_id, user_id],
+                read_marker_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn advance_read_marker(&self, marker: &ReadMarker) -> Result<bool, StoreError> {
+        let marker = marker.clone();
+        let changed = self
+            .call(move |conn| {
+                conn.execute(
+                    &format!(
+                        "INSERT INTO read_markers ({}) VALUES (?1, ?2, ?3, ?4)
+                         ON CONFLICT (room_id, user_id) DO UPDATE
+                         SET message_id = excluded.message_id, read_at = excluded.read_at
+                         WHERE excluded.message_id > read_markers.message_id",
+                        READ_MARKER_COLUMNS
+                    ),
+                    params![
+                        marker.room_id,
+                        marker.user_id,
+                        marker.message_id,
+                        marker.read_at.to_rfc3339()
+                    ],
+                )
+            })
+            .await?;
+        Ok(changed > 0)
+    }
+
+    async fn room_read_markers(&self, room_id: &str) -> Result<Vec<ReadMarker>, StoreError> {
+        let room_id = room_id.to_string();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM read_markers WHERE room_id = ?1 ORDER BY user_id ASC",
+                READ_MARKER_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![room_id], read_marker_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn user_read_markers(&self, user_id: &str) -> Result<Vec<ReadMarker>, StoreError> {
+        let user_id = user_id.to_string();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM read_markers WHERE user_id = ?1 ORDER BY room_id ASC",
+                READ_MARKER_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user_id], read_marker_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn put_connection(&self, connection: &Connection) -> Result<(), StoreError> {
+        let c = connection.clone();
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO connections ({}) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    CONNECTION_COLUMNS
+                ),
+                params![
+                    c.connection_id,
+                    c.room_id,
+                    c.user_id,
+                    c.username,
+                    c.connected_at,
+                    c.domain,
+                    c.stage,
+                    c.transport,
+                    c.push_url,
+                    c.ttl,
+                    c.typing_until,
+                ],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get_connection(&self, connection_id: &str) -> Result<Option<Connection>, StoreError> {
+        let connection_id = connection_id.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM connections WHERE connection_id = ?1", CONNECTION_COLUMNS),
+                params![connection_id],
+                connection_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn delete_connection(&self, connection_id: &str) -> Result<(), StoreError> {
+        let connection_id = connection_id.to_string();
+        self.call(move |conn| {
+            conn.execute("DELETE FROM connections WHERE connection_id = ?1", params![connection_id])
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn set_typing(
+        &self,
+        connection_id: &str,
+        typing_until: Option<i64>,
+    ) -> Result<(), StoreError> {
+        let connection_id = connection_id.to_string();
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE connections SET typing_until = ?2 WHERE connection_id = ?1",
+                params![connection_id, typing_until],
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn room_connections(&self, room_id: &str) -> Result<Vec<Connection>, StoreError> {
+        let room_id = room_id.to_string();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM connections WHERE room_id = ?1 ORDER BY connected_at ASC",
+                CONNECTION_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![room_id], connection_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(room_id: &str, id: &str, ts: i64) -> ChatMessage {
+        ChatMessage {
+            id: id.to_string(),
+            room_id: room_id.to_string(),
+            user_id: "user-1".to_string(),
+            username: "alice".to_string(),
+            message_text: format!("message {}", id),
+            created_at: DateTime::from_timestamp_millis(ts).unwrap(),
+            client_message_id: None,
+            edited_at: None,
+            deleted_at: None,
+            reactions: Vec::new(),
+            parent_id: None,
+            reply_count: 0,
+            last_reply_at: None,
+            filter: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrations_are_idempotent() {
+        let dir = std::env::temp_dir().join(format!("chat-{}.db", uuid::Uuid::new_v4()));
+        let path = dir.to_str().unwrap();
+
+        let store = SqliteStore::open(path).unwrap();
+        let mut room = Room {
+            id: "general".to_string(),
+            name: "General".to_string(),
+            created_at: Utc::now(),
+            description: None,
+            topic: Some("Anything goes".to_string()),
+            archived: false,
+            visibility: RoomVisibility::Public,
+            kind: RoomKind::Channel,
+            last_message_at: None,
+        };
+        store.create_room(&room).await.unwrap();
+        drop(store);
+
+        // Re-opening must not re-run migrations or lose data
+        let store = SqliteStore::open(path).unwrap();
+        assert_eq!(store.create_room(&room).await, Err(StoreError::ConditionFailed));
+        assert_eq!(store.get_room("general").await.unwrap().unwrap().name, "General");
+
+        room.archived = true;
+        room.visibility = RoomVisibility::Private;
+        store.update_room(&room).await.unwrap();
+        let rooms = store.list_rooms().await.unwrap();
+        assert_eq!(rooms.len(), 1);
+        assert!(rooms[0].archived);
+        assert_eq!(rooms[0].visibility, RoomVisibility::Private);
+
+        let at = DateTime::from_timestamp_millis(5_000).unwrap();
+        store.record_room_activity("general", at).await.unwrap();
+        assert_eq!(store.get_room("general").await.unwrap().unwrap().last_message_at, Some(at));
+        assert_eq!(rooms[0].topic.as_deref(), Some("Anything goes"));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_ts_keyed_messages_are_rekeyed() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        for migration in &MIGRATIONS[..2] {
+            if let Migration::Sql(sql) = migration {
+                conn.execute_batch(sql).unwrap();
+            }
+        }
+        conn.pragma_update(None, "user_version", 2).unwrap();
+        conn.execute_batch(
+            "INSERT INTO messages (room_id, ts, id, user_id, username, message_text)
+                VALUES ('general', 2000, 'legacy-b', 'u1', 'alice', 'second'),
+                       ('general', 1000, 'legacy-a', 'u1', 'alice', 'first');
+             INSERT INTO message_dedup (dedup_key, room_id, ts, expires_at)
+                VALUES ('general#u1#client-1', 'general', 1000, 9999999999);",
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+        let store = SqliteStore { conn: Arc::new(Mutex::new(conn)) };
+
+        let page = store
+            .query_messages("general", &MessageQuery { limit: 10, ..Default::default() })
+            .await
+            .unwrap();
+        let texts: Vec<&str> = page.messages.iter().map(|m| m.message_text.as_str()).collect();
+        assert_eq!(texts, vec!["first", "second"]);
+        assert_eq!(page.messages[0].id, legacy_message_id(1000, "legacy-a"));
+        assert_eq!(page.messages[0].created_at.timestamp_millis(), 1000);
+
+        // Markers follow their message to the new key
+        let mut retry = message("general", "01J00000000000000000000000", 5_000);
+        retry.user_id = "u1".to_string();
+        retry.client_message_id = Some("client-1".to_string());
+        match store.put_message_once(&retry, 9_999_999_999).await.unwrap() {
+            PutMessageOutcome::Duplicate(original) => assert_eq!(original.message_text, "first"),
+            PutMessageOutcome::Created => panic!("retry should be deduplicated"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_messages_ordered_and_limited() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        store.put_message(&message("general", "b", 2_000)).await.unwrap();
+        store.put_message(&message("general", "a", 1_000)).await.unwrap();
+        store.put_message(&message("general", "c", 3_000)).await.unwrap();
+        store.put_message(&message("other", "x", 500)).await.unwrap();
+        assert_eq!(
+            store.put_message(&message("general", "a", 4_000)).await,
+            Err(StoreError::ConditionFailed)
+        );
+
+        let page = store
+            .query_messages("general", &MessageQuery { limit: 2, ..Default::default() })
+            .await
+            .unwrap();
+        let ids: Vec<String> = page.messages.into_iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(page.next_cursor.as_deref(), Some("b"));
+
+        let page = store
+            .query_messages(
+                "general",
+                &MessageQuery {
+                    before: Some("c".to_string()),
+                    limit: 5,
+                    newest_first: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let ids: Vec<String> = page.messages.into_iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec!["b", "a"]);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn test_edit_message_keeps_revisions() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let original = message("general", "a", 1_000);
+        store.put_message(&original).await.unwrap();
+
+        let edited_at = DateTime::from_timestamp_millis(5_000).unwrap();
+        let edited = store.edit_message(&original, "fixed", None, edited_at).await.unwrap();
+        store.edit_message(&edited, "fixed twice", None, edited_at).await.unwrap();
+        assert_eq!(
+            store.edit_message(&original, "stale", None, edited_at).await.unwrap_err(),
+            StoreError::ConditionFailed
+        );
+
+        let stored = store.get_message("general", "a").await.unwrap().unwrap();
+        assert_eq!(stored.message_text, "fixed twice");
+        assert_eq!(stored.edited_at, Some(edited_at));
+        let texts: Vec<String> = store
+            .message_revisions("general", "a")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.message_text)
+            .collect();
+        assert_eq!(texts, vec!["message a", "fixed"]);
+
+        // Only the latest MAX_REVISIONS are kept
+        let mut current = stored;
+        for i in 0..MAX_REVISIONS {
+            current = store
+                .edit_message(&current, &format!("edit {}", i), None, edited_at)
+                .await
+                .unwrap();
+        }
+        let revisions = store.message_revisions("general", "a").await.unwrap();
+        assert_eq!(revisions.len(), MAX_REVISIONS);
+        assert_eq!(revisions[0].message_text, "fixed twice");
+        assert_eq!(
+            revisions[MAX_REVISIONS - 1].message_text,
+            format!("edit {}", MAX_REVISIONS - 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_message_and_members() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let filter = FilterDecision {
+            action: FilterAction::Flag,
+            filters: vec!["links".to_string(), "mentions".to_string()],
+        };
+        let flagged =
+            ChatMessage { filter: Some(filter.clone()), ..message("general", "a", 1_000) };
+        store.put_message(&flagged).await.unwrap();
+        let stored = store.get_message("general", "a").await.unwrap().unwrap();
+        assert_eq!(stored.filter, Some(filter));
+        let at = DateTime::from_timestamp_millis(5_000).unwrap();
+
+        let deleted = store.delete_message("general", "a", at).await.unwrap();
+        assert_eq!(deleted.message_text, DELETED_MESSAGE_TEXT);
+        assert_eq!(deleted.deleted_at, Some(at));
+        assert_eq!(
+            store.delete_message("general", "missing", at).await.unwrap_err(),
+            StoreError::ConditionFailed
+        );
+
+        let member = RoomMember {
+            room_id: "general".to_string(),
+            user_id: "u1".to_string(),
+            role: RoomRole::Moderator,
+            joined_at: at,
+        };
+        store.put_member(&member).await.unwrap();
+        let stored = store.get_member("general", "u1").await.unwrap().unwrap();
+        assert_eq!(stored.role, RoomRole::Moderator);
+        assert!(store.get_member("general", "u2").await.unwrap().is_none());
+        assert_eq!(store.room_members("general").await.unwrap()[0].user_id, "u1");
+        assert_eq!(store.user_memberships("u1").await.unwrap()[0].room_id, "general");
+
+        assert!(store.delete_member("general", "u1").await.unwrap());
+        assert!(!store.delete_member("general", "u1").await.unwrap());
+        assert!(store.room_members("general").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_room_sanctions() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let at = DateTime::from_timestamp_millis(5_000).unwrap();
+        let muted = RoomSanction {
+            room_id: "general".to_string(),
+            user_id: "u2".to_string(),
+            muted_until: Some(at),
+            banned: false,
+            reason: Some("spam".to_string()),
+            moderator_id: "u1".to_string(),
+            updated_at: at,
+        };
+        store.put_sanction(&muted).await.unwrap();
+        assert_eq!(store.get_sanction("general", "u2").await.unwrap(), Some(muted.clone()));
+
+        // Replacing keeps one row per user
+        let banned = RoomSanction { muted_until: None, banned: true, reason: None, ..muted };
+        store.put_sanction(&banned).await.unwrap();
+        assert_eq!(store.room_sanctions("general").await.unwrap(), vec![banned]);
+        assert!(store.get_sanction("general", "u1").await.unwrap().is_none());
+
+        assert!(store.delete_sanction("general", "u2").await.unwrap());
+        assert!(!store.delete_sanction("general", "u2").await.unwrap());
+        assert!(store.room_sanctions("general").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_message_reports() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let at = DateTime::from_timestamp_millis(5_000).unwrap();
+        let report = |id: &str| MessageReport {
+            id: id.to_string(),
+            room_id: "general".to_string(),
+            message_id: "m1".to_string(),
+            reporter_id: "u2".to_string(),
+            reason: "spam".to_string(),
+            message_user_id: "u1".to_string(),
+            message_text: "buy now".to_string(),
+            status: ReportStatus::Open,
+            created_at: at,
+            resolved_by: None,
+            resolved_at: None,
+        };
+        store.put_report(&report("r2")).await.unwrap();
+        store.put_report(&report("r1")).await.unwrap();
+        assert_eq!(store.get_report("general", "r1").await.unwrap(), Some(report("r1")));
+        assert!(store.get_report("other", "r1").await.unwrap().is_none());
+
+        // Resolving overwrites the report in place
+        let dismissed = MessageReport {
+            status: ReportStatus::Dismissed,
+            resolved_by: Some("u3".to_string()),
+            resolved_at: Some(at),
+            ..report("r2")
+        };
+        store.put_report(&dismissed).await.unwrap();
+        let all = store.room_reports("general", None).await.unwrap();
+        assert_eq!(all, vec![report("r1"), dismissed.clone()]);
+        let open = store.room_reports("general", Some(ReportStatus::Open)).await.unwrap();
+        assert_eq!(open, vec![report("r1")]);
+        let closed = store.room_reports("general", Some(ReportStatus::Dismissed)).await.unwrap();
+        assert_eq!(closed, vec![dismissed]);
+    }
+
+    #[tokio::test]
+    async fn test_read_markers() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let marker = |room_id: &str, user_id: &str, message_id: &str| ReadMarker {
+            room_id: room_id.to_string(),
+            user_id: user_id.to_string(),
+            message_id: message_id.to_string(),
+            read_at: DateTime::from_timestamp_millis(5_000).unwrap(),
+        };
+        assert!(store.advance_read_marker(&marker("general", "u1", "b")).await.unwrap());
+        assert!(store.advance_read_marker(&marker("random", "u1", "a")).await.unwrap());
+        assert!(store.advance_read_marker(&marker("general", "u2", "a")).await.unwrap());
+
+        // Markers only move forward
+        assert!(!store.advance_read_marker(&marker("general", "u1", "a")).await.unwrap());
+        assert!(!store.advance_read_marker(&marker("general", "u1", "b")).await.unwrap());
+        let stored = store.get_read_marker("general", "u1").await.unwrap();
+        assert_eq!(stored, Some(marker("general", "u1", "b")));
+        assert!(store.advance_read_marker(&marker("general", "u1", "c")).await.unwrap());
+
+        let room = store.room_read_markers("general").await.unwrap();
+        assert_eq!(room, vec![marker("general", "u1", "c"), marker("general", "u2", "a")]);
+        let user = store.user_read_markers("u1").await.unwrap();
+        assert_eq!(user, vec![marker("general", "u1", "c"), marker("random", "u1", "a")]);
+        assert!(store.get_read_marker("random", "u2").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_thread_replies() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        store.put_message(&message("general", "a", 1_000)).await.unwrap();
+        let mut reply = message("general", "b", 2_000);
+        reply.parent_id = Some("a".to_string());
+        store.put_message(&reply).await.unwrap();
+        store.record_reply("general", "a", reply.created_at).await.unwrap();
+
+        let query = MessageQuery { limit: 10, ..Default::default() };
+        let page = store.query_messages("general", &query).await.unwrap();
+        assert_eq!(page.messages.len(), 1);
+        assert_eq!(page.messages[0].reply_count, 1);
+        assert_eq!(page.messages[0].last_reply_at, Some(reply.created_at));
+
+        let thread = MessageQuery { parent_id: Some("a".to_string()), ..query };
+        let page = store.query_messages("general", &thread).await.unwrap();
+        let ids: Vec<_> = page.messages.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["b"]);
+        assert_eq!(page.messages[0].parent_id.as_deref(), Some("a"));
+        assert_eq!(
+            store.record_reply("general", "missing", Utc::now()).await,
+            Err(StoreError::ConditionFailed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reactions() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        store.put_message(&message("general", "a", 1_000)).await.unwrap();
+        store.put_message(&message("general", "b", 2_000)).await.unwrap();
+
+        let react = |emoji: &'static str, user_id: &'static str| {
+            store.add_reaction("general", "a", emoji, user_id, 2)
+        };
+        assert_eq!(react("👍", "u1").await, Ok(ReactionOutcome::Added));
+        assert_eq!(react("👍", "u2").await, Ok(ReactionOutcome::Added));
+        assert_eq!(react("👍", "u2").await, Ok(ReactionOutcome::AlreadyPresent));
+        assert_eq!(react("🎉", "u2").await, Ok(ReactionOutcome::Added));
+        assert_eq!(react("🔥", "u2").await, Ok(ReactionOutcome::LimitReached));
+
+        let page = store
+            .query_messages("general", &MessageQuery { limit: 10, ..Default::default() })
+            .await
+            .unwrap();
+        let counts: Vec<_> =
+            page.messages[0].reactions.iter().map(|r| (r.emoji.as_str(), r.count)).collect();
+        assert_eq!(counts, vec![("🎉", 1), ("👍", 2)]);
+        assert!(page.messages[1].reactions.is_empty());
+
+        assert_eq!(store.remove_reaction("general", "a", "👍", "u1").await, Ok(true));
+        assert_eq!(store.remove_reaction("general", "a", "👍", "u1").await, Ok(false));
+        let stored = store.get_message("general", "a").await.unwrap().unwrap();
+        assert_eq!(stored.reactions[1].user_ids, vec!["u2".to_string()]);
+
+        store.delete_message("general", "a", Utc::now()).await.unwrap();
+        let stored = store.get_message("general", "a").await.unwrap().unwrap();
+        assert!(stored.reactions.is_empty());
+        assert_eq!(react("👍", "u1").await, Err(StoreError::ConditionFailed));
+        assert_eq!(
+            store.add_reaction("general", "missing", "👍", "u1", 2).await,
+            Err(StoreError::ConditionFailed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_room_connections() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        store.put_connection(&Connection::new("c2", "general", "u2", "bob", 2_000)).await.unwrap();
+        store
+            .put_connection(&Connection::new("c1", "general", "u1", "alice", 1_000))
+            .await
+            .unwrap();
+        store.put_connection(&Connection::new("c3", "random", "u3", "carol", 500)).await.unwrap();
+
+        let ids: Vec<String> = store
+            .room_connections("general")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.connection_id)
+            .collect();
+        assert_eq!(ids, vec!["c1", "c2"]);
+
+        store.delete_connection("c1").await.unwrap();
+        assert!(store.get_connection("c1").await.unwrap().is_none());
+    }
+}
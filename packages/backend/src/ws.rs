@@ -0,0 +1,428 @@
+use chrono::Utc;
+use tracing::{info, warn};
+use types::{
+    ChatMessage, ClientEvent, ClientFrame, MarkReadRequest, PresenceEvent, SendMessageRequest,
+    ServerEvent, ServerFrame, TypingEvent, WsError, WS_PROTOCOL_VERSION,
+};
+
+use crate::handlers::{self, HandlerError};
+use crate::rate_limit::{PostRateLimiter, TypingThrottle};
+use crate::store::{ChatStore, Connection, StoreError};
+
+// Frames clients send up the socket. The ws_default lambda and the dev server both hand
+// each text frame here along with the connection it arrived on, then deliver the result.
+
+/// How long a typing indicator lasts without another `typing.start`. The dev server sends
+/// the stop itself once it lapses. The lambdas keep it on the connection record and send
+/// the stop when a later frame or stream record in the room finds it lapsed (see
+/// [`expire_typing`]); until then, clients expire it from `expires_in_secs`.
+pub const TYPING_EXPIRY_SECS: u64 = 8;
+
+/// What came of a frame
+#[derive(Debug, Default)]
+pub struct Handled {
+    /// For the connection that sent the frame
+    pub reply: Option<ServerFrame>,
+    /// For the other users connected to the room. Never stored; whoever is not
+    /// connected right now misses it.
+    pub broadcast: Option<ServerFrame>,
+    /// A message the frame stored, unless it was a retry. Deployed, the broadcast lambda
+    /// counts posts off the table's stream; the dev server has none and counts this.
+    pub posted: Option<ChatMessage>,
+}
+
+impl Handled {
+    fn reply(frame: ServerFrame) -> Self {
+        Self { reply: Some(frame), ..Self::default() }
+    }
+
+    fn broadcast(frame: ServerFrame) -> Self {
+        Self { broadcast: Some(frame), ..Self::default() }
+    }
+}
+
+/// Handle one text frame from `connection`
+pub async fn handle_frame(
+    store: &dyn ChatStore,
+    limits: &PostRateLimiter,
+    typing: &TypingThrottle,
+    connection: &Connection,
+    text: &str,
+) -> Handled {
+    let frame = match serde_json::from_str::<ClientFrame>(text) {
+        Ok(frame) => frame,
+        Err(e) => {
+            info!("Unreadable frame on {}: {}", connection.connection_id, e);
+            let err = HandlerError::bad_request(format!("Invalid frame: {}", e));
+            return Handled::reply(error_frame(err, None));
+        }
+    };
+    if frame.v.is_some_and(|v| v > WS_PROTOCOL_VERSION) {
+        let err = HandlerError::bad_request(format!(
+            "Unsupported protocol version; this server speaks v{}",
+            WS_PROTOCOL_VERSION
+        ));
+        return Handled::reply(error_frame(err, None));
+    }
+
+    match frame.event {
+        ClientEvent::SendMessage { message_text, client_message_id, parent_id } => {
+            let request = SendMessageRequest {
+                room_id: connection.room_id.clone(),
+                user_id: connection.user_id.clone(),
+                username: connection.username.clone(),
+                message_text,
+                client_message_id,
+                parent_id,
+            };
+            // The same limits and checks as POST /chat/messages
+            match handlers::post_message_handler(store, limits, request.clone()).await {
+                Ok(posted) => Handled {
+                    reply: Some(
+                        ServerEvent::Ack {
+                            message_id: posted.message.id.clone(),
+                            client_message_id: request.client_message_id,
+                        }
+                        .into(),
+                    ),
+                    broadcast: None,
+                    posted: (!posted.replayed).then_some(posted.message),
+                },
+                Err(err) => Handled::reply(error_frame(err, request.client_message_id)),
+            }
+        }
+        ClientEvent::TypingStart => {
+            // Throttled or not, the user is still typing
+            let until = Utc::now().timestamp() + TYPING_EXPIRY_SECS as i64;
+            record_typing(store, connection, Some(until)).await;
+            if !typing.allow(&connection.connection_id).await {
+                return Handled::default();
+            }
+            Handled::broadcast(typing_frame(connection, true))
+        }
+        // Never throttled, or an indicator could hang around until it expires
+        ClientEvent::TypingStop => {
+            record_typing(store, connection, None).await;
+            Handled::broadcast(typing_frame(connection, false))
+        }
+        ClientEvent::MarkRead { message_id } => {
+            let request = MarkReadRequest { user_id: connection.user_id.clone(), message_id };
+            match handlers::mark_read_handler(store, connection.room_id.clone(), request).await {
+                // Everyone hears about it, the user's other tabs included
+                Ok(marked) if marked.advanced => {
+                    Handled::broadcast(ServerEvent::Read(marked.marker).into())
+                }
+                Ok(_) => Handled::default(),
+                Err(err) => Handled::reply(error_frame(err, None)),
+            }
+        }
+    }
+}
+
+/// The typing indicator of `connection`'s user starting or stopping
+pub fn typing_frame(connection: &Connection, active: bool) -> ServerFrame {
+    ServerEvent::Typing(TypingEvent {
+        room_id: connection.room_id.clone(),
+        user_id: connection.user_id.clone(),
+        username: connection.username.clone(),
+        active,
+        expires_in_secs: active.then_some(TYPING_EXPIRY_SECS),
+    })
+    .into()
+}
+
+// The indicator still goes out if this fails; it just may not be swept when it lapses
+async fn record_typing(store: &dyn ChatStore, connection: &Connection, until: Option<i64>) {
+    if let Err(e) = store.set_typing(&connection.connection_id, until).await {
+        warn!("Failed to record typing on {}: {:?}", connection.connection_id, e);
+    }
+}
+
+/// Stop frames for the typing indicators in `room_id` that lapsed by `now` (epoch seconds),
+/// which are cleared so that each stop goes out once
+pub async fn expire_typing(
+    store: &dyn ChatStore,
+    room_id: &str,
+    now: i64,
+) -> Result<Vec<ServerFrame>, StoreError> {
+    let mut stopped = Vec::new();
+    for connection in handlers::live_connections(store, room_id).await? {
+        if connection.typing_until.is_some_and(|until| until <= now) {
+            store.set_typing(&connection.connection_id, None).await?;
+            stopped.push(typing_frame(&connection, false));
+        }
+    }
+    Ok(stopped)
+}
+
+/// `connection`'s user coming online in its room, or going offline, if this changes
+/// anything: only a user's first connection joins and only their last one leaves, so extra
+/// tabs go unnoticed. Call after storing a new connection or deleting a closed one.
+pub async fn presence_change(
+    store: &dyn ChatStore,
+    connection: &Connection,
+    joined: bool,
+) -> Result<Option<ServerFrame>, StoreError> {
+    let connections = handlers::live_connections(store, &connection.room_id).await?;
+    let others = connections
+        .iter()
+        .filter(|c| c.user_id == connection.user_id && c.connection_id != connection.connection_id)
+        .count();
+    if others > 0 {
+        return Ok(None);
+    }
+    let event = PresenceEvent {
+        room_id: connection.room_id.clone(),
+        user_id: connection.user_id.clone(),
+        username: connection.username.clone(),
+    };
+    let event =
+        if joined { ServerEvent::PresenceJoin(event) } else { ServerEvent::PresenceLeave(event) };
+    Ok(Some(event.into()))
+}
+
+/// Whether `frame` should skip `connection`: users are not told about their own typing or
+/// presence, even in another tab. Read markers do go to the reader's other tabs.
+pub fn skips(frame: &ServerFrame, connection: &Connection) -> bool {
+    let user_id = match &frame.event {
+        ServerEvent::Typing(typing) => &typing.user_id,
+        ServerEvent::PresenceJoin(presence) | ServerEvent::PresenceLeave(presence) => {
+            &presence.user_id
+        }
+        _ => return false,
+    };
+    *user_id == connection.user_id
+}
+
+/// The connections in `room_id` that `frame` should go to, for the lambdas to push to
+pub async fn recipients(
+    store: &dyn ChatStore,
+    room_id: &str,
+    frame: &ServerFrame,
+) -> Result<Vec<Connection>, StoreError> {
+    let connections = handlers::live_connections(store, room_id).await?;
+    let connections = handlers::room_audience(store, room_id, connections).await?;
+    Ok(connections.into_iter().filter(|connection| !skips(frame, connection)).collect())
+}
+
+fn error_frame(err: HandlerError, client_message_id: Option<String>) -> ServerFrame {
+    ServerEvent::Error(WsError {
+        status: err.status,
+        message: err.message,
+        retry_after_secs: err.retry_after_secs,
+        client_message_id,
+    })
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::{MemoryRateLimiter, RateLimit};
+    use crate::store::{MemoryStore, MessageQuery};
+    use std::sync::Arc;
+
+    struct Socket {
+        store: MemoryStore,
+        limits: PostRateLimiter,
+        typing: TypingThrottle,
+        connection: Connection,
+    }
+
+    impl Socket {
+        fn new(limits: PostRateLimiter) -> Self {
+            let buckets = Arc::new(MemoryRateLimiter::new());
+            Self {
+                store: MemoryStore::new(),
+                limits,
+                typing: TypingThrottle::new(buckets, TypingThrottle::DEFAULT),
+                connection: Connection::new("c1", "general", "alice", "Alice", 0),
+            }
+        }
+
+        async fn send(&self, text: &str) -> Handled {
+            handle_frame(&self.store, &self.limits, &self.typing, &self.connection, text).await
+        }
+
+        async fn reply(&self, text: &str) -> ServerEvent {
+            let handled = self.send(text).await;
+            assert!(handled.broadcast.is_none());
+            handled.reply.expect("expected a reply").event
+        }
+
+        async fn error(&self, text: &str) -> WsError {
+            match self.reply(text).await {
+                ServerEvent::Error(error) => error,
+                other => panic!("expected an error, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_message_frames() {
+        let socket = Socket::new(PostRateLimiter::new(
+            Arc::new(MemoryRateLimiter::new()),
+            Some(RateLimit::new(2, 1)),
+            None,
+        ));
+        let send =
+            r#"{"v": 1, "type": "send_message", "message_text": "hi", "client_message_id": "m-1"}"#;
+
+        let ServerEvent::Ack { message_id, client_message_id } = socket.reply(send).await else {
+            panic!("expected an ack");
+        };
+        assert_eq!(client_message_id.as_deref(), Some("m-1"));
+        let stored = socket.store.get_message("general", &message_id).await.unwrap().unwrap();
+        assert_eq!((stored.user_id.as_str(), stored.message_text.as_str()), ("alice", "hi"));
+
+        // A retry is acked with the original id
+        let ServerEvent::Ack { message_id: replayed, .. } = socket.reply(send).await else {
+            panic!("expected an ack");
+        };
+        assert_eq!(replayed, message_id);
+        let query = MessageQuery { limit: 10, ..Default::default() };
+        let page = socket.store.query_messages("general", &query).await.unwrap();
+        assert_eq!(page.messages.len(), 1);
+
+        // The same per-user bucket as REST posts, which retries do not spend
+        let next = r#"{"type": "send_message", "message_text": "hi", "client_message_id": "m-2"}"#;
+        assert!(matches!(socket.reply(next).await, ServerEvent::Ack { .. }));
+        let third = r#"{"type": "send_message", "message_text": "hi", "client_message_id": "m-3"}"#;
+        let limited = socket.error(third).await;
+        assert_eq!(limited.status, 429);
+        assert_eq!(limited.retry_after_secs, Some(60));
+        assert_eq!(limited.client_message_id.as_deref(), Some("m-3"));
+        assert!(matches!(socket.reply(send).await, ServerEvent::Ack { .. }));
+
+        let socket = Socket::new(PostRateLimiter::unlimited());
+        let blank = r#"{"type": "send_message", "message_text": "  "}"#;
+        assert_eq!(socket.error(blank).await.status, 400);
+        assert_eq!(socket.error("{").await.status, 400);
+        let future = r#"{"v": 99, "type": "send_message", "message_text": "hi"}"#;
+        assert_eq!(socket.error(future).await.status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_typing_frames() {
+        let socket = Socket::new(PostRateLimiter::unlimited());
+        let typing = |handled: Handled| {
+            assert!(handled.reply.is_none());
+            match handled.broadcast.map(|frame| frame.event) {
+                Some(ServerEvent::Typing(typing)) => Some(typing),
+                None => None,
+                other => panic!("expected typing, got {:?}", other),
+            }
+        };
+
+        let started = typing(socket.send(r#"{"type": "typing.start"}"#).await).unwrap();
+        assert_eq!((started.user_id.as_str(), started.room_id.as_str()), ("alice", "general"));
+        assert!(started.active);
+        assert_eq!(started.expires_in_secs, Some(TYPING_EXPIRY_SECS));
+
+        // Repeats within the throttle go nowhere, but a stop always does
+        assert_eq!(typing(socket.send(r#"{"type": "typing.start"}"#).await), None);
+        let stopped = typing(socket.send(r#"{"type": "typing.stop"}"#).await).unwrap();
+        assert!(!stopped.active);
+        assert_eq!(stopped.expires_in_secs, None);
+
+        // Typing never becomes a message
+        let query = MessageQuery { limit: 10, ..Default::default() };
+        assert!(socket.store.query_messages("general", &query).await.unwrap().messages.is_empty());
+
+        let frame = typing_frame(&socket.connection, true);
+        assert!(skips(&frame, &socket.connection));
+        let other_tab = Connection::new("c2", "general", "alice", "Alice", 0);
+        assert!(skips(&frame, &other_tab));
+        assert!(!skips(&frame, &Connection::new("c3", "general", "bob", "Bob", 0)));
+    }
+
+    #[tokio::test]
+    async fn test_typing_expiry() {
+        let socket = Socket::new(PostRateLimiter::unlimited());
+        let now = Utc::now().timestamp();
+        let connection = Connection::new("c1", "general", "alice", "Alice", now * 1000);
+        socket.store.put_connection(&connection).await.unwrap();
+        let idle = Connection::new("c2", "general", "bob", "Bob", now * 1000);
+        socket.store.put_connection(&idle).await.unwrap();
+
+        socket.send(r#"{"type": "typing.start"}"#).await;
+        let until = socket.store.get_connection("c1").await.unwrap().unwrap().typing_until;
+        let until = until.expect("typing is recorded on the connection");
+        assert!(until >= now + TYPING_EXPIRY_SECS as i64);
+
+        // Nothing lapses while the indicator is fresh
+        assert!(expire_typing(&socket.store, "general", until - 1).await.unwrap().is_empty());
+
+        let stopped = expire_typing(&socket.store, "general", until).await.unwrap();
+        let [frame] = stopped.as_slice() else {
+            panic!("expected one stop, got {:?}", stopped);
+        };
+        assert!(
+            matches!(&frame.event, ServerEvent::Typing(t) if t.user_id == "alice" && !t.active)
+        );
+        // Each stop goes out once
+        assert!(expire_typing(&socket.store, "general", until + 60).await.unwrap().is_empty());
+
+        // An explicit stop clears it too
+        socket.send(r#"{"type": "typing.start"}"#).await;
+        socket.send(r#"{"type": "typing.stop"}"#).await;
+        assert!(expire_typing(&socket.store, "general", until + 60).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_presence_changes_once_per_user() {
+        let store = MemoryStore::new();
+        let now = chrono::Utc::now().timestamp_millis();
+        let first = Connection::new("c1", "general", "alice", "Alice", now);
+        let second = Connection::new("c2", "general", "alice", "Alice", now);
+
+        store.put_connection(&first).await.unwrap();
+        let joined = presence_change(&store, &first, true).await.unwrap().unwrap();
+        assert!(matches!(&joined.event, ServerEvent::PresenceJoin(p) if p.user_id == "alice"));
+        assert!(skips(&joined, &second));
+        assert!(!skips(&joined, &Connection::new("c3", "general", "bob", "Bob", now)));
+
+        // A second tab comes and goes unnoticed
+        store.put_connection(&second).await.unwrap();
+        assert!(presence_change(&store, &second, true).await.unwrap().is_none());
+        store.delete_connection("c2").await.unwrap();
+        assert!(presence_change(&store, &second, false).await.unwrap().is_none());
+
+        store.delete_connection("c1").await.unwrap();
+        let left = presence_change(&store, &first, false).await.unwrap().unwrap();
+        assert!(matches!(left.event, ServerEvent::PresenceLeave(_)));
+    }
+
+    #[tokio::test]
+    async fn test_mark_read_frames() {
+        let socket = Socket::new(PostRateLimiter::unlimited());
+        let send =
+            |text: &str| format!(r#"{{"type": "send_message", "message_text": "{}"}}"#, text);
+        let ServerEvent::Ack { message_id: first, .. } = socket.reply(&send("one")).await else {
+            panic!("expected an ack");
+        };
+        let ServerEvent::Ack { message_id: second, .. } = socket.reply(&send("two")).await else {
+            panic!("expected an ack");
+        };
+        let mark = |id: &str| format!(r#"{{"type": "mark_read", "message_id": "{}"}}"#, id);
+
+        let handled = socket.send(&mark(&second)).await;
+        assert!(handled.reply.is_none());
+        let frame = handled.broadcast.expect("expected a read event");
+        let ServerEvent::Read(marker) = &frame.event else {
+            panic!("expected a read event, got {:?}", frame.event);
+        };
+        assert_eq!((marker.user_id.as_str(), marker.message_id.as_str()), ("alice", &*second));
+        let other_tab = Connection::new("c2", "general", "alice", "Alice", 0);
+        assert!(!skips(&frame, &other_tab));
+
+        // Going back is a no-op, not an error
+        let handled = socket.send(&mark(&first)).await;
+        assert!(handled.reply.is_none() && handled.broadcast.is_none());
+        let stored = socket.store.get_read_marker("general", "alice").await.unwrap().unwrap();
+        assert_eq!(stored.message_id, second);
+
+        assert_eq!(socket.error(&mark("nope")).await.status, 400);
+        assert_eq!(socket.error(&mark(&ulid::Ulid::new().to_string())).await.status, 404);
+    }
+}
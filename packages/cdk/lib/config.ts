@@ -6,6 +6,8 @@ export interface StageConfig {
     account: string
     region: string
     cognitoUserPoolId?: string
+    cognitoClientId?: string
+    authApiKeys?: string // user_id=<sha256 of key>,... for bots
     cloudfrontDomain?: string
     testAssumeRoleArn?: string
     isProd: boolean
@@ -114,8 +116,16 @@ export const pipelineConfig: PipelineConfig = {
 // DynamoDB Table Names and ARNs
 export const DYNAMODB_TABLES = {
     CHAT_ROOMS: 'chat-rooms',
-    CHAT_MESSAGES: 'chat-messages',
+    // Keyed by room_id + id (ULID); replaces the ts-keyed CHAT_MESSAGES_LEGACY table
+    CHAT_MESSAGES: 'chat-messages-v2',
+    CHAT_MESSAGES_LEGACY: 'chat-messages',
     CHAT_CONNECTIONS: 'chat-connections',
+    CHAT_MESSAGE_DEDUP: 'chat-message-dedup',
+    CHAT_ROOM_MEMBERS: 'chat-room-members',
+    CHAT_ROOM_SANCTIONS: 'chat-room-sanctions',
+    CHAT_MESSAGE_REPORTS: 'chat-message-reports',
+    CHAT_READ_MARKERS: 'chat-read-markers',
+    CHAT_RATE_LIMITS: 'chat-rate-limits',
 } as const
 
 // DynamoDB Table ARN builders (requires region and account)
@@ -124,8 +134,22 @@ export const DYNAMODB_ARNS = {
         `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_ROOMS}`,
     CHAT_MESSAGES: (region: string, account: string) =>
         `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_MESSAGES}`,
+    CHAT_MESSAGES_LEGACY: (region: string, account: string) =>
+        `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_MESSAGES_LEGACY}`,
     CHAT_CONNECTIONS: (region: string, account: string) =>
         `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_CONNECTIONS}`,
+    CHAT_MESSAGE_DEDUP: (region: string, account: string) =>
+        `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_MESSAGE_DEDUP}`,
+    CHAT_ROOM_MEMBERS: (region: string, account: string) =>
+        `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_ROOM_MEMBERS}`,
+    CHAT_ROOM_SANCTIONS: (region: string, account: string) =>
+        `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_ROOM_SANCTIONS}`,
+    CHAT_MESSAGE_REPORTS: (region: string, account: string) =>
+        `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_MESSAGE_REPORTS}`,
+    CHAT_READ_MARKERS: (region: string, account: string) =>
+        `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_READ_MARKERS}`,
+    CHAT_RATE_LIMITS: (region: string, account: string) =>
+        `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_RATE_LIMITS}`,
     CHAT_MESSAGES_STREAM: (region: string, account: string) =>
         `arn:aws:dynamodb:${region}:${account}:table/${DYNAMODB_TABLES.CHAT_MESSAGES}/stream/*`,
 } as const
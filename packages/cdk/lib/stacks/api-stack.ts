@@ -27,6 +27,19 @@ export class ApiStack extends cdk.Stack {
         const chatRoomsTableArn = DYNAMODB_ARNS.CHAT_ROOMS(this.region, this.account)
         const chatMessagesTableArn = DYNAMODB_ARNS.CHAT_MESSAGES(this.region, this.account)
         const chatConnectionsTableArn = DYNAMODB_ARNS.CHAT_CONNECTIONS(this.region, this.account)
+        const chatMessageDedupTableArn = DYNAMODB_ARNS.CHAT_MESSAGE_DEDUP(this.region, this.account)
+        const chatRoomMembersTableArn = DYNAMODB_ARNS.CHAT_ROOM_MEMBERS(this.region, this.account)
+        const chatRoomSanctionsTableArn = DYNAMODB_ARNS.CHAT_ROOM_SANCTIONS(this.region, this.account)
+        const chatMessageReportsTableArn = DYNAMODB_ARNS.CHAT_MESSAGE_REPORTS(this.region, this.account)
+        const chatReadMarkersTableArn = DYNAMODB_ARNS.CHAT_READ_MARKERS(this.region, this.account)
+        const chatRateLimitsTableArn = DYNAMODB_ARNS.CHAT_RATE_LIMITS(this.region, this.account)
+
+        // Authenticators: the stage's Cognito pool, and API keys (SHA-256 digests) for bots
+        const authEnv: Record<string, string> = {
+            ...(stageConfig.cognitoUserPoolId ? { COGNITO_USER_POOL_ID: stageConfig.cognitoUserPoolId } : {}),
+            ...(stageConfig.cognitoClientId ? { COGNITO_CLIENT_ID: stageConfig.cognitoClientId } : {}),
+            ...(stageConfig.authApiKeys ? { AUTH_API_KEYS: stageConfig.authApiKeys } : {}),
+        }
 
         // === DNS/Certificates for Custom Domains ===
         // Use the hosted zone provided by DNS stack
@@ -55,8 +68,16 @@ export class ApiStack extends cdk.Stack {
             environment: {
                 CHAT_ROOMS_TABLE: DYNAMODB_TABLES.CHAT_ROOMS,
                 CHAT_MESSAGES_TABLE: DYNAMODB_TABLES.CHAT_MESSAGES,
+                CONNECTIONS_TABLE: DYNAMODB_TABLES.CHAT_CONNECTIONS,
+                CHAT_DEDUP_TABLE: DYNAMODB_TABLES.CHAT_MESSAGE_DEDUP,
+                CHAT_MEMBERS_TABLE: DYNAMODB_TABLES.CHAT_ROOM_MEMBERS,
+                CHAT_SANCTIONS_TABLE: DYNAMODB_TABLES.CHAT_ROOM_SANCTIONS,
+                CHAT_REPORTS_TABLE: DYNAMODB_TABLES.CHAT_MESSAGE_REPORTS,
+                CHAT_READS_TABLE: DYNAMODB_TABLES.CHAT_READ_MARKERS,
+                RATE_LIMIT_TABLE: DYNAMODB_TABLES.CHAT_RATE_LIMITS,
                 STAGE: stageConfig.name,
                 DOMAIN: stageConfig.domain,
+                ...authEnv,
             },
             timeout: cdk.Duration.seconds(30),
         })
@@ -72,8 +93,23 @@ export class ApiStack extends cdk.Stack {
                     'dynamodb:DeleteItem',
                     'dynamodb:Query',
                     'dynamodb:Scan',
+                    'dynamodb:ConditionCheckItem',
+                ],
+                resources: [
+                    chatRoomsTableArn,
+                    chatMessagesTableArn,
+                    `${chatMessagesTableArn}/index/*`,
+                    chatConnectionsTableArn,
+                    `${chatConnectionsTableArn}/index/*`,
+                    chatMessageDedupTableArn,
+                    chatRoomMembersTableArn,
+                    `${chatRoomMembersTableArn}/index/*`,
+                    chatRoomSanctionsTableArn,
+                    chatMessageReportsTableArn,
+                    chatReadMarkersTableArn,
+                    `${chatReadMarkersTableArn}/index/*`,
+                    chatRateLimitsTableArn,
                 ],
-                resources: [chatRoomsTableArn, chatMessagesTableArn],
             })
         )
 
@@ -124,11 +160,14 @@ export class ApiStack extends cdk.Stack {
                 allowMethods: [
                     apigatewayv2.CorsHttpMethod.GET,
                     apigatewayv2.CorsHttpMethod.POST,
+                    apigatewayv2.CorsHttpMethod.PATCH,
+                    apigatewayv2.CorsHttpMethod.DELETE,
                     apigatewayv2.CorsHttpMethod.OPTIONS,
                 ],
                 allowHeaders: [
                     'content-type',
                     'authorization',
+                    'x-api-key',
                     'x-amz-date',
                     'x-amz-security-token',
                     'x-amz-user-agent',
@@ -159,6 +198,71 @@ export class ApiStack extends cdk.Stack {
             'ChatIntegration',
             rustChatFn
         )
+        httpApi.addRoutes({
+            path: '/chat/rooms',
+            methods: [apigatewayv2.HttpMethod.GET, apigatewayv2.HttpMethod.POST],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/rooms/{room_id}',
+            methods: [apigatewayv2.HttpMethod.PATCH],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/rooms/{room_id}/members',
+            methods: [apigatewayv2.HttpMethod.GET, apigatewayv2.HttpMethod.POST],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/rooms/{room_id}/presence',
+            methods: [apigatewayv2.HttpMethod.GET],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/rooms/{room_id}/read',
+            methods: [apigatewayv2.HttpMethod.GET, apigatewayv2.HttpMethod.POST],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/unread',
+            methods: [apigatewayv2.HttpMethod.GET],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/rooms/{room_id}/join',
+            methods: [apigatewayv2.HttpMethod.POST],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/rooms/{room_id}/leave',
+            methods: [apigatewayv2.HttpMethod.POST],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/rooms/{room_id}/moderation',
+            methods: [apigatewayv2.HttpMethod.GET, apigatewayv2.HttpMethod.POST],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/auth/token',
+            methods: [apigatewayv2.HttpMethod.POST],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/reports',
+            methods: [apigatewayv2.HttpMethod.GET],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/reports/{room_id}/{report_id}/resolve',
+            methods: [apigatewayv2.HttpMethod.POST],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/dms',
+            methods: [apigatewayv2.HttpMethod.GET, apigatewayv2.HttpMethod.POST],
+            integration: chatIntegration,
+        })
         httpApi.addRoutes({
             path: '/chat/messages',
             methods: [apigatewayv2.HttpMethod.POST],
@@ -169,6 +273,26 @@ export class ApiStack extends cdk.Stack {
             methods: [apigatewayv2.HttpMethod.GET],
             integration: chatIntegration,
         })
+        httpApi.addRoutes({
+            path: '/chat/messages/{room_id}/{message_id}',
+            methods: [apigatewayv2.HttpMethod.PATCH, apigatewayv2.HttpMethod.DELETE],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/messages/{room_id}/{message_id}/replies',
+            methods: [apigatewayv2.HttpMethod.GET],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/messages/{room_id}/{message_id}/report',
+            methods: [apigatewayv2.HttpMethod.POST],
+            integration: chatIntegration,
+        })
+        httpApi.addRoutes({
+            path: '/chat/messages/{room_id}/{message_id}/reactions/{emoji}',
+            methods: [apigatewayv2.HttpMethod.POST, apigatewayv2.HttpMethod.DELETE],
+            integration: chatIntegration,
+        })
 
         // Custom domain for HTTP API (API Gateway v2)
         const restDomainName = new apigatewayv2.DomainName(this, 'HttpCustomDomainName', {
@@ -193,8 +317,16 @@ export class ApiStack extends cdk.Stack {
             handler: 'bootstrap',
             code: lambda.Code.fromAsset('../backend/target/lambda/ws-connect'),
             environment: {
+                CHAT_ROOMS_TABLE: DYNAMODB_TABLES.CHAT_ROOMS,
+                CHAT_MESSAGES_TABLE: DYNAMODB_TABLES.CHAT_MESSAGES,
                 CONNECTIONS_TABLE: DYNAMODB_TABLES.CHAT_CONNECTIONS,
+                CHAT_DEDUP_TABLE: DYNAMODB_TABLES.CHAT_MESSAGE_DEDUP,
+                CHAT_MEMBERS_TABLE: DYNAMODB_TABLES.CHAT_ROOM_MEMBERS,
+                CHAT_SANCTIONS_TABLE: DYNAMODB_TABLES.CHAT_ROOM_SANCTIONS,
+                CHAT_REPORTS_TABLE: DYNAMODB_TABLES.CHAT_MESSAGE_REPORTS,
+                CHAT_READS_TABLE: DYNAMODB_TABLES.CHAT_READ_MARKERS,
                 STAGE: stageConfig.name,
+                ...authEnv,
             },
             timeout: cdk.Duration.seconds(10),
         })
@@ -206,7 +338,14 @@ export class ApiStack extends cdk.Stack {
             handler: 'bootstrap',
             code: lambda.Code.fromAsset('../backend/target/lambda/ws-disconnect'),
             environment: {
+                CHAT_ROOMS_TABLE: DYNAMODB_TABLES.CHAT_ROOMS,
+                CHAT_MESSAGES_TABLE: DYNAMODB_TABLES.CHAT_MESSAGES,
                 CONNECTIONS_TABLE: DYNAMODB_TABLES.CHAT_CONNECTIONS,
+                CHAT_DEDUP_TABLE: DYNAMODB_TABLES.CHAT_MESSAGE_DEDUP,
+                CHAT_MEMBERS_TABLE: DYNAMODB_TABLES.CHAT_ROOM_MEMBERS,
+                CHAT_SANCTIONS_TABLE: DYNAMODB_TABLES.CHAT_ROOM_SANCTIONS,
+                CHAT_REPORTS_TABLE: DYNAMODB_TABLES.CHAT_MESSAGE_REPORTS,
+                CHAT_READS_TABLE: DYNAMODB_TABLES.CHAT_READ_MARKERS,
                 STAGE: stageConfig.name,
             },
             timeout: cdk.Duration.seconds(10),
@@ -219,11 +358,49 @@ export class ApiStack extends cdk.Stack {
             handler: 'bootstrap',
             code: lambda.Code.fromAsset('../backend/target/lambda/ws-default'),
             environment: {
+                CHAT_ROOMS_TABLE: DYNAMODB_TABLES.CHAT_ROOMS,
+                CHAT_MESSAGES_TABLE: DYNAMODB_TABLES.CHAT_MESSAGES,
+                CONNECTIONS_TABLE: DYNAMODB_TABLES.CHAT_CONNECTIONS,
+                CHAT_DEDUP_TABLE: DYNAMODB_TABLES.CHAT_MESSAGE_DEDUP,
+                CHAT_MEMBERS_TABLE: DYNAMODB_TABLES.CHAT_ROOM_MEMBERS,
+                CHAT_SANCTIONS_TABLE: DYNAMODB_TABLES.CHAT_ROOM_SANCTIONS,
+                CHAT_REPORTS_TABLE: DYNAMODB_TABLES.CHAT_MESSAGE_REPORTS,
+                CHAT_READS_TABLE: DYNAMODB_TABLES.CHAT_READ_MARKERS,
+                RATE_LIMIT_TABLE: DYNAMODB_TABLES.CHAT_RATE_LIMITS,
                 STAGE: stageConfig.name,
             },
             timeout: cdk.Duration.seconds(10),
         })
 
+        // send_message and mark_read frames go through the same paths as the REST lambda;
+        // typing frames go to the room's connections, dropping any that turn out to be gone,
+        // and are recorded on the sender's connection until they lapse
+        defaultFunction.addToRolePolicy(
+            new iam.PolicyStatement({
+                effect: iam.Effect.ALLOW,
+                actions: [
+                    'dynamodb:GetItem',
+                    'dynamodb:PutItem',
+                    'dynamodb:UpdateItem',
+                    'dynamodb:DeleteItem',
+                    'dynamodb:Query',
+                    'dynamodb:ConditionCheckItem',
+                ],
+                resources: [
+                    chatRoomsTableArn,
+                    chatMessagesTableArn,
+                    `${chatMessagesTableArn}/index/*`,
+                    chatConnectionsTableArn,
+                    `${chatConnectionsTableArn}/index/*`,
+                    chatMessageDedupTableArn,
+                    chatRoomMembersTableArn,
+                    chatRoomSanctionsTableArn,
+                    chatReadMarkersTableArn,
+                    chatRateLimitsTableArn,
+                ],
+            })
+        )
+
         // Reference broadcast function from DbStack
         const broadcastFunction = dbStack.broadcastFunction
 
@@ -241,7 +418,14 @@ export class ApiStack extends cdk.Stack {
                         'dynamodb:Query',
                         'dynamodb:Scan',
                     ],
-                    resources: [chatConnectionsTableArn],
+                    resources: [
+                        chatRoomsTableArn,
+                        chatMessagesTableArn,
+                        chatConnectionsTableArn,
+                        `${chatConnectionsTableArn}/index/*`,
+                        chatRoomMembersTableArn,
+                        chatRoomSanctionsTableArn,
+                    ],
                 })
             )
         })
@@ -306,6 +490,36 @@ export class ApiStack extends cdk.Stack {
             })
         )
 
+        // Acks and errors go back to the connection that sent a frame, typing to its room;
+        // connecting and disconnecting tell the room who came online or went offline
+        const pushingFunctions = [defaultFunction, onConnectFunction, onDisconnectFunction]
+        pushingFunctions.forEach((fn) => {
+            fn.addToRolePolicy(
+                new iam.PolicyStatement({
+                    effect: iam.Effect.ALLOW,
+                    actions: ['execute-api:ManageConnections'],
+                    resources: [
+                        `arn:aws:execute-api:${this.region}:${this.account}:${wsApi.apiId}/${wsStage.stageName}/POST/@connections/*`,
+                    ],
+                })
+            )
+        })
+
+        // Kicks and bans hang up on the target's sockets from the REST lambda, and read
+        // markers set over REST are pushed to the room's sockets
+        rustChatFn.addEnvironment('WS_API_ID', wsApi.apiId)
+        rustChatFn.addEnvironment('WS_STAGE', wsStage.stageName)
+        rustChatFn.addToRolePolicy(
+            new iam.PolicyStatement({
+                effect: iam.Effect.ALLOW,
+                actions: ['execute-api:ManageConnections'],
+                resources: [
+                    `arn:aws:execute-api:${this.region}:${this.account}:${wsApi.apiId}/${wsStage.stageName}/DELETE/@connections/*`,
+                    `arn:aws:execute-api:${this.region}:${this.account}:${wsApi.apiId}/${wsStage.stageName}/POST/@connections/*`,
+                ],
+            })
+        )
+
         // === DNS Records ===
         // REST A-record (api.<domain>) -> API Gateway v2 HTTP custom domain
         new route53.ARecord(this, 'RestApiAliasRecord', {
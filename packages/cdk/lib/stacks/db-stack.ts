@@ -14,7 +14,14 @@ export interface DbStackProps extends cdk.StackProps {
 export class DbStack extends cdk.Stack {
     public readonly chatRoomsTable: dynamodb.Table
     public readonly chatMessagesTable: dynamodb.Table
+    public readonly legacyChatMessagesTable: dynamodb.Table
     public readonly chatConnectionsTable: dynamodb.Table
+    public readonly chatMessageDedupTable: dynamodb.Table
+    public readonly chatRoomMembersTable: dynamodb.Table
+    public readonly chatRoomSanctionsTable: dynamodb.Table
+    public readonly chatMessageReportsTable: dynamodb.Table
+    public readonly chatReadMarkersTable: dynamodb.Table
+    public readonly chatRateLimitsTable: dynamodb.Table
     public readonly broadcastFunction: lambda.Function
 
     constructor(scope: Construct, id: string, props: DbStackProps) {
@@ -33,16 +40,34 @@ export class DbStack extends cdk.Stack {
             removalPolicy: isProd ? cdk.RemovalPolicy.RETAIN : cdk.RemovalPolicy.DESTROY,
         })
 
-        // Chat Messages Table (with DynamoDB Streams for real-time broadcasting)
-        this.chatMessagesTable = new dynamodb.Table(this, 'ChatMessagesTable', {
+        // Chat Messages Table (with DynamoDB Streams for real-time broadcasting).
+        // Sorted by message id, a ULID, so messages in the same millisecond never collide.
+        this.chatMessagesTable = new dynamodb.Table(this, 'ChatMessagesV2Table', {
             tableName: DYNAMODB_TABLES.CHAT_MESSAGES,
             partitionKey: { name: 'room_id', type: dynamodb.AttributeType.STRING },
-            sortKey: { name: 'ts', type: dynamodb.AttributeType.NUMBER },
-            stream: dynamodb.StreamViewType.NEW_IMAGE,
+            sortKey: { name: 'id', type: dynamodb.AttributeType.STRING },
+            // Old images let the broadcaster tell edits apart from other updates
+            stream: dynamodb.StreamViewType.NEW_AND_OLD_IMAGES,
             billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
             removalPolicy: isProd ? cdk.RemovalPolicy.RETAIN : cdk.RemovalPolicy.DESTROY,
         })
 
+        // Sparse index of thread replies: only replies carry parent_id
+        this.chatMessagesTable.addGlobalSecondaryIndex({
+            indexName: 'thread-index',
+            partitionKey: { name: 'parent_id', type: dynamodb.AttributeType.STRING },
+            sortKey: { name: 'id', type: dynamodb.AttributeType.STRING },
+        })
+
+        // Legacy ts-keyed messages table, kept until `migrate-messages` has copied it over
+        this.legacyChatMessagesTable = new dynamodb.Table(this, 'ChatMessagesTable', {
+            tableName: DYNAMODB_TABLES.CHAT_MESSAGES_LEGACY,
+            partitionKey: { name: 'room_id', type: dynamodb.AttributeType.STRING },
+            sortKey: { name: 'ts', type: dynamodb.AttributeType.NUMBER },
+            billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+            removalPolicy: cdk.RemovalPolicy.RETAIN,
+        })
+
         // Chat Connections Table (for WebSocket client management)
         this.chatConnectionsTable = new dynamodb.Table(this, 'ChatConnectionsTable', {
             tableName: DYNAMODB_TABLES.CHAT_CONNECTIONS,
@@ -60,6 +85,77 @@ export class DbStack extends cdk.Stack {
             sortKey: { name: 'connected_at', type: dynamodb.AttributeType.NUMBER },
         })
 
+        // Message Dedup Table (idempotency markers for retried posts, keyed on
+        // room_id#user_id#client_message_id)
+        this.chatMessageDedupTable = new dynamodb.Table(this, 'ChatMessageDedupTable', {
+            tableName: DYNAMODB_TABLES.CHAT_MESSAGE_DEDUP,
+            partitionKey: { name: 'dedup_key', type: dynamodb.AttributeType.STRING },
+            billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+            removalPolicy: isProd ? cdk.RemovalPolicy.RETAIN : cdk.RemovalPolicy.DESTROY,
+            timeToLiveAttribute: 'ttl',
+        })
+
+        // Room Members Table (roles per room: owner, moderator, member)
+        this.chatRoomMembersTable = new dynamodb.Table(this, 'ChatRoomMembersTable', {
+            tableName: DYNAMODB_TABLES.CHAT_ROOM_MEMBERS,
+            partitionKey: { name: 'room_id', type: dynamodb.AttributeType.STRING },
+            sortKey: { name: 'user_id', type: dynamodb.AttributeType.STRING },
+            billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+            removalPolicy: isProd ? cdk.RemovalPolicy.RETAIN : cdk.RemovalPolicy.DESTROY,
+        })
+
+        // Rooms per user, for listing a user's direct messages
+        this.chatRoomMembersTable.addGlobalSecondaryIndex({
+            indexName: 'user-index',
+            partitionKey: { name: 'user_id', type: dynamodb.AttributeType.STRING },
+            sortKey: { name: 'room_id', type: dynamodb.AttributeType.STRING },
+        })
+
+        // Room Sanctions Table (mutes and bans per room; lapsed mutes expire via ttl)
+        this.chatRoomSanctionsTable = new dynamodb.Table(this, 'ChatRoomSanctionsTable', {
+            tableName: DYNAMODB_TABLES.CHAT_ROOM_SANCTIONS,
+            partitionKey: { name: 'room_id', type: dynamodb.AttributeType.STRING },
+            sortKey: { name: 'user_id', type: dynamodb.AttributeType.STRING },
+            billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+            removalPolicy: isProd ? cdk.RemovalPolicy.RETAIN : cdk.RemovalPolicy.DESTROY,
+            timeToLiveAttribute: 'ttl',
+        })
+
+        // Message Reports Table (user reports queued for each room's moderators)
+        this.chatMessageReportsTable = new dynamodb.Table(this, 'ChatMessageReportsTable', {
+            tableName: DYNAMODB_TABLES.CHAT_MESSAGE_REPORTS,
+            partitionKey: { name: 'room_id', type: dynamodb.AttributeType.STRING },
+            sortKey: { name: 'report_id', type: dynamodb.AttributeType.STRING },
+            billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+            removalPolicy: isProd ? cdk.RemovalPolicy.RETAIN : cdk.RemovalPolicy.DESTROY,
+        })
+
+        // Read Markers Table (the last message each user has read in each room)
+        this.chatReadMarkersTable = new dynamodb.Table(this, 'ChatReadMarkersTable', {
+            tableName: DYNAMODB_TABLES.CHAT_READ_MARKERS,
+            partitionKey: { name: 'room_id', type: dynamodb.AttributeType.STRING },
+            sortKey: { name: 'user_id', type: dynamodb.AttributeType.STRING },
+            billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+            removalPolicy: isProd ? cdk.RemovalPolicy.RETAIN : cdk.RemovalPolicy.DESTROY,
+        })
+
+        // Rooms per user, for unread counts across every room a user has read
+        this.chatReadMarkersTable.addGlobalSecondaryIndex({
+            indexName: 'user-index',
+            partitionKey: { name: 'user_id', type: dynamodb.AttributeType.STRING },
+            sortKey: { name: 'room_id', type: dynamodb.AttributeType.STRING },
+        })
+
+        // Rate Limits Table (token buckets for posting, per user and per room)
+        // Buckets are disposable: they expire via ttl once full again, and are never retained
+        this.chatRateLimitsTable = new dynamodb.Table(this, 'ChatRateLimitsTable', {
+            tableName: DYNAMODB_TABLES.CHAT_RATE_LIMITS,
+            partitionKey: { name: 'bucket_key', type: dynamodb.AttributeType.STRING },
+            billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+            removalPolicy: cdk.RemovalPolicy.DESTROY,
+            timeToLiveAttribute: 'ttl',
+        })
+
         // Seed default "general" room on deployment
         new cr.AwsCustomResource(this, 'SeedGeneralRoom', {
             onCreate: {
@@ -91,7 +187,14 @@ export class DbStack extends cdk.Stack {
             handler: 'bootstrap',
             code: lambda.Code.fromAsset('../backend/target/lambda/ws-broadcast'),
             environment: {
+                CHAT_ROOMS_TABLE: DYNAMODB_TABLES.CHAT_ROOMS,
+                CHAT_MESSAGES_TABLE: DYNAMODB_TABLES.CHAT_MESSAGES,
                 CONNECTIONS_TABLE: DYNAMODB_TABLES.CHAT_CONNECTIONS,
+                CHAT_DEDUP_TABLE: DYNAMODB_TABLES.CHAT_MESSAGE_DEDUP,
+                CHAT_MEMBERS_TABLE: DYNAMODB_TABLES.CHAT_ROOM_MEMBERS,
+                CHAT_SANCTIONS_TABLE: DYNAMODB_TABLES.CHAT_ROOM_SANCTIONS,
+                CHAT_REPORTS_TABLE: DYNAMODB_TABLES.CHAT_MESSAGE_REPORTS,
+                CHAT_READS_TABLE: DYNAMODB_TABLES.CHAT_READ_MARKERS,
                 STAGE: stageConfig.name,
             },
             timeout: cdk.Duration.seconds(30),
@@ -99,6 +202,9 @@ export class DbStack extends cdk.Stack {
 
         // Grant DynamoDB permissions to broadcast function
         this.chatConnectionsTable.grantReadWriteData(this.broadcastFunction)
+        this.chatRoomsTable.grantReadData(this.broadcastFunction)
+        this.chatMessagesTable.grantReadData(this.broadcastFunction)
+        this.chatRoomMembersTable.grantReadData(this.broadcastFunction)
 
         // Grant WebSocket management permissions to broadcast function
         // Note: The WebSocket API ID and stage will be added when this function is used in ApiStack
@@ -119,7 +225,7 @@ export class DbStack extends cdk.Stack {
                 batchSize: 10,
                 filters: [
                     lambda.FilterCriteria.filter({
-                        eventName: lambda.FilterRule.isEqual('INSERT'),
+                        eventName: lambda.FilterRule.or('INSERT', 'MODIFY', 'REMOVE'),
                     }),
                 ],
             })
@@ -136,9 +242,44 @@ export class DbStack extends cdk.Stack {
             description: 'Chat messages DynamoDB table name',
         })
 
+        new cdk.CfnOutput(this, 'LegacyChatMessagesTableName', {
+            value: this.legacyChatMessagesTable.tableName,
+            description: 'Legacy ts-keyed chat messages DynamoDB table name',
+        })
+
         new cdk.CfnOutput(this, 'ChatConnectionsTableName', {
             value: this.chatConnectionsTable.tableName,
             description: 'Chat connections DynamoDB table name',
         })
+
+        new cdk.CfnOutput(this, 'ChatMessageDedupTableName', {
+            value: this.chatMessageDedupTable.tableName,
+            description: 'Chat message dedup DynamoDB table name',
+        })
+
+        new cdk.CfnOutput(this, 'ChatRoomMembersTableName', {
+            value: this.chatRoomMembersTable.tableName,
+            description: 'Chat room members DynamoDB table name',
+        })
+
+        new cdk.CfnOutput(this, 'ChatRoomSanctionsTableName', {
+            value: this.chatRoomSanctionsTable.tableName,
+            description: 'Chat room sanctions DynamoDB table name',
+        })
+
+        new cdk.CfnOutput(this, 'ChatMessageReportsTableName', {
+            value: this.chatMessageReportsTable.tableName,
+            description: 'Chat message reports DynamoDB table name',
+        })
+
+        new cdk.CfnOutput(this, 'ChatReadMarkersTableName', {
+            value: this.chatReadMarkersTable.tableName,
+            description: 'Chat read markers DynamoDB table name',
+        })
+
+        new cdk.CfnOutput(this, 'ChatRateLimitsTableName', {
+            value: this.chatRateLimitsTable.tableName,
+            description: 'Chat rate limits DynamoDB table name',
+        })
     }
 }
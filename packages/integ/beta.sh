@@ -3,6 +3,8 @@
 export TEST_BASE_URL=https://api.beta.swflcoders.jknott.dev
 export TEST_TARGET_STAGE=beta
 export AWS_DEFAULT_REGION=us-east-1
+# API key of the integration test bot (see AUTH_API_KEYS on the backend)
+export TEST_API_KEY=${TEST_API_KEY:-}
 
 echo "Environment variables set for beta integration testing:"
 echo "TEST_BASE_URL=$TEST_BASE_URL"
@@ -36,9 +36,11 @@ const TEST_BASE_URL = process.env.TEST_BASE_URL || 'http://localhost:3001'
 const TEST_TARGET_STAGE = process.env.TEST_TARGET_STAGE || 'dev'
 const AWS_REGION = process.env.AWS_DEFAULT_REGION || 'us-east-1'
 const TEST_ASSUME_ROLE_ARN = process.env.TEST_ASSUME_ROLE_ARN
+// Deployed stages need credentials; the local dev server trusts X-User-Id instead
+const TEST_API_KEY = process.env.TEST_API_KEY
 
 // DynamoDB table names from environment variables
-const CHAT_MESSAGES_TABLE = process.env.CHAT_MESSAGES_TABLE || 'chat-messages'
+const CHAT_MESSAGES_TABLE = process.env.CHAT_MESSAGES_TABLE || 'chat-messages-v2'
 // const CHAT_CONNECTIONS_TABLE = process.env.CHAT_CONNECTIONS_TABLE || 'chat-connections'
 
 // Test user data
@@ -93,6 +95,9 @@ async function sendMessage(messageText: string): Promise<Message> {
         const response = await axios.post(`${TEST_BASE_URL}/chat/messages`, request, {
             headers: {
                 'Content-Type': 'application/json',
+                ...(TEST_API_KEY
+                    ? { 'X-Api-Key': TEST_API_KEY }
+                    : { 'X-User-Id': TEST_USER.userId, 'X-Username': TEST_USER.username }),
             },
             timeout: 10000,
         })
@@ -174,33 +179,16 @@ async function deleteMessageFromDynamoDB(message: Message): Promise<void> {
     console.log('🗑️  Deleting message from DynamoDB:', message.id)
 
     try {
-        // First, we need to find the exact item with both partition key and sort key
-        const queryParams = {
-            TableName: CHAT_MESSAGES_TABLE,
-            KeyConditionExpression: 'room_id = :roomId',
-            FilterExpression: 'id = :messageId',
-            ExpressionAttributeValues: {
-                ':roomId': ROOM_ID,
-                ':messageId': message.id,
-            },
-        }
-
-        const dynamodb = await getDynamo()
-        const queryResult = await dynamodb.query(queryParams).promise()
-
-        if (!queryResult.Items || queryResult.Items.length === 0) {
-            throw new Error('Message not found for deletion')
-        }
-
-        const itemToDelete = queryResult.Items[0]
+        // Messages are keyed by room_id + id, so the item can be deleted directly
         const deleteParams = {
             TableName: CHAT_MESSAGES_TABLE,
             Key: {
-                room_id: itemToDelete.room_id,
-                ts: itemToDelete.ts,
+                room_id: ROOM_ID,
+                id: message.id,
             },
         }
 
+        const dynamodb = await getDynamo()
         await dynamodb.delete(deleteParams).promise()
         console.log('✅ Message deleted from DynamoDB')
     } catch (error) {
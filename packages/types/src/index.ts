@@ -8,3 +8,61 @@ export * from '../bindings/Message'
 export * from '../bindings/ChatMessage'
 export * from '../bindings/SendMessageRequest'
 export * from '../bindings/GetMessagesResponse'
+export * from '../bindings/GetMessagesQuery'
+export * from '../bindings/MessageOrder'
+export * from '../bindings/EditMessageRequest'
+export * from '../bindings/DeleteMessageQuery'
+export * from '../bindings/RoomRole'
+export * from '../bindings/RoomMember'
+export * from '../bindings/ReactionSummary'
+export * from '../bindings/ReactionQuery'
+export * from '../bindings/GetThreadResponse'
+export * from '../bindings/CreateRoomRequest'
+export * from '../bindings/UpdateRoomRequest'
+export * from '../bindings/ListRoomsQuery'
+export * from '../bindings/ListRoomsResponse'
+export * from '../bindings/RoomVisibility'
+export * from '../bindings/InviteMemberRequest'
+export * from '../bindings/MembershipRequest'
+export * from '../bindings/ListMembersQuery'
+export * from '../bindings/ListMembersResponse'
+export * from '../bindings/RoomPresenceQuery'
+export * from '../bindings/OnlineUser'
+export * from '../bindings/RoomPresenceResponse'
+export * from '../bindings/RoomKind'
+export * from '../bindings/OpenDirectMessageRequest'
+export * from '../bindings/ListDirectMessagesQuery'
+export * from '../bindings/DirectConversation'
+export * from '../bindings/ListDirectMessagesResponse'
+export * from '../bindings/AuthTokenResponse'
+export * from '../bindings/ModerationAction'
+export * from '../bindings/ModerateRequest'
+export * from '../bindings/RoomSanction'
+export * from '../bindings/ModerationResponse'
+export * from '../bindings/ListSanctionsQuery'
+export * from '../bindings/ListSanctionsResponse'
+export * from '../bindings/FilterAction'
+export * from '../bindings/FilterDecision'
+export * from '../bindings/ReportStatus'
+export * from '../bindings/MessageReport'
+export * from '../bindings/ReportMessageRequest'
+export * from '../bindings/ListReportsQuery'
+export * from '../bindings/ListReportsResponse'
+export * from '../bindings/ReportResolution'
+export * from '../bindings/ResolveReportRequest'
+export * from '../bindings/ResolveReportResponse'
+export * from '../bindings/ReadMarker'
+export * from '../bindings/MarkReadRequest'
+export * from '../bindings/ListReadMarkersQuery'
+export * from '../bindings/ListReadMarkersResponse'
+export * from '../bindings/UnreadCountsQuery'
+export * from '../bindings/UnreadCount'
+export * from '../bindings/UnreadCountsResponse'
+export * from '../bindings/ServerFrame'
+export * from '../bindings/ServerEvent'
+export * from '../bindings/ReactionChange'
+export * from '../bindings/TypingEvent'
+export * from '../bindings/PresenceEvent'
+export * from '../bindings/WsError'
+export * from '../bindings/ClientFrame'
+export * from '../bindings/ClientEvent'
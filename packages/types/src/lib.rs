@@ -24,8 +24,484 @@ pub enum HealthStatus {
 #[ts(export)]
 pub struct Room {
     pub id: String,
+    pub name: String, // Display name
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub archived: bool, // Archived rooms stay readable but take no new posts
+    #[serde(default)]
+    pub visibility: RoomVisibility,
+    #[serde(default)]
+    pub kind: RoomKind,
+    #[serde(default)]
+    pub last_message_at: Option<DateTime<Utc>>, // Newest top-level message or reply
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, TS, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum RoomKind {
+    #[default]
+    Channel, // Named room managed through /chat/rooms
+    Direct, // Private conversation between exactly two users
+}
+
+impl RoomKind {
+    /// Same spelling as the serde representation, for storage backends
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomKind::Channel => "channel",
+            RoomKind::Direct => "direct",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "channel" => Some(RoomKind::Channel),
+            "direct" => Some(RoomKind::Direct),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, TS, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum RoomVisibility {
+    #[default]
+    Public, // Anyone can read, post and join
+    Private, // Members only; new members are invited
+}
+
+impl RoomVisibility {
+    /// Same spelling as the serde representation, for storage backends
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomVisibility::Public => "public",
+            RoomVisibility::Private => "private",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "public" => Some(RoomVisibility::Public),
+            "private" => Some(RoomVisibility::Private),
+            _ => None,
+        }
+    }
+}
+
+// Body for POST /chat/rooms
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct CreateRoomRequest {
+    #[serde(default)]
+    pub id: Option<String>, // Slug; derived from the name when omitted
     pub name: String,
+    pub description: Option<String>,
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub visibility: RoomVisibility,
+    #[ts(rename = "userId")]
+    #[serde(default)]
+    pub user_id: Option<String>, // Creator, who becomes the owner; required for private rooms
+}
+
+// Body for PATCH /chat/rooms/:room_id; absent fields are left unchanged
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct UpdateRoomRequest {
+    pub name: Option<String>,
+    pub description: Option<String>, // Empty string clears it
+    pub topic: Option<String>,       // Empty string clears it
+    pub archived: Option<bool>,
+    pub visibility: Option<RoomVisibility>,
+    #[ts(rename = "userId")]
+    #[serde(default)]
+    pub user_id: Option<String>, // Must be an owner/moderator once the room has an owner
+}
+
+// Query parameters for GET /chat/rooms
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListRoomsQuery {
+    pub include_archived: Option<bool>,
+    #[ts(rename = "userId")]
+    pub user_id: Option<String>, // Private rooms are only listed for their members
+}
+
+// Body for POST /chat/rooms/:room_id/members
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct InviteMemberRequest {
+    #[ts(rename = "userId")]
+    pub user_id: String, // The inviting owner/moderator
+    pub member_id: String,
+    pub role: Option<RoomRole>, // Member unless given; only owners hand out other roles
+}
+
+// Body for POST /chat/rooms/:room_id/join and /leave
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct MembershipRequest {
+    #[ts(rename = "userId")]
+    pub user_id: String,
+}
+
+// Query parameters for GET /chat/rooms/:room_id/members
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListMembersQuery {
+    #[ts(rename = "userId")]
+    pub user_id: Option<String>, // Required for private rooms
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListMembersResponse {
+    pub room_id: String,
+    pub members: Vec<RoomMember>,
+}
+
+// Query parameters for GET /chat/rooms/:room_id/presence
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct RoomPresenceQuery {
+    #[ts(rename = "userId")]
+    pub user_id: Option<String>, // Required for private rooms
+}
+
+// A user with at least one socket open in the room
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct OnlineUser {
+    pub user_id: String,
+    pub username: String,
+    pub connections: u32,            // Open tabs and devices
+    pub online_since: DateTime<Utc>, // When the oldest of them connected
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct RoomPresenceResponse {
+    pub room_id: String,
+    pub users: Vec<OnlineUser>, // By username
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum ModerationAction {
+    Mute,   // No posting for `duration_secs`
+    Unmute, // Lift a mute early
+    Kick,   // Remove from the room and close the user's connections to it
+    Ban,    // Kick, and refuse posts, joins and connections until unbanned
+    Unban,
+}
+
+// Body for POST /chat/rooms/:room_id/moderation
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ModerateRequest {
+    #[ts(rename = "userId")]
+    pub user_id: String, // The acting owner/moderator
+    pub target_user_id: String,
+    pub action: ModerationAction,
+    #[serde(default)]
+    pub duration_secs: Option<u64>, // Required for mutes
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+// A user's standing restrictions in a room
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct RoomSanction {
+    pub room_id: String,
+    #[ts(rename = "userId")]
+    pub user_id: String,
+    pub muted_until: Option<DateTime<Utc>>,
+    pub banned: bool,
+    pub reason: Option<String>,
+    pub moderator_id: String, // Who applied the latest action
+    pub updated_at: DateTime<Utc>,
+}
+
+impl RoomSanction {
+    pub fn is_muted_at(&self, now: DateTime<Utc>) -> bool {
+        self.muted_until.is_some_and(|until| until > now)
+    }
+
+    /// Whether anything still restricts the user; lapsed mutes do not
+    pub fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        self.banned || self.is_muted_at(now)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ModerationResponse {
+    pub room_id: String,
+    pub target_user_id: String,
+    pub action: ModerationAction,
+    pub sanction: Option<RoomSanction>, // None once no restriction is left
+    pub disconnected: u32,              // Connections closed by a kick or ban
+}
+
+// Query parameters for GET /chat/rooms/:room_id/moderation
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListSanctionsQuery {
+    #[ts(rename = "userId")]
+    pub user_id: String, // Must be an owner/moderator
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListSanctionsResponse {
+    pub room_id: String,
+    pub sanctions: Vec<RoomSanction>, // Active ones only, sorted by user id
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum ReportStatus {
+    Open,      // Waiting for a moderator
+    Dismissed, // A moderator found nothing wrong
+    Deleted,   // A moderator deleted the message
+}
+
+impl ReportStatus {
+    /// Same spelling as the serde representation, for storage backends
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportStatus::Open => "open",
+            ReportStatus::Dismissed => "dismissed",
+            ReportStatus::Deleted => "deleted",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "open" => Some(ReportStatus::Open),
+            "dismissed" => Some(ReportStatus::Dismissed),
+            "deleted" => Some(ReportStatus::Deleted),
+            _ => None,
+        }
+    }
+}
+
+// A user's complaint about a message, queued for the room's owners and moderators
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct MessageReport {
+    pub id: String, // ULID, so reports sort by when they were made
+    pub room_id: String,
+    pub message_id: String,
+    pub reporter_id: String,
+    pub reason: String,
+    pub message_user_id: String, // The reported message's author
+    pub message_text: String,    // The text as it was when reported
+    pub status: ReportStatus,
     pub created_at: DateTime<Utc>,
+    pub resolved_by: Option<String>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+// Body for POST /chat/messages/:room_id/:message_id/report
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ReportMessageRequest {
+    #[ts(rename = "userId")]
+    pub user_id: String, // The reporter
+    pub reason: String,
+}
+
+// Query parameters for GET /chat/reports
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListReportsQuery {
+    #[ts(rename = "userId")]
+    pub user_id: String, // Only reports from rooms this user owns or moderates are listed
+    #[serde(default)]
+    pub room_id: Option<String>, // Every room the user moderates when omitted
+    #[serde(default)]
+    pub status: Option<ReportStatus>, // Any status when omitted
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListReportsResponse {
+    pub reports: Vec<MessageReport>, // Oldest first
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum ReportResolution {
+    Dismiss, // Leave the message up
+    Delete,  // Tombstone the message
+}
+
+// Body for POST /chat/reports/:room_id/:report_id/resolve
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ResolveReportRequest {
+    #[ts(rename = "userId")]
+    pub user_id: String, // Must be an owner/moderator of the report's room
+    pub resolution: ReportResolution,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ResolveReportResponse {
+    pub report: MessageReport,
+    pub resolved: u32, // Open reports of the same message closed along with this one
+    pub message: Option<ChatMessage>, // The tombstone, when the message was deleted
+}
+
+// How far one user has read in a room. Message ids sort by time, so everything up to and
+// including message_id counts as read.
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct ReadMarker {
+    pub room_id: String,
+    pub user_id: String,
+    pub message_id: String, // The last message read
+    pub read_at: DateTime<Utc>,
+}
+
+// Body for POST /chat/rooms/:room_id/read
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct MarkReadRequest {
+    #[ts(rename = "userId")]
+    pub user_id: String,
+    pub message_id: String,
+}
+
+// Query parameters for GET /chat/rooms/:room_id/read
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListReadMarkersQuery {
+    #[ts(rename = "userId")]
+    pub user_id: Option<String>, // Required for private rooms
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListReadMarkersResponse {
+    pub room_id: String,
+    pub markers: Vec<ReadMarker>, // By user id
+}
+
+// Query parameters for GET /chat/unread
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct UnreadCountsQuery {
+    #[ts(rename = "userId")]
+    pub user_id: String,
+}
+
+// Messages posted in a room by others since the user last read it
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct UnreadCount {
+    pub room_id: String,
+    pub last_read_id: String,
+    pub unread: u32, // Top-level messages only; thread replies are not counted
+    pub more: bool,  // True when there are more than `unread`, which is capped
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct UnreadCountsResponse {
+    pub rooms: Vec<UnreadCount>, // Every room the user has marked read, by room id
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListRoomsResponse {
+    pub rooms: Vec<Room>,
+}
+
+// Body for POST /chat/dms
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct OpenDirectMessageRequest {
+    #[ts(rename = "userId")]
+    pub user_id: String,
+    pub other_user_id: String,
+}
+
+// Query parameters for GET /chat/dms
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListDirectMessagesQuery {
+    #[ts(rename = "userId")]
+    pub user_id: String,
+}
+
+// A direct message room from one participant's point of view
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct DirectConversation {
+    pub room: Room,
+    pub other_user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ListDirectMessagesResponse {
+    pub conversations: Vec<DirectConversation>, // Most recently active first
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum RoomRole {
+    Owner,
+    Moderator,
+    Member,
+}
+
+impl RoomRole {
+    /// Owners and moderators can act on other members' messages
+    pub fn can_moderate(&self) -> bool {
+        matches!(self, RoomRole::Owner | RoomRole::Moderator)
+    }
+
+    /// Same spelling as the serde representation, for storage backends
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomRole::Owner => "owner",
+            RoomRole::Moderator => "moderator",
+            RoomRole::Member => "member",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "owner" => Some(RoomRole::Owner),
+            "moderator" => Some(RoomRole::Moderator),
+            "member" => Some(RoomRole::Member),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct RoomMember {
+    pub room_id: String,
+    #[ts(rename = "userId")]
+    pub user_id: String,
+    pub role: RoomRole,
+    pub joined_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
@@ -51,6 +527,80 @@ pub struct ChatMessage {
     pub created_at: DateTime<Utc>,
     #[ts(rename = "clientMessageId")]
     pub client_message_id: Option<String>,
+    #[serde(default)]
+    pub edited_at: Option<DateTime<Utc>>, // Set once the text has been edited
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>, // Set on tombstones; the text is no longer available
+    #[serde(default)]
+    pub reactions: Vec<ReactionSummary>, // One entry per distinct emoji, sorted by emoji
+    #[serde(default)]
+    pub parent_id: Option<String>, // Set on thread replies; the root message's id
+    #[serde(default)]
+    pub reply_count: u32, // Thread roots only
+    #[serde(default)]
+    pub last_reply_at: Option<DateTime<Utc>>, // Thread roots only
+    #[serde(default)]
+    pub filter: Option<FilterDecision>, // Set when a content filter masked or flagged the text
+}
+
+// What a content filter does with a message it matches, from mildest to strictest
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum FilterAction {
+    Flag,   // Store it as sent, marked for moderators
+    Mask,   // Store it with the matched text replaced
+    Reject, // Refuse to store it
+}
+
+impl FilterAction {
+    /// Same spelling as the serde representation, for storage backends and configuration
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterAction::Flag => "flag",
+            FilterAction::Mask => "mask",
+            FilterAction::Reject => "reject",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "flag" => Some(FilterAction::Flag),
+            "mask" => Some(FilterAction::Mask),
+            "reject" => Some(FilterAction::Reject),
+            _ => None,
+        }
+    }
+}
+
+// The content filters' verdict on a stored message
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct FilterDecision {
+    pub action: FilterAction, // The strictest action taken; never reject on a stored message
+    pub filters: Vec<String>, // Names of the filters that matched, in the order they ran
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    pub count: u32,
+    #[ts(rename = "userIds")]
+    pub user_ids: Vec<String>, // Who reacted, sorted
+    #[serde(default)]
+    pub reacted_by_me: bool, // Relative to the `user_id` the request was made for
+}
+
+// Auth Types
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct AuthTokenResponse {
+    pub token: String, // Bearer token signed by the backend; also accepted as ?token= on /ws
+    #[ts(rename = "userId")]
+    pub user_id: String,
+    pub username: String,
+    pub expires_at: DateTime<Utc>,
 }
 
 // Legacy room-based API types (keep for backward compatibility)
@@ -64,6 +614,33 @@ pub struct SendMessageRequest {
     pub message_text: String,
     #[ts(rename = "clientMessageId")]
     pub client_message_id: Option<String>,
+    #[serde(default)]
+    pub parent_id: Option<String>, // Post as a reply in this message's thread
+}
+
+// Body for PATCH /chat/messages/:room_id/:message_id
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct EditMessageRequest {
+    #[ts(rename = "userId")]
+    pub user_id: String, // Must match the original sender
+    pub message_text: String,
+}
+
+// Query parameters for DELETE /chat/messages/:room_id/:message_id
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct DeleteMessageQuery {
+    #[ts(rename = "userId")]
+    pub user_id: String, // The author or a room owner/moderator
+}
+
+// Query parameters for POST/DELETE /chat/messages/:room_id/:message_id/reactions/:emoji
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ReactionQuery {
+    #[ts(rename = "userId")]
+    pub user_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
@@ -71,6 +648,171 @@ pub struct SendMessageRequest {
 pub struct GetMessagesResponse {
     pub room_id: String,
     pub messages: Vec<ChatMessage>,
+    pub next_cursor: Option<String>, // Pass back as `before`/`after` to fetch the next page
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, TS, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum MessageOrder {
+    #[default]
+    Asc, // Oldest first
+    Desc, // Latest first
+}
+
+// Query parameters for GET /chat/messages/:room_id
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct GetMessagesQuery {
+    pub before: Option<String>, // Opaque cursor - only messages older than it
+    pub after: Option<String>,  // Opaque cursor - only messages newer than it
+    pub limit: Option<u32>,     // Page size, capped server-side
+    pub order: Option<MessageOrder>,
+    #[ts(rename = "userId")]
+    pub user_id: Option<String>, // Viewer, used to fill `reacted_by_me`
+}
+
+// Response for GET /chat/messages/:room_id/:message_id/replies
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct GetThreadResponse {
+    pub room_id: String,
+    pub root: ChatMessage,
+    pub replies: Vec<ChatMessage>,
+    pub next_cursor: Option<String>, // Same cursor semantics as GetMessagesResponse
+}
+
+// WebSocket protocol. Everything the server pushes is a `ServerFrame`: the protocol version
+// and a `type` tag, with the event's fields alongside, e.g.
+// `{"v": 1, "type": "message.new", "message": {...}}`
+
+/// Bumped on incompatible changes to the frames below
+pub const WS_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ServerFrame {
+    pub v: u32,
+    #[serde(flatten)]
+    pub event: ServerEvent,
+}
+
+impl From<ServerEvent> for ServerFrame {
+    fn from(event: ServerEvent) -> Self {
+        Self {
+            v: WS_PROTOCOL_VERSION,
+            event,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum ServerEvent {
+    #[serde(rename = "message.new")]
+    MessageNew { message: ChatMessage },
+    #[serde(rename = "message.edited")]
+    MessageEdited { message: ChatMessage },
+    #[serde(rename = "message.deleted")]
+    MessageDeleted { message: ChatMessage }, // The tombstone
+    #[serde(rename = "reaction.added")]
+    ReactionAdded(ReactionChange),
+    #[serde(rename = "reaction.removed")]
+    ReactionRemoved(ReactionChange),
+    #[serde(rename = "typing")]
+    Typing(TypingEvent),
+    #[serde(rename = "presence.join")]
+    PresenceJoin(PresenceEvent),
+    #[serde(rename = "presence.leave")]
+    PresenceLeave(PresenceEvent),
+    // Someone's read marker moved forward, for "seen by"
+    #[serde(rename = "read")]
+    Read(ReadMarker),
+    // Sent only to the connection whose frame was handled
+    #[serde(rename = "ack")]
+    Ack {
+        message_id: String,
+        client_message_id: Option<String>,
+    },
+    #[serde(rename = "error")]
+    Error(WsError),
+}
+
+// One user's reaction appearing on or disappearing from a message
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct ReactionChange {
+    pub room_id: String,
+    pub message_id: String,
+    pub emoji: String,
+    pub user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct TypingEvent {
+    pub room_id: String,
+    pub user_id: String,
+    pub username: String,
+    pub active: bool, // False once they stop, or their indicator expires
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>, // How long a start lasts unless repeated
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct PresenceEvent {
+    pub room_id: String,
+    pub user_id: String,
+    pub username: String,
+}
+
+// Why a frame was refused; `status` follows the REST API's HTTP statuses
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct WsError {
+    pub status: u16,
+    pub message: String,
+    #[serde(default)]
+    pub retry_after_secs: Option<u64>,
+    #[serde(default)]
+    pub client_message_id: Option<String>, // Echoed from the refused frame, if it had one
+}
+
+// What a client sends up the socket, in the same shape as `ServerFrame`. The connection
+// already fixes the room and the sender, so frames carry neither.
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ClientFrame {
+    #[serde(default)]
+    #[ts(optional)]
+    pub v: Option<u32>, // Assumed current when missing
+    #[serde(flatten)]
+    pub event: ClientEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum ClientEvent {
+    // Answered with an `ack`, or an `error` echoing the client_message_id
+    #[serde(rename = "send_message")]
+    SendMessage {
+        message_text: String,
+        #[serde(default)]
+        client_message_id: Option<String>,
+        #[serde(default)]
+        parent_id: Option<String>,
+    },
+    // Repeated while the user types; relayed to the room at most every few seconds
+    #[serde(rename = "typing.start")]
+    TypingStart,
+    #[serde(rename = "typing.stop")]
+    TypingStop,
+    // Same as POST /chat/rooms/:room_id/read for the connection's room
+    #[serde(rename = "mark_read")]
+    MarkRead { message_id: String },
 }
 
 // New frontend-expected API types
@@ -139,6 +881,7 @@ mod tests {
             username: "alice".to_string(),
             message_text: "Hello!".to_string(),
             client_message_id: Some("01ARZ3NDEKTSV4RRFFQ69G5FB2".to_string()),
+            parent_id: None,
         };
 
         let json = serde_json::to_string(&request).unwrap();
@@ -162,6 +905,13 @@ mod tests {
                 message_text: "Hello!".to_string(),
                 created_at: Utc::now(),
                 client_message_id: None,
+                edited_at: None,
+                deleted_at: None,
+                reactions: Vec::new(),
+                parent_id: None,
+                reply_count: 0,
+                last_reply_at: None,
+                filter: None,
             },
             ChatMessage {
                 id: "01ARZ3NDEKTSV4RRFFQ69G5FB2".to_string(),
@@ -171,12 +921,20 @@ mod tests {
                 message_text: "Hi Alice!".to_string(),
                 created_at: Utc::now(),
                 client_message_id: None,
+                edited_at: None,
+                deleted_at: None,
+                reactions: Vec::new(),
+                parent_id: None,
+                reply_count: 0,
+                last_reply_at: None,
+                filter: None,
             },
         ];
 
         let response = GetMessagesResponse {
             room_id: "general".to_string(),
             messages,
+            next_cursor: Some("1700000000000".to_string()),
         };
 
         let json = serde_json::to_string(&response).unwrap();
@@ -186,5 +944,132 @@ mod tests {
         assert_eq!(response.messages.len(), deserialized.messages.len());
         assert_eq!(response.messages[0].username, "alice");
         assert_eq!(response.messages[1].username, "bob");
+        assert_eq!(deserialized.next_cursor.as_deref(), Some("1700000000000"));
+    }
+
+    #[test]
+    fn test_get_messages_query() {
+        let query: GetMessagesQuery =
+            serde_json::from_str(r#"{"before": "42", "limit": 10, "order": "desc"}"#).unwrap();
+
+        assert_eq!(query.before.as_deref(), Some("42"));
+        assert_eq!(query.after, None);
+        assert_eq!(query.limit, Some(10));
+        assert_eq!(query.order, Some(MessageOrder::Desc));
+        assert_eq!(MessageOrder::default(), MessageOrder::Asc);
+    }
+
+    #[test]
+    fn test_chat_message_without_edited_at() {
+        // Messages serialized before edits existed still deserialize
+        let message: ChatMessage = serde_json::from_str(
+            r#"{"id": "01ARZ3NDEKTSV4RRFFQ69G5FAV", "room_id": "general", "user_id": "u1",
+                "username": "alice", "message_text": "Hello!",
+                "created_at": "2024-01-01T00:00:00Z", "client_message_id": null}"#,
+        )
+        .unwrap();
+
+        assert_eq!(message.edited_at, None);
+        assert_eq!(message.deleted_at, None);
+        assert!(message.reactions.is_empty());
+    }
+
+    #[test]
+    fn test_room_role() {
+        assert_eq!(
+            serde_json::to_string(&RoomRole::Moderator).unwrap(),
+            r#""moderator""#
+        );
+        assert!(RoomRole::Owner.can_moderate());
+        assert!(RoomRole::Moderator.can_moderate());
+        assert!(!RoomRole::Member.can_moderate());
+        assert_eq!(
+            RoomRole::parse(RoomRole::Owner.as_str()),
+            Some(RoomRole::Owner)
+        );
+        assert_eq!(RoomRole::parse("admin"), None);
+    }
+
+    #[test]
+    fn test_room_defaults() {
+        // Rooms stored before descriptions and archiving existed
+        let room: Room = serde_json::from_str(
+            r#"{"id": "general", "name": "General", "created_at": "2024-01-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(room.topic, None);
+        assert!(!room.archived);
+        assert_eq!(room.visibility, RoomVisibility::Public);
+        assert_eq!(room.kind, RoomKind::Channel);
+        assert_eq!(room.last_message_at, None);
+        assert_eq!(
+            RoomKind::parse(RoomKind::Direct.as_str()),
+            Some(RoomKind::Direct)
+        );
+        assert_eq!(
+            RoomVisibility::parse("private"),
+            Some(RoomVisibility::Private)
+        );
+    }
+
+    #[test]
+    fn test_server_frames() {
+        let change = ReactionChange {
+            room_id: "general".to_string(),
+            message_id: "01ARZ3NDEKTSV4RRFFQ69G5FAV".to_string(),
+            emoji: "👍".to_string(),
+            user_id: "alice".to_string(),
+        };
+        let frame = ServerFrame::from(ServerEvent::ReactionAdded(change.clone()));
+        let json = serde_json::to_value(&frame).unwrap();
+        assert_eq!(json["v"], WS_PROTOCOL_VERSION);
+        assert_eq!(json["type"], "reaction.added");
+        assert_eq!(json["emoji"], "👍");
+
+        let frame: ServerFrame = serde_json::from_value(json).unwrap();
+        assert!(matches!(frame.event, ServerEvent::ReactionAdded(c) if c == change));
+
+        // Frames without a known type are refused rather than guessed at
+        let untyped = serde_json::json!({ "v": 1, "id": "m1", "message_text": "hi" });
+        assert!(serde_json::from_value::<ServerFrame>(untyped).is_err());
+        let error: ServerFrame = serde_json::from_str(
+            r#"{"v": 1, "type": "error", "status": 429, "message": "Slow down"}"#,
+        )
+        .unwrap();
+        let ServerEvent::Error(error) = error.event else {
+            panic!("expected an error frame");
+        };
+        assert_eq!(error.status, 429);
+        assert_eq!(error.client_message_id, None);
+    }
+
+    #[test]
+    fn test_client_frames() {
+        let frame: ClientFrame = serde_json::from_str(
+            r#"{"type": "send_message", "message_text": "hi", "client_message_id": "c1"}"#,
+        )
+        .unwrap();
+        assert_eq!(frame.v, None);
+        let ClientEvent::SendMessage {
+            message_text,
+            client_message_id,
+            parent_id,
+        } = frame.event
+        else {
+            panic!("expected send_message");
+        };
+        assert_eq!(message_text, "hi");
+        assert_eq!(client_message_id.as_deref(), Some("c1"));
+        assert_eq!(parent_id, None);
+
+        let typing: ClientFrame =
+            serde_json::from_str(r#"{"v": 1, "type": "typing.stop"}"#).unwrap();
+        assert!(matches!(typing.event, ClientEvent::TypingStop));
+        let read: ClientFrame =
+            serde_json::from_str(r#"{"type": "mark_read", "message_id": "m1"}"#).unwrap();
+        assert!(matches!(read.event, ClientEvent::MarkRead { message_id } if message_id == "m1"));
+        assert!(serde_json::from_str::<ClientFrame>(r#"{"v": 1, "type": "shout"}"#).is_err());
+        assert!(serde_json::from_str::<ClientFrame>(r#"{"type": "send_message"}"#).is_err());
     }
 }